@@ -0,0 +1,138 @@
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use nalgebra::Matrix4;
+use nalgebra::Perspective3;
+use nalgebra::Point3;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+use szumi::batch_math;
+use szumi::batch_math::AabbSoa;
+use szumi::batch_math::Matrix4Soa;
+use szumi::bounds::Aabb;
+use szumi::frustum::Frustum;
+
+const OBJECT_COUNT: usize = 100_000;
+
+/// Objects scattered over a 200 m cube, so roughly a fifth of them are in view.
+fn scene() -> (Vec<Matrix4<f32>>, Vec<Matrix4<f32>>, Vec<Aabb>) {
+    let mut seed = 0x9E37_79B9u32;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        (seed >> 8) as f32 / (1u32 << 24) as f32
+    };
+    let mut parents = Vec::with_capacity(OBJECT_COUNT);
+    let mut locals = Vec::with_capacity(OBJECT_COUNT);
+    let mut aabbs = Vec::with_capacity(OBJECT_COUNT);
+    for _ in 0..OBJECT_COUNT {
+        let translation =
+            Vector3::new(random(), random(), random()) * 200.0 - Vector3::new(100.0, 100.0, 100.0);
+        parents.push(Matrix4::new_translation(&translation));
+        locals.push(
+            UnitQuaternion::from_euler_angles(random(), random(), random()).to_homogeneous()
+                * Matrix4::new_scaling(0.5 + random()),
+        );
+        aabbs.push(Aabb::new(
+            Vector3::new(-0.5, -0.5, -0.5),
+            Vector3::new(0.5, 0.5, 0.5),
+        ));
+    }
+    (parents, locals, aabbs)
+}
+
+fn frustum() -> Frustum {
+    let view = Matrix4::look_at_rh(
+        &Point3::new(0.0, 0.0, 0.0),
+        &Point3::new(0.0, 0.0, -1.0),
+        &Vector3::y(),
+    );
+    let projection = Perspective3::new(1.0, 60f32.to_radians(), 0.1, 1000.0).to_homogeneous();
+    Frustum::from_view_projection(&(projection * view))
+}
+
+fn world_matrices(c: &mut Criterion) {
+    let (parents, locals, _) = scene();
+    let parents_soa = Matrix4Soa::from_matrices(&parents);
+    let locals_soa = Matrix4Soa::from_matrices(&locals);
+    let mut out = Matrix4Soa::default();
+
+    let mut group = c.benchmark_group("world_matrices_100k");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            let world: Vec<Matrix4<f32>> = parents
+                .iter()
+                .zip(&locals)
+                .map(|(parent, local)| parent * local)
+                .collect();
+            black_box(world)
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| batch_math::multiply_matrices(&parents_soa, &locals_soa, black_box(&mut out)))
+    });
+    group.finish();
+}
+
+fn transform_aabbs(c: &mut Criterion) {
+    let (parents, _, aabbs) = scene();
+    let matrices_soa = Matrix4Soa::from_matrices(&parents);
+    let aabbs_soa = AabbSoa::from_aabbs(&aabbs);
+    let mut out = AabbSoa::default();
+
+    let mut group = c.benchmark_group("transform_aabbs_100k");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            let world: Vec<Aabb> = aabbs
+                .iter()
+                .zip(&parents)
+                .map(|(aabb, matrix)| aabb.transform(matrix))
+                .collect();
+            black_box(world)
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| batch_math::transform_aabbs(&aabbs_soa, &matrices_soa, black_box(&mut out)))
+    });
+    group.finish();
+}
+
+fn frustum_cull(c: &mut Criterion) {
+    let (parents, _, aabbs) = scene();
+    let world: Vec<Aabb> = aabbs
+        .iter()
+        .zip(&parents)
+        .map(|(aabb, matrix)| aabb.transform(matrix))
+        .collect();
+    let world_soa = AabbSoa::from_aabbs(&world);
+    let frustum = frustum();
+    let mut visible = Vec::with_capacity(OBJECT_COUNT);
+
+    let mut group = c.benchmark_group("frustum_cull_100k");
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            visible.clear();
+            visible.extend(
+                world
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, aabb)| frustum.intersects_aabb(aabb))
+                    .map(|(i, _)| i as u32),
+            );
+            black_box(visible.len())
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| {
+            visible.clear();
+            batch_math::cull_aabbs(&frustum, &world_soa, &mut visible);
+            black_box(visible.len())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, world_matrices, transform_aabbs, frustum_cull);
+criterion_main!(benches);
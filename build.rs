@@ -0,0 +1,50 @@
+//! Compiles the task and mesh shaders of the `mesh_shader` feature, which vulkano-shaders has no
+//! shader kinds for, into SPIR-V in `OUT_DIR`.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "mesh_shader")]
+    mesh_shaders::compile();
+}
+
+#[cfg(feature = "mesh_shader")]
+mod mesh_shaders {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use shaderc::CompileOptions;
+    use shaderc::Compiler;
+    use shaderc::EnvVersion;
+    use shaderc::ShaderKind;
+    use shaderc::SpirvVersion;
+    use shaderc::TargetEnv;
+
+    const SHADERS: [(&str, ShaderKind); 2] = [
+        ("meshlet.task", ShaderKind::Task),
+        ("meshlet.mesh", ShaderKind::Mesh),
+    ];
+
+    pub fn compile() {
+        let out_dir = env::var("OUT_DIR").unwrap();
+        let compiler = Compiler::new().expect("failed to create the shader compiler");
+        let mut options = CompileOptions::new().unwrap();
+        options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_2 as u32);
+        // Mesh shaders need SPIR-V 1.4.
+        options.set_target_spirv(SpirvVersion::V1_4);
+
+        for (name, kind) in SHADERS {
+            let path = format!("shaders/{name}.glsl");
+            println!("cargo:rerun-if-changed={path}");
+            let source = fs::read_to_string(&path).unwrap();
+            let artifact = compiler
+                .compile_into_spirv(&source, kind, &path, "main", Some(&options))
+                .unwrap_or_else(|error| panic!("failed to compile {path}: {error}"));
+            fs::write(
+                Path::new(&out_dir).join(format!("{name}.spv")),
+                artifact.as_binary_u8(),
+            )
+            .unwrap();
+        }
+    }
+}
@@ -0,0 +1,71 @@
+#version 460
+#extension GL_EXT_mesh_shader : require
+
+// One workgroup per meshlet the task shader kept. Limits match `crate::meshlet`.
+layout(local_size_x = 32) in;
+layout(triangles, max_vertices = 64, max_primitives = 124) out;
+
+layout(location = 0) out vec3 v_color[];
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} mvp;
+
+struct MeshletVertex {
+    vec4 position;
+    vec4 normal;
+};
+
+struct Meshlet {
+    vec4 bounding_sphere;
+    uint vertex_offset;
+    uint vertex_count;
+    uint triangle_offset;
+    uint triangle_count;
+};
+
+layout(binding = 1) readonly buffer Vertices {
+    MeshletVertex vertices[];
+};
+
+layout(binding = 2) readonly buffer Meshlets {
+    Meshlet meshlets[];
+};
+
+layout(binding = 3) readonly buffer MeshletVertices {
+    uint meshlet_vertices[];
+};
+
+// Three byte-sized corners per triangle.
+layout(binding = 4) readonly buffer Triangles {
+    uint triangles[];
+};
+
+struct TaskPayload {
+    uint meshlets[32];
+};
+
+taskPayloadSharedEXT TaskPayload payload;
+
+void main() {
+    uint meshlet_index = payload.meshlets[gl_WorkGroupID.x];
+    Meshlet meshlet = meshlets[meshlet_index];
+    SetMeshOutputsEXT(meshlet.vertex_count, meshlet.triangle_count);
+
+    mat4 model_view_projection = mvp.proj * mvp.view * mvp.model;
+    // Same tint as `vs_meshlet`, so both paths look alike.
+    vec3 tint = fract(float(meshlet_index) * vec3(0.754877, 0.569840, 0.362211));
+    for (uint i = gl_LocalInvocationIndex; i < meshlet.vertex_count; i += 32) {
+        MeshletVertex vertex = vertices[meshlet_vertices[meshlet.vertex_offset + i]];
+        gl_MeshVerticesEXT[i].gl_Position = model_view_projection * vec4(vertex.position.xyz, 1.0);
+        float shade = normalize(mat3(mvp.model) * vertex.normal.xyz).y * 0.25 + 0.75;
+        v_color[i] = (tint * 0.6 + 0.4) * shade;
+    }
+    for (uint i = gl_LocalInvocationIndex; i < meshlet.triangle_count; i += 32) {
+        uint corners = triangles[meshlet.triangle_offset + i];
+        gl_PrimitiveTriangleIndicesEXT[i] =
+            uvec3(corners & 0xFF, (corners >> 8) & 0xFF, (corners >> 16) & 0xFF);
+    }
+}
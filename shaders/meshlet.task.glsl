@@ -0,0 +1,65 @@
+#version 460
+#extension GL_EXT_mesh_shader : require
+
+// One invocation per meshlet; the ones inside the frustum are handed on to the mesh shader.
+layout(local_size_x = 32) in;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} mvp;
+
+struct Meshlet {
+    vec4 bounding_sphere;
+    uint vertex_offset;
+    uint vertex_count;
+    uint triangle_offset;
+    uint triangle_count;
+};
+
+layout(binding = 2) readonly buffer Meshlets {
+    Meshlet meshlets[];
+};
+
+layout(push_constant) uniform MeshletParams {
+    vec4 planes[6];
+} params;
+
+struct TaskPayload {
+    uint meshlets[32];
+};
+
+taskPayloadSharedEXT TaskPayload payload;
+
+shared uint visible_count;
+
+void main() {
+    if (gl_LocalInvocationIndex == 0) {
+        visible_count = 0;
+    }
+    barrier();
+
+    uint index = gl_GlobalInvocationID.x;
+    if (index < meshlets.length()) {
+        vec4 sphere = meshlets[index].bounding_sphere;
+        vec3 center = (mvp.model * vec4(sphere.xyz, 1.0)).xyz;
+        float scale = max(
+            length(mvp.model[0].xyz),
+            max(length(mvp.model[1].xyz), length(mvp.model[2].xyz))
+        );
+        float radius = sphere.w * scale;
+        bool visible = true;
+        for (int i = 0; i < 6; i++) {
+            if (dot(params.planes[i].xyz, center) + params.planes[i].w < -radius) {
+                visible = false;
+            }
+        }
+        if (visible) {
+            payload.meshlets[atomicAdd(visible_count, 1)] = index;
+        }
+    }
+    barrier();
+
+    EmitMeshTasksEXT(visible_count, 1, 1);
+}
@@ -0,0 +1,333 @@
+use nalgebra::DVector;
+use nalgebra::Matrix4;
+use nalgebra::Quaternion;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+use crate::transform::Transform;
+
+/// Local transform of a node, stored decomposed so animation channels can overwrite single components.
+pub type NodeTransform = Transform;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Every keyframe holds three values: in-tangent, value, out-tangent.
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    /// Quaternions in glTF order (x, y, z, w), kept raw so cubic tangents are not normalized.
+    Rotations(Vec<Vector4<f32>>),
+    Scales(Vec<Vector3<f32>>),
+    /// One vector per keyframe with a weight for every morph target of the node's mesh.
+    MorphWeights(Vec<DVector<f32>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationChannel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+    pub duration: f32,
+}
+impl AnimationClip {
+    pub fn new(name: Option<String>, channels: Vec<AnimationChannel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    /// Overwrites the animated components of `pose` with the clip's values at `time` (in seconds).
+    pub fn sample(&self, time: f32, pose: &mut [NodeTransform]) {
+        for channel in &self.channels {
+            let Some(transform) = pose.get_mut(channel.node) else {
+                continue;
+            };
+            match &channel.values {
+                ChannelValues::Translations(values) => {
+                    transform.translation = sample_keyframes(channel, values, time);
+                }
+                ChannelValues::Rotations(values) => {
+                    let q = sample_keyframes(channel, values, time);
+                    transform.rotation =
+                        UnitQuaternion::from_quaternion(Quaternion::new(q.w, q.x, q.y, q.z));
+                }
+                ChannelValues::Scales(values) => {
+                    transform.scale = sample_keyframes(channel, values, time);
+                }
+                ChannelValues::MorphWeights(_) => {}
+            }
+        }
+    }
+
+    /// Overwrites the animated morph target weights in `weights` (indexed by node) at `time`.
+    pub fn sample_morph_weights(&self, time: f32, weights: &mut [Vec<f32>]) {
+        for channel in &self.channels {
+            let (ChannelValues::MorphWeights(values), Some(node_weights)) =
+                (&channel.values, weights.get_mut(channel.node))
+            else {
+                continue;
+            };
+            let sampled = sample_keyframes(channel, values, time);
+            node_weights.clear();
+            node_weights.extend(sampled.iter());
+        }
+    }
+}
+
+fn sample_keyframes<V>(channel: &AnimationChannel, values: &[V], time: f32) -> V
+where
+    V: Clone + std::ops::Add<Output = V> + std::ops::Mul<f32, Output = V>,
+{
+    let times = &channel.times;
+    // Cubic spline keyframes are stored as (in-tangent, value, out-tangent) triplets.
+    let stride = match channel.interpolation {
+        Interpolation::CubicSpline => 3,
+        _ => 1,
+    };
+    let value = |i: usize| values[i * stride + stride / 2].clone();
+
+    let next = times.partition_point(|&t| t <= time);
+    if next == 0 {
+        return value(0);
+    }
+    if next >= times.len() {
+        return value(times.len() - 1);
+    }
+    let prev = next - 1;
+    let delta = times[next] - times[prev];
+    let t = (time - times[prev]) / delta;
+
+    match channel.interpolation {
+        Interpolation::Step => value(prev),
+        Interpolation::Linear => value(prev) * (1.0 - t) + value(next) * t,
+        Interpolation::CubicSpline => {
+            let out_tangent = values[prev * 3 + 2].clone() * delta;
+            let in_tangent = values[next * 3].clone() * delta;
+            let t2 = t * t;
+            let t3 = t2 * t;
+            value(prev) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + out_tangent * (t3 - 2.0 * t2 + t)
+                + value(next) * (-2.0 * t3 + 3.0 * t2)
+                + in_tangent * (t3 - t2)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Skin {
+    /// Node indices of the joints, in the order the vertex joint indices refer to them.
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+impl Skin {
+    /// Computes the matrices the skinning shader multiplies vertices by, one per joint.
+    pub fn joint_matrices(&self, world_matrices: &[Matrix4<f32>]) -> Vec<[[f32; 4]; 4]> {
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(&joint, inverse_bind)| (world_matrices[joint] * inverse_bind).into())
+            .collect()
+    }
+}
+
+/// Walks the node hierarchy from `roots` and returns the world matrix of every node.
+pub fn compute_world_matrices(
+    children: &[Vec<usize>],
+    roots: &[usize],
+    pose: &[NodeTransform],
+) -> Vec<Matrix4<f32>> {
+    let mut world_matrices = vec![Matrix4::identity(); pose.len()];
+    let mut stack: Vec<(usize, Matrix4<f32>)> = roots
+        .iter()
+        .map(|&root| (root, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent)) = stack.pop() {
+        let world = parent * pose[node].to_matrix();
+        world_matrices[node] = world;
+        stack.extend(children[node].iter().map(|&child| (child, world)));
+    }
+    world_matrices
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PlayingClip {
+    clip: usize,
+    time: f32,
+    looping: bool,
+}
+impl PlayingClip {
+    fn advance(&mut self, dt: f32, duration: f32) {
+        self.time += dt;
+        if duration <= 0.0 {
+            self.time = 0.0;
+        } else if self.looping {
+            self.time = self.time.rem_euclid(duration);
+        } else {
+            self.time = self.time.clamp(0.0, duration);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Crossfade {
+    from: PlayingClip,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// Plays clips of one model, optionally crossfading from the previous clip into the next one.
+/// Clips are referred to by their index in the model's animation list.
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    current: Option<PlayingClip>,
+    fade: Option<Crossfade>,
+    paused: bool,
+    pub speed: f32,
+}
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self {
+            current: None,
+            fade: None,
+            paused: false,
+            speed: 1.0,
+        }
+    }
+}
+impl AnimationPlayer {
+    /// Switches to `clip` immediately, restarting it from the beginning.
+    pub fn play(&mut self, clip: usize, looping: bool) {
+        self.current = Some(PlayingClip {
+            clip,
+            time: 0.0,
+            looping,
+        });
+        self.fade = None;
+        self.paused = false;
+    }
+
+    /// Starts `clip` and blends it in over `duration` seconds while the current clip keeps playing.
+    pub fn crossfade_to(&mut self, clip: usize, looping: bool, duration: f32) {
+        let from = self.current.take();
+        self.play(clip, looping);
+        if let (Some(from), true) = (from, duration > 0.0) {
+            self.fade = Some(Crossfade {
+                from,
+                elapsed: 0.0,
+                duration,
+            });
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.fade = None;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Returns true while a clip is active and has not reached the end (looping clips never end).
+    pub fn is_playing(&self, clips: &[AnimationClip]) -> bool {
+        self.current
+            .is_some_and(|c| c.looping || c.time < clips[c.clip].duration)
+    }
+
+    pub fn current_clip(&self) -> Option<usize> {
+        self.current.map(|c| c.clip)
+    }
+
+    pub fn current_time(&self) -> f32 {
+        self.current.map_or(0.0, |c| c.time)
+    }
+
+    /// Moves the playhead by `dt` seconds of wall time, scaled by `speed`.
+    pub fn advance(&mut self, dt: f32, clips: &[AnimationClip]) {
+        if self.paused {
+            return;
+        }
+        let dt = dt * self.speed;
+        if let Some(current) = self.current.as_mut() {
+            current.advance(dt, clips[current.clip].duration);
+        }
+        if let Some(fade) = self.fade.as_mut() {
+            fade.from.advance(dt, clips[fade.from.clip].duration);
+            fade.elapsed += dt.abs();
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+    }
+
+    /// Samples the active clips on top of `rest_pose` and returns the blended local transforms.
+    pub fn sample(
+        &self,
+        clips: &[AnimationClip],
+        rest_pose: &[NodeTransform],
+    ) -> Vec<NodeTransform> {
+        let mut pose = rest_pose.to_vec();
+        if let Some(current) = self.current {
+            clips[current.clip].sample(current.time, &mut pose);
+        }
+        if let Some(fade) = self.fade {
+            let mut from_pose = rest_pose.to_vec();
+            clips[fade.from.clip].sample(fade.from.time, &mut from_pose);
+            let weight = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            for (to, from) in pose.iter_mut().zip(&from_pose) {
+                *to = from.lerp(to, weight);
+            }
+        }
+        pose
+    }
+
+    /// Same as `sample`, for morph target weights (indexed by node).
+    pub fn sample_morph_weights(
+        &self,
+        clips: &[AnimationClip],
+        rest_weights: &[Vec<f32>],
+    ) -> Vec<Vec<f32>> {
+        let mut weights = rest_weights.to_vec();
+        if let Some(current) = self.current {
+            clips[current.clip].sample_morph_weights(current.time, &mut weights);
+        }
+        if let Some(fade) = self.fade {
+            let mut from_weights = rest_weights.to_vec();
+            clips[fade.from.clip].sample_morph_weights(fade.from.time, &mut from_weights);
+            let blend = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            for (to, from) in weights.iter_mut().zip(&from_weights) {
+                for (to, from) in to.iter_mut().zip(from) {
+                    *to = from + (*to - from) * blend;
+                }
+            }
+        }
+        weights
+    }
+}
@@ -0,0 +1,126 @@
+//! Positional audio glue: the camera is the listener, scene nodes carry emitters, and a backend
+//! moves the actual sounds. With the `audio` feature, `RodioBackend` plays them through rodio.
+
+#[cfg(feature = "audio")]
+mod rodio_backend;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use slotmap::SecondaryMap;
+
+use crate::handles::NodeId;
+use crate::scene::Scene;
+
+#[cfg(feature = "audio")]
+pub use self::rodio_backend::RodioBackend;
+
+/// Where sounds are heard from, in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioListener {
+    pub position: Vector3<f32>,
+    /// Unit vectors.
+    pub right: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub forward: Vector3<f32>,
+}
+impl Default for AudioListener {
+    fn default() -> Self {
+        Self::from_view_matrix(&Matrix4::identity())
+    }
+}
+impl AudioListener {
+    /// The camera of a view matrix looking down its -Z axis, as glTF cameras do.
+    pub fn from_view_matrix(view: &Matrix4<f32>) -> Self {
+        let camera = view.try_inverse().unwrap_or_else(Matrix4::identity);
+        let axis = |i: usize| camera.fixed_view::<3, 1>(0, i).into_owned();
+        Self {
+            position: axis(3),
+            right: axis(0).normalize(),
+            up: axis(1).normalize(),
+            forward: -axis(2).normalize(),
+        }
+    }
+
+    /// Left and right ear positions, `ear_distance` apart.
+    pub fn ears(&self, ear_distance: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let offset = self.right * (ear_distance * 0.5);
+        (self.position - offset, self.position + offset)
+    }
+}
+
+/// Moves sounds in an audio library.
+pub trait AudioBackend {
+    /// A playing or playable sound with a position, e.g. a spatial sink.
+    type Emitter;
+
+    /// Called once per update, before the emitters.
+    fn update_listener(&mut self, listener: &AudioListener);
+
+    fn update_emitter(
+        &mut self,
+        emitter: &mut Self::Emitter,
+        position: &Vector3<f32>,
+        listener: &AudioListener,
+    );
+}
+
+/// Emitters attached to scene nodes, kept at their nodes' world positions.
+pub struct SpatialAudio<B: AudioBackend> {
+    backend: B,
+    emitters: SecondaryMap<NodeId, B::Emitter>,
+    listener: AudioListener,
+}
+impl<B: AudioBackend> SpatialAudio<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            emitters: SecondaryMap::new(),
+            listener: AudioListener::default(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn listener(&self) -> &AudioListener {
+        &self.listener
+    }
+
+    /// Attaches `emitter` to `node`, replacing and returning any emitter it had.
+    pub fn attach(&mut self, node: NodeId, emitter: B::Emitter) -> Option<B::Emitter> {
+        self.emitters.insert(node, emitter)
+    }
+
+    pub fn detach(&mut self, node: NodeId) -> Option<B::Emitter> {
+        self.emitters.remove(node)
+    }
+
+    pub fn emitter(&self, node: NodeId) -> Option<&B::Emitter> {
+        self.emitters.get(node)
+    }
+
+    pub fn emitter_mut(&mut self, node: NodeId) -> Option<&mut B::Emitter> {
+        self.emitters.get_mut(node)
+    }
+
+    /// Moves the listener to the camera of `view` and every emitter to its node. Call once per
+    /// frame after `Scene::update_transforms`. Emitters of removed nodes are dropped.
+    pub fn update(&mut self, scene: &Scene, view: &Matrix4<f32>) {
+        self.listener = AudioListener::from_view_matrix(view);
+        self.backend.update_listener(&self.listener);
+        self.emitters.retain(|node, _| scene.contains(node));
+        for (node, emitter) in &mut self.emitters {
+            let position = scene
+                .world_matrix(node)
+                .fixed_view::<3, 1>(0, 3)
+                .into_owned();
+            self.backend
+                .update_emitter(emitter, &position, &self.listener);
+        }
+    }
+}
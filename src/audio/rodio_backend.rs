@@ -0,0 +1,61 @@
+use nalgebra::Vector3;
+use rodio::OutputStream;
+use rodio::OutputStreamHandle;
+use rodio::PlayError;
+use rodio::SpatialSink;
+use rodio::StreamError;
+
+use super::AudioBackend;
+use super::AudioListener;
+
+/// Plays emitters as rodio `SpatialSink`s on the default output device.
+pub struct RodioBackend {
+    // Sound stops when the stream is dropped.
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    /// Distance between the listener's ears, in world units.
+    pub ear_distance: f32,
+    listener: AudioListener,
+}
+impl RodioBackend {
+    pub fn new() -> Result<Self, StreamError> {
+        let (stream, handle) = OutputStream::try_default()?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+            ear_distance: 0.2,
+            listener: AudioListener::default(),
+        })
+    }
+
+    /// A silent sink at the listener; `append` sources to it and attach it to a node.
+    pub fn create_emitter(&self) -> Result<SpatialSink, PlayError> {
+        let (left, right) = self.listener.ears(self.ear_distance);
+        SpatialSink::try_new(
+            &self.handle,
+            self.listener.position.into(),
+            left.into(),
+            right.into(),
+        )
+    }
+}
+impl AudioBackend for RodioBackend {
+    type Emitter = SpatialSink;
+
+    fn update_listener(&mut self, listener: &AudioListener) {
+        self.listener = *listener;
+    }
+
+    fn update_emitter(
+        &mut self,
+        emitter: &mut SpatialSink,
+        position: &Vector3<f32>,
+        listener: &AudioListener,
+    ) {
+        // rodio keeps the ears per sink, so every sink follows the listener itself.
+        let (left, right) = listener.ears(self.ear_distance);
+        emitter.set_left_ear_position(left.into());
+        emitter.set_right_ear_position(right.into());
+        emitter.set_emitter_position((*position).into());
+    }
+}
@@ -0,0 +1,226 @@
+//! Transform and culling math over many objects at once. Objects are packed eight to a block,
+//! component by component, so one `f32x8` holds the same component of eight objects and every
+//! block is read front to back.
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use wide::f32x8;
+use wide::CmpGe;
+use wide::CmpGt;
+
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+
+const LANES: usize = 8;
+
+/// 4x4 matrices packed eight to a block. Elements are in nalgebra's column-major order.
+#[derive(Clone, Debug, Default)]
+pub struct Matrix4Soa {
+    blocks: Vec<[[f32; LANES]; 16]>,
+    len: usize,
+}
+impl Matrix4Soa {
+    pub fn from_matrices<'a>(matrices: impl IntoIterator<Item = &'a Matrix4<f32>>) -> Self {
+        let mut soa = Self::default();
+        for matrix in matrices {
+            soa.push(matrix);
+        }
+        soa
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, matrix: &Matrix4<f32>) {
+        let lane = self.len % LANES;
+        if lane == 0 {
+            self.blocks.push([[0.0; LANES]; 16]);
+        }
+        let block = self.blocks.last_mut().unwrap();
+        for (element, &value) in block.iter_mut().zip(matrix.as_slice()) {
+            element[lane] = value;
+        }
+        self.len += 1;
+    }
+
+    pub fn get(&self, index: usize) -> Matrix4<f32> {
+        assert!(index < self.len, "matrix index out of bounds");
+        let block = &self.blocks[index / LANES];
+        Matrix4::from_column_slice(&block.map(|element| element[index % LANES]))
+    }
+
+    fn resize(&mut self, len: usize) {
+        self.blocks.resize(len.div_ceil(LANES), [[0.0; LANES]; 16]);
+        self.len = len;
+    }
+}
+
+/// Padding lanes hold empty boxes, which transform to empty boxes and are never visible.
+const EMPTY_AABB_BLOCK: [[f32; LANES]; 6] = [
+    [f32::INFINITY; LANES],
+    [f32::INFINITY; LANES],
+    [f32::INFINITY; LANES],
+    [f32::NEG_INFINITY; LANES],
+    [f32::NEG_INFINITY; LANES],
+    [f32::NEG_INFINITY; LANES],
+];
+
+/// Axis-aligned boxes packed eight to a block: min x, y, z, then max x, y, z.
+#[derive(Clone, Debug, Default)]
+pub struct AabbSoa {
+    blocks: Vec<[[f32; LANES]; 6]>,
+    len: usize,
+}
+impl AabbSoa {
+    pub fn from_aabbs<'a>(aabbs: impl IntoIterator<Item = &'a Aabb>) -> Self {
+        let mut soa = Self::default();
+        for aabb in aabbs {
+            soa.push(aabb);
+        }
+        soa
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, aabb: &Aabb) {
+        let lane = self.len % LANES;
+        if lane == 0 {
+            self.blocks.push(EMPTY_AABB_BLOCK);
+        }
+        let block = self.blocks.last_mut().unwrap();
+        for axis in 0..3 {
+            block[axis][lane] = aabb.min[axis];
+            block[3 + axis][lane] = aabb.max[axis];
+        }
+        self.len += 1;
+    }
+
+    pub fn get(&self, index: usize) -> Aabb {
+        assert!(index < self.len, "box index out of bounds");
+        let block = &self.blocks[index / LANES];
+        let lane = index % LANES;
+        Aabb::new(
+            Vector3::from_fn(|axis, _| block[axis][lane]),
+            Vector3::from_fn(|axis, _| block[3 + axis][lane]),
+        )
+    }
+
+    fn resize(&mut self, len: usize) {
+        self.blocks.resize(len.div_ceil(LANES), EMPTY_AABB_BLOCK);
+        self.len = len;
+    }
+}
+
+/// `out[i] = lhs[i] * rhs[i]`, e.g. parent world matrices times local matrices.
+pub fn multiply_matrices(lhs: &Matrix4Soa, rhs: &Matrix4Soa, out: &mut Matrix4Soa) {
+    assert_eq!(lhs.len(), rhs.len(), "matrix batches differ in length");
+    out.resize(lhs.len());
+    for ((a, b), out) in lhs.blocks.iter().zip(&rhs.blocks).zip(&mut out.blocks) {
+        let a = a.map(f32x8::new);
+        let b = b.map(f32x8::new);
+        for column in 0..4 {
+            for row in 0..4 {
+                let sum = a[row] * b[column * 4]
+                    + a[4 + row] * b[column * 4 + 1]
+                    + a[8 + row] * b[column * 4 + 2]
+                    + a[12 + row] * b[column * 4 + 3];
+                out[column * 4 + row] = sum.to_array();
+            }
+        }
+    }
+}
+
+/// `out[i] = aabbs[i].transform(matrices[i])`, using the same Arvo method as `Aabb::transform`.
+/// Matrices must be affine. Empty boxes stay empty.
+pub fn transform_aabbs(aabbs: &AabbSoa, matrices: &Matrix4Soa, out: &mut AabbSoa) {
+    assert_eq!(aabbs.len(), matrices.len(), "batches differ in length");
+    out.resize(aabbs.len());
+    let half = f32x8::splat(0.5);
+    for ((aabb, m), out) in aabbs
+        .blocks
+        .iter()
+        .zip(&matrices.blocks)
+        .zip(&mut out.blocks)
+    {
+        let aabb = aabb.map(f32x8::new);
+        let m = m.map(f32x8::new);
+        let (min, max) = (&aabb[..3], &aabb[3..]);
+        let empty = min[0].simd_gt(max[0]) | min[1].simd_gt(max[1]) | min[2].simd_gt(max[2]);
+        let center: [f32x8; 3] = std::array::from_fn(|axis| (min[axis] + max[axis]) * half);
+        let extent: [f32x8; 3] = std::array::from_fn(|axis| (max[axis] - min[axis]) * half);
+        for row in 0..3 {
+            let new_center =
+                m[12 + row] + m[row] * center[0] + m[4 + row] * center[1] + m[8 + row] * center[2];
+            let new_extent = m[row].abs() * extent[0]
+                + m[4 + row].abs() * extent[1]
+                + m[8 + row].abs() * extent[2];
+            out[row] = empty.blend(min[row], new_center - new_extent).to_array();
+            out[3 + row] = empty.blend(max[row], new_center + new_extent).to_array();
+        }
+    }
+}
+
+/// Appends the indices of the boxes that intersect `frustum` to `visible`, in ascending order.
+/// Same conservative test as `Frustum::intersects_aabb`; empty boxes are never visible.
+pub fn cull_aabbs(frustum: &Frustum, aabbs: &AabbSoa, visible: &mut Vec<u32>) {
+    let half = f32x8::splat(0.5);
+    let zero = f32x8::splat(0.0);
+    // Per plane: normal x, y, z, distance, then the absolute normal for the extent term.
+    let planes = frustum.planes.map(|plane| {
+        [
+            plane.x,
+            plane.y,
+            plane.z,
+            plane.w,
+            plane.x.abs(),
+            plane.y.abs(),
+            plane.z.abs(),
+        ]
+        .map(f32x8::splat)
+    });
+    for (block_index, aabb) in aabbs.blocks.iter().enumerate() {
+        let aabb = aabb.map(f32x8::new);
+        let (min, max) = (&aabb[..3], &aabb[3..]);
+        let empty = min[0].simd_gt(max[0]) | min[1].simd_gt(max[1]) | min[2].simd_gt(max[2]);
+        let center: [f32x8; 3] = std::array::from_fn(|axis| (min[axis] + max[axis]) * half);
+        let extent: [f32x8; 3] = std::array::from_fn(|axis| (max[axis] - min[axis]) * half);
+        let mut inside = !empty;
+        for plane in &planes {
+            let distance = plane[3]
+                + plane[0] * center[0]
+                + plane[1] * center[1]
+                + plane[2] * center[2]
+                + plane[4] * extent[0]
+                + plane[5] * extent[1]
+                + plane[6] * extent[2];
+            inside &= distance.simd_ge(zero);
+        }
+        let mut mask = inside.to_bitmask();
+        while mask != 0 {
+            let lane = mask.trailing_zeros() as usize;
+            visible.push((block_index * LANES + lane) as u32);
+            mask &= mask - 1;
+        }
+    }
+}
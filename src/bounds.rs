@@ -0,0 +1,135 @@
+use nalgebra::Matrix4;
+use nalgebra::Point3;
+use nalgebra::Vector3;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+impl Aabb {
+    /// A box containing nothing; growing it by any point yields that point.
+    pub const EMPTY: Aabb = Aabb {
+        min: Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a [f32; 3]>) -> Self {
+        points
+            .into_iter()
+            .fold(Aabb::EMPTY, |aabb, p| aabb.grow(&Vector3::from(*p)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&self, point: &Vector3<f32>) -> Aabb {
+        Aabb {
+            min: self.min.inf(point),
+            max: self.max.sup(point),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn contains_point(&self, point: &Vector3<f32>) -> bool {
+        self.min.x <= point.x
+            && self.min.y <= point.y
+            && self.min.z <= point.z
+            && self.max.x >= point.x
+            && self.max.y >= point.y
+            && self.max.z >= point.z
+    }
+
+    /// Whether `other` lies completely inside this box.
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+            && self.max.z >= other.max.z
+    }
+
+    /// Grows the box by `margin` on every side.
+    pub fn expand(&self, margin: f32) -> Aabb {
+        let margin = Vector3::repeat(margin);
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box enclosing this box after it has been transformed by `matrix`.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        // Arvo's method: project the half extents onto the absolute rotation/scale part.
+        let center = matrix.transform_point(&Point3::from(self.center())).coords;
+        let linear = matrix.fixed_view::<3, 3>(0, 0).abs();
+        let half_extents = linear * self.half_extents();
+        Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    /// Slab test. Returns the distance along the ray at which it enters the box (0 if it starts inside).
+    pub fn intersect_ray(
+        &self,
+        origin: &Vector3<f32>,
+        direction: &Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / direction[axis];
+            let mut t0 = (self.min[axis] - origin[axis]) * inverse;
+            let mut t1 = (self.max[axis] - origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
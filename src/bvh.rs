@@ -0,0 +1,400 @@
+use slotmap::SecondaryMap;
+
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+use crate::handles::NodeId;
+use crate::raycast::Ray;
+
+/// Marks a missing parent, child or root.
+const NULL: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug)]
+struct TreeNode {
+    /// For leaves the fattened object bounds, for branches the union of both children.
+    bounds: Aabb,
+    parent: u32,
+    /// Both `NULL` for leaves.
+    children: [u32; 2],
+    item: Option<NodeId>,
+}
+impl TreeNode {
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NULL
+    }
+}
+
+/// Dynamic bounding volume hierarchy over scene nodes, for culling and picking without testing
+/// every object.
+///
+/// Leaves store their bounds grown by a margin, so an object that moves a little stays inside its
+/// leaf and costs nothing; only objects that leave it are removed and reinserted. The tree is
+/// never rebuilt from scratch.
+pub struct Bvh {
+    nodes: Vec<TreeNode>,
+    free_nodes: Vec<u32>,
+    root: u32,
+    leaves: SecondaryMap<NodeId, u32>,
+    margin: f32,
+}
+impl Default for Bvh {
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+impl Bvh {
+    /// `margin` is how far, in world units, an object may move before its leaf is reinserted.
+    pub fn new(margin: f32) -> Self {
+        Self {
+            nodes: Vec::new(),
+            free_nodes: Vec::new(),
+            root: NULL,
+            leaves: SecondaryMap::new(),
+            margin,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.leaves.contains_key(id)
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free_nodes.clear();
+        self.root = NULL;
+        self.leaves.clear();
+    }
+
+    /// Bounds of the whole tree, empty if it holds nothing.
+    pub fn bounds(&self) -> Aabb {
+        match self.root {
+            NULL => Aabb::EMPTY,
+            root => self.nodes[root as usize].bounds,
+        }
+    }
+
+    /// Adds `id` with world-space `bounds`, or moves it there if it is already in the tree.
+    /// Returns whether the tree changed shape.
+    pub fn update(&mut self, id: NodeId, bounds: &Aabb) -> bool {
+        if let Some(&leaf) = self.leaves.get(id) {
+            if self.nodes[leaf as usize].bounds.contains(bounds) {
+                return false;
+            }
+            self.remove_leaf(leaf);
+            self.nodes[leaf as usize].bounds = bounds.expand(self.margin);
+            self.insert_leaf(leaf);
+        } else {
+            let leaf = self.allocate(TreeNode {
+                bounds: bounds.expand(self.margin),
+                parent: NULL,
+                children: [NULL; 2],
+                item: Some(id),
+            });
+            self.insert_leaf(leaf);
+            self.leaves.insert(id, leaf);
+        }
+        true
+    }
+
+    /// Returns whether `id` was in the tree.
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        let Some(leaf) = self.leaves.remove(id) else {
+            return false;
+        };
+        self.remove_leaf(leaf);
+        self.free(leaf);
+        true
+    }
+
+    /// Appends every object whose bounds may overlap `bounds`.
+    pub fn query_aabb(&self, bounds: &Aabb, out: &mut Vec<NodeId>) {
+        self.query(|node_bounds| node_bounds.intersects(bounds), out);
+    }
+
+    /// Appends every object that may be visible in `frustum`.
+    pub fn query_frustum(&self, frustum: &Frustum, out: &mut Vec<NodeId>) {
+        self.query(|node_bounds| frustum.intersects_aabb(node_bounds), out);
+    }
+
+    /// Finds the closest object hit by `ray`. `hit` is asked about each object whose bounds the
+    /// ray passes through before the closest hit so far and returns its exact hit distance, e.g.
+    /// from `raycast_primitive`, or `None` if the ray misses it.
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        mut hit: impl FnMut(NodeId) -> Option<f32>,
+    ) -> Option<(NodeId, f32)> {
+        let mut closest: Option<(NodeId, f32)> = None;
+        let mut stack = Vec::new();
+        if self.root != NULL {
+            stack.push(self.root);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            let limit = closest.map_or(max_distance, |(_, distance)| distance);
+            if node
+                .bounds
+                .intersect_ray(&ray.origin, &ray.direction, limit)
+                .is_none()
+            {
+                continue;
+            }
+            match node.item {
+                Some(id) => {
+                    if let Some(distance) = hit(id).filter(|&distance| distance < limit) {
+                        closest = Some((id, distance));
+                    }
+                }
+                None => stack.extend_from_slice(&node.children),
+            }
+        }
+        closest
+    }
+
+    fn query(&self, overlaps: impl Fn(&Aabb) -> bool, out: &mut Vec<NodeId>) {
+        let mut stack = Vec::new();
+        if self.root != NULL {
+            stack.push(self.root);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if !overlaps(&node.bounds) {
+                continue;
+            }
+            match node.item {
+                Some(id) => out.push(id),
+                None => stack.extend_from_slice(&node.children),
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: TreeNode) -> u32 {
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index as usize] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        }
+    }
+
+    fn free(&mut self, index: u32) {
+        self.nodes[index as usize].item = None;
+        self.free_nodes.push(index);
+    }
+
+    /// Places `leaf` next to the sibling that grows the tree's surface area the least.
+    fn insert_leaf(&mut self, leaf: u32) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf as usize].parent = NULL;
+            return;
+        }
+        let leaf_bounds = self.nodes[leaf as usize].bounds;
+
+        let mut sibling = self.root;
+        while !self.nodes[sibling as usize].is_leaf() {
+            let node = &self.nodes[sibling as usize];
+            let combined_area = node.bounds.union(&leaf_bounds).surface_area();
+            // Cost of pairing with this node, and of pushing the leaf further down.
+            let cost = 2.0 * combined_area;
+            let inherited_cost = 2.0 * (combined_area - node.bounds.surface_area());
+            let child_cost = |child: u32| {
+                let child = &self.nodes[child as usize];
+                let area = child.bounds.union(&leaf_bounds).surface_area();
+                if child.is_leaf() {
+                    area + inherited_cost
+                } else {
+                    area - child.bounds.surface_area() + inherited_cost
+                }
+            };
+            let [left, right] = node.children;
+            let (left_cost, right_cost) = (child_cost(left), child_cost(right));
+            if cost < left_cost && cost < right_cost {
+                break;
+            }
+            sibling = if left_cost < right_cost { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling as usize].parent;
+        let new_parent = self.allocate(TreeNode {
+            bounds: leaf_bounds.union(&self.nodes[sibling as usize].bounds),
+            parent: old_parent,
+            children: [sibling, leaf],
+            item: None,
+        });
+        self.nodes[sibling as usize].parent = new_parent;
+        self.nodes[leaf as usize].parent = new_parent;
+        if old_parent == NULL {
+            self.root = new_parent;
+        } else {
+            self.replace_child(old_parent, sibling, new_parent);
+        }
+        self.refit(old_parent);
+    }
+
+    /// Unlinks `leaf` and frees its parent, whose other child takes the parent's place.
+    fn remove_leaf(&mut self, leaf: u32) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        }
+        let parent = self.nodes[leaf as usize].parent;
+        let grandparent = self.nodes[parent as usize].parent;
+        let [left, right] = self.nodes[parent as usize].children;
+        let sibling = if left == leaf { right } else { left };
+
+        self.nodes[sibling as usize].parent = grandparent;
+        if grandparent == NULL {
+            self.root = sibling;
+        } else {
+            self.replace_child(grandparent, parent, sibling);
+        }
+        self.free(parent);
+        self.refit(grandparent);
+    }
+
+    fn replace_child(&mut self, parent: u32, old_child: u32, new_child: u32) {
+        let children = &mut self.nodes[parent as usize].children;
+        let slot = if children[0] == old_child { 0 } else { 1 };
+        children[slot] = new_child;
+    }
+
+    /// Recomputes the bounds of `index` and its ancestors.
+    fn refit(&mut self, mut index: u32) {
+        while index != NULL {
+            let [left, right] = self.nodes[index as usize].children;
+            self.nodes[index as usize].bounds = self.nodes[left as usize]
+                .bounds
+                .union(&self.nodes[right as usize].bounds);
+            index = self.nodes[index as usize].parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use slotmap::SlotMap;
+
+    use super::*;
+
+    fn ids(count: usize) -> Vec<NodeId> {
+        let mut nodes = SlotMap::<NodeId, ()>::with_key();
+        (0..count).map(|_| nodes.insert(())).collect()
+    }
+
+    /// A unit cube centered on `x` along the x axis.
+    fn cube_at(x: f32) -> Aabb {
+        Aabb::new(
+            Vector3::new(x - 0.5, -0.5, -0.5),
+            Vector3::new(x + 0.5, 0.5, 0.5),
+        )
+    }
+
+    fn query(bvh: &Bvh, bounds: &Aabb) -> Vec<NodeId> {
+        let mut found = Vec::new();
+        bvh.query_aabb(bounds, &mut found);
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn insert_finds_every_object() {
+        let ids = ids(8);
+        let mut bvh = Bvh::new(0.1);
+        for (i, &id) in ids.iter().enumerate() {
+            assert!(bvh.update(id, &cube_at(i as f32 * 4.0)));
+        }
+        assert_eq!(bvh.len(), ids.len());
+        for (i, &id) in ids.iter().enumerate() {
+            assert!(bvh.contains(id));
+            assert!(bvh.bounds().contains(&cube_at(i as f32 * 4.0)));
+            assert_eq!(query(&bvh, &cube_at(i as f32 * 4.0)), vec![id]);
+        }
+        let mut all = ids.clone();
+        all.sort();
+        assert_eq!(query(&bvh, &bvh.bounds()), all);
+    }
+
+    #[test]
+    fn remove_unlinks_objects() {
+        let ids = ids(5);
+        let mut bvh = Bvh::new(0.1);
+        for (i, &id) in ids.iter().enumerate() {
+            bvh.update(id, &cube_at(i as f32 * 4.0));
+        }
+        assert!(bvh.remove(ids[2]));
+        assert!(!bvh.remove(ids[2]));
+        assert!(!bvh.contains(ids[2]));
+        assert_eq!(bvh.len(), 4);
+        assert!(query(&bvh, &cube_at(8.0)).is_empty());
+        assert_eq!(query(&bvh, &cube_at(12.0)), vec![ids[3]]);
+
+        for &id in &ids {
+            bvh.remove(id);
+        }
+        assert!(bvh.is_empty());
+        assert!(bvh.bounds().is_empty());
+    }
+
+    #[test]
+    fn update_refits_moved_objects() {
+        let ids = ids(4);
+        let mut bvh = Bvh::new(0.1);
+        for (i, &id) in ids.iter().enumerate() {
+            bvh.update(id, &cube_at(i as f32 * 4.0));
+        }
+        // Within the margin the leaf is left alone.
+        assert!(!bvh.update(ids[0], &cube_at(0.05)));
+
+        assert!(bvh.update(ids[0], &cube_at(100.0)));
+        assert_eq!(bvh.len(), 4);
+        assert!(bvh.bounds().contains(&cube_at(100.0)));
+        assert!(query(&bvh, &cube_at(0.0)).is_empty());
+        assert_eq!(query(&bvh, &cube_at(100.0)), vec![ids[0]]);
+
+        // Shrinking back refits the ancestors, so the tree bounds no longer reach the old spot.
+        assert!(bvh.update(ids[0], &cube_at(0.0)));
+        assert!(!bvh.bounds().contains_point(&Vector3::new(100.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn raycast_returns_nearest_hit() {
+        let ids = ids(4);
+        let mut bvh = Bvh::new(0.1);
+        // Inserted far to near, so the traversal order differs from the distance order.
+        let xs = [20.0, 15.0, 10.0, 5.0];
+        for (&id, &x) in ids.iter().zip(&xs) {
+            bvh.update(id, &cube_at(x));
+        }
+        let ray = Ray::new(Vector3::zeros(), Vector3::new(1.0, 0.0, 0.0));
+        let hit = |id: NodeId| {
+            let x = xs[ids.iter().position(|&other| other == id).unwrap()];
+            cube_at(x).intersect_ray(&ray.origin, &ray.direction, f32::MAX)
+        };
+
+        let (id, distance) = bvh.raycast(&ray, 100.0, hit).unwrap();
+        assert_eq!(id, ids[3]);
+        assert!((distance - 4.5).abs() < 1e-5);
+
+        // Objects the exact test misses are skipped for the next nearest.
+        let (id, _) = bvh
+            .raycast(&ray, 100.0, |id| if id == ids[3] { None } else { hit(id) })
+            .unwrap();
+        assert_eq!(id, ids[2]);
+
+        assert!(bvh.raycast(&ray, 4.0, hit).is_none());
+    }
+}
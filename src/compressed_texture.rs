@@ -0,0 +1,289 @@
+//! Textures kept in the format the GPU samples them in, e.g. BC7 from a KTX2 file, so they are
+//! copied to VRAM as they are instead of being decoded to RGBA first.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use vulkano::format::Format;
+use vulkano::image::mip_level_extent;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Identifier, header and the index up to the level index.
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+#[derive(Debug)]
+pub enum Ktx2Error {
+    Io(io::Error),
+    /// The file doesn't start with the KTX2 identifier.
+    NotKtx2,
+    /// A header field or level points past the end of the file.
+    Truncated,
+    /// A `VkFormat` other than BC1, BC3, BC5, BC7 or RGBA8. Basis Universal's UASTC files have
+    /// no format and need transcoding first, e.g. with `ktx transcode`.
+    UnsupportedFormat(u32),
+    /// BasisLZ, Zstandard or zlib supercompression, which needs transcoding or inflating first.
+    Supercompressed(u32),
+    /// 3D, array or cube map textures.
+    UnsupportedLayout,
+}
+impl fmt::Display for Ktx2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ktx2Error::Io(error) => write!(f, "failed to read KTX2 file: {error}"),
+            Ktx2Error::NotKtx2 => write!(f, "not a KTX2 file"),
+            Ktx2Error::Truncated => write!(f, "KTX2 file is truncated"),
+            Ktx2Error::UnsupportedFormat(format) => {
+                write!(f, "unsupported KTX2 format {format}")
+            }
+            Ktx2Error::Supercompressed(scheme) => {
+                write!(f, "unsupported KTX2 supercompression scheme {scheme}")
+            }
+            Ktx2Error::UnsupportedLayout => {
+                write!(f, "only single 2D KTX2 textures are supported")
+            }
+        }
+    }
+}
+impl std::error::Error for Ktx2Error {}
+impl From<io::Error> for Ktx2Error {
+    fn from(error: io::Error) -> Self {
+        Ktx2Error::Io(error)
+    }
+}
+
+/// A 2D texture with its mip levels, ready to copy as is, e.g. for
+/// `Resources::create_compressed_texture`.
+#[derive(Clone, Debug)]
+pub struct CompressedTexture {
+    pub format: Format,
+    pub extent: [u32; 2],
+    /// Tightly packed blocks of each mip level, largest first.
+    pub levels: Vec<Vec<u8>>,
+}
+impl CompressedTexture {
+    /// Raw BCn data, e.g. from a DDS file, with every level's size checked against `format`.
+    pub fn from_bcn(format: Format, extent: [u32; 2], levels: Vec<Vec<u8>>) -> Self {
+        assert!(
+            format.compression().is_some(),
+            "{format:?} is not a compressed format"
+        );
+        let texture = Self {
+            format,
+            extent,
+            levels,
+        };
+        assert!(
+            texture.levels_match_extent(),
+            "texture data does not match its size"
+        );
+        texture
+    }
+
+    pub fn load_ktx2(path: impl AsRef<Path>) -> Result<Self, Ktx2Error> {
+        Self::from_ktx2(&fs::read(path)?)
+    }
+
+    /// Parses a KTX2 file with BCn or RGBA8 data and no supercompression. A level count of 0,
+    /// which asks the loader to generate mips, loads just the first level.
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, Ktx2Error> {
+        if !bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Err(Ktx2Error::NotKtx2);
+        }
+        let u32_at = |offset: usize| {
+            bytes
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(Ktx2Error::Truncated)
+        };
+        let u64_at = |offset: usize| {
+            bytes
+                .get(offset..offset + 8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(Ktx2Error::Truncated)
+        };
+
+        let vk_format = u32_at(12)?;
+        let width = u32_at(20)?;
+        let height = u32_at(24)?;
+        let depth = u32_at(28)?;
+        let layers = u32_at(32)?;
+        let faces = u32_at(36)?;
+        let level_count = u32_at(40)?.max(1);
+        let supercompression = u32_at(44)?;
+
+        if supercompression != 0 {
+            return Err(Ktx2Error::Supercompressed(supercompression));
+        }
+        let format = ktx2_format(vk_format).ok_or(Ktx2Error::UnsupportedFormat(vk_format))?;
+        if width == 0 || height == 0 || depth > 1 || layers > 1 || faces != 1 {
+            return Err(Ktx2Error::UnsupportedLayout);
+        }
+
+        let levels = (0..level_count as usize)
+            .map(|level| {
+                let entry = KTX2_LEVEL_INDEX_OFFSET + level * 24;
+                let offset = u64_at(entry)? as usize;
+                let length = u64_at(entry + 8)? as usize;
+                bytes
+                    .get(offset..offset.saturating_add(length))
+                    .map(<[u8]>::to_vec)
+                    .ok_or(Ktx2Error::Truncated)
+            })
+            .collect::<Result<_, _>>()?;
+        let texture = Self {
+            format,
+            extent: [width, height],
+            levels,
+        };
+        if !texture.levels_match_extent() {
+            return Err(Ktx2Error::Truncated);
+        }
+        Ok(texture)
+    }
+
+    /// Bytes copied to the GPU.
+    pub fn size(&self) -> u64 {
+        self.levels.iter().map(|level| level.len() as u64).sum()
+    }
+
+    fn levels_match_extent(&self) -> bool {
+        let [block_width, block_height, _] = self.format.block_extent();
+        let extent = [self.extent[0], self.extent[1], 1];
+        !self.levels.is_empty()
+            && self.levels.iter().enumerate().all(|(level, data)| {
+                mip_level_extent(extent, level as u32).is_some_and(|[width, height, _]| {
+                    let blocks =
+                        width.div_ceil(block_width) as u64 * height.div_ceil(block_height) as u64;
+                    data.len() as u64 == blocks * self.format.block_size()
+                })
+            })
+    }
+}
+
+/// The formats KTX2 files are read in, by `VkFormat` value.
+fn ktx2_format(vk_format: u32) -> Option<Format> {
+    Some(match vk_format {
+        37 => Format::R8G8B8A8_UNORM,
+        43 => Format::R8G8B8A8_SRGB,
+        131 => Format::BC1_RGB_UNORM_BLOCK,
+        132 => Format::BC1_RGB_SRGB_BLOCK,
+        133 => Format::BC1_RGBA_UNORM_BLOCK,
+        134 => Format::BC1_RGBA_SRGB_BLOCK,
+        137 => Format::BC3_UNORM_BLOCK,
+        138 => Format::BC3_SRGB_BLOCK,
+        141 => Format::BC5_UNORM_BLOCK,
+        142 => Format::BC5_SNORM_BLOCK,
+        145 => Format::BC7_UNORM_BLOCK,
+        146 => Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BC7_UNORM: u32 = 145;
+
+    /// A KTX2 file of a 2D texture with `levels` as its mip levels, claiming `level_count` of
+    /// them in the header.
+    fn ktx2(vk_format: u32, extent: [u32; 2], level_count: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for field in [vk_format, 1, extent[0], extent[1], 0, 0, 1, level_count, 0] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        // No data format descriptor, key/value or supercompression data.
+        bytes.resize(KTX2_LEVEL_INDEX_OFFSET, 0);
+        let mut offset = KTX2_LEVEL_INDEX_OFFSET + levels.len() * 24;
+        for level in levels {
+            for field in [offset, level.len(), level.len()] {
+                bytes.extend_from_slice(&(field as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        for level in levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    #[test]
+    fn loads_every_level() {
+        // 8x8 is 2x2 blocks of 16 bytes, 4x4 is one block.
+        let levels = [vec![1; 64], vec![2; 16]];
+        let texture = CompressedTexture::from_ktx2(&ktx2(BC7_UNORM, [8, 8], 2, &levels)).unwrap();
+        assert_eq!(texture.format, Format::BC7_UNORM_BLOCK);
+        assert_eq!(texture.extent, [8, 8]);
+        assert_eq!(texture.levels, levels);
+        assert_eq!(texture.size(), 80);
+    }
+
+    #[test]
+    fn level_count_zero_loads_the_first_level() {
+        let levels = [vec![1; 64]];
+        let texture = CompressedTexture::from_ktx2(&ktx2(BC7_UNORM, [8, 8], 0, &levels)).unwrap();
+        assert_eq!(texture.levels, levels);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = ktx2(BC7_UNORM, [8, 8], 2, &[vec![1; 64], vec![2; 16]]);
+        // Within the header.
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&bytes[..30]),
+            Err(Ktx2Error::Truncated)
+        ));
+        // Within the level index.
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&bytes[..KTX2_LEVEL_INDEX_OFFSET + 30]),
+            Err(Ktx2Error::Truncated)
+        ));
+        // Within the last level's data.
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&bytes[..bytes.len() - 1]),
+            Err(Ktx2Error::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_levels_not_matching_the_extent() {
+        let too_small = ktx2(BC7_UNORM, [8, 8], 1, &[vec![1; 48]]);
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&too_small),
+            Err(Ktx2Error::Truncated)
+        ));
+        let wrong_mip = ktx2(BC7_UNORM, [8, 8], 2, &[vec![1; 64], vec![2; 64]]);
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&wrong_mip),
+            Err(Ktx2Error::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        assert!(matches!(
+            CompressedTexture::from_ktx2(b"\x89PNG\r\n\x1a\n"),
+            Err(Ktx2Error::NotKtx2)
+        ));
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&ktx2(0, [8, 8], 1, &[vec![1; 64]])),
+            Err(Ktx2Error::UnsupportedFormat(0))
+        ));
+        let mut supercompressed = ktx2(BC7_UNORM, [8, 8], 1, &[vec![1; 64]]);
+        supercompressed[44] = 2;
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&supercompressed),
+            Err(Ktx2Error::Supercompressed(2))
+        ));
+        let mut cube = ktx2(BC7_UNORM, [8, 8], 1, &[vec![1; 64]]);
+        cube[36] = 6;
+        assert!(matches!(
+            CompressedTexture::from_ktx2(&cube),
+            Err(Ktx2Error::UnsupportedLayout)
+        ));
+    }
+}
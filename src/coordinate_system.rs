@@ -0,0 +1,133 @@
+use nalgebra::Matrix3;
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+/// Axis convention an asset was authored in or a scene is expressed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoordinateSystem {
+    pub up: UpAxis,
+    pub handedness: Handedness,
+}
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        CoordinateSystem::GLTF
+    }
+}
+impl CoordinateSystem {
+    /// Y up, right-handed, +Z towards the viewer. Also used by Maya and OpenGL-style engines.
+    pub const GLTF: CoordinateSystem = CoordinateSystem {
+        up: UpAxis::Y,
+        handedness: Handedness::Right,
+    };
+    /// Z up, right-handed, +Y forward. Also used by 3ds Max.
+    pub const BLENDER: CoordinateSystem = CoordinateSystem {
+        up: UpAxis::Z,
+        handedness: Handedness::Right,
+    };
+    /// Y up, left-handed, +Z forward.
+    pub const UNITY: CoordinateSystem = CoordinateSystem {
+        up: UpAxis::Y,
+        handedness: Handedness::Left,
+    };
+    /// Z up, left-handed.
+    pub const UNREAL: CoordinateSystem = CoordinateSystem {
+        up: UpAxis::Z,
+        handedness: Handedness::Left,
+    };
+
+    /// Maps this system's axes onto the glTF convention.
+    fn to_gltf(self) -> Matrix3<f32> {
+        match (self.up, self.handedness) {
+            (UpAxis::Y, Handedness::Right) => Matrix3::identity(),
+            (UpAxis::Y, Handedness::Left) => Matrix3::new(
+                1.0, 0.0, 0.0, //
+                0.0, 1.0, 0.0, //
+                0.0, 0.0, -1.0,
+            ),
+            (UpAxis::Z, Handedness::Right) => Matrix3::new(
+                1.0, 0.0, 0.0, //
+                0.0, 0.0, 1.0, //
+                0.0, -1.0, 0.0,
+            ),
+            (UpAxis::Z, Handedness::Left) => Matrix3::new(
+                1.0, 0.0, 0.0, //
+                0.0, 0.0, 1.0, //
+                0.0, 1.0, 0.0,
+            ),
+        }
+    }
+
+    /// Change of basis that takes data from this system into `target`.
+    pub fn conversion_to(self, target: CoordinateSystem) -> BasisChange {
+        // Both bases are orthonormal, so the inverse is the transpose.
+        BasisChange::new(target.to_gltf().transpose() * self.to_gltf())
+    }
+}
+
+/// An orthonormal change of basis, possibly mirroring (when the handedness changes).
+#[derive(Clone, Copy, Debug)]
+pub struct BasisChange {
+    basis: Matrix3<f32>,
+    determinant: f32,
+}
+impl BasisChange {
+    fn new(basis: Matrix3<f32>) -> Self {
+        Self {
+            basis,
+            determinant: basis.determinant().signum(),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.basis == Matrix3::identity()
+    }
+
+    /// True when the conversion mirrors geometry, which flips triangle winding and bitangents.
+    pub fn flips_handedness(&self) -> bool {
+        self.determinant < 0.0
+    }
+
+    /// Converts a position, direction or normal.
+    pub fn vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.basis * v
+    }
+
+    /// Converts a tangent with its bitangent sign in `w`.
+    pub fn tangent(&self, t: Vector4<f32>) -> Vector4<f32> {
+        let xyz = self.basis * t.xyz();
+        Vector4::new(xyz.x, xyz.y, xyz.z, t.w * self.determinant)
+    }
+
+    /// Converts a quaternion given as raw (x, y, z, w) components. Linear, so it also works on
+    /// cubic-spline tangents. Conjugating by a mirror equals conjugating by the negated mirror,
+    /// which is a proper rotation, hence the determinant factor.
+    pub fn quaternion(&self, q: Vector4<f32>) -> Vector4<f32> {
+        let v = self.basis * q.xyz() * self.determinant;
+        Vector4::new(v.x, v.y, v.z, q.w)
+    }
+
+    /// Converts the per-axis scale of a node. Only exact for axis-aligned bases, which is all
+    /// `CoordinateSystem` produces.
+    pub fn scale(&self, s: Vector3<f32>) -> Vector3<f32> {
+        (self.basis * Matrix3::from_diagonal(&s) * self.basis.transpose()).diagonal()
+    }
+
+    /// Converts a full affine transform: `B * m * B^-1`.
+    pub fn matrix(&self, m: &Matrix4<f32>) -> Matrix4<f32> {
+        let basis = self.basis.to_homogeneous();
+        basis * m * basis.transpose()
+    }
+}
@@ -0,0 +1,105 @@
+//! What the device can do with the features and extensions the renderer enabled, queried once
+//! at startup, so optional subsystems can be skipped or scaled down instead of failing when
+//! their pipelines are created.
+
+use vulkano::device::physical::PhysicalDeviceType;
+use vulkano::device::Device;
+use vulkano::image::SampleCount;
+use vulkano::image::SampleCounts;
+
+#[derive(Clone, Debug)]
+pub struct DeviceCapabilities {
+    pub device_name: String,
+    pub device_type: PhysicalDeviceType,
+    /// Largest width and height of 2D textures and render targets.
+    pub max_texture_size: u32,
+    /// Largest extent of 3D textures on every axis.
+    pub max_volume_size: u32,
+    pub max_texture_array_layers: u32,
+    /// Sample counts usable for both color and depth attachments.
+    pub msaa_samples: SampleCounts,
+    /// Color attachments one subpass can write, at least 4.
+    pub max_color_attachments: u32,
+    /// Color attachments of one pipeline blending differently, for `RenderTargets`.
+    pub independent_blend: bool,
+    /// Largest anisotropy samplers can use; `None` without `sampler_anisotropy`.
+    pub max_anisotropy: Option<f32>,
+    pub texture_compression_bc: bool,
+    /// Indirect draws with more than one command per call.
+    pub multi_draw_indirect: bool,
+    /// Acceleration structures and ray queries, which every ray-traced pass needs.
+    pub ray_query: bool,
+    /// Raygen, miss and hit shader stages, which `RayTracedOutput` prefers to ray queries.
+    pub ray_tracing_pipeline: bool,
+    /// Tessellation control and evaluation stages, for `TessellationStages`.
+    pub tessellation: bool,
+    /// Task and mesh shader stages.
+    pub mesh_shader: bool,
+    /// Several views drawn by one render pass, for `StereoTarget`.
+    pub multiview: bool,
+    pub buffer_device_address: bool,
+    /// Vertex, tessellation and geometry shaders writing storage buffers and images.
+    pub vertex_stores: bool,
+    /// Fragment shaders writing storage buffers and images.
+    pub fragment_stores: bool,
+    /// Bindless textures through `BindlessTextures`.
+    pub descriptor_indexing: bool,
+}
+impl DeviceCapabilities {
+    /// Reads the capabilities of `device` as it was created, i.e. only what it enabled.
+    pub fn new(device: &Device) -> Self {
+        let physical_device = device.physical_device();
+        let properties = physical_device.properties();
+        let features = device.enabled_features();
+        Self {
+            device_name: properties.device_name.clone(),
+            device_type: properties.device_type,
+            max_texture_size: properties.max_image_dimension2_d,
+            max_volume_size: properties.max_image_dimension3_d,
+            max_texture_array_layers: properties.max_image_array_layers,
+            msaa_samples: properties.framebuffer_color_sample_counts
+                & properties.framebuffer_depth_sample_counts,
+            max_color_attachments: properties.max_color_attachments,
+            independent_blend: features.independent_blend,
+            max_anisotropy: features
+                .sampler_anisotropy
+                .then_some(properties.max_sampler_anisotropy),
+            texture_compression_bc: features.texture_compression_bc,
+            multi_draw_indirect: features.multi_draw_indirect,
+            ray_query: features.ray_query,
+            ray_tracing_pipeline: features.ray_tracing_pipeline,
+            tessellation: features.tessellation_shader,
+            mesh_shader: device.enabled_extensions().ext_mesh_shader,
+            multiview: features.multiview,
+            buffer_device_address: features.buffer_device_address,
+            vertex_stores: features.vertex_pipeline_stores_and_atomics,
+            fragment_stores: features.fragment_stores_and_atomics,
+            descriptor_indexing: features.descriptor_binding_variable_descriptor_count,
+        }
+    }
+
+    pub fn max_msaa_samples(&self) -> SampleCount {
+        self.msaa_samples.max_count()
+    }
+
+    /// The highest supported sample count up to `requested`, e.g. to turn an MSAA setting the
+    /// device can't do into the closest one it can.
+    pub fn clamp_msaa_samples(&self, requested: SampleCount) -> SampleCount {
+        [
+            SampleCount::Sample64,
+            SampleCount::Sample32,
+            SampleCount::Sample16,
+            SampleCount::Sample8,
+            SampleCount::Sample4,
+            SampleCount::Sample2,
+        ]
+        .into_iter()
+        .find(|&count| count as u32 <= requested as u32 && self.msaa_samples.contains_enum(count))
+        .unwrap_or(SampleCount::Sample1)
+    }
+
+    /// Whether a 2D texture of `extent` fits the device.
+    pub fn fits_texture(&self, extent: [u32; 2]) -> bool {
+        extent[0] <= self.max_texture_size && extent[1] <= self.max_texture_size
+    }
+}
@@ -0,0 +1,315 @@
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use slotmap::SecondaryMap;
+
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+use crate::handles::MaterialId;
+use crate::handles::MeshId;
+use crate::handles::NodeId;
+use crate::lod::Lods;
+use crate::scene::Scene;
+
+/// Below this many items per task, rayon's splitting costs more than it saves.
+const MIN_ITEMS_PER_TASK: usize = 4096;
+/// Below this many batches per chunk, a secondary command buffer costs more than recording
+/// the batches inline.
+const MIN_BATCHES_PER_CHUNK: usize = 64;
+
+/// Something that may be drawn this frame.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderObject {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub world_matrix: Matrix4<f32>,
+    /// World-space bounds.
+    pub bounds: Aabb,
+    /// Test the object with a hardware occlusion query and skip it while it was hidden last
+    /// frame. Worth it for objects that are expensive to draw; see `OcclusionCuller`.
+    pub occlusion_query: bool,
+    /// Blended over what is behind it, so drawn after all opaque objects, back to front.
+    pub transparent: bool,
+}
+impl RenderObject {
+    /// Places an object whose mesh has `local_bounds`, e.g. from `GltfPrimitive::bounds`.
+    pub fn new(
+        mesh: MeshId,
+        material: MaterialId,
+        world_matrix: Matrix4<f32>,
+        local_bounds: &Aabb,
+    ) -> Self {
+        Self {
+            mesh,
+            material,
+            world_matrix,
+            bounds: local_bounds.transform(&world_matrix),
+            occlusion_query: false,
+            transparent: false,
+        }
+    }
+}
+
+/// A visible object, in draw order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawItem {
+    /// Index into the objects the list was built from.
+    pub object: u32,
+    /// The level of detail chosen for this frame, not necessarily `RenderObject::mesh`.
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub transparent: bool,
+    /// Camera distance as float bits; non-negative floats order the same as their bits.
+    depth: u32,
+}
+impl DrawItem {
+    /// Opaque items first, by material to minimize pipeline and descriptor changes, then mesh
+    /// so equal meshes become one instanced draw, then front to back so the depth test rejects
+    /// more of what is hidden. Transparent items after them, back to front, since blending
+    /// needs that order; only items at the same distance are grouped by material. The object
+    /// index makes the order total, which keeps the unstable parallel sort deterministic.
+    fn sort_key(&self) -> (bool, u32, MaterialId, MeshId, u32, u32) {
+        let back_to_front = if self.transparent { !self.depth } else { 0 };
+        (
+            self.transparent,
+            back_to_front,
+            self.material,
+            self.mesh,
+            self.depth,
+            self.object,
+        )
+    }
+}
+
+/// A run of items sharing mesh, material and transparency, drawable as one instanced draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawBatch {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    /// Draw with blending; transparent batches come after all opaque ones.
+    pub transparent: bool,
+    /// Index of the first item of the batch in `DrawList::items`.
+    pub first_item: u32,
+    pub item_count: u32,
+}
+
+/// Visible objects of a frame, sorted and grouped into batches. Built on all cores; the result
+/// depends only on the input, never on thread scheduling.
+#[derive(Default)]
+pub struct DrawList {
+    items: Vec<DrawItem>,
+    batch_starts: Vec<u32>,
+    batches: Vec<DrawBatch>,
+    /// Per object index, whether its occlusion query found it hidden last frame.
+    occluded: Vec<bool>,
+    occlusion_candidates: Vec<u32>,
+    /// Per object index, the scene node of the last `build_scene`.
+    nodes: Vec<NodeId>,
+}
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn items(&self) -> &[DrawItem] {
+        &self.items
+    }
+
+    pub fn batches(&self) -> &[DrawBatch] {
+        &self.batches
+    }
+
+    /// The batches of the opaque pass, to draw first with pipelines writing depth.
+    pub fn opaque_batches(&self) -> &[DrawBatch] {
+        &self.batches[..self.transparent_start()]
+    }
+
+    /// The batches of the transparent pass, back to front, to draw after the opaque pass with
+    /// blending pipelines that test depth without writing it, e.g. `MaterialPipelines` ones
+    /// with a blend feature.
+    pub fn transparent_batches(&self) -> &[DrawBatch] {
+        &self.batches[self.transparent_start()..]
+    }
+
+    fn transparent_start(&self) -> usize {
+        self.batches.partition_point(|batch| !batch.transparent)
+    }
+
+    /// Splits the batches into at most `max_chunks` runs of about equal item counts, e.g. one
+    /// per thread recording its own secondary command buffer. Small lists give fewer chunks.
+    pub fn batch_chunks(&self, max_chunks: usize) -> Vec<&[DrawBatch]> {
+        let chunk_count = max_chunks
+            .min(self.batches.len() / MIN_BATCHES_PER_CHUNK)
+            .max(1);
+        let items_per_chunk = self.items.len().div_ceil(chunk_count).max(1) as u32;
+        let mut chunks = Vec::with_capacity(chunk_count);
+        let mut rest = self.batches.as_slice();
+        while !rest.is_empty() {
+            let chunk_end = if chunks.len() + 1 == chunk_count {
+                rest.len()
+            } else {
+                let end_item = rest[0].first_item + items_per_chunk;
+                rest.partition_point(|batch| batch.first_item < end_item)
+            };
+            let (chunk, tail) = rest.split_at(chunk_end);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    /// Feeds last frame's occlusion results, e.g. `OcclusionCuller::occluded`, into the next
+    /// build. Objects without results count as visible.
+    pub fn set_occlusion_results(&mut self, occluded: &[bool]) {
+        self.occluded.clear();
+        self.occluded.extend_from_slice(occluded);
+    }
+
+    /// Objects in the frustum whose `occlusion_query` flag is set, drawn or not, to be queried
+    /// this frame. Objects the camera is inside of are never queried, since their box would be
+    /// clipped away.
+    pub fn occlusion_candidates(&self) -> &[u32] {
+        &self.occlusion_candidates
+    }
+
+    /// Per object index, the scene node the object of the last `build_scene` belongs to.
+    pub fn nodes(&self) -> &[NodeId] {
+        &self.nodes
+    }
+
+    /// Rebuilds the list from `objects`, reusing the previous frame's allocations.
+    pub fn build(
+        &mut self,
+        objects: &[RenderObject],
+        frustum: &Frustum,
+        camera_position: &Vector3<f32>,
+    ) {
+        self.build_with_lods(objects, frustum, camera_position, &Lods::default());
+    }
+
+    /// Like `build_with_lods`, for the nodes of `scene` that its spatial index finds in the
+    /// frustum, so objects far from view are never tested. `objects` is refilled with just those
+    /// objects, in the order of `nodes`, and is what item `object` indices then index into.
+    /// Occlusion results are matched to the last build's objects by node, since the indices
+    /// change with the view.
+    pub fn build_scene(
+        &mut self,
+        scene: &Scene,
+        frustum: &Frustum,
+        camera_position: &Vector3<f32>,
+        lods: &Lods,
+        objects: &mut Vec<RenderObject>,
+    ) {
+        let mut occluded_nodes = SecondaryMap::new();
+        for (&node, &occluded) in self.nodes.iter().zip(&self.occluded) {
+            if occluded {
+                occluded_nodes.insert(node, ());
+            }
+        }
+
+        self.nodes.clear();
+        scene
+            .spatial_index()
+            .query_frustum(frustum, &mut self.nodes);
+        objects.clear();
+        self.nodes.retain(|&node| match scene.render_object(node) {
+            Some(object) => {
+                objects.push(object);
+                true
+            }
+            None => false,
+        });
+        self.occluded.clear();
+        self.occluded.extend(
+            self.nodes
+                .iter()
+                .map(|&node| occluded_nodes.contains_key(node)),
+        );
+
+        self.build_with_lods(objects, frustum, camera_position, lods);
+    }
+
+    /// Like `build`, but draws each object's mesh at the level of detail `lods` picks for it.
+    pub fn build_with_lods(
+        &mut self,
+        objects: &[RenderObject],
+        frustum: &Frustum,
+        camera_position: &Vector3<f32>,
+        lods: &Lods,
+    ) {
+        let in_view = |object: &RenderObject| frustum.intersects_aabb(&object.bounds);
+        let queried = |object: &RenderObject| {
+            object.occlusion_query && !object.bounds.contains_point(camera_position)
+        };
+        self.occlusion_candidates.clear();
+        self.occlusion_candidates.par_extend(
+            objects
+                .par_iter()
+                .with_min_len(MIN_ITEMS_PER_TASK)
+                .enumerate()
+                .filter(|(_, object)| queried(object) && in_view(object))
+                .map(|(index, _)| index as u32),
+        );
+
+        // Visibility. `collect` keeps the input order, whatever thread handled each object.
+        let occluded = &self.occluded;
+        self.items.clear();
+        self.items.par_extend(
+            objects
+                .par_iter()
+                .with_min_len(MIN_ITEMS_PER_TASK)
+                .enumerate()
+                .filter(|&(index, object)| {
+                    in_view(object)
+                        && !(queried(object) && occluded.get(index).copied().unwrap_or(false))
+                })
+                .map(|(index, object)| {
+                    let distance = (object.bounds.center() - camera_position).norm();
+                    DrawItem {
+                        object: index as u32,
+                        mesh: lods.select(object.mesh, &object.bounds, distance),
+                        material: object.material,
+                        transparent: object.transparent,
+                        depth: distance.to_bits(),
+                    }
+                }),
+        );
+
+        self.items.par_sort_unstable_by_key(DrawItem::sort_key);
+
+        // Batching: every item whose mesh, material or transparency differs from its predecessor
+        // starts a batch.
+        let items = &self.items;
+        self.batch_starts.clear();
+        self.batch_starts.par_extend(
+            (0..items.len())
+                .into_par_iter()
+                .with_min_len(MIN_ITEMS_PER_TASK)
+                .filter(|&i| {
+                    i == 0
+                        || (items[i].mesh, items[i].material, items[i].transparent)
+                            != (
+                                items[i - 1].mesh,
+                                items[i - 1].material,
+                                items[i - 1].transparent,
+                            )
+                })
+                .map(|i| i as u32),
+        );
+        let item_count = items.len() as u32;
+        let starts = &self.batch_starts;
+        self.batches.clear();
+        self.batches
+            .extend(starts.iter().enumerate().map(|(i, &first_item)| {
+                let end = starts.get(i + 1).copied().unwrap_or(item_count);
+                let item = &items[first_item as usize];
+                DrawBatch {
+                    mesh: item.mesh,
+                    material: item.material,
+                    transparent: item.transparent,
+                    first_item,
+                    item_count: end - first_item,
+                }
+            }));
+    }
+}
@@ -0,0 +1,73 @@
+//! Render extraction from a `bevy_ecs` world, so game logic can live in entities and systems
+//! while the renderer keeps its draw list. Entities with a `Transform`, `MeshHandle`,
+//! `MaterialHandle` and `LocalBounds` are drawn; the rest are left alone.
+
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::query::Has;
+use bevy_ecs::query::QueryState;
+use bevy_ecs::world::World;
+
+use crate::bounds::Aabb;
+use crate::draw_list::RenderObject;
+use crate::handles::MaterialId;
+use crate::handles::MeshId;
+
+/// Where the entity is in the world. The ECS has no hierarchy here; resolve parents before
+/// extraction.
+pub use crate::transform::Transform;
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshHandle(pub MeshId);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaterialHandle(pub MaterialId);
+
+/// Bounds of the entity's mesh in its own space, e.g. from `GltfPrimitive::bounds`.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LocalBounds(pub Aabb);
+
+/// Marks entities to test with occlusion queries; see `RenderObject::occlusion_query`.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct OcclusionQuery;
+
+type Renderable = (
+    Entity,
+    &'static Transform,
+    &'static MeshHandle,
+    &'static MaterialHandle,
+    &'static LocalBounds,
+    Has<OcclusionQuery>,
+);
+
+/// Copies the drawable entities of a world into render objects once per frame. Keeps its query
+/// between frames, so matching archetypes are only looked up again after new ones appear.
+pub struct RenderExtractor {
+    query: QueryState<Renderable>,
+}
+impl RenderExtractor {
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+
+    /// Replaces `objects` with the drawable entities, ready for `DrawList::build`, and
+    /// `entities` with their ids in the same order, so `DrawItem::object` indexes both.
+    pub fn extract(
+        &mut self,
+        world: &World,
+        objects: &mut Vec<RenderObject>,
+        entities: &mut Vec<Entity>,
+    ) {
+        objects.clear();
+        entities.clear();
+        for (entity, transform, mesh, material, bounds, occlusion_query) in self.query.iter(world) {
+            let mut object =
+                RenderObject::new(mesh.0, material.0, transform.to_matrix(), &bounds.0);
+            object.occlusion_query = occlusion_query;
+            objects.push(object);
+            entities.push(entity);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+
+/// Memory use of a `FrameArena`, in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameArenaStats {
+    /// Bytes handed out during the last completed frame.
+    pub last_frame_bytes: usize,
+    /// Most bytes handed out during any single frame so far.
+    pub high_water_bytes: usize,
+    /// Bytes currently reserved from the system allocator.
+    pub capacity_bytes: usize,
+    /// Number of completed frames.
+    pub frames: u64,
+}
+
+/// Bump allocator for data that lives for one frame only: draw lists, sort keys, batching
+/// scratch space. Allocating is a pointer bump and `reset` frees everything at once, keeping
+/// the memory, so after the first few frames the hot path stops touching the system allocator.
+pub struct FrameArena {
+    bump: Bump,
+    stats: FrameArenaStats,
+}
+impl FrameArena {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Reserves `bytes` up front, e.g. the high-water mark of a previous run.
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self {
+            bump: Bump::with_capacity(bytes),
+            stats: FrameArenaStats::default(),
+        }
+    }
+
+    /// An empty vector backed by the arena. Growing it never calls the system allocator once the
+    /// arena has warmed up.
+    pub fn vec<T>(&self) -> BumpVec<'_, T> {
+        BumpVec::new_in(&self.bump)
+    }
+
+    pub fn vec_with_capacity<T>(&self, capacity: usize) -> BumpVec<'_, T> {
+        BumpVec::with_capacity_in(capacity, &self.bump)
+    }
+
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        self.bump.alloc_slice_copy(src)
+    }
+
+    pub fn alloc_slice_fill_iter<T, I>(&self, iter: I) -> &mut [T]
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.bump.alloc_slice_fill_iter(iter)
+    }
+
+    /// Bytes handed out since the last `reset`. Approximate: padding left at the end of
+    /// outgrown chunks counts as used.
+    pub fn used_bytes(&self) -> usize {
+        self.bump.allocated_bytes() - self.bump.chunk_capacity()
+    }
+
+    /// Frees everything allocated this frame and records its usage. Call once per frame, after
+    /// the frame's transient data has been consumed.
+    pub fn reset(&mut self) {
+        let used = self.used_bytes();
+        self.stats.last_frame_bytes = used;
+        self.stats.high_water_bytes = self.stats.high_water_bytes.max(used);
+        self.stats.frames += 1;
+        // Keeps only the newest chunk, which is the largest, so the next frame fits in one chunk.
+        self.bump.reset();
+    }
+
+    pub fn stats(&self) -> FrameArenaStats {
+        FrameArenaStats {
+            capacity_bytes: self.bump.allocated_bytes(),
+            ..self.stats
+        }
+    }
+}
+impl Default for FrameArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
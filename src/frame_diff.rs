@@ -0,0 +1,89 @@
+use std::fmt;
+
+use image::Rgba;
+use image::RgbaImage;
+
+/// Summary of how much two frames differ.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiffStats {
+    pub total_pixels: u32,
+    /// Pixels where some channel differs by more than the threshold.
+    pub differing_pixels: u32,
+    /// Largest difference of any channel, 0..=255.
+    pub max_delta: u8,
+    /// Mean absolute difference over all color channels, 0..=255.
+    pub mean_delta: f32,
+    /// Peak signal-to-noise ratio in dB; infinite for identical frames.
+    pub psnr: f32,
+}
+impl DiffStats {
+    pub fn differing_fraction(&self) -> f32 {
+        self.differing_pixels as f32 / self.total_pixels.max(1) as f32
+    }
+}
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} pixels differ ({:.2}%), max delta {}, mean delta {:.3}, PSNR {:.2} dB",
+            self.differing_pixels,
+            self.total_pixels,
+            self.differing_fraction() * 100.0,
+            self.max_delta,
+            self.mean_delta,
+            self.psnr
+        )
+    }
+}
+
+pub struct FrameDiff {
+    /// Black where the frames agree, brighter red the larger the difference. Pixels under the
+    /// threshold are dark gray so small noise stays visible without drowning real changes.
+    pub image: RgbaImage,
+    pub stats: DiffStats,
+}
+
+/// Compares two frames of the same size pixel by pixel. Alpha is ignored. Differences of at most
+/// `threshold` per channel do not count as differing pixels, but still go into the mean and PSNR.
+pub fn diff_images(a: &RgbaImage, b: &RgbaImage, threshold: u8) -> FrameDiff {
+    assert_eq!(
+        a.dimensions(),
+        b.dimensions(),
+        "frames to compare differ in size"
+    );
+    let (width, height) = a.dimensions();
+    let mut image = RgbaImage::new(width, height);
+    let mut stats = DiffStats {
+        total_pixels: width * height,
+        ..Default::default()
+    };
+    let mut sum = 0u64;
+    let mut squared_sum = 0u64;
+    for ((pa, pb), out) in a.pixels().zip(b.pixels()).zip(image.pixels_mut()) {
+        let delta: [u8; 3] = std::array::from_fn(|c| pa[c].abs_diff(pb[c]));
+        let largest = delta.into_iter().max().unwrap_or(0);
+        for d in delta {
+            sum += d as u64;
+            squared_sum += (d as u64) * (d as u64);
+        }
+        stats.max_delta = stats.max_delta.max(largest);
+        *out = if largest > threshold {
+            stats.differing_pixels += 1;
+            Rgba([64 + (largest as u16 * 191 / 255) as u8, 0, 0, 255])
+        } else if largest > 0 {
+            Rgba([32, 32, 32, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        };
+    }
+
+    let samples = (stats.total_pixels as u64 * 3).max(1);
+    stats.mean_delta = sum as f32 / samples as f32;
+    let mse = squared_sum as f64 / samples as f64;
+    stats.psnr = if mse == 0.0 {
+        f32::INFINITY
+    } else {
+        (10.0 * (255.0 * 255.0 / mse).log10()) as f32
+    };
+    FrameDiff { image, stats }
+}
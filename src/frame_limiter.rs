@@ -0,0 +1,55 @@
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long before a frame is due the limiter stops sleeping and spins, since sleeps
+/// overshoot by up to a scheduler tick.
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/// Caps the frame rate, independent of vsync, e.g. so tools don't drain a laptop battery or so
+/// captures get evenly spaced frames. Frames are due one frame duration after the previous
+/// one was due, so short stalls are made up; after a longer stall, pacing starts over.
+pub struct FrameLimiter {
+    frame_duration: Duration,
+    /// When the next frame is due, `None` before the first one.
+    next_frame: Option<Instant>,
+}
+impl FrameLimiter {
+    pub fn new(target_fps: f64) -> Self {
+        assert!(target_fps > 0.0, "the target frame rate must be positive");
+        Self {
+            frame_duration: Duration::from_secs_f64(1.0 / target_fps),
+            next_frame: None,
+        }
+    }
+
+    pub fn target_fps(&self) -> f64 {
+        1.0 / self.frame_duration.as_secs_f64()
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// Blocks until the next frame is due, sleeping most of the time and spinning the rest.
+    /// Call right before starting each frame.
+    pub fn wait(&mut self) {
+        if let Some(next_frame) = self.next_frame {
+            let sleep_until = next_frame.checked_sub(SPIN_MARGIN).unwrap_or(next_frame);
+            let now = Instant::now();
+            if now < sleep_until {
+                std::thread::sleep(sleep_until - now);
+            }
+            while Instant::now() < next_frame {
+                std::hint::spin_loop();
+            }
+        }
+        let now = Instant::now();
+        self.next_frame = Some(match self.next_frame {
+            // More than a frame behind: don't rush frames out to catch up.
+            Some(next_frame) if now < next_frame + self.frame_duration => {
+                next_frame + self.frame_duration
+            }
+            _ => now + self.frame_duration,
+        });
+    }
+}
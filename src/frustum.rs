@@ -0,0 +1,45 @@
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+use crate::bounds::Aabb;
+
+/// View frustum as six inward-facing planes `(normal, distance)`: a point `p` is inside a plane
+/// when `normal.dot(p) + distance >= 0`.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far.
+    pub planes: [Vector4<f32>; 6],
+}
+impl Frustum {
+    /// Extracts the planes from a projection * view matrix with Vulkan's 0..1 depth range.
+    pub fn from_view_projection(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.xyz().norm());
+        Self { planes }
+    }
+
+    pub fn contains_point(&self, point: &Vector3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(point) + plane.w >= 0.0)
+    }
+
+    /// Conservative: boxes near a frustum corner may pass although they are outside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            normal.dot(&center) + normal.abs().dot(&half_extents) + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,434 @@
+use std::path::Path;
+
+use gltf::animation::util::ReadOutputs;
+use nalgebra::DVector;
+use nalgebra::Matrix4;
+use nalgebra::Quaternion;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+use crate::animation::compute_world_matrices;
+use crate::animation::AnimationChannel;
+use crate::animation::AnimationClip;
+use crate::animation::ChannelValues;
+use crate::animation::Interpolation;
+use crate::animation::NodeTransform;
+use crate::animation::Skin;
+use crate::bounds::Aabb;
+use crate::coordinate_system::BasisChange;
+use crate::coordinate_system::CoordinateSystem;
+use crate::units::LengthUnit;
+use crate::units::SceneUnits;
+
+/// CPU-side copy of a glTF document, ready to be uploaded to the GPU.
+pub struct GltfModel {
+    pub nodes: Vec<GltfNode>,
+    pub roots: Vec<usize>,
+    pub meshes: Vec<GltfMesh>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<AnimationClip>,
+}
+
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub transform: NodeTransform,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+    /// Morph target weights overriding the mesh defaults for this instance.
+    pub weights: Option<Vec<f32>>,
+}
+
+pub struct GltfMesh {
+    pub primitives: Vec<GltfPrimitive>,
+    /// Default morph target weights, one per target.
+    pub weights: Vec<f32>,
+}
+
+pub struct GltfPrimitive {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Empty if the file has no tangents. `w` holds the bitangent sign.
+    pub tangents: Vec<[f32; 4]>,
+    /// First UV set (`TEXCOORD_0`), for material textures. Empty if absent.
+    pub uvs: Vec<[f32; 2]>,
+    /// Second UV set (`TEXCOORD_1`), where glTF files keep lightmap UVs. Empty if absent.
+    pub lightmap_uvs: Vec<[f32; 2]>,
+    /// Linear RGBA vertex colors (`COLOR_0`). Empty if absent.
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    /// Empty unless the primitive is skinned.
+    pub joints: Vec<[u32; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+impl GltfPrimitive {
+    /// Local bounds, large enough for any blend of morph target weights between 0 and 1.
+    pub fn bounds(&self) -> Aabb {
+        self.positions
+            .iter()
+            .enumerate()
+            .fold(Aabb::EMPTY, |bounds, (i, position)| {
+                let mut low = Vector3::from(*position);
+                let mut high = low;
+                for target in &self.morph_targets {
+                    let delta = Vector3::from(target.position_deltas[i]);
+                    low += delta.inf(&Vector3::zeros());
+                    high += delta.sup(&Vector3::zeros());
+                }
+                bounds.grow(&low).grow(&high)
+            })
+    }
+}
+
+/// Per-vertex displacements blended on top of the base mesh, scaled by the target's weight.
+pub struct MorphTarget {
+    pub position_deltas: Vec<[f32; 3]>,
+    /// Zeroes if the file has no normal deltas.
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+/// Conversions applied to the data while it is read from the file.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportOptions {
+    /// Unit the asset was authored in. glTF mandates meters, but exporters from
+    /// centimeter-based tools frequently write centimeters anyway.
+    pub source_unit: LengthUnit,
+    pub scene_units: SceneUnits,
+    /// Axis convention of the asset, e.g. `CoordinateSystem::BLENDER` for an unconverted Blender export.
+    pub source_coordinates: CoordinateSystem,
+    pub scene_coordinates: CoordinateSystem,
+}
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            source_unit: LengthUnit::Meters,
+            scene_units: SceneUnits::default(),
+            source_coordinates: CoordinateSystem::GLTF,
+            scene_coordinates: CoordinateSystem::GLTF,
+        }
+    }
+}
+
+impl GltfModel {
+    pub fn load(path: impl AsRef<Path>) -> Result<GltfModel, gltf::Error> {
+        GltfModel::load_with_options(path, &ImportOptions::default())
+    }
+
+    pub fn load_with_options(
+        path: impl AsRef<Path>,
+        options: &ImportOptions,
+    ) -> Result<GltfModel, gltf::Error> {
+        let (document, buffers, _images) = gltf::import(path)?;
+
+        let nodes = document.nodes().map(load_node).collect();
+
+        let roots = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| GltfMesh {
+                primitives: mesh
+                    .primitives()
+                    .map(|primitive| load_primitive(&primitive, &buffers))
+                    .collect(),
+                weights: mesh.weights().map(<[f32]>::to_vec).unwrap_or_default(),
+            })
+            .collect();
+
+        let skins = document
+            .skins()
+            .map(|skin| {
+                let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+                let inverse_bind_matrices = skin
+                    .reader(|buffer| Some(&buffers[buffer.index()]))
+                    .read_inverse_bind_matrices()
+                    .map(|matrices| matrices.map(Matrix4::from).collect())
+                    // The spec says missing inverse bind matrices are identity matrices.
+                    .unwrap_or_else(|| vec![Matrix4::identity(); joints.len()]);
+                Skin {
+                    joints,
+                    inverse_bind_matrices,
+                }
+            })
+            .collect();
+
+        let animations = document
+            .animations()
+            .map(|animation| {
+                let channels = animation
+                    .channels()
+                    .filter_map(|channel| load_channel(&channel, &buffers))
+                    .collect();
+                AnimationClip::new(animation.name().map(str::to_owned), channels)
+            })
+            .collect();
+
+        let mut model = GltfModel {
+            nodes,
+            roots,
+            meshes,
+            skins,
+            animations,
+        };
+        model.scale_lengths(options.scene_units.import_scale(options.source_unit));
+        model.convert_coordinates(
+            options
+                .source_coordinates
+                .conversion_to(options.scene_coordinates),
+        );
+        Ok(model)
+    }
+
+    /// Multiplies every length in the model by `factor`. Rotations and node scales are unitless
+    /// and stay untouched, so skinning keeps working.
+    fn scale_lengths(&mut self, factor: f32) {
+        if factor == 1.0 {
+            return;
+        }
+        for node in &mut self.nodes {
+            node.transform.translation *= factor;
+        }
+        for primitive in self.meshes.iter_mut().flat_map(|mesh| &mut mesh.primitives) {
+            let morph_deltas = primitive
+                .morph_targets
+                .iter_mut()
+                .flat_map(|target| &mut target.position_deltas);
+            for position in primitive.positions.iter_mut().chain(morph_deltas) {
+                *position = position.map(|p| p * factor);
+            }
+        }
+        for skin in &mut self.skins {
+            for inverse_bind in &mut skin.inverse_bind_matrices {
+                inverse_bind.fixed_view_mut::<3, 1>(0, 3).scale_mut(factor);
+            }
+        }
+        for clip in &mut self.animations {
+            for channel in &mut clip.channels {
+                if let ChannelValues::Translations(values) = &mut channel.values {
+                    values.iter_mut().for_each(|value| *value *= factor);
+                }
+            }
+        }
+    }
+
+    /// Re-expresses the whole model in another axis convention. Mirroring conversions also flip
+    /// bitangent signs and triangle winding so front faces stay front faces.
+    fn convert_coordinates(&mut self, basis: BasisChange) {
+        if basis.is_identity() {
+            return;
+        }
+        for node in &mut self.nodes {
+            let transform = &mut node.transform;
+            transform.translation = basis.vector(transform.translation);
+            transform.rotation = UnitQuaternion::from_quaternion(Quaternion::from(
+                basis.quaternion(transform.rotation.coords),
+            ));
+            transform.scale = basis.scale(transform.scale);
+        }
+        for primitive in self.meshes.iter_mut().flat_map(|mesh| &mut mesh.primitives) {
+            for position in &mut primitive.positions {
+                *position = basis.vector(Vector3::from(*position)).into();
+            }
+            for normal in &mut primitive.normals {
+                *normal = basis.vector(Vector3::from(*normal)).into();
+            }
+            for tangent in &mut primitive.tangents {
+                *tangent = basis.tangent(Vector4::from(*tangent)).into();
+            }
+            for target in &mut primitive.morph_targets {
+                for delta in target
+                    .position_deltas
+                    .iter_mut()
+                    .chain(&mut target.normal_deltas)
+                {
+                    *delta = basis.vector(Vector3::from(*delta)).into();
+                }
+            }
+            if basis.flips_handedness() {
+                for triangle in primitive.indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+        }
+        for skin in &mut self.skins {
+            for inverse_bind in &mut skin.inverse_bind_matrices {
+                *inverse_bind = basis.matrix(inverse_bind);
+            }
+        }
+        for clip in &mut self.animations {
+            for channel in &mut clip.channels {
+                match &mut channel.values {
+                    ChannelValues::Translations(values) => {
+                        values.iter_mut().for_each(|v| *v = basis.vector(*v));
+                    }
+                    ChannelValues::Rotations(values) => {
+                        values.iter_mut().for_each(|q| *q = basis.quaternion(*q));
+                    }
+                    ChannelValues::Scales(values) => {
+                        values.iter_mut().for_each(|v| *v = basis.scale(*v));
+                    }
+                    ChannelValues::MorphWeights(_) => {}
+                }
+            }
+        }
+    }
+
+    /// The bind pose: every node's transform as authored in the file.
+    pub fn rest_pose(&self) -> Vec<NodeTransform> {
+        self.nodes.iter().map(|node| node.transform).collect()
+    }
+
+    pub fn children(&self) -> Vec<Vec<usize>> {
+        self.nodes
+            .iter()
+            .map(|node| node.children.clone())
+            .collect()
+    }
+
+    /// Morph target weights of every node before any animation is applied (empty for nodes without morph targets).
+    pub fn rest_morph_weights(&self) -> Vec<Vec<f32>> {
+        self.nodes
+            .iter()
+            .map(|node| match (&node.weights, node.mesh) {
+                (Some(weights), _) => weights.clone(),
+                (None, Some(mesh)) => self.meshes[mesh].weights.clone(),
+                (None, None) => Vec::new(),
+            })
+            .collect()
+    }
+
+    /// World matrix of every node for the given local pose, e.g. one returned by `AnimationPlayer::sample`.
+    pub fn world_matrices(&self, pose: &[NodeTransform]) -> Vec<Matrix4<f32>> {
+        compute_world_matrices(&self.children(), &self.roots, pose)
+    }
+}
+
+fn load_node(node: gltf::Node) -> GltfNode {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    GltfNode {
+        name: node.name().map(str::to_owned),
+        transform: NodeTransform {
+            translation: Vector3::from(translation),
+            rotation: UnitQuaternion::from_quaternion(Quaternion::from(Vector4::from(rotation))),
+            scale: Vector3::from(scale),
+        },
+        children: node.children().map(|child| child.index()).collect(),
+        mesh: node.mesh().map(|mesh| mesh.index()),
+        skin: node.skin().map(|skin| skin.index()),
+        weights: node.weights().map(<[f32]>::to_vec),
+    }
+}
+
+fn load_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> GltfPrimitive {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .map(|positions| positions.collect())
+        .unwrap_or_default();
+    let normals = reader
+        .read_normals()
+        .map(|normals| normals.collect())
+        .unwrap_or_else(|| vec![[0.0, 0.0, 1.0]; positions.len()]);
+    let tangents = reader
+        .read_tangents()
+        .map(|tangents| tangents.collect())
+        .unwrap_or_default();
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().collect())
+        .unwrap_or_default();
+    let lightmap_uvs = reader
+        .read_tex_coords(1)
+        .map(|uvs| uvs.into_f32().collect())
+        .unwrap_or_default();
+    let colors = reader
+        .read_colors(0)
+        .map(|colors| colors.into_rgba_f32().collect())
+        .unwrap_or_default();
+    let indices = reader
+        .read_indices()
+        .map(|indices| indices.into_u32().collect())
+        .unwrap_or_else(|| (0..positions.len() as u32).collect());
+    let joints = reader
+        .read_joints(0)
+        .map(|joints| joints.into_u16().map(|j| j.map(u32::from)).collect())
+        .unwrap_or_default();
+    let weights = reader
+        .read_weights(0)
+        .map(|weights| weights.into_f32().collect())
+        .unwrap_or_default();
+    let morph_targets = reader
+        .read_morph_targets()
+        .map(
+            |(position_deltas, normal_deltas, _tangent_deltas)| MorphTarget {
+                position_deltas: position_deltas
+                    .map(|deltas| deltas.collect())
+                    .unwrap_or_else(|| vec![[0.0; 3]; positions.len()]),
+                normal_deltas: normal_deltas
+                    .map(|deltas| deltas.collect())
+                    .unwrap_or_else(|| vec![[0.0; 3]; positions.len()]),
+            },
+        )
+        .collect();
+    GltfPrimitive {
+        positions,
+        normals,
+        tangents,
+        uvs,
+        lightmap_uvs,
+        colors,
+        indices,
+        joints,
+        weights,
+        morph_targets,
+    }
+}
+
+fn load_channel(
+    channel: &gltf::animation::Channel,
+    buffers: &[gltf::buffer::Data],
+) -> Option<AnimationChannel> {
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times: Vec<f32> = reader.read_inputs()?.collect();
+    let values = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => {
+            ChannelValues::Translations(values.map(Vector3::from).collect())
+        }
+        ReadOutputs::Rotations(values) => {
+            ChannelValues::Rotations(values.into_f32().map(Vector4::from).collect())
+        }
+        ReadOutputs::Scales(values) => ChannelValues::Scales(values.map(Vector3::from).collect()),
+        ReadOutputs::MorphTargetWeights(values) => {
+            let values: Vec<f32> = values.into_f32().collect();
+            let keyframes = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::CubicSpline => times.len() * 3,
+                _ => times.len(),
+            };
+            let target_count = values.len() / keyframes.max(1);
+            ChannelValues::MorphWeights(
+                values
+                    .chunks_exact(target_count.max(1))
+                    .map(DVector::from_column_slice)
+                    .collect(),
+            )
+        }
+    };
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+    Some(AnimationChannel {
+        node: channel.target().node().index(),
+        interpolation,
+        times,
+        values,
+    })
+}
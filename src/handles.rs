@@ -0,0 +1,13 @@
+//! Generational handles to things owned by the renderer or the scene. Handles are plain `Copy`
+//! values: they keep nothing alive, and a handle to something that was removed is simply stale,
+//! never pointing at whatever reused its slot.
+
+slotmap::new_key_type! {
+    pub struct MeshId;
+    pub struct TextureId;
+    pub struct TextureArrayId;
+    pub struct VolumeTextureId;
+    pub struct MaterialId;
+    pub struct NodeId;
+    pub struct WidgetId;
+}
@@ -0,0 +1,34 @@
+pub mod animation;
+pub mod audio;
+pub mod batch_math;
+pub mod bounds;
+pub mod bvh;
+pub mod compressed_texture;
+pub mod coordinate_system;
+pub mod device_capabilities;
+pub mod draw_list;
+#[cfg(feature = "bevy_ecs")]
+pub mod ecs;
+pub mod frame_arena;
+pub mod frame_diff;
+pub mod frame_limiter;
+pub mod frustum;
+pub mod gltf_loader;
+pub mod handles;
+pub mod lightmap;
+pub mod lod;
+pub mod meshlet;
+pub mod particles;
+pub mod raycast;
+pub mod renderer;
+pub mod renderer_core;
+pub mod scene;
+pub mod startup_config;
+pub mod tilemap;
+pub mod transform;
+pub mod ui;
+pub mod ui_scale;
+pub mod units;
+pub mod vulkan_api_connection;
+pub mod winit_app;
+pub mod xr;
@@ -0,0 +1,452 @@
+use nalgebra::Matrix4;
+use nalgebra::Point3;
+use nalgebra::Vector2;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::bounds::Aabb;
+use crate::gltf_loader::GltfPrimitive;
+use crate::raycast::intersect_triangle;
+use crate::raycast::Ray;
+use crate::units::SceneUnits;
+
+/// Triangles per leaf of the occluder hierarchy.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// A mesh with one lightmap UV per vertex, every triangle covering its own texels.
+#[derive(Clone, Debug, Default)]
+pub struct LightmapMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub lightmap_uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+impl LightmapMesh {
+    /// Uses the primitive's own lightmap UVs when it has them. Otherwise every triangle gets a
+    /// cell of a square grid, with a texel and a half of padding so bilinear filtering does not
+    /// bleed between neighbours at `resolution`. The generated layout ignores triangle sizes, so
+    /// texel density varies; author UVs where that matters.
+    pub fn new(primitive: &GltfPrimitive, resolution: u32) -> Self {
+        if primitive.lightmap_uvs.len() == primitive.positions.len() {
+            return Self {
+                positions: primitive.positions.clone(),
+                normals: primitive.normals.clone(),
+                lightmap_uvs: primitive.lightmap_uvs.clone(),
+                indices: primitive.indices.clone(),
+            };
+        }
+
+        let triangle_count = primitive.indices.len() / 3;
+        let cells_per_row = (triangle_count as f32).sqrt().ceil().max(1.0) as usize;
+        let cell = 1.0 / cells_per_row as f32;
+        let padding = 1.5 / resolution as f32;
+        let corners = [
+            [padding, padding],
+            [cell - padding, padding],
+            [padding, cell - padding],
+        ];
+
+        let mut mesh = Self::default();
+        for (triangle, indices) in primitive.indices.chunks_exact(3).enumerate() {
+            let origin = [
+                (triangle % cells_per_row) as f32 * cell,
+                (triangle / cells_per_row) as f32 * cell,
+            ];
+            for (&index, corner) in indices.iter().zip(corners) {
+                mesh.indices.push(mesh.positions.len() as u32);
+                mesh.positions.push(primitive.positions[index as usize]);
+                mesh.normals.push(primitive.normals[index as usize]);
+                mesh.lightmap_uvs
+                    .push([origin[0] + corner[0], origin[1] + corner[1]]);
+            }
+        }
+        mesh
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BakeLight {
+    Directional {
+        /// The direction the light travels in.
+        direction: Vector3<f32>,
+        color: [f32; 3],
+    },
+    Point {
+        position: Vector3<f32>,
+        color: [f32; 3],
+        /// Distance at which the light fades out completely.
+        range: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct BakeSettings {
+    /// Width and height of the lightmap in texels.
+    pub resolution: u32,
+    pub lights: Vec<BakeLight>,
+    /// Light arriving from directions where nothing blocks the sky.
+    pub sky_color: [f32; 3],
+    /// Hemisphere rays per texel for sky light and ambient occlusion.
+    pub sky_samples: u32,
+    /// Occluders further away than this do not darken the sky light.
+    pub occlusion_distance: f32,
+    /// How far rays start off the surface, to avoid hitting it.
+    pub bias: f32,
+    /// Texels of edge padding filled around every chart, against seams.
+    pub dilation: u32,
+    /// Units of the scene's lengths, so point lights fall off by physical distance.
+    pub units: SceneUnits,
+}
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 256,
+            lights: Vec::new(),
+            sky_color: [0.3, 0.35, 0.4],
+            sky_samples: 64,
+            occlusion_distance: 10.0,
+            bias: 1e-3,
+            dilation: 2,
+            units: SceneUnits::default(),
+        }
+    }
+}
+
+/// Baked linear irradiance, rows from the top (v = 0) down.
+#[derive(Clone, Debug)]
+pub struct Lightmap {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[f32; 3]>,
+}
+impl Lightmap {
+    /// sRGB-encoded RGBA8 for `Resources::create_texture`. Values above 1 are clamped.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.texels
+            .iter()
+            .flat_map(|texel| {
+                let [r, g, b] = texel.map(|c| (linear_to_srgb(c) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BakeNode {
+    bounds: Aabb,
+    /// Leaves: first triangle. Branches: index of the second child; the first follows the node.
+    start: u32,
+    /// Zero for branches.
+    count: u32,
+}
+
+/// The static geometry that casts shadows during a bake, in world space.
+pub struct BakeScene {
+    triangles: Vec<[Vector3<f32>; 3]>,
+    nodes: Vec<BakeNode>,
+}
+impl BakeScene {
+    /// Include the baked meshes themselves so they shadow their own surfaces.
+    pub fn new<'a>(occluders: impl IntoIterator<Item = (&'a GltfPrimitive, Matrix4<f32>)>) -> Self {
+        let mut triangles = Vec::new();
+        for (primitive, model_matrix) in occluders {
+            let world = |index: u32| {
+                let position = Point3::from(primitive.positions[index as usize]);
+                model_matrix.transform_point(&position).coords
+            };
+            triangles.extend(
+                primitive
+                    .indices
+                    .chunks_exact(3)
+                    .map(|t| [world(t[0]), world(t[1]), world(t[2])]),
+            );
+        }
+        let mut scene = Self {
+            nodes: Vec::with_capacity(triangles.len() * 2 / MAX_LEAF_TRIANGLES + 1),
+            triangles,
+        };
+        if !scene.triangles.is_empty() {
+            scene.build_node(0, scene.triangles.len());
+        }
+        scene
+    }
+
+    /// Median split along the longest axis of the triangle centers.
+    fn build_node(&mut self, start: usize, end: usize) {
+        let triangles = &mut self.triangles[start..end];
+        let bounds = triangles
+            .iter()
+            .flatten()
+            .fold(Aabb::EMPTY, |bounds, p| bounds.grow(p));
+        let node = self.nodes.len();
+        self.nodes.push(BakeNode {
+            bounds,
+            start: start as u32,
+            count: triangles.len() as u32,
+        });
+        if triangles.len() <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+
+        let center = |t: &[Vector3<f32>; 3]| t[0] + t[1] + t[2];
+        let center_bounds = triangles
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, t| bounds.grow(&center(t)));
+        let axis = (center_bounds.max - center_bounds.min).imax();
+        let middle = triangles.len() / 2;
+        triangles
+            .select_nth_unstable_by(middle, |a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+        self.build_node(start, start + middle);
+        let second = self.nodes.len() as u32;
+        self.build_node(start + middle, end);
+        self.nodes[node].start = second;
+        self.nodes[node].count = 0;
+    }
+
+    /// Whether anything lies along `direction` (normalized) within `max_distance` of `origin`.
+    pub fn occluded(
+        &self,
+        origin: &Vector3<f32>,
+        direction: &Vector3<f32>,
+        max_distance: f32,
+    ) -> bool {
+        let ray = Ray {
+            origin: *origin,
+            direction: *direction,
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node
+                .bounds
+                .intersect_ray(origin, direction, max_distance)
+                .is_none()
+            {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(index + 1);
+                stack.push(node.start as usize);
+                continue;
+            }
+            let first = node.start as usize;
+            let hit = self.triangles[first..first + node.count as usize]
+                .iter()
+                .any(|[a, b, c]| {
+                    intersect_triangle(&ray, a, b, c, false)
+                        .is_some_and(|(distance, _)| distance < max_distance)
+                });
+            if hit {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Bakes direct light with shadows plus occluded sky light for `mesh`, placed by
+    /// `model_matrix`. Texels are baked in parallel; the result does not depend on scheduling.
+    pub fn bake(
+        &self,
+        mesh: &LightmapMesh,
+        model_matrix: &Matrix4<f32>,
+        settings: &BakeSettings,
+    ) -> Lightmap {
+        let resolution = settings.resolution;
+        let samples = rasterize(mesh, model_matrix, resolution);
+        let mut texels: Vec<Option<[f32; 3]>> = samples
+            .par_iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                sample.map(|(position, normal)| {
+                    self.shade(&position, &normal, index as u32, settings)
+                })
+            })
+            .collect();
+        for _ in 0..settings.dilation {
+            texels = dilate(&texels, resolution);
+        }
+
+        Lightmap {
+            width: resolution,
+            height: resolution,
+            texels: texels.into_iter().map(Option::unwrap_or_default).collect(),
+        }
+    }
+
+    fn shade(
+        &self,
+        position: &Vector3<f32>,
+        normal: &Vector3<f32>,
+        seed: u32,
+        settings: &BakeSettings,
+    ) -> [f32; 3] {
+        let origin = position + normal * settings.bias;
+        let mut light = Vector3::zeros();
+
+        for bake_light in &settings.lights {
+            let (direction, distance, radiance) = match *bake_light {
+                BakeLight::Directional { direction, color } => {
+                    (-direction.normalize(), f32::INFINITY, Vector3::from(color))
+                }
+                BakeLight::Point {
+                    position: light_position,
+                    color,
+                    range,
+                } => {
+                    let to_light = light_position - position;
+                    let distance = to_light.norm();
+                    let attenuation = settings.units.light_attenuation(distance, range);
+                    (
+                        to_light / distance,
+                        distance,
+                        Vector3::from(color) * attenuation,
+                    )
+                }
+            };
+            let cosine = normal.dot(&direction);
+            if cosine > 0.0 && !self.occluded(&origin, &direction, distance) {
+                light += radiance * cosine;
+            }
+        }
+
+        if settings.sky_samples > 0 {
+            // Cosine-weighted hemisphere directions from a Hammersley set, rotated per texel so
+            // neighbouring texels do not repeat the same pattern.
+            let (tangent, bitangent) = orthonormal_basis(normal);
+            let rotation = hash(seed) as f32 / u32::MAX as f32;
+            let visible = (0..settings.sky_samples)
+                .filter(|&i| {
+                    let u = (i as f32 + 0.5) / settings.sky_samples as f32;
+                    let v = (radical_inverse(i) + rotation).fract();
+                    let radius = u.sqrt();
+                    let angle = std::f32::consts::TAU * v;
+                    let direction = tangent * (radius * angle.cos())
+                        + bitangent * (radius * angle.sin())
+                        + normal * (1.0 - u).sqrt();
+                    !self.occluded(&origin, &direction, settings.occlusion_distance)
+                })
+                .count();
+            light +=
+                Vector3::from(settings.sky_color) * (visible as f32 / settings.sky_samples as f32);
+        }
+        light.into()
+    }
+}
+
+/// World position and normal at the center of every texel covered by a triangle.
+fn rasterize(
+    mesh: &LightmapMesh,
+    model_matrix: &Matrix4<f32>,
+    resolution: u32,
+) -> Vec<Option<(Vector3<f32>, Vector3<f32>)>> {
+    let normal_matrix = model_matrix
+        .fixed_view::<3, 3>(0, 0)
+        .try_inverse()
+        .unwrap_or_default()
+        .transpose();
+    let mut samples = vec![None; (resolution * resolution) as usize];
+    let size = resolution as f32;
+    for triangle in mesh.indices.chunks_exact(3) {
+        let triangle: [u32; 3] = triangle.try_into().unwrap();
+        let uv = triangle.map(|i| Vector2::from(mesh.lightmap_uvs[i as usize]) * size);
+        let low = uv[0].inf(&uv[1]).inf(&uv[2]);
+        let high = uv[0].sup(&uv[1]).sup(&uv[2]);
+        let area = edge(&uv[0], &uv[1], &uv[2]);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let x_range = (low.x.floor().max(0.0) as u32)..(high.x.ceil().min(size) as u32);
+        for y in (low.y.floor().max(0.0) as u32)..(high.y.ceil().min(size) as u32) {
+            for x in x_range.clone() {
+                let center = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let weights = [
+                    edge(&uv[1], &uv[2], &center) / area,
+                    edge(&uv[2], &uv[0], &center) / area,
+                    edge(&uv[0], &uv[1], &center) / area,
+                ];
+                if weights.iter().any(|&w| w < 0.0) {
+                    continue;
+                }
+                let interpolate = |attribute: &[[f32; 3]]| {
+                    (0..3).fold(Vector3::zeros(), |sum, k| {
+                        sum + Vector3::from(attribute[triangle[k] as usize]) * weights[k]
+                    })
+                };
+                let position = model_matrix
+                    .transform_point(&Point3::from(interpolate(&mesh.positions)))
+                    .coords;
+                let normal = (normal_matrix * interpolate(&mesh.normals)).normalize();
+                samples[(y * resolution + x) as usize] = Some((position, normal));
+            }
+        }
+    }
+    samples
+}
+
+/// Fills every empty texel next to baked ones with the average of those neighbours.
+fn dilate(texels: &[Option<[f32; 3]>], resolution: u32) -> Vec<Option<[f32; 3]>> {
+    let size = resolution as i32;
+    (0..texels.len())
+        .into_par_iter()
+        .map(|index| {
+            if texels[index].is_some() {
+                return texels[index];
+            }
+            let (x, y) = (index as i32 % size, index as i32 / size);
+            let mut sum = Vector3::zeros();
+            let mut count = 0;
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= size || ny >= size {
+                    continue;
+                }
+                if let Some(texel) = texels[(ny * size + nx) as usize] {
+                    sum += Vector3::from(texel);
+                    count += 1;
+                }
+            }
+            (count > 0).then(|| (sum / count as f32).into())
+        })
+        .collect()
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `c`.
+fn edge(a: &Vector2<f32>, b: &Vector2<f32>, c: &Vector2<f32>) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+fn orthonormal_basis(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let tangent = normal.cross(&helper).normalize();
+    (tangent, normal.cross(&tangent))
+}
+
+fn radical_inverse(i: u32) -> f32 {
+    i.reverse_bits() as f32 / 4_294_967_296.0
+}
+
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
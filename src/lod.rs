@@ -0,0 +1,100 @@
+use slotmap::SecondaryMap;
+
+use crate::bounds::Aabb;
+use crate::handles::MeshId;
+
+/// One level of detail of a mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodLevel {
+    pub mesh: MeshId,
+    /// Smallest screen coverage at which this level is still used; see `Lods::screen_coverage`.
+    pub min_coverage: f32,
+}
+
+/// Levels of a mesh, most detailed first.
+#[derive(Clone, Debug, Default)]
+pub struct LodGroup {
+    levels: Vec<LodLevel>,
+}
+impl LodGroup {
+    pub fn levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+
+    /// The first level whose threshold `coverage` reaches; the coarsest level below all of them.
+    pub fn select(&self, coverage: f32) -> usize {
+        self.levels
+            .iter()
+            .position(|level| coverage >= level.min_coverage)
+            .unwrap_or(self.levels.len() - 1)
+    }
+}
+
+/// LOD levels registered per base mesh, plus the camera parameters used to pick between them.
+/// Objects keep referring to the base mesh; `DrawList::build_with_lods` swaps in the level to draw.
+#[derive(Clone, Debug)]
+pub struct Lods {
+    groups: SecondaryMap<MeshId, LodGroup>,
+    /// `1 / tan(fovy / 2)` of the camera's projection.
+    pub projection_scale: f32,
+    /// Multiplies every coverage before selection: above 1 keeps detail longer, below 1 drops it
+    /// sooner, e.g. as a quality setting.
+    pub bias: f32,
+}
+impl Default for Lods {
+    fn default() -> Self {
+        Self::new(std::f32::consts::FRAC_PI_4)
+    }
+}
+impl Lods {
+    pub fn new(fovy: f32) -> Self {
+        Self {
+            groups: SecondaryMap::new(),
+            projection_scale: 1.0 / (fovy * 0.5).tan(),
+            bias: 1.0,
+        }
+    }
+
+    /// Registers the levels drawn instead of `base`. The first level is usually `base` itself.
+    /// Thresholds must decrease from level to level.
+    pub fn register(&mut self, base: MeshId, levels: Vec<LodLevel>) {
+        assert!(!levels.is_empty(), "a LOD group needs at least one level");
+        assert!(
+            levels
+                .windows(2)
+                .all(|pair| pair[0].min_coverage > pair[1].min_coverage),
+            "LOD thresholds must decrease"
+        );
+        self.groups.insert(base, LodGroup { levels });
+    }
+
+    pub fn unregister(&mut self, base: MeshId) -> Option<LodGroup> {
+        self.groups.remove(base)
+    }
+
+    pub fn group(&self, base: MeshId) -> Option<&LodGroup> {
+        self.groups.get(base)
+    }
+
+    /// Radius of the bounds' enclosing sphere projected on screen, as a fraction of half the
+    /// screen height. Falls with distance, so thresholds work like distances that adapt to the
+    /// field of view and the object's size.
+    pub fn screen_coverage(&self, bounds: &Aabb, distance: f32) -> f32 {
+        let radius = bounds.half_extents().norm();
+        if distance <= radius {
+            return f32::INFINITY;
+        }
+        radius * self.projection_scale / distance
+    }
+
+    /// The mesh to draw for an object using `base` at `distance` from the camera.
+    pub fn select(&self, base: MeshId, bounds: &Aabb, distance: f32) -> MeshId {
+        match self.groups.get(base) {
+            Some(group) => {
+                let coverage = self.screen_coverage(bounds, distance) * self.bias;
+                group.levels[group.select(coverage)].mesh
+            }
+            None => base,
+        }
+    }
+}
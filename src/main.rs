@@ -1,13 +1,146 @@
-mod renderer;
-mod renderer_core;
-mod vulkan_api_connection;
-mod winit_app;
-
+use szumi::renderer::{Renderer, RendererConfig};
+use szumi::startup_config::{StartupConfig, CONFIG_FILE};
+use szumi::vulkan_api_connection::AdapterSelection;
+use szumi::winit_app::App;
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit_app::App;
+
+const USAGE: &str = "usage: szumi [options]
+  --config <path>      settings file to start with (default szumi.toml, if it exists)
+  --gpu <index|name>   GPU to render on, an index or part of its name
+  --vsync <on|off>     wait for the display to present frames (default on)
+  --msaa <samples>     MSAA samples: 1, 2, 4, 8, 16, 32 or 64 (default 1)
+  --resolution <WxH>   render at a fixed resolution, letterboxed, e.g. 640x360
+  --validation         enable the Vulkan validation layer
+  --track-leaks        report renderer buffers and images still alive at exit
+  --list-gpus          print the GPUs and exit
+  --help               print this and exit";
+
+/// Environment variable with the most verbose level logged, e.g. `SZUMI_LOG=debug`.
+const LOG_ENV_VAR: &str = "SZUMI_LOG";
+
+/// Prints log records to stderr.
+struct StderrLogger;
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs at the level `LOG_ENV_VAR` names, info by default.
+fn init_logging() {
+    let level = std::env::var(LOG_ENV_VAR)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    log::set_logger(&StderrLogger).unwrap();
+    log::set_max_level(level);
+}
+
+/// The settings file `--config` names, or the default one.
+fn config_path(args: &[String]) -> String {
+    args.iter()
+        .position(|arg| arg == "--config")
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+        .unwrap_or(CONFIG_FILE.to_string())
+}
+
+/// Overrides the renderer settings of `config` with command-line flags, so they can change
+/// without recompiling.
+fn parse_args(
+    mut config: RendererConfig,
+    mut args: impl Iterator<Item = String>,
+) -> Result<RendererConfig, String> {
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--config" => {
+                // Read before the other flags, by `config_path`.
+                value()?;
+            }
+            "--gpu" => {
+                let gpu = value()?;
+                config.adapter = match gpu.parse() {
+                    Ok(index) => AdapterSelection::Index(index),
+                    Err(_) => AdapterSelection::Name(gpu),
+                };
+            }
+            "--vsync" => {
+                config.vsync = match value()?.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    other => return Err(format!("--vsync takes on or off, not {other}")),
+                };
+            }
+            "--msaa" => {
+                let samples = value()?;
+                config.msaa_samples = match samples.parse() {
+                    Ok(samples @ (1 | 2 | 4 | 8 | 16 | 32 | 64)) => samples,
+                    _ => {
+                        return Err(format!(
+                            "--msaa takes 1, 2, 4, 8, 16, 32 or 64, not {samples}"
+                        ))
+                    }
+                };
+            }
+            "--resolution" => {
+                let resolution = value()?;
+                let extent = resolution
+                    .split_once('x')
+                    .and_then(|(width, height)| Some([width.parse().ok()?, height.parse().ok()?]))
+                    .filter(|extent: &[u32; 2]| extent[0] > 0 && extent[1] > 0);
+                config.internal_resolution = match extent {
+                    Some(extent) => Some(extent),
+                    None => return Err(format!("--resolution takes WxH, not {resolution}")),
+                };
+            }
+            "--validation" => config.validation = true,
+            "--track-leaks" => config.track_leaks = true,
+            "--list-gpus" => {
+                for adapter in Renderer::enumerate_adapters() {
+                    println!(
+                        "{}: {} ({:?})",
+                        adapter.index, adapter.name, adapter.device_type
+                    );
+                }
+                std::process::exit(0);
+            }
+            "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => return Err(format!("unknown option {other}")),
+        }
+    }
+    Ok(config)
+}
 
 fn main() {
-    let mut app: App = App::default();
+    init_logging();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let startup = match StartupConfig::load(config_path(&args)) {
+        Ok(startup) => startup,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let renderer_config = match parse_args(startup.renderer, args.into_iter()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let mut app: App = App::with_config(startup.window, renderer_config);
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     event_loop.run_app(&mut app).expect("Error on running app");
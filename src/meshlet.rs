@@ -0,0 +1,100 @@
+use crate::bounds::Aabb;
+
+/// Vertex limit per meshlet, the usual mesh shader output size.
+pub const MAX_MESHLET_VERTICES: usize = 64;
+/// Triangle limit per meshlet.
+pub const MAX_MESHLET_TRIANGLES: usize = 124;
+
+/// A small cluster of a mesh's triangles, drawn as one unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Meshlet {
+    /// First entry of the meshlet's vertices in `Meshlets::vertices`.
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    /// First entry of the meshlet's triangles in `Meshlets::triangles`.
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+    pub bounds: Aabb,
+}
+
+/// A mesh split into meshlets. Each meshlet indexes its own vertex list with one byte per corner,
+/// so its triangles stay small enough for a single mesh shader workgroup.
+#[derive(Clone, Debug, Default)]
+pub struct Meshlets {
+    pub meshlets: Vec<Meshlet>,
+    /// Indices into the mesh's vertices, `vertex_count` per meshlet.
+    pub vertices: Vec<u32>,
+    /// Corners of each triangle, as indices into its meshlet's vertices.
+    pub triangles: Vec<[u8; 3]>,
+}
+impl Meshlets {
+    /// Groups triangles in index order, starting a new meshlet when either limit would be
+    /// exceeded. Meshes exported with a vertex cache optimization already keep neighbors close.
+    pub fn build(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let mut result = Self::default();
+        // Local index of each mesh vertex in the current meshlet.
+        let mut local = vec![u8::MAX; positions.len()];
+        let mut current = Meshlet {
+            vertex_offset: 0,
+            vertex_count: 0,
+            triangle_offset: 0,
+            triangle_count: 0,
+            bounds: Aabb::EMPTY,
+        };
+
+        for triangle in indices.chunks_exact(3) {
+            let new_vertices = triangle
+                .iter()
+                .enumerate()
+                .filter(|&(i, &vertex)| {
+                    local[vertex as usize] == u8::MAX && !triangle[..i].contains(&vertex)
+                })
+                .count();
+            if current.vertex_count as usize + new_vertices > MAX_MESHLET_VERTICES
+                || current.triangle_count as usize == MAX_MESHLET_TRIANGLES
+            {
+                result.finish(&mut current, &mut local);
+            }
+
+            let corners = [0, 1, 2].map(|corner| {
+                let vertex = triangle[corner];
+                if local[vertex as usize] == u8::MAX {
+                    local[vertex as usize] = current.vertex_count as u8;
+                    current.vertex_count += 1;
+                    current.bounds = current.bounds.grow(&positions[vertex as usize].into());
+                    result.vertices.push(vertex);
+                }
+                local[vertex as usize]
+            });
+            result.triangles.push(corners);
+            current.triangle_count += 1;
+        }
+        result.finish(&mut current, &mut local);
+        result
+    }
+
+    /// Mesh vertex indices of a meshlet's triangles.
+    pub fn triangle_indices(&self, meshlet: &Meshlet) -> impl Iterator<Item = [u32; 3]> + '_ {
+        let vertices = &self.vertices[meshlet.vertex_offset as usize..];
+        self.triangles[meshlet.triangle_offset as usize..][..meshlet.triangle_count as usize]
+            .iter()
+            .map(move |corners| corners.map(|corner| vertices[corner as usize]))
+    }
+
+    fn finish(&mut self, current: &mut Meshlet, local: &mut [u8]) {
+        if current.triangle_count == 0 {
+            return;
+        }
+        for &vertex in &self.vertices[current.vertex_offset as usize..] {
+            local[vertex as usize] = u8::MAX;
+        }
+        self.meshlets.push(*current);
+        *current = Meshlet {
+            vertex_offset: self.vertices.len() as u32,
+            vertex_count: 0,
+            triangle_offset: self.triangles.len() as u32,
+            triangle_count: 0,
+            bounds: Aabb::EMPTY,
+        };
+    }
+}
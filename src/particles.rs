@@ -0,0 +1,229 @@
+use nalgebra::Vector3;
+
+use crate::frame_arena::FrameArena;
+use crate::renderer_core::SpriteInstance;
+
+/// Values that can be blended by a `Curve`.
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+impl Lerp for f32 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+impl<const N: usize> Lerp for [f32; N] {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].lerp(other[i], t))
+    }
+}
+
+/// Piecewise-linear curve over a particle's normalized age (0 at birth, 1 at death).
+#[derive(Clone, Debug)]
+pub struct Curve<T: Lerp> {
+    /// `(age, value)` pairs sorted by age.
+    keys: Vec<(f32, T)>,
+}
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    pub fn from_keys(mut keys: Vec<(f32, T)>) -> Self {
+        assert!(!keys.is_empty(), "a curve needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn evaluate(&self, age: f32) -> T {
+        let next = self.keys.partition_point(|&(key_age, _)| key_age <= age);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (a_age, a) = self.keys[next - 1];
+        let (b_age, b) = self.keys[next];
+        a.lerp(b, (age - a_age) / (b_age - a_age))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EmitterConfig {
+    /// Particles spawned per second.
+    pub rate: f32,
+    /// Lifetime in seconds, picked uniformly from the range.
+    pub lifetime: (f32, f32),
+    pub initial_velocity: Vector3<f32>,
+    /// Random velocity added per axis, picked uniformly from `-spread..spread`.
+    pub velocity_spread: Vector3<f32>,
+    /// Constant acceleration such as gravity.
+    pub acceleration: Vector3<f32>,
+    /// Multiplies the particle's velocity over its life, e.g. to slow sparks down.
+    pub speed_over_life: Curve<f32>,
+    pub size_over_life: Curve<f32>,
+    pub color_over_life: Curve<[f32; 4]>,
+}
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            rate: 50.0,
+            lifetime: (1.0, 2.0),
+            initial_velocity: Vector3::new(0.0, 1.0, 0.0),
+            velocity_spread: Vector3::new(0.5, 0.2, 0.5),
+            acceleration: Vector3::zeros(),
+            speed_over_life: Curve::constant(1.0),
+            size_over_life: Curve::linear(0.1, 0.0),
+            color_over_life: Curve::linear([1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 0.0]),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub config: EmitterConfig,
+    pub position: Vector3<f32>,
+    pub enabled: bool,
+    /// Fractional particles carried over between updates so low rates still emit.
+    spawn_accumulator: f32,
+}
+impl Emitter {
+    pub fn new(config: EmitterConfig, position: Vector3<f32>) -> Self {
+        Self {
+            config,
+            position,
+            enabled: true,
+            spawn_accumulator: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    emitter: usize,
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    age: f32,
+    lifetime: f32,
+}
+
+/// Simulates particles on the CPU and turns them into sprite instances for `SpriteRenderer`.
+pub struct ParticleSystem {
+    pub emitters: Vec<Emitter>,
+    particles: Vec<Particle>,
+    max_particles: usize,
+    rng_state: u32,
+}
+impl ParticleSystem {
+    pub fn new(max_particles: usize) -> Self {
+        Self {
+            emitters: Vec::new(),
+            particles: Vec::with_capacity(max_particles),
+            max_particles,
+            rng_state: 0x9E37_79B9,
+        }
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) -> usize {
+        self.emitters.push(emitter);
+        self.emitters.len() - 1
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Advances the simulation by `dt` seconds: ages and moves particles, then spawns new ones.
+    pub fn update(&mut self, dt: f32) {
+        let emitters = &self.emitters;
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                return false;
+            }
+            let config = &emitters[particle.emitter].config;
+            let life = particle.age / particle.lifetime;
+            particle.velocity += config.acceleration * dt;
+            particle.position += particle.velocity * config.speed_over_life.evaluate(life) * dt;
+            true
+        });
+
+        for emitter_index in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[emitter_index];
+            if !emitter.enabled {
+                continue;
+            }
+            emitter.spawn_accumulator += emitter.config.rate * dt;
+            let spawn_count = emitter.spawn_accumulator.floor() as usize;
+            emitter.spawn_accumulator -= spawn_count as f32;
+            for _ in 0..spawn_count {
+                if self.particles.len() >= self.max_particles {
+                    break;
+                }
+                self.spawn(emitter_index);
+            }
+        }
+    }
+
+    fn spawn(&mut self, emitter: usize) {
+        let (min_life, max_life) = self.emitters[emitter].config.lifetime;
+        let lifetime = min_life + (max_life - min_life) * self.next_random();
+        let jitter = Vector3::new(
+            self.next_random() * 2.0 - 1.0,
+            self.next_random() * 2.0 - 1.0,
+            self.next_random() * 2.0 - 1.0,
+        );
+        let emitter_ref = &self.emitters[emitter];
+        let velocity = emitter_ref.config.initial_velocity
+            + jitter.component_mul(&emitter_ref.config.velocity_spread);
+        self.particles.push(Particle {
+            emitter,
+            position: emitter_ref.position,
+            velocity,
+            age: 0.0,
+            lifetime: lifetime.max(f32::EPSILON),
+        });
+    }
+
+    /// Xorshift: fast, deterministic and good enough for visual noise. Returns a value in `0..1`.
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Camera-facing quads for the live particles, ready for `SpriteRenderer::upload_instances`.
+    pub fn instances(&self) -> Vec<SpriteInstance> {
+        self.particles.iter().map(|p| self.instance(p)).collect()
+    }
+
+    /// Same as `instances`, but allocated in this frame's arena instead of a fresh `Vec`.
+    pub fn instances_in<'a>(&self, arena: &'a FrameArena) -> &'a [SpriteInstance] {
+        arena.alloc_slice_fill_iter(self.particles.iter().map(|p| self.instance(p)))
+    }
+
+    fn instance(&self, particle: &Particle) -> SpriteInstance {
+        let config = &self.emitters[particle.emitter].config;
+        let life = particle.age / particle.lifetime;
+        let size = config.size_over_life.evaluate(life);
+        SpriteInstance {
+            position: particle.position.into(),
+            size: [size, size],
+            rotation: 0.0,
+            color: config.color_over_life.evaluate(life),
+            layer: 0,
+        }
+    }
+}
@@ -149,7 +149,8 @@ pub(crate) fn get_shaders(device: Arc<Device>) -> (Arc<ShaderModule>, Arc<Shader
     )
 }
 
-pub(crate) fn get_pipeline(
+/// Pipeline reading vertex buffers of `V`, with `vs` taking inputs named like its fields.
+pub(crate) fn get_pipeline<V: Vertex>(
     device: Arc<Device>,
     vs: Arc<ShaderModule>,
     fs: Arc<ShaderModule>,
@@ -159,7 +160,7 @@ pub(crate) fn get_pipeline(
     let vs = vs.entry_point("main").unwrap();
     let fs = fs.entry_point("main").unwrap();
 
-    let vertex_input_state = MyVertex::per_vertex()
+    let vertex_input_state = V::per_vertex()
         .definition(&vs.info().input_interface)
         .unwrap();
 
@@ -282,15 +283,15 @@ pub(crate) fn get_triangle_vertex_buffer(
 ) -> vulkano::buffer::Subbuffer<[MyVertex]> {
     let vertex1 = MyVertex {
         position: [100, 100],
-        color: [255, 0, 0],
+        color: [255, 0, 0, 255],
     };
     let vertex2 = MyVertex {
         position: [0, 100],
-        color: [0, 255, 0],
+        color: [0, 255, 0, 255],
     };
     let vertex3 = MyVertex {
         position: [100, 25],
-        color: [0, 0, 255],
+        color: [0, 0, 255, 255],
     };
 
     let vertex_buffer = Buffer::from_iter(
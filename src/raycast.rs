@@ -0,0 +1,304 @@
+use nalgebra::Matrix4;
+use nalgebra::Point3;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+
+use crate::animation::Skin;
+use crate::bounds::Aabb;
+use crate::gltf_loader::GltfModel;
+use crate::gltf_loader::GltfPrimitive;
+use crate::handles::NodeId;
+use crate::scene::Scene;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Normalized.
+    pub direction: Vector3<f32>,
+}
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastOptions {
+    /// Test every triangle instead of stopping at the bounding box. Slower, but exact,
+    /// and takes the current skinned pose into account.
+    pub precise: bool,
+    pub max_distance: f32,
+    /// Ignore triangles facing away from the ray.
+    pub cull_back_faces: bool,
+}
+impl Default for RaycastOptions {
+    fn default() -> Self {
+        Self {
+            precise: true,
+            max_distance: f32::INFINITY,
+            cull_back_faces: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: Vector3<f32>,
+    /// Index of the first vertex index of the hit triangle; `None` for bounding-box hits.
+    pub triangle: Option<usize>,
+    /// Barycentric weights of the hit point inside the triangle.
+    pub barycentric: Option<[f32; 3]>,
+}
+
+/// Which geometry a ray is tested against.
+#[derive(Clone, Copy)]
+pub enum MeshPose<'a> {
+    /// The vertices as stored, placed in the world by the model matrix.
+    Static,
+    /// The vertices deformed by the skin, exactly as the skinning shader does it.
+    Skinned {
+        skin: &'a Skin,
+        world_matrices: &'a [Matrix4<f32>],
+    },
+}
+
+/// Casts `ray` (in world space) against one primitive drawn with `model_matrix`.
+pub fn raycast_primitive(
+    ray: &Ray,
+    primitive: &GltfPrimitive,
+    model_matrix: &Matrix4<f32>,
+    pose: MeshPose,
+    options: &RaycastOptions,
+) -> Option<RayHit> {
+    let bounds = Aabb::from_points(&primitive.positions).transform(model_matrix);
+    let box_distance = bounds.intersect_ray(&ray.origin, &ray.direction, options.max_distance);
+    // Skinned vertices may leave the bind-pose box, so only static meshes can early out.
+    if box_distance.is_none() && matches!(pose, MeshPose::Static) {
+        return None;
+    }
+    if !options.precise {
+        return box_distance.map(|distance| RayHit {
+            distance,
+            point: ray.at(distance),
+            triangle: None,
+            barycentric: None,
+        });
+    }
+
+    let vertices = posed_vertices(primitive, model_matrix, pose);
+    let mut closest: Option<RayHit> = None;
+    for (triangle, indices) in primitive.indices.chunks_exact(3).enumerate() {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[indices[i] as usize]);
+        let max_distance = closest.map_or(options.max_distance, |hit| hit.distance);
+        if let Some((distance, barycentric)) =
+            intersect_triangle(ray, &a, &b, &c, options.cull_back_faces)
+        {
+            if distance < max_distance {
+                closest = Some(RayHit {
+                    distance,
+                    point: ray.at(distance),
+                    triangle: Some(triangle * 3),
+                    barycentric: Some(barycentric),
+                });
+            }
+        }
+    }
+    closest
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ModelHit {
+    pub node: usize,
+    pub mesh: usize,
+    pub primitive: usize,
+    pub hit: RayHit,
+}
+
+/// Casts `ray` against every mesh in `model`. `world_matrices` is the current pose,
+/// e.g. from `GltfModel::world_matrices`, so animated and skinned models are hit where they are drawn.
+pub fn raycast_model(
+    ray: &Ray,
+    model: &GltfModel,
+    world_matrices: &[Matrix4<f32>],
+    options: &RaycastOptions,
+) -> Option<ModelHit> {
+    let mut closest: Option<ModelHit> = None;
+    for (node_index, node) in model.nodes.iter().enumerate() {
+        let Some(mesh_index) = node.mesh else {
+            continue;
+        };
+        // Skinned meshes ignore their node transform: the joints place them in the world.
+        let (model_matrix, pose) = match node.skin {
+            Some(skin) => (
+                Matrix4::identity(),
+                MeshPose::Skinned {
+                    skin: &model.skins[skin],
+                    world_matrices,
+                },
+            ),
+            None => (world_matrices[node_index], MeshPose::Static),
+        };
+        for (primitive_index, primitive) in model.meshes[mesh_index].primitives.iter().enumerate() {
+            let options = RaycastOptions {
+                max_distance: closest.map_or(options.max_distance, |c| c.hit.distance),
+                ..*options
+            };
+            if let Some(hit) = raycast_primitive(ray, primitive, &model_matrix, pose, &options) {
+                closest = Some(ModelHit {
+                    node: node_index,
+                    mesh: mesh_index,
+                    primitive: primitive_index,
+                    hit,
+                });
+            }
+        }
+    }
+    closest
+}
+
+/// A camera as far as turning cursor positions into rays goes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickCamera {
+    pub view: Matrix4<f32>,
+    /// Vulkan clip space: Y down, depth 0 at the near plane. Perspective or orthographic.
+    pub projection: Matrix4<f32>,
+    /// Size of the viewport the camera renders to, in the cursor's pixels.
+    pub viewport_extent: [f32; 2],
+}
+impl PickCamera {
+    /// The world space ray through `cursor`, in pixels from the viewport's top left, starting
+    /// on the near plane. `None` when the matrices can't be inverted.
+    pub fn ray(&self, cursor: [f32; 2]) -> Option<Ray> {
+        let inverse = (self.projection * self.view).try_inverse()?;
+        let [x, y] = [0, 1].map(|axis| cursor[axis] / self.viewport_extent[axis] * 2.0 - 1.0);
+        // Halfway in depth rather than the far plane keeps infinite projections finite.
+        let [near, middle] = [0.0, 0.5].map(|depth| {
+            let point = inverse * Vector4::new(x, y, depth, 1.0);
+            point.xyz() / point.w
+        });
+        Some(Ray::new(near, middle - near))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PickHit {
+    pub node: NodeId,
+    pub hit: RayHit,
+}
+
+/// Finds the scene node under `cursor` on the CPU, for when waiting on the GPU like
+/// `Renderer::pick` does is too slow. The scene's spatial index narrows the candidates down by
+/// bounds, then `primitive` gives each candidate's geometry, which is tested triangle by
+/// triangle with the node's world matrix. Nodes without a primitive are hit by their bounds.
+pub fn pick_ray<'a>(
+    camera: &PickCamera,
+    cursor: [f32; 2],
+    scene: &Scene,
+    mut primitive: impl FnMut(NodeId) -> Option<&'a GltfPrimitive>,
+    options: &RaycastOptions,
+) -> Option<PickHit> {
+    let ray = camera.ray(cursor)?;
+    let mut hits: Vec<PickHit> = Vec::new();
+    let (node, _) = scene
+        .spatial_index()
+        .raycast(&ray, options.max_distance, |node| {
+            let hit = match primitive(node) {
+                Some(primitive) => raycast_primitive(
+                    &ray,
+                    primitive,
+                    scene.world_matrix(node),
+                    MeshPose::Static,
+                    options,
+                ),
+                None => scene
+                    .world_bounds(node)?
+                    .intersect_ray(&ray.origin, &ray.direction, options.max_distance)
+                    .map(|distance| RayHit {
+                        distance,
+                        point: ray.at(distance),
+                        triangle: None,
+                        barycentric: None,
+                    }),
+            }?;
+            hits.push(PickHit { node, hit });
+            Some(hit.distance)
+        })?;
+    hits.into_iter().rev().find(|hit| hit.node == node)
+}
+
+fn posed_vertices(
+    primitive: &GltfPrimitive,
+    model_matrix: &Matrix4<f32>,
+    pose: MeshPose,
+) -> Vec<Vector3<f32>> {
+    let joint_matrices: Vec<Matrix4<f32>> = match pose {
+        MeshPose::Static => Vec::new(),
+        MeshPose::Skinned {
+            skin,
+            world_matrices,
+        } => skin
+            .joint_matrices(world_matrices)
+            .into_iter()
+            .map(Matrix4::from)
+            .collect(),
+    };
+    primitive
+        .positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let position = Point3::from(*position);
+            let skinned = match (primitive.joints.get(i), primitive.weights.get(i)) {
+                (Some(joints), Some(weights)) if !joint_matrices.is_empty() => {
+                    let skin_matrix = (0..4).fold(Matrix4::zeros(), |sum, k| {
+                        sum + joint_matrices[joints[k] as usize] * weights[k]
+                    });
+                    skin_matrix.transform_point(&position)
+                }
+                _ => position,
+            };
+            model_matrix.transform_point(&skinned).coords
+        })
+        .collect()
+}
+
+/// Möller–Trumbore. Returns the hit distance and barycentric weights of `a`, `b` and `c`.
+pub fn intersect_triangle(
+    ray: &Ray,
+    a: &Vector3<f32>,
+    b: &Vector3<f32>,
+    c: &Vector3<f32>,
+    cull_back_faces: bool,
+) -> Option<(f32, [f32; 3])> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if cull_back_faces && determinant < f32::EPSILON {
+        return None;
+    }
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = s.dot(&p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(&q) * inverse;
+    (distance >= 0.0).then_some((distance, [1.0 - u - v, u, v]))
+}
@@ -1,61 +1,549 @@
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use vulkano::{
-    swapchain::{self, SwapchainPresentInfo},
+    command_buffer::{
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, SecondaryAutoCommandBuffer,
+        SecondaryCommandBufferAbstract,
+    },
+    image::{ImageUsage, SampleCount},
+    pipeline::GraphicsPipeline,
+    swapchain::{self, Surface, SwapchainPresentInfo},
     sync::{self, GpuFuture},
-    Validated,
+    Validated, VulkanError,
 };
 use winit::window::Window;
 
-use crate::{renderer_core::RendererCore, vulkan_api_connection::VulkanConnection};
+use crate::{
+    device_capabilities::DeviceCapabilities,
+    draw_list::{DrawBatch, DrawList},
+    frame_arena::{FrameArena, FrameArenaStats},
+    renderer_core::{
+        capture_diff, AttachmentOps, CaptureComparison, CaptureSettings, CaptureTarget,
+        ComputeContext, DescriptorSets, FrameStats, IdBuffer, LeakCheck, MeshDraws, ObjectId,
+        RayTracedOutput, RendererCore, ResourceLoader, Resources, ScreenView, UniformRing,
+        UploadStats, VirtualBackbuffer,
+    },
+    vulkan_api_connection::{
+        AdapterInfo, AdapterSelection, ConnectionRequirements, VulkanConnection,
+    },
+};
+
+/// Frames between sweeps of the descriptor set cache for sets nothing uses anymore.
+const DESCRIPTOR_TRIM_INTERVAL: u64 = 256;
+
+/// How a `Renderer` sets up the device and its window's frames.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RendererConfig {
+    /// The GPU to render on. Renderers sharing another's connection render on its GPU.
+    pub adapter: AdapterSelection,
+    /// Presents in step with the display's refresh; off presents frames as soon as they're
+    /// done.
+    pub vsync: bool,
+    /// MSAA samples of the window's frames: 1, 2, 4, 8, 16, 32 or 64, 1 being off. Lowered to
+    /// the closest count the device supports.
+    pub msaa_samples: u32,
+    /// Renders at this fixed resolution and letterboxes it into the window instead of
+    /// rendering at the window's, e.g. `[640, 360]` for pixel art. See `VirtualBackbuffer`.
+    pub internal_resolution: Option<[u32; 2]>,
+    /// Enables the Vulkan validation layer. Like `adapter`, only for the renderer creating the
+    /// connection.
+    pub validation: bool,
+    /// Records where every buffer and image of the renderer was created and logs those still
+    /// alive once it is dropped, to find leaked `Arc`s. Slows down resource creation.
+    pub track_leaks: bool,
+}
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            adapter: AdapterSelection::Auto,
+            vsync: true,
+            msaa_samples: 1,
+            internal_resolution: None,
+            validation: false,
+            track_leaks: false,
+        }
+    }
+}
+impl RendererConfig {
+    fn sample_count(&self) -> SampleCount {
+        SampleCount::try_from(self.msaa_samples).expect("unsupported sample count")
+    }
+}
 
 pub struct Renderer {
     vapi: Arc<VulkanConnection>,
+    config: RendererConfig,
     core: RendererCore,
     last_frame_future: Option<Box<dyn GpuFuture>>,
+    frame_arena: FrameArena,
+    compute: ComputeContext,
+    resources: Resources,
+    upload_stats: UploadStats,
+    /// Frames begun so far, for amortized upkeep.
+    frame_number: u64,
+    /// Compute work submitted this frame; the frame's draw waits for it.
+    pending_compute: Option<Box<dyn GpuFuture>>,
+    /// When set, frames are ray traced instead of rasterized.
+    ray_traced_output: Option<RayTracedOutput>,
+    /// Object ids at the window's size, for `pick`.
+    id_buffer: Option<IdBuffer>,
+    /// Draws queued for the next frame.
+    frame_draws: Vec<Arc<dyn SecondaryCommandBufferAbstract>>,
+    /// Of what was recorded for the next frame outside the core.
+    pending_stats: Mutex<FrameStats>,
+    /// Of the last frame drawn.
+    frame_stats: FrameStats,
+    /// Last, so it reports once everything above has been dropped.
+    _leak_check: Option<LeakCheck>,
 }
 impl Renderer {
     pub fn new(window: Arc<Window>) -> Self {
-        let vapi = Arc::new(VulkanConnection::new(window.clone()));
-        let core = RendererCore::new(vapi.clone(), [1024, 1024]);
+        Self::with_config(window, RendererConfig::default())
+    }
+
+    /// `new`, rendering on the GPU `adapter` picks, e.g. from `enumerate_adapters`.
+    pub fn with_adapter(window: Arc<Window>, adapter: AdapterSelection) -> Self {
+        Self::with_config(
+            window,
+            RendererConfig {
+                adapter,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// `new`, set up as `config` says, e.g. from command-line flags.
+    pub fn with_config(window: Arc<Window>, config: RendererConfig) -> Self {
+        let vapi = Arc::new(VulkanConnection::with_requirements(
+            window.clone(),
+            &ConnectionRequirements {
+                adapter: config.adapter.clone(),
+                validation: config.validation,
+                ..Default::default()
+            },
+        ));
+        let core = RendererCore::with_settings(
+            vapi.clone(),
+            vapi.surface.clone(),
+            [1024, 1024],
+            config.vsync,
+            config.sample_count(),
+        );
+        Self::with_core(vapi, core, config)
+    }
+
+    /// Every GPU of the system, e.g. for a settings menu choosing one for `with_adapter`.
+    pub fn enumerate_adapters() -> Vec<AdapterInfo> {
+        VulkanConnection::enumerate_adapters()
+    }
+
+    /// A renderer for another window on the device of `vapi`, with its own surface and
+    /// swapchain. Resources aren't shared with the other windows' renderers.
+    pub fn with_connection(vapi: Arc<VulkanConnection>, window: Arc<Window>) -> Self {
+        Self::with_connection_config(vapi, window, RendererConfig::default())
+    }
+
+    /// `with_connection`, with the window's frames set up as `config` says.
+    pub fn with_connection_config(
+        vapi: Arc<VulkanConnection>,
+        window: Arc<Window>,
+        config: RendererConfig,
+    ) -> Self {
+        let surface = Surface::from_window(vapi.device.instance().clone(), window.clone()).unwrap();
+        assert!(
+            vapi.physical_device
+                .surface_support(vapi.queues.present().queue_family_index(), &surface)
+                .unwrap_or(false),
+            "the present queue can't present to the window"
+        );
+        let core = RendererCore::with_settings(
+            vapi.clone(),
+            surface,
+            window.inner_size().into(),
+            config.vsync,
+            config.sample_count(),
+        );
+        Self::with_core(vapi, core, config)
+    }
+
+    fn with_core(
+        vapi: Arc<VulkanConnection>,
+        mut core: RendererCore,
+        config: RendererConfig,
+    ) -> Self {
+        // Before the virtual backbuffer, so it's recorded too.
+        core.memory_tracker().set_leak_tracking(config.track_leaks);
+        if let Some(extent) = config.internal_resolution {
+            if !core.set_virtual_backbuffer(Some(VirtualBackbuffer::new(extent))) {
+                log::warn!("the swapchain can't be blitted to, rendering at the window's size");
+            }
+        }
+        let compute = ComputeContext::with_async_queue(
+            vapi.device.clone(),
+            vapi.queues.graphics().clone(),
+            vapi.queues.async_compute().cloned(),
+            core.descriptor_sets(),
+        );
+        let resources = Resources::with_upload_queue(
+            vapi.device.clone(),
+            vapi.queues.graphics().clone(),
+            vapi.queues.transfer().clone(),
+            core.descriptor_sets(),
+        );
+        let leak_check = config.track_leaks.then(|| {
+            LeakCheck::new([
+                core.memory_tracker().clone(),
+                compute.memory_tracker().clone(),
+                resources.memory_tracker().clone(),
+            ])
+        });
         Self {
             vapi,
+            config,
             core,
             last_frame_future: None,
+            frame_arena: FrameArena::new(),
+            compute,
+            resources,
+            upload_stats: UploadStats::default(),
+            frame_number: 0,
+            pending_compute: None,
+            ray_traced_output: None,
+            id_buffer: None,
+            frame_draws: Vec::new(),
+            pending_stats: Mutex::new(FrameStats::default()),
+            frame_stats: FrameStats::default(),
+            _leak_check: leak_check,
         }
     }
 
+    pub fn config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    /// The instance and device, to share with renderers of other windows.
+    pub fn connection(&self) -> Arc<VulkanConnection> {
+        self.vapi.clone()
+    }
+
     /// This method recreates everything that depends on the window size
     pub fn recreate_core(&mut self, window: Arc<Window>) {
-        let dimensions = window.inner_size().into();
+        let dimensions: [u32; 2] = window.inner_size().into();
+        log::debug!("recreating the swapchain at {dimensions:?}");
         self.core.recreate(dimensions);
     }
 
+    /// Scratch memory for the frame being built. Emptied at the start of every `on_draw`.
+    pub fn frame_arena(&self) -> &FrameArena {
+        &self.frame_arena
+    }
+
+    pub fn frame_arena_stats(&self) -> FrameArenaStats {
+        self.frame_arena.stats()
+    }
+
+    /// Meshes, textures and materials, created and referred to through handles.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    /// Uploads done at the start of the last frame and those still waiting for budget.
+    pub fn upload_stats(&self) -> UploadStats {
+        self.upload_stats
+    }
+
+    /// For creating meshes, textures and materials from other threads.
+    pub fn resource_loader(&self) -> ResourceLoader {
+        self.resources.loader()
+    }
+
+    /// Renders the scene recorded by `record` offscreen with two settings profiles, e.g. MSAA on
+    /// and off, and returns both frames with a per-pixel diff. Waits for the GPU.
+    pub fn capture_settings_diff<F>(
+        &self,
+        extent: [u32; 2],
+        a: &CaptureSettings,
+        b: &CaptureSettings,
+        threshold: u8,
+        record: F,
+    ) -> CaptureComparison
+    where
+        F: FnMut(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &CaptureTarget),
+    {
+        capture_diff(&self.compute, extent, a, b, threshold, record)
+    }
+
+    pub fn compute(&self) -> &ComputeContext {
+        &self.compute
+    }
+
+    /// Submits compute work that the next frame's draw waits for, e.g. a simulation whose
+    /// buffers the frame reads. Runs on the async compute queue where there is one, so it
+    /// overlaps with the frames still in flight.
+    pub fn submit_compute<F>(&mut self, record: F)
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        let after = self
+            .pending_compute
+            .take()
+            .unwrap_or_else(|| sync::now(self.vapi.device.clone()).boxed());
+        self.pending_compute = Some(self.compute.submit_async(after, record));
+    }
+
+    /// Chains compute work submitted by `submit` after the earlier work of this frame, for work
+    /// that synchronizes itself, e.g. `GpuFrustumCuller::submit_cull_bda`. The next frame's
+    /// draw waits for the returned future.
+    pub fn chain_compute<F>(&mut self, submit: F)
+    where
+        F: FnOnce(&ComputeContext, Box<dyn GpuFuture>) -> Box<dyn GpuFuture>,
+    {
+        let after = self
+            .pending_compute
+            .take()
+            .unwrap_or_else(|| sync::now(self.vapi.device.clone()).boxed());
+        self.pending_compute = Some(submit(&self.compute, after));
+    }
+
+    /// Limits and optional features of the device, e.g. to hide settings it can't do.
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        &self.vapi.capabilities
+    }
+
+    /// Whether the device can ray trace and the swapchain accepts the traced frames.
+    pub fn ray_tracing_supported(&self) -> bool {
+        self.vapi.capabilities.ray_query
+            && self
+                .core
+                .swapchain
+                .image_usage()
+                .intersects(ImageUsage::TRANSFER_DST)
+    }
+
+    /// Switches between rasterized and ray-traced frames. Returns whether frames are now ray
+    /// traced, which stays false where `ray_tracing_supported` is. Turning it off drops the
+    /// acceleration structures.
+    pub fn set_ray_traced_output(&mut self, enabled: bool) -> bool {
+        if !enabled || !self.ray_tracing_supported() {
+            self.ray_traced_output = None;
+        } else if self.ray_traced_output.is_none() {
+            self.ray_traced_output = RayTracedOutput::try_new(&self.compute);
+        }
+        self.ray_traced_output.is_some()
+    }
+
+    /// Meshes, instances and camera of the ray-traced output, while it is on.
+    pub fn ray_traced_output_mut(&mut self) -> Option<&mut RayTracedOutput> {
+        self.ray_traced_output.as_mut()
+    }
+
+    /// Renders at a fixed resolution whatever the window size, or at the window's again with
+    /// `None`. Returns false where the swapchain can't be blitted to.
+    pub fn set_virtual_backbuffer(
+        &mut self,
+        virtual_backbuffer: Option<VirtualBackbuffer>,
+    ) -> bool {
+        self.core.set_virtual_backbuffer(virtual_backbuffer)
+    }
+
+    pub fn virtual_backbuffer(&self) -> Option<&VirtualBackbuffer> {
+        self.core.virtual_backbuffer()
+    }
+
+    /// Renders object ids at the window's size for `pick`, e.g. after an edit or when the
+    /// cursor moves. `record` draws meshes with the given pipeline, calling
+    /// `IdBuffer::push_object` before each draw.
+    pub fn render_object_ids<F>(&mut self, record: F)
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &Arc<GraphicsPipeline>),
+    {
+        let extent = self.core.swapchain.image_extent();
+        if self
+            .id_buffer
+            .as_ref()
+            .is_none_or(|id_buffer| id_buffer.extent() != extent)
+        {
+            self.id_buffer = Some(IdBuffer::new(&self.compute, extent));
+        }
+        self.id_buffer
+            .as_mut()
+            .unwrap()
+            .render(&self.compute, record);
+    }
+
+    /// The object under window pixel (`x`, `y`) in the last `render_object_ids`, or `None` over
+    /// the background. Waits for the GPU.
+    pub fn pick(&mut self, x: u32, y: u32) -> Option<ObjectId> {
+        self.id_buffer.as_mut()?.pick(&self.compute, [x, y])
+    }
+
+    /// Renders the scene once per view into its part of the frame, e.g. for split screen, or
+    /// over the whole frame with an empty list. Ray-traced frames ignore the views.
+    pub fn set_split_views(&mut self, views: Vec<ScreenView>) {
+        self.core.set_split_views(views);
+    }
+
+    pub fn split_views(&self) -> &[ScreenView] {
+        self.core.split_views()
+    }
+
+    /// What rasterized frames are cleared to, `[0.1, 0.1, 0.1, 1.0]` by default.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.core.set_clear_color(clear_color);
+    }
+
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.core.clear_color()
+    }
+
+    /// Whether rasterized frames start cleared or, e.g. with `AttachmentOps::LOAD`, from what
+    /// was drawn into the frame before.
+    pub fn set_attachment_ops(&mut self, ops: AttachmentOps) {
+        self.core.set_attachment_ops(ops);
+    }
+
+    pub fn attachment_ops(&self) -> AttachmentOps {
+        self.core.attachment_ops()
+    }
+
+    /// Secondary command buffers drawn over the scene in every rasterized frame, e.g. a UI.
+    pub fn set_overlays(&mut self, overlays: Vec<Arc<SecondaryAutoCommandBuffer>>) {
+        self.core.set_overlays(overlays);
+    }
+
+    /// Records `draw_list` on all cores, one secondary command buffer per chunk of batches.
+    /// `record` binds each chunk's pipeline and descriptor sets, then draws its batches' meshes
+    /// from the given `MeshDraws`. Queue the chunks for the next frame with `queue_draws`.
+    /// The mesh draws count towards the next `frame_stats`.
+    pub fn record_draw_list_parallel<F>(
+        &self,
+        draw_list: &DrawList,
+        record: F,
+    ) -> Vec<Arc<SecondaryAutoCommandBuffer>>
+    where
+        F: Fn(&mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>, &MeshDraws, &[DrawBatch])
+            + Sync,
+    {
+        let meshes = self.resources.mesh_draws();
+        let draws = self
+            .core
+            .record_draw_list_parallel(draw_list, |builder, batches| {
+                record(builder, &meshes, batches)
+            });
+        self.add_frame_stats(meshes.stats());
+        draws
+    }
+
+    /// Counts draws and binds recorded for the next frame without the renderer's recorders,
+    /// e.g. the pipeline binds of `record_draw_list_parallel`'s `record`.
+    pub fn add_frame_stats(&self, stats: FrameStats) {
+        *self.pending_stats.lock().unwrap() += stats;
+    }
+
+    /// What the last submitted frame recorded: the core's views, then everything counted for it
+    /// with `record_draw_list_parallel` and `add_frame_stats`. Zero for ray-traced frames.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    /// Secondary command buffers drawn after the scene in the next rasterized frame only, e.g.
+    /// from `record_draw_list_parallel` or `MeshletMesh::record_mesh_tasks`. Queue the current
+    /// draw list every frame.
+    pub fn queue_draws<C>(&mut self, draws: Vec<Arc<C>>)
+    where
+        C: SecondaryCommandBufferAbstract + 'static,
+    {
+        self.frame_draws.extend(
+            draws
+                .into_iter()
+                .map(|draw| draw as Arc<dyn SecondaryCommandBufferAbstract>),
+        );
+    }
+
+    /// A builder for overlays or other draws executed inside the frame's render pass.
+    pub fn secondary_builder(&self) -> AutoCommandBufferBuilder<SecondaryAutoCommandBuffer> {
+        self.core.secondary_builder()
+    }
+
+    /// Shared descriptor set allocation and cache, for the `get_descriptor_set` helpers.
+    pub fn descriptor_sets_mut(&mut self) -> &mut DescriptorSets {
+        self.core.descriptor_sets_mut()
+    }
+
+    /// Streams per-frame and per-object uniform data; see `UniformRing`.
+    pub fn uniforms(&self) -> &UniformRing {
+        self.core.uniforms()
+    }
+
     pub fn on_draw(&mut self, window: Arc<Window>) {
+        self.frame_arena.reset();
+        self.core.descriptor_sets_mut().next_frame();
+        self.frame_number += 1;
+        if self.frame_number.is_multiple_of(DESCRIPTOR_TRIM_INTERVAL) {
+            self.core.descriptor_sets().trim();
+        }
+        self.upload_stats = self.resources.process_uploads();
+
         // Acquire the next image to render to
         let (image_i, _suboptimal, acquire_future) =
             match swapchain::acquire_next_image(self.core.swapchain.clone(), None)
                 .map_err(Validated::unwrap)
             {
                 Ok(r) => r,
+                Err(VulkanError::OutOfDate) => {
+                    log::warn!("the swapchain is out of date, recreating it");
+                    self.recreate_core(window.clone());
+                    return;
+                }
                 Err(e) => panic!("failed to acquire next image: {e}"),
             };
 
         if _suboptimal {
+            log::debug!("the swapchain is suboptimal for the window");
             self.recreate_core(window.clone());
             return;
         }
 
-        // Execute the command buffer
-        let execution = sync::now(self.vapi.device.clone())
-            .join(acquire_future)
-            .then_execute(
-                self.vapi.queue.clone(),
-                self.core.command_buffers[image_i as usize].clone(),
-            )
-            .unwrap()
+        // Record and execute the frame's command buffer
+        let draws = std::mem::take(&mut self.frame_draws);
+        let before_frame = self
+            .pending_compute
+            .take()
+            .unwrap_or_else(|| sync::now(self.vapi.device.clone()).boxed());
+        let frame = before_frame.join(acquire_future).boxed();
+        // Of the core's views and draws; nothing is rasterized in ray-traced frames.
+        let mut core_stats = FrameStats::default();
+        let frame = match &mut self.ray_traced_output {
+            Some(output) => {
+                let image = self.core.render_target(image_i);
+                self.compute.submit(frame, |builder| {
+                    output.record(&self.compute, builder, image);
+                    self.core.record_present(builder, image_i);
+                })
+            }
+            None => {
+                let (command_buffer, stats) = self.core.record_frame(image_i, &draws);
+                core_stats = stats;
+                frame
+                    .then_execute(self.vapi.queues.graphics().clone(), command_buffer)
+                    .unwrap()
+                    .boxed()
+            }
+        };
+        // Presenting on another queue waits for the frame through a semaphore.
+        let frame = if self.vapi.queues.separate_present() {
+            frame.then_signal_semaphore().boxed()
+        } else {
+            frame
+        };
+        let execution = frame
             .then_swapchain_present(
-                self.vapi.queue.clone(),
+                self.vapi.queues.present().clone(),
                 SwapchainPresentInfo::swapchain_image_index(self.core.swapchain.clone(), image_i),
             )
             .then_signal_fence_and_flush();
@@ -67,9 +555,18 @@ impl Renderer {
                     self.last_frame_future.as_mut().unwrap().cleanup_finished();
                 }
                 self.last_frame_future = Some(Box::new(future));
+
+                let pending_stats = std::mem::take(self.pending_stats.get_mut().unwrap());
+                self.frame_stats = match self.ray_traced_output {
+                    Some(_) => FrameStats::default(),
+                    None => core_stats + pending_stats,
+                };
             }
             Err(e) => {
-                println!("failed to flush future: {e}");
+                log::error!("failed to flush the frame: {e}");
+                // The draws they counted were dropped with the frame; `frame_stats` keeps the
+                // last submitted frame's.
+                *self.pending_stats.get_mut().unwrap() = FrameStats::default();
             }
         }
     }
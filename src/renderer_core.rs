@@ -1,22 +1,81 @@
+mod batch_2d;
+mod bindless;
+mod blend;
 mod buffer_structs;
+mod canvas;
+mod capture;
+mod clip_rect;
+mod compute;
+mod decals;
+mod descriptor_sets;
+mod draw_commands;
+mod draw_data;
+mod dynamic_mesh;
+mod frame_stats;
+mod gpu_culling;
+mod gpu_particles;
+mod hi_z;
+mod indirect;
+mod lightmap;
+mod material_pipelines;
+mod memory_stats;
+mod mesh_pool;
+#[cfg(feature = "mesh_shader")]
+mod meshlets;
+mod morph;
+mod multiview;
+mod nine_slice;
+mod object_data;
+mod occlusion;
+mod oit;
+mod picking;
+mod polyline;
+mod ray_traced_ao;
+mod ray_traced_shadows;
+mod ray_tracing;
+mod ray_tracing_pipeline;
+mod render_targets;
+mod resources;
+mod sampler;
 mod shaders;
+mod skinning;
+mod split_screen;
+mod sprites;
+mod staging;
+mod stencil;
+mod storage_buffer;
+mod svg;
+mod tessellation;
+mod text;
+mod tilemap;
+mod uniform_ring;
+mod vertex_layout;
+mod virtual_backbuffer;
+mod volume;
+mod water;
 
 use std::sync::Arc;
 
 use std::ops::Deref;
 
+use crate::draw_list::DrawBatch;
+use crate::draw_list::DrawList;
 use crate::vulkan_api_connection::VulkanConnection;
 use nalgebra::Matrix4;
 use nalgebra::Orthographic3;
+use rayon::prelude::*;
 use vulkano::buffer::Buffer;
 use vulkano::buffer::BufferCreateInfo;
 use vulkano::buffer::BufferUsage;
 use vulkano::buffer::Subbuffer;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBufferInheritanceInfo;
 use vulkano::command_buffer::CommandBufferUsage;
 use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SecondaryAutoCommandBuffer;
+use vulkano::command_buffer::SecondaryCommandBufferAbstract;
 use vulkano::command_buffer::SubpassBeginInfo;
 use vulkano::command_buffer::SubpassContents;
 use vulkano::command_buffer::SubpassEndInfo;
@@ -29,37 +88,169 @@ use vulkano::format::Format;
 use vulkano::image::view::ImageView;
 use vulkano::image::Image;
 use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageLayout;
 use vulkano::image::ImageType;
 use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
 use vulkano::memory::allocator::AllocationCreateInfo;
 use vulkano::memory::allocator::MemoryTypeFilter;
 use vulkano::memory::allocator::StandardMemoryAllocator;
-use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
-use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
 use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
 use vulkano::pipeline::graphics::multisample::MultisampleState;
 use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::tessellation::TessellationState;
 use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::vertex_input::VertexBufferDescription;
 use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
 use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::pipeline::graphics::viewport::ViewportState;
 use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
 use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::DynamicState;
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::pipeline::Pipeline;
 use vulkano::pipeline::PipelineLayout;
 use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::AttachmentDescription;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentReference;
+use vulkano::render_pass::AttachmentStoreOp;
 use vulkano::render_pass::Framebuffer;
 use vulkano::render_pass::FramebufferCreateInfo;
 use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::RenderPassCreateInfo;
 use vulkano::render_pass::Subpass;
+use vulkano::render_pass::SubpassDescription;
 use vulkano::shader::EntryPoint;
 use vulkano::shader::ShaderModule;
+use vulkano::swapchain::PresentMode;
+use vulkano::swapchain::Surface;
 use vulkano::swapchain::Swapchain;
 use vulkano::swapchain::SwapchainCreateInfo;
+use vulkano::sync::Sharing;
 
+pub use self::batch_2d::Batch2D;
+pub use self::batch_2d::Batch2DStats;
+pub use self::batch_2d::Quad2D;
+pub use self::bindless::BindlessMaterial;
+pub use self::bindless::BindlessTextures;
+pub use self::bindless::BINDLESS_SET;
+pub use self::bindless::NO_TEXTURE;
+pub use self::blend::BlendMode;
+pub use self::buffer_structs::MeshVertex;
 use self::buffer_structs::MyVertex;
 use self::buffer_structs::MVP;
+pub use self::canvas::Canvas;
+pub use self::capture::capture_diff;
+pub use self::capture::capture_frame;
+pub use self::capture::CaptureComparison;
+pub use self::capture::CaptureSettings;
+pub use self::capture::CaptureTarget;
+pub use self::clip_rect::ClipRect;
+pub use self::compute::ComputeContext;
+pub use self::decals::Decal;
+pub use self::decals::DecalAtlas;
+pub use self::decals::DecalRenderer;
+pub use self::descriptor_sets::DescriptorSets;
+pub use self::draw_commands::DrawCommand;
+pub use self::draw_commands::DrawCommands;
+pub use self::draw_data::DrawDataBuffer;
+pub use self::dynamic_mesh::DynamicMesh;
+pub use self::frame_stats::FrameStats;
+pub use self::gpu_culling::CullObject;
+pub use self::gpu_culling::GpuFrustumCuller;
+pub use self::gpu_particles::GpuEmitterConfig;
+pub use self::gpu_particles::GpuParticleSystem;
+pub use self::hi_z::HiZBuffer;
+pub use self::indirect::IndirectBuffer;
+pub use self::indirect::IndirectCommand;
+pub use self::lightmap::LightmappedMesh;
+pub use self::material_pipelines::MaterialFeatures;
+pub use self::material_pipelines::MaterialPipelines;
+pub use self::memory_stats::HeapBudget;
+pub use self::memory_stats::LeakCheck;
+pub use self::memory_stats::LeakedResource;
+pub use self::memory_stats::MemoryBudget;
+pub use self::memory_stats::MemoryCategory;
+pub use self::memory_stats::MemoryTracker;
+pub use self::memory_stats::MemoryUsage;
+#[cfg(feature = "mesh_shader")]
+pub use self::meshlets::MeshShaderPipeline;
+#[cfg(feature = "mesh_shader")]
+pub use self::meshlets::MeshTaskCommands;
+#[cfg(feature = "mesh_shader")]
+pub use self::meshlets::MeshletMesh;
+pub use self::morph::MorphedMesh;
+pub use self::multiview::StereoCamera;
+pub use self::multiview::StereoTarget;
+pub use self::nine_slice::NineSlice;
+pub use self::nine_slice::NineSliceRenderer;
+pub use self::nine_slice::Panel;
+pub use self::object_data::ObjectData;
+pub use self::occlusion::OcclusionCuller;
+pub use self::oit::WeightedOit;
+pub use self::picking::IdBuffer;
+pub use self::picking::ObjectId;
+pub use self::polyline::Polyline;
+pub use self::polyline::PolylineRenderer;
+pub use self::ray_traced_ao::RayTracedAo;
+pub use self::ray_traced_shadows::RayTracedShadows;
+pub use self::ray_traced_shadows::ShadowLight;
+pub use self::ray_tracing::RayTracedOutput;
+pub use self::ray_tracing::RayTracingScene;
+pub use self::render_targets::AttachmentOps;
+pub use self::render_targets::ColorTarget;
+pub use self::render_targets::RenderTargets;
+pub use self::resources::Material;
+pub use self::resources::MeshDraws;
+pub use self::resources::ResourceLoader;
+pub use self::resources::Resources;
+pub use self::resources::UploadPriority;
+pub use self::resources::UploadStats;
+pub use self::sampler::MipSampling;
+pub use self::sampler::SamplerDesc;
+pub use self::skinning::SkinnedMesh;
+pub use self::split_screen::ScreenView;
+pub use self::sprites::Flipbook;
+pub use self::sprites::FlipbookPlayer;
+pub use self::sprites::LoopMode;
+pub use self::sprites::SpriteInstance;
+pub use self::sprites::SpriteRenderer;
+pub use self::staging::StagingUploader;
+pub use self::stencil::StencilMode;
+pub use self::storage_buffer::StorageBuffer;
+pub use self::svg::SvgError;
+pub use self::svg::SvgMesh;
+pub use self::tessellation::DisplacedSurface;
+pub use self::tessellation::DisplacementDraw;
+pub use self::tessellation::TessellationStages;
+pub use self::text::BitmapFont;
+pub use self::text::TextRenderer;
+pub use self::text::TextRun;
+pub use self::tilemap::TilemapRenderer;
+pub use self::uniform_ring::UniformRing;
+pub use self::vertex_layout::LayoutMesh;
+pub use self::vertex_layout::VertexLayout;
+pub use self::virtual_backbuffer::VirtualBackbuffer;
+pub use self::volume::VolumeDraw;
+pub use self::volume::VolumeRenderer;
+pub use self::water::WaterDraw;
+pub use self::water::WaterRenderer;
+
+/// Options of pipelines built by `RendererCore::build_pipeline_with`.
+#[derive(Clone, Debug, Default)]
+struct PipelineOptions {
+    /// Leaves the scissor dynamic, for draws clipped by a `ClipRect`.
+    dynamic_scissor: bool,
+    blend: BlendMode,
+    /// Tests depth where the subpass has a depth attachment, and writes it unless `blend`
+    /// blends.
+    depth_test: bool,
+    /// Draws patches through these stages instead of triangles.
+    tessellation: Option<TessellationStages>,
+}
 
 // Core is the struct that holds objects that depend on window size. They need to be remade each time a window is resized.
 pub struct RendererCore {
@@ -71,19 +262,74 @@ pub struct RendererCore {
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     framebuffers: Vec<Arc<Framebuffer>>,
     pipeline: Arc<GraphicsPipeline>,
-    pub command_buffers: Vec<Arc<PrimaryAutoCommandBuffer>>,
     pub swapchain: Arc<Swapchain>,
     vertex_buffer: Arc<Subbuffer<[MyVertex]>>,
+    virtual_backbuffer: Option<VirtualBackbuffer>,
+    /// Rendered into instead of the swapchain images while `virtual_backbuffer` is set.
+    virtual_image: Option<Arc<Image>>,
+    /// Views drawn into parts of the frame, each with its own camera. Empty draws one view over
+    /// the whole frame.
+    split_views: Vec<ScreenView>,
+    descriptor_sets: DescriptorSets,
+    uniforms: UniformRing,
+    /// The draws of each view, drawn in order.
+    passes: Vec<DrawCommands>,
+    /// Drawn after the views in every frame, until replaced.
+    draws: DrawCommands,
+    /// Secondary command buffers executed in every frame's render pass after the scene.
+    overlays: Vec<Arc<SecondaryAutoCommandBuffer>>,
+    /// What the frame is cleared to while `attachment_ops` clears it.
+    clear_color: [f32; 4],
+    attachment_ops: AttachmentOps,
+    /// The core's own render targets and buffers.
+    memory_tracker: MemoryTracker,
 }
 impl RendererCore {
     pub fn new(vapi: Arc<VulkanConnection>, dimensions: [u32; 2]) -> Self {
-        let (swapchain, images) = RendererCore::create_swapchain(vapi.clone(), dimensions);
+        let surface = vapi.surface.clone();
+        RendererCore::with_surface(vapi, surface, dimensions)
+    }
 
-        let render_pass = RendererCore::get_render_pass(vapi.device.clone(), swapchain.clone());
+    /// Presents to `surface` instead of the connection's, e.g. that of another window. The
+    /// connection's queue must be able to present to it.
+    pub fn with_surface(
+        vapi: Arc<VulkanConnection>,
+        surface: Arc<Surface>,
+        dimensions: [u32; 2],
+    ) -> Self {
+        RendererCore::with_settings(vapi, surface, dimensions, true, SampleCount::Sample1)
+    }
+
+    /// `with_surface`, presenting as soon as frames are done when `vsync` is off, and drawing
+    /// the core's views with `samples` MSAA samples, resolved into the frame. Sample counts the
+    /// device doesn't support are lowered to the closest one it does.
+    pub fn with_settings(
+        vapi: Arc<VulkanConnection>,
+        surface: Arc<Surface>,
+        dimensions: [u32; 2],
+        vsync: bool,
+        samples: SampleCount,
+    ) -> Self {
+        let (swapchain, images) =
+            RendererCore::create_swapchain(vapi.clone(), surface, dimensions, vsync);
+
+        let samples = vapi.capabilities.clamp_msaa_samples(samples);
+        let render_pass = RendererCore::get_render_pass(
+            vapi.device.clone(),
+            swapchain.image_format(),
+            samples,
+            AttachmentOps::default(),
+        );
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(vapi.device.clone()));
 
-        let framebuffers = RendererCore::get_framebuffers(&images, &render_pass);
+        let memory_tracker = MemoryTracker::new();
+        let framebuffers = RendererCore::get_framebuffers(
+            &images,
+            &render_pass,
+            &memory_allocator,
+            &memory_tracker,
+        );
 
         let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
             vapi.device.clone(),
@@ -111,47 +357,66 @@ impl RendererCore {
             vec![
                 MyVertex {
                     position: [100, 100],
-                    color: [255, 0, 35],
+                    color: [255, 0, 35, 255],
                 },
                 MyVertex {
                     position: [200, 100],
-                    color: [0, 255, 50],
+                    color: [0, 255, 50, 255],
                 },
                 MyVertex {
                     position: [150, 200],
-                    color: [0, 100, 255],
+                    color: [0, 100, 255, 255],
                 },
             ],
         ));
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "triangle vertex buffer",
+            vertex_buffer.buffer(),
+        );
+        let uniforms = UniformRing::new(memory_allocator.clone(), &memory_tracker);
         let mvp_buffer = Arc::new(RendererCore::get_mvp_buffer(
-            memory_allocator.clone(),
+            &uniforms,
             viewport.clone(),
+            Matrix4::identity(),
+            None,
         ));
+        let descriptor_sets = DescriptorSets::new(vapi.device.clone());
         let mvp_set = RendererCore::get_mvp_descriptor_set(
-            vapi.device.clone(),
+            descriptor_sets.allocator(),
             pipeline.clone(),
             mvp_buffer.clone(),
         );
-        let command_buffers = RendererCore::get_command_buffers(
-            &command_buffer_allocator,
-            &vapi.queue,
-            &pipeline,
-            &framebuffers,
-            &vertex_buffer,
+        let mut pass = DrawCommands::new();
+        pass.push(DrawCommand::new(
+            pipeline.clone(),
             vec![mvp_set],
-        );
+            vertex_buffer.deref().clone(),
+        ));
+        let passes = vec![pass];
+        let draws = DrawCommands::new();
         Self {
             vapi,
             viewport,
             images,
             framebuffers,
-            command_buffers,
             render_pass,
             swapchain,
             memory_allocator,
             command_buffer_allocator,
             vertex_buffer,
             pipeline,
+            virtual_backbuffer: None,
+            virtual_image: None,
+            split_views: Vec::new(),
+            descriptor_sets,
+            uniforms,
+            passes,
+            draws,
+            overlays: Vec::new(),
+            clear_color: [0.1, 0.1, 0.1, 1.0],
+            attachment_ops: AttachmentOps::default(),
+            memory_tracker,
         }
     }
 
@@ -165,41 +430,394 @@ impl RendererCore {
             .expect("failed to recreate swapchain: {e}");
         self.swapchain = new_swapchain;
         self.images = new_images;
-        self.framebuffers = RendererCore::get_framebuffers(&self.images, &self.render_pass);
-        self.viewport.extent = [dimensions[0] as f32, dimensions[1] as f32];
-        let (vs, fs) = RendererCore::get_shaders(self.vapi.device.clone());
-        let pipeline = RendererCore::get_pipeline(
-            self.vapi.device.clone(),
-            vs.entry_point("main").unwrap(),
-            fs.entry_point("main").unwrap(),
-            self.render_pass.clone(),
-            self.viewport.clone(),
-        );
-        let mvp_buffer = Arc::new(RendererCore::get_mvp_buffer(
-            self.memory_allocator.clone(),
-            self.viewport.clone(),
-        ));
-        let mvp_set = RendererCore::get_mvp_descriptor_set(
+        self.rebuild();
+    }
+
+    /// Renders into a fixed-size offscreen target from now on, scaled to the window when
+    /// presenting, or straight into the swapchain images again with `None`. Returns false and
+    /// changes nothing when the swapchain images can't be blitted to.
+    pub fn set_virtual_backbuffer(
+        &mut self,
+        virtual_backbuffer: Option<VirtualBackbuffer>,
+    ) -> bool {
+        if virtual_backbuffer.is_some()
+            && !self
+                .swapchain
+                .image_usage()
+                .intersects(ImageUsage::TRANSFER_DST)
+        {
+            return false;
+        }
+        self.virtual_image = virtual_backbuffer.map(|virtual_backbuffer| {
+            let image = Image::new(
+                self.memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: self.swapchain.image_format(),
+                    extent: [
+                        virtual_backbuffer.extent[0],
+                        virtual_backbuffer.extent[1],
+                        1,
+                    ],
+                    usage: ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::SAMPLED
+                        | ImageUsage::TRANSFER_SRC
+                        | ImageUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            self.memory_tracker.track_image(
+                MemoryCategory::RenderTargets,
+                "virtual backbuffer",
+                &image,
+            );
+            image
+        });
+        self.virtual_backbuffer = virtual_backbuffer;
+        self.rebuild();
+        true
+    }
+
+    pub fn virtual_backbuffer(&self) -> Option<&VirtualBackbuffer> {
+        self.virtual_backbuffer.as_ref()
+    }
+
+    /// Draws the scene once per view, each into its rectangle with its camera, or once over
+    /// the whole frame again with an empty list.
+    pub fn set_split_views(&mut self, views: Vec<ScreenView>) {
+        self.split_views = views;
+        self.rebuild();
+    }
+
+    pub fn split_views(&self) -> &[ScreenView] {
+        &self.split_views
+    }
+
+    /// What frames are cleared to before the scene is drawn, while the attachment ops clear.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.clear_color
+    }
+
+    /// What the frame's render pass does with the frame: clear it, the default, or keep what
+    /// was drawn into it before, e.g. when the app draws into `render_target` with its own pass
+    /// first and the core only adds overlays on top. Remakes the render pass, which stays
+    /// compatible with pipelines and secondaries made for the old one.
+    pub fn set_attachment_ops(&mut self, ops: AttachmentOps) {
+        if ops == self.attachment_ops {
+            return;
+        }
+        self.render_pass = RendererCore::get_render_pass(
             self.vapi.device.clone(),
-            pipeline.clone(),
-            mvp_buffer.clone(),
+            self.swapchain.image_format(),
+            self.render_pass.attachments()[0].samples,
+            ops,
         );
-        self.command_buffers = RendererCore::get_command_buffers(
+        self.attachment_ops = ops;
+        self.rebuild();
+    }
+
+    pub fn attachment_ops(&self) -> AttachmentOps {
+        self.attachment_ops
+    }
+
+    /// Executes `overlays` in every frame's render pass after the scene and the frame's draws,
+    /// e.g. a UI recorded with `secondary_builder`, until replaced, or none again with an empty
+    /// list. Rerecord and set them when their content changes.
+    pub fn set_overlays(&mut self, overlays: Vec<Arc<SecondaryAutoCommandBuffer>>) {
+        self.overlays = overlays;
+    }
+
+    pub fn overlays(&self) -> &[Arc<SecondaryAutoCommandBuffer>] {
+        &self.overlays
+    }
+
+    /// Draws `draws` in every frame's render pass after the core's views, until replaced, each
+    /// with its own pipeline, descriptor sets and vertex buffer, e.g. meshes of different
+    /// vertex layouts and materials. Their pipelines must be made for subpass 0 of the core's
+    /// render pass.
+    pub fn set_draws(&mut self, draws: DrawCommands) {
+        self.draws = draws;
+    }
+
+    pub fn draws(&self) -> &DrawCommands {
+        &self.draws
+    }
+
+    /// A builder for draws executed inside the render pass of many frames, e.g. by
+    /// `set_overlays`. Pipelines for it must be made for subpass 0 of the core's render pass.
+    pub fn secondary_builder(&self) -> AutoCommandBufferBuilder<SecondaryAutoCommandBuffer> {
+        RendererCore::get_secondary_builder(
             &self.command_buffer_allocator,
-            &self.vapi.queue,
-            &pipeline,
-            &self.framebuffers,
-            &self.vertex_buffer,
-            vec![mvp_set],
-        );
+            self.vapi.queues.graphics(),
+            &self.render_pass,
+            // Frames overlap, so several may execute it at once.
+            CommandBufferUsage::SimultaneousUse,
+        )
+    }
+
+    /// Records `count` secondary command buffers on rayon's threads, calling `record` with each
+    /// index and its builder, e.g. one per chunk of a large scene. They are for one frame's
+    /// `record_frame` only.
+    pub fn record_secondaries_parallel<F>(
+        &self,
+        count: usize,
+        record: F,
+    ) -> Vec<Arc<SecondaryAutoCommandBuffer>>
+    where
+        F: Fn(usize, &mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>) + Sync,
+    {
+        let allocator = &self.command_buffer_allocator;
+        let queue = self.vapi.queues.graphics();
+        let render_pass = &self.render_pass;
+        (0..count)
+            .into_par_iter()
+            .map(|i| {
+                let mut builder = RendererCore::get_secondary_builder(
+                    allocator,
+                    queue,
+                    render_pass,
+                    CommandBufferUsage::OneTimeSubmit,
+                );
+                record(i, &mut builder);
+                builder.build().unwrap()
+            })
+            .collect()
+    }
+
+    /// Records `draw_list`'s batches into one secondary command buffer per chunk of
+    /// `DrawList::batch_chunks`, on all of rayon's threads, so recording large scenes scales
+    /// with the core count. Secondaries inherit no state, so `record` binds the pipeline and
+    /// descriptor sets of each chunk itself before drawing its batches, e.g. with
+    /// `MeshDraws::record_draw_meshes`. The results keep draw list order; pass them to this
+    /// frame's `record_frame`.
+    pub fn record_draw_list_parallel<F>(
+        &self,
+        draw_list: &DrawList,
+        record: F,
+    ) -> Vec<Arc<SecondaryAutoCommandBuffer>>
+    where
+        F: Fn(&mut AutoCommandBufferBuilder<SecondaryAutoCommandBuffer>, &[DrawBatch]) + Sync,
+    {
+        let chunks = draw_list.batch_chunks(rayon::current_num_threads());
+        self.record_secondaries_parallel(chunks.len(), |i, builder| record(builder, chunks[i]))
+    }
+
+    /// Shares the core's descriptor set allocator and cache, e.g. with a `ComputeContext`.
+    pub fn descriptor_sets(&self) -> &DescriptorSets {
+        &self.descriptor_sets
+    }
+
+    pub fn descriptor_sets_mut(&mut self) -> &mut DescriptorSets {
+        &mut self.descriptor_sets
+    }
+
+    /// Memory of the core's MSAA targets, virtual backbuffer and buffers, including the uniform
+    /// ring's arenas under `MemoryCategory::Uniforms`. Swapchain images belong to the driver and
+    /// aren't counted.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_tracker.usage()
+    }
+
+    /// What `memory_usage` counts, e.g. for a `LeakCheck`.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory_tracker
+    }
+
+    /// How much VRAM and host memory the process uses and may use, as the driver sees it.
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.vapi.memory_budget()
+    }
+
+    /// Where per-frame and per-object uniform data goes, e.g. the cameras of dynamic passes.
+    pub fn uniforms(&self) -> &UniformRing {
+        &self.uniforms
+    }
+
+    /// The image frames for swapchain image `index` are rendered into: the virtual backbuffer
+    /// while one is set, the swapchain image otherwise.
+    pub fn render_target(&self, index: u32) -> Arc<Image> {
+        match &self.virtual_image {
+            Some(image) => image.clone(),
+            None => self.image(index),
+        }
+    }
+
+    /// Scales the virtual backbuffer into swapchain image `index`, for frames not drawn by
+    /// `record_frame`, which does this itself. Does nothing without a virtual backbuffer.
+    pub fn record_present(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        index: u32,
+    ) {
+        if let (Some(virtual_backbuffer), Some(image)) =
+            (&self.virtual_backbuffer, &self.virtual_image)
+        {
+            virtual_backbuffer.record_present(builder, image.clone(), self.image(index));
+        }
+    }
+
+    /// Remakes everything that depends on the render target's size.
+    fn rebuild(&mut self) {
+        self.framebuffers = match &self.virtual_image {
+            Some(image) => {
+                let framebuffer = RendererCore::get_framebuffers(
+                    std::slice::from_ref(image),
+                    &self.render_pass,
+                    &self.memory_allocator,
+                    &self.memory_tracker,
+                )
+                .remove(0);
+                vec![framebuffer; self.images.len()]
+            }
+            None => RendererCore::get_framebuffers(
+                &self.images,
+                &self.render_pass,
+                &self.memory_allocator,
+                &self.memory_tracker,
+            ),
+        };
+        let dimensions = match &self.virtual_backbuffer {
+            Some(virtual_backbuffer) => virtual_backbuffer.extent,
+            None => self.swapchain.image_extent(),
+        };
+        self.viewport.extent = [dimensions[0] as f32, dimensions[1] as f32];
+        let (vs, fs) = RendererCore::get_shaders(self.vapi.device.clone());
+        let whole_frame = [ScreenView::new([0.0, 0.0], [1.0, 1.0])];
+        let views = match self.split_views.as_slice() {
+            [] => whole_frame.as_slice(),
+            split_views => split_views,
+        };
+        // Viewports are baked into pipelines, so every view gets its own.
+        let passes: Vec<_> = views
+            .iter()
+            .map(|view| {
+                let viewport = view.viewport(dimensions);
+                let pipeline = RendererCore::get_pipeline(
+                    self.vapi.device.clone(),
+                    vs.entry_point("main").unwrap(),
+                    fs.entry_point("main").unwrap(),
+                    self.render_pass.clone(),
+                    viewport.clone(),
+                );
+                let mvp_buffer = Arc::new(RendererCore::get_mvp_buffer(
+                    &self.uniforms,
+                    viewport,
+                    view.view,
+                    view.projection,
+                ));
+                let mvp_set = RendererCore::get_mvp_descriptor_set(
+                    self.descriptor_sets.allocator(),
+                    pipeline.clone(),
+                    mvp_buffer,
+                );
+                let mut pass = DrawCommands::new();
+                pass.push(DrawCommand::new(
+                    pipeline,
+                    vec![mvp_set],
+                    self.vertex_buffer.deref().clone(),
+                ));
+                pass
+            })
+            .collect();
+        self.pipeline = passes[0].draws()[0].pipeline.clone();
+        self.passes = passes;
+    }
+
+    /// Records the frame for swapchain image `index`: the core's views and `set_draws` draws,
+    /// then `draws`, e.g. this frame's draw list chunks from `record_draw_list_parallel`, then
+    /// the overlays, and the blit to the swapchain image when a virtual backbuffer is set.
+    /// Recorded anew every frame, so the scene can change from frame to frame. Also returns
+    /// what was recorded of the views and `set_draws` draws; `draws` and the overlays are
+    /// recorded elsewhere and aren't counted.
+    pub fn record_frame(
+        &self,
+        index: u32,
+        draws: &[Arc<dyn SecondaryCommandBufferAbstract>],
+    ) -> (Arc<PrimaryAutoCommandBuffer>, FrameStats) {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.as_ref(),
+            self.vapi.queues.graphics().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        // A subpass is recorded either inline or from secondary command buffers, so as soon as
+        // there are secondaries the views come from theirs too.
+        let inline = draws.is_empty() && self.overlays.is_empty();
+        // With MSAA, the second attachment is the resolved frame, which isn't cleared.
+        let clear_values = self
+            .render_pass
+            .attachments()
+            .iter()
+            .map(|attachment| {
+                (attachment.load_op == AttachmentLoadOp::Clear).then_some(self.clear_color.into())
+            })
+            .collect();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values,
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffers[index as usize].clone())
+                },
+                SubpassBeginInfo {
+                    contents: if inline {
+                        SubpassContents::Inline
+                    } else {
+                        SubpassContents::SecondaryCommandBuffers
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut stats = FrameStats::default();
+        if inline {
+            for pass in self.passes.iter().chain([&self.draws]) {
+                stats += pass.record(&mut builder);
+            }
+        } else {
+            let mut views = RendererCore::get_secondary_builder(
+                &self.command_buffer_allocator,
+                self.vapi.queues.graphics(),
+                &self.render_pass,
+                CommandBufferUsage::OneTimeSubmit,
+            );
+            for pass in self.passes.iter().chain([&self.draws]) {
+                stats += pass.record(&mut views);
+            }
+            builder.execute_commands(views.build().unwrap()).unwrap();
+            let overlays = self.overlays.iter().map(|overlay| overlay.clone() as _);
+            for command_buffer in draws.iter().cloned().chain(overlays) {
+                builder.execute_commands(command_buffer).unwrap();
+            }
+        }
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+        self.record_present(&mut builder, index);
+        (builder.build().unwrap(), stats)
+    }
+
+    pub fn image(&self, index: u32) -> Arc<Image> {
+        self.images[index as usize].clone()
     }
 
     fn create_swapchain(
         vapi: Arc<VulkanConnection>,
+        surface: Arc<Surface>,
         dimensions: [u32; 2],
+        vsync: bool,
     ) -> (Arc<Swapchain>, Vec<Arc<Image>>) {
-        let composite_alpha = vapi
-            .surface_caps
+        let surface_caps = vapi
+            .physical_device
+            .surface_capabilities(&surface, Default::default())
+            .expect("failed to get surface capabilities");
+        let composite_alpha = surface_caps
             .supported_composite_alpha
             .into_iter()
             .next()
@@ -207,19 +825,49 @@ impl RendererCore {
 
         let image_format = vapi
             .physical_device
-            .surface_formats(&vapi.surface, Default::default())
+            .surface_formats(&surface, Default::default())
             .unwrap()[0]
             .0;
 
+        // FIFO is always there. Without vsync, mailbox doesn't tear; immediate may.
+        let present_mode = if vsync {
+            PresentMode::Fifo
+        } else {
+            let present_modes: Vec<_> = vapi
+                .physical_device
+                .surface_present_modes(&surface, Default::default())
+                .unwrap()
+                .collect();
+            [PresentMode::Mailbox, PresentMode::Immediate]
+                .into_iter()
+                .find(|mode| present_modes.contains(mode))
+                .unwrap_or(PresentMode::Fifo)
+        };
+
+        // Images rendered on one family and presented on another are shared by both, instead
+        // of transferring their ownership every frame.
+        let graphics_family = vapi.queues.graphics().queue_family_index();
+        let present_family = vapi.queues.present().queue_family_index();
+        let image_sharing = if graphics_family == present_family {
+            Sharing::Exclusive
+        } else {
+            Sharing::Concurrent([graphics_family, present_family].into_iter().collect())
+        };
+
         let (swapchain, images) = Swapchain::new(
             vapi.device.clone(),
-            vapi.surface.clone(),
+            surface,
             SwapchainCreateInfo {
-                min_image_count: vapi.surface_caps.min_image_count + 1, // How many buffers to use in the swapchain
+                min_image_count: surface_caps.min_image_count + 1, // How many buffers to use in the swapchain
                 image_format,
                 image_extent: dimensions,
-                image_usage: ImageUsage::COLOR_ATTACHMENT, // What the images are going to be used for
+                // What the images are going to be used for. Transfers let the ray-traced output
+                // and the virtual backbuffer be blitted in.
+                image_usage: ImageUsage::COLOR_ATTACHMENT
+                    | (surface_caps.supported_usage_flags & ImageUsage::TRANSFER_DST),
+                image_sharing,
                 composite_alpha,
+                present_mode,
                 ..Default::default()
             },
         )
@@ -248,38 +896,22 @@ impl RendererCore {
     }
 
     fn get_mvp_buffer(
-        memory_allocator: Arc<
-            vulkano::memory::allocator::GenericMemoryAllocator<
-                vulkano::memory::allocator::FreeListAllocator,
-            >,
-        >,
+        uniforms: &UniformRing,
         viewport: Viewport,
+        view: Matrix4<f32>,
+        projection: Option<Matrix4<f32>>,
     ) -> Subbuffer<MVP> {
         let model: Matrix4<f32> = Matrix4::identity();
-        let view: Matrix4<f32> = Matrix4::identity();
-        let projection =
+        let projection = projection.unwrap_or_else(|| {
             Orthographic3::new(0.0, viewport.extent[1], 0.0, viewport.extent[1], -1.0, 1.0)
-                .to_homogeneous();
+                .to_homogeneous()
+        });
         let mvp = MVP {
             model: model.into(),
             view: view.into(),
             proj: projection.into(),
         };
-        let uniform_buffer = Buffer::from_data(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            mvp,
-        )
-        .unwrap();
-        uniform_buffer
+        uniforms.write(mvp)
     }
 
     fn get_triangle_vertex_buffer(
@@ -307,69 +939,33 @@ impl RendererCore {
         vertex_buffer
     }
 
-    fn get_command_buffers(
+    fn get_secondary_builder(
         command_buffer_allocator: &StandardCommandBufferAllocator,
         queue: &Arc<Queue>,
-        pipeline: &Arc<GraphicsPipeline>,
-        framebuffers: &Vec<Arc<Framebuffer>>,
-        vertex_buffer: &Subbuffer<[MyVertex]>,
-        descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
-    ) -> Vec<Arc<PrimaryAutoCommandBuffer>> {
-        framebuffers
-            .iter()
-            .map(|framebuffer| {
-                let mut builder = AutoCommandBufferBuilder::primary(
-                    command_buffer_allocator,
-                    queue.queue_family_index(),
-                    // Don't forget to write the correct buffer usage.
-                    CommandBufferUsage::MultipleSubmit,
-                )
-                .unwrap();
-
-                builder
-                    .begin_render_pass(
-                        RenderPassBeginInfo {
-                            clear_values: vec![Some([0.1, 0.1, 0.1, 1.0].into())],
-                            ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
-                        },
-                        SubpassBeginInfo {
-                            contents: SubpassContents::Inline,
-                            ..Default::default()
-                        },
-                    )
-                    .unwrap()
-                    .bind_pipeline_graphics(pipeline.clone())
-                    .unwrap()
-                    .bind_descriptor_sets(
-                        pipeline.bind_point(),
-                        pipeline.layout().clone(),
-                        0,
-                        descriptor_sets.clone(),
-                    )
-                    .unwrap()
-                    .bind_vertex_buffers(0, vertex_buffer.clone())
-                    .unwrap()
-                    .draw(vertex_buffer.len() as u32, 1, 0, 0)
-                    .unwrap()
-                    .end_render_pass(SubpassEndInfo::default())
-                    .unwrap();
-
-                builder.build().unwrap()
-            })
-            .collect()
+        render_pass: &Arc<RenderPass>,
+        usage: CommandBufferUsage,
+    ) -> AutoCommandBufferBuilder<SecondaryAutoCommandBuffer> {
+        AutoCommandBufferBuilder::secondary(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            usage,
+            CommandBufferInheritanceInfo {
+                render_pass: Some(Subpass::from(render_pass.clone(), 0).unwrap().into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
     }
 
     fn get_mvp_descriptor_set(
-        device: Arc<Device>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
         pipeline: Arc<GraphicsPipeline>,
         buffer: Arc<Subbuffer<MVP>>,
     ) -> Arc<PersistentDescriptorSet> {
         let descriptor_set_layout = pipeline.layout().set_layouts().get(0).unwrap().clone();
-        let descriptor_set_allocator =
-            StandardDescriptorSetAllocator::new(device.clone(), Default::default());
         let descriptor_writes = [WriteDescriptorSet::buffer(0, buffer.deref().clone())];
         let descriptor_set = PersistentDescriptorSet::new(
-            &descriptor_set_allocator,
+            descriptor_set_allocator,
             descriptor_set_layout,
             descriptor_writes,
             [],
@@ -385,14 +981,87 @@ impl RendererCore {
         render_pass: Arc<RenderPass>,
         viewport: Viewport,
     ) -> Arc<GraphicsPipeline> {
-        let vertex_input_state = MyVertex::per_vertex()
+        RendererCore::get_vertex_pipeline::<MyVertex>(
+            device,
+            vs_entry_point,
+            fs_entry_point,
+            render_pass,
+            viewport,
+            BlendMode::Opaque,
+            false,
+        )
+    }
+
+    /// Pipeline for subpass 0 of `render_pass` reading vertex buffers of `V`, e.g. a vertex
+    /// type of your own deriving `BufferContents` and `Vertex`, drawn with a `vs` whose inputs
+    /// are named like `V`'s fields. `depth_test` only applies where the subpass has a depth
+    /// attachment.
+    pub fn get_vertex_pipeline<V: Vertex>(
+        device: Arc<Device>,
+        vs_entry_point: EntryPoint,
+        fs_entry_point: EntryPoint,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        blend: BlendMode,
+        depth_test: bool,
+    ) -> Arc<GraphicsPipeline> {
+        RendererCore::build_pipeline_with(
+            device,
+            vs_entry_point,
+            fs_entry_point,
+            V::per_vertex(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                blend,
+                depth_test,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Pipeline for subpass 0 of `render_pass`, shared by every vertex layout the core draws.
+    fn build_pipeline(
+        device: Arc<Device>,
+        vs_entry_point: EntryPoint,
+        fs_entry_point: EntryPoint,
+        vertex_buffer_description: VertexBufferDescription,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        RendererCore::build_pipeline_with(
+            device,
+            vs_entry_point,
+            fs_entry_point,
+            vertex_buffer_description,
+            render_pass,
+            viewport,
+            PipelineOptions::default(),
+        )
+    }
+
+    /// `build_pipeline` with other `options`, e.g. blending.
+    fn build_pipeline_with(
+        device: Arc<Device>,
+        vs_entry_point: EntryPoint,
+        fs_entry_point: EntryPoint,
+        vertex_buffer_description: VertexBufferDescription,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        options: PipelineOptions,
+    ) -> Arc<GraphicsPipeline> {
+        let vertex_input_state = vertex_buffer_description
             .definition(&vs_entry_point.info().input_interface)
             .unwrap();
 
-        let stages = [
-            PipelineShaderStageCreateInfo::new(vs_entry_point),
-            PipelineShaderStageCreateInfo::new(fs_entry_point),
-        ];
+        let mut stages = vec![PipelineShaderStageCreateInfo::new(vs_entry_point)];
+        if let Some(tessellation) = &options.tessellation {
+            stages.extend([
+                PipelineShaderStageCreateInfo::new(tessellation.control.clone()),
+                PipelineShaderStageCreateInfo::new(tessellation.evaluation.clone()),
+            ]);
+        }
+        stages.push(PipelineShaderStageCreateInfo::new(fs_entry_point));
 
         let layout = PipelineLayout::new(
             device.clone(),
@@ -403,6 +1072,13 @@ impl RendererCore {
         .unwrap();
 
         let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let input_assembly_state = match options.tessellation {
+            Some(_) => InputAssemblyState {
+                topology: PrimitiveTopology::PatchList,
+                ..Default::default()
+            },
+            None => InputAssemblyState::default(),
+        };
 
         GraphicsPipeline::new(
             device.clone(),
@@ -410,18 +1086,39 @@ impl RendererCore {
             GraphicsPipelineCreateInfo {
                 stages: stages.into_iter().collect(),
                 vertex_input_state: Some(vertex_input_state),
-                input_assembly_state: Some(InputAssemblyState::default()),
+                input_assembly_state: Some(input_assembly_state),
+                tessellation_state: options.tessellation.as_ref().map(|tessellation| {
+                    TessellationState {
+                        patch_control_points: tessellation.patch_control_points,
+                        ..Default::default()
+                    }
+                }),
                 viewport_state: Some(ViewportState {
                     viewports: [viewport].into_iter().collect(),
                     ..Default::default()
                 }),
                 rasterization_state: Some(RasterizationState::default()),
-                multisample_state: Some(MultisampleState::default()),
-                color_blend_state: Some(ColorBlendState::with_attachment_states(
-                    subpass.num_color_attachments(),
-                    ColorBlendAttachmentState::default(),
-                )),
+                multisample_state: Some(MultisampleState {
+                    rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                    ..Default::default()
+                }),
+                depth_stencil_state: (options.depth_test
+                    && subpass.subpass_desc().depth_stencil_attachment.is_some())
+                .then(|| DepthStencilState {
+                    depth: Some(options.blend.depth_state()),
+                    ..Default::default()
+                }),
+                color_blend_state: Some(
+                    options
+                        .blend
+                        .color_blend_state(subpass.num_color_attachments()),
+                ),
                 subpass: Some(subpass.into()),
+                dynamic_state: if options.dynamic_scissor {
+                    [DynamicState::Scissor].into_iter().collect()
+                } else {
+                    Default::default()
+                },
                 ..GraphicsPipelineCreateInfo::layout(layout)
             },
         )
@@ -435,18 +1132,52 @@ impl RendererCore {
         )
     }
 
+    /// Framebuffers drawing into `images`, through a multisampled image of their own each
+    /// when `render_pass` resolves.
     fn get_framebuffers(
         images: &[Arc<Image>],
         render_pass: &Arc<RenderPass>,
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
     ) -> Vec<Arc<Framebuffer>> {
+        let samples = render_pass.attachments()[0].samples;
+        // Only kept between passes when the pass loads it.
+        let usage = if render_pass.attachments()[0].load_op == AttachmentLoadOp::Load {
+            ImageUsage::COLOR_ATTACHMENT
+        } else {
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT
+        };
         images
             .iter()
             .map(|image| {
                 let view = ImageView::new_default(image.clone()).unwrap();
+                let attachments = if samples == SampleCount::Sample1 {
+                    vec![view]
+                } else {
+                    let multisampled = Image::new(
+                        memory_allocator.clone(),
+                        ImageCreateInfo {
+                            image_type: ImageType::Dim2d,
+                            format: image.format(),
+                            extent: image.extent(),
+                            samples,
+                            usage,
+                            ..Default::default()
+                        },
+                        AllocationCreateInfo::default(),
+                    )
+                    .unwrap();
+                    memory_tracker.track_image(
+                        MemoryCategory::RenderTargets,
+                        "multisampled frame",
+                        &multisampled,
+                    );
+                    vec![ImageView::new_default(multisampled).unwrap(), view]
+                };
                 Framebuffer::new(
                     render_pass.clone(),
                     FramebufferCreateInfo {
-                        attachments: vec![view],
+                        attachments,
                         ..Default::default()
                     },
                 )
@@ -455,21 +1186,67 @@ impl RendererCore {
             .collect::<Vec<_>>()
     }
 
-    fn get_render_pass(device: Arc<Device>, swapchain: Arc<Swapchain>) -> Arc<RenderPass> {
-        vulkano::single_pass_renderpass!(
-            device,
-            attachments: {
-                color: {
-                    // Set the format the same as the swapchain.
-                    format: swapchain.image_format(),
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: Store,
+    fn get_render_pass(
+        device: Arc<Device>,
+        format: Format,
+        samples: SampleCount,
+        ops: AttachmentOps,
+    ) -> Arc<RenderPass> {
+        let color = |attachment| AttachmentReference {
+            attachment,
+            layout: ImageLayout::ColorAttachmentOptimal,
+            ..Default::default()
+        };
+        let frame = AttachmentDescription {
+            format,
+            samples: SampleCount::Sample1,
+            load_op: ops.load,
+            store_op: ops.store,
+            initial_layout: ops.initial_layout(ImageLayout::ColorAttachmentOptimal),
+            final_layout: ImageLayout::ColorAttachmentOptimal,
+            ..Default::default()
+        };
+        let (attachments, subpass) = if samples == SampleCount::Sample1 {
+            (
+                vec![frame],
+                SubpassDescription {
+                    color_attachments: vec![Some(color(0))],
+                    ..Default::default()
                 },
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {},
+            )
+        } else {
+            // Drawn multisampled, then resolved into the frame at the end of the pass. Loading
+            // needs the multisampled image kept too.
+            let loads = ops.load == AttachmentLoadOp::Load;
+            let multisampled = AttachmentDescription {
+                samples,
+                store_op: if loads {
+                    AttachmentStoreOp::Store
+                } else {
+                    AttachmentStoreOp::DontCare
+                },
+                ..frame
+            };
+            let resolved = AttachmentDescription {
+                load_op: AttachmentLoadOp::DontCare,
+                initial_layout: ImageLayout::Undefined,
+                ..frame
+            };
+            (
+                vec![multisampled, resolved],
+                SubpassDescription {
+                    color_attachments: vec![Some(color(0))],
+                    color_resolve_attachments: vec![Some(color(1))],
+                    ..Default::default()
+                },
+            )
+        };
+        RenderPass::new(
+            device,
+            RenderPassCreateInfo {
+                attachments,
+                subpasses: vec![subpass],
+                ..Default::default()
             },
         )
         .unwrap()
@@ -0,0 +1,340 @@
+use std::sync::Arc;
+
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use super::buffer_structs::Vertex2d;
+use super::canvas::Canvas;
+use super::descriptor_sets::DescriptorSets;
+use super::nine_slice::NineSlice;
+use super::nine_slice::NineSliceRenderer;
+use super::nine_slice::Panel;
+use super::nine_slice::PANEL_VERTICES;
+use super::text::BitmapFont;
+use super::text::TextRenderer;
+use super::text::TextRun;
+use super::uniform_ring::UniformRing;
+
+/// One textured rectangle, e.g. a sprite or an icon, in the same units as the projection, with
+/// y down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad2D {
+    /// Top-left corner.
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    /// Texture coordinates of the top-left and bottom-right corners, e.g. a frame of a sprite
+    /// sheet. Never negative: the shader takes those for untextured shapes.
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+}
+impl Default for Quad2D {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            size: [0.0, 0.0],
+            uv_min: [0.0, 0.0],
+            uv_max: [1.0, 1.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// What the last `Batch2D::prepare` made of the frame, e.g. for a debug overlay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Batch2DStats {
+    /// Quads, panels, text runs and shapes submitted.
+    pub items: u32,
+    pub vertices: u32,
+    pub textures: u32,
+    pub draws: u32,
+    /// Draws beyond one per texture, where layers interleave textures. Atlasing the textures
+    /// used together, or giving them fewer layers, brings it down.
+    pub batches_broken: u32,
+}
+
+/// Something submitted, as a range of `Batch2D::submitted`.
+struct Item {
+    layer: i32,
+    /// Index into `Batch2D::textures`, `None` for shapes, which draw with any texture.
+    texture: Option<u32>,
+    start: u32,
+    count: u32,
+}
+
+/// One draw, as a range of `Batch2D::vertices`.
+struct Batch {
+    texture: Option<u32>,
+    start: u32,
+    count: u32,
+}
+
+/// Merges sprites, panels, text and `Canvas` shapes into as few draws as it can, for 2D scenes
+/// and UIs mixing them.
+///
+/// Submit everything every frame, then `prepare`, then `record_draw`, then `clear`. Lower
+/// layers are drawn first. Within a layer, what shares a texture is drawn in submission order,
+/// but different textures may be drawn in any order, so give overlapping things of different
+/// textures different layers. Shapes don't break batches: they join whichever texture is drawn
+/// around them.
+pub struct Batch2D {
+    /// Bound for batches of nothing but shapes.
+    blank: Arc<ImageView>,
+    textures: Vec<Arc<ImageView>>,
+    items: Vec<Item>,
+    submitted: Vec<Vertex2d>,
+    /// `submitted` in drawing order.
+    vertices: Vec<Vertex2d>,
+    batches: Vec<Batch>,
+    stats: Batch2DStats,
+}
+impl Batch2D {
+    /// `blank` is bound when there is nothing but shapes to draw; any texture will do, since
+    /// shapes don't sample it.
+    pub fn new(blank: Arc<ImageView>) -> Self {
+        Self {
+            blank,
+            textures: Vec::new(),
+            items: Vec::new(),
+            submitted: Vec::new(),
+            vertices: Vec::new(),
+            batches: Vec::new(),
+            stats: Batch2DStats::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.items.clear();
+        self.submitted.clear();
+        self.vertices.clear();
+        self.batches.clear();
+    }
+
+    pub fn quad(&mut self, layer: i32, texture: &Arc<ImageView>, quad: &Quad2D) {
+        let [left, top] = quad.offset;
+        let [right, bottom] = [left + quad.size[0], top + quad.size[1]];
+        let corners = [
+            ([left, top], quad.uv_min),
+            ([right, top], [quad.uv_max[0], quad.uv_min[1]]),
+            ([left, bottom], [quad.uv_min[0], quad.uv_max[1]]),
+            ([right, bottom], quad.uv_max),
+        ];
+        let vertices = [0, 1, 2, 2, 1, 3].map(|corner| {
+            let (position, uv) = corners[corner];
+            Vertex2d {
+                position,
+                uv,
+                color: quad.color,
+            }
+        });
+        let texture = self.texture_index(texture);
+        self.push(layer, Some(texture), vertices);
+    }
+
+    /// A nine-slice panel, like `NineSliceRenderer` draws.
+    pub fn panel(
+        &mut self,
+        layer: i32,
+        texture: &Arc<ImageView>,
+        slice: &NineSlice,
+        panel: &Panel,
+    ) {
+        let mut vertices = [Vertex2d {
+            position: [0.0, 0.0],
+            uv: [0.0, 0.0],
+            color: [0.0; 4],
+        }; PANEL_VERTICES as usize];
+        NineSliceRenderer::panel_vertices(slice, panel, &mut vertices);
+        let texture = self.texture_index(texture);
+        self.push(layer, Some(texture), vertices);
+    }
+
+    /// Text like `TextRenderer` draws.
+    pub fn text(
+        &mut self,
+        layer: i32,
+        font_texture: &Arc<ImageView>,
+        font: &BitmapFont,
+        run: &TextRun,
+    ) {
+        let texture = self.texture_index(font_texture);
+        self.push(layer, Some(texture), font.glyph_vertices(run).flatten());
+    }
+
+    /// The shapes drawn on `canvas` since its last `clear`.
+    pub fn shapes(&mut self, layer: i32, canvas: &Canvas) {
+        // Negative texture coordinates tell the shader not to sample.
+        let vertices = canvas.vertices().iter().map(|vertex| Vertex2d {
+            uv: [-1.0, -1.0],
+            ..*vertex
+        });
+        self.push(layer, None, vertices);
+    }
+
+    fn texture_index(&mut self, texture: &Arc<ImageView>) -> u32 {
+        match self
+            .textures
+            .iter()
+            .position(|known| Arc::ptr_eq(known, texture))
+        {
+            Some(index) => index as u32,
+            None => {
+                self.textures.push(texture.clone());
+                self.textures.len() as u32 - 1
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        layer: i32,
+        texture: Option<u32>,
+        vertices: impl IntoIterator<Item = Vertex2d>,
+    ) {
+        let start = self.submitted.len() as u32;
+        self.submitted.extend(vertices);
+        let count = self.submitted.len() as u32 - start;
+        if count > 0 {
+            self.items.push(Item {
+                layer,
+                texture,
+                start,
+                count,
+            });
+        }
+    }
+
+    /// Sorts what was submitted into draws.
+    pub fn prepare(&mut self) -> Batch2DStats {
+        self.vertices.clear();
+        self.batches.clear();
+        self.items
+            .sort_by_key(|item| (item.layer, item.texture.is_none(), item.texture));
+
+        let mut first = 0;
+        while first < self.items.len() {
+            let layer = self.items[first].layer;
+            let end = first
+                + self.items[first..]
+                    .iter()
+                    .take_while(|item| item.layer == layer)
+                    .count();
+            let group = &mut self.items[first..end];
+            // Start the layer with the texture the last one ended with, saving a draw.
+            if let Some(current) = self.batches.last().and_then(|batch| batch.texture) {
+                group.sort_by_key(|item| {
+                    (
+                        item.texture != Some(current),
+                        item.texture.is_none(),
+                        item.texture,
+                    )
+                });
+            }
+            for item in group.iter() {
+                match self.batches.last_mut() {
+                    Some(batch)
+                        if item.texture.is_none()
+                            || batch.texture.is_none()
+                            || batch.texture == item.texture =>
+                    {
+                        batch.texture = batch.texture.or(item.texture);
+                        batch.count += item.count;
+                    }
+                    _ => self.batches.push(Batch {
+                        texture: item.texture,
+                        start: self.vertices.len() as u32,
+                        count: item.count,
+                    }),
+                }
+                let range = item.start as usize..(item.start + item.count) as usize;
+                self.vertices.extend_from_slice(&self.submitted[range]);
+            }
+            first = end;
+        }
+
+        let draws = self.batches.len() as u32;
+        let textures = self.textures.len() as u32;
+        self.stats = Batch2DStats {
+            items: self.items.len() as u32,
+            vertices: self.vertices.len() as u32,
+            textures,
+            draws,
+            batches_broken: draws.saturating_sub(textures.max(1)),
+        };
+        self.stats
+    }
+
+    /// Of the last `prepare`.
+    pub fn stats(&self) -> Batch2DStats {
+        self.stats
+    }
+
+    /// Alpha-blended, like `TextRenderer`'s.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        TextRenderer::get_pipeline(device, render_pass, viewport)
+    }
+
+    /// Streams the prepared vertices through `uniforms` and draws them, binding `mvp_buffer`
+    /// at binding 0 and each batch's texture at binding 1.
+    pub fn record_draw<L, T: BufferContents + ?Sized>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        descriptor_sets: &mut DescriptorSets,
+        uniforms: &UniformRing,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        sampler: Arc<Sampler>,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_vertex_buffers(0, uniforms.write_slice(&self.vertices))
+            .unwrap();
+        // One set per texture, however many batches it's split into.
+        let mut sets: Vec<Option<Arc<PersistentDescriptorSet>>> =
+            vec![None; self.textures.len() + 1];
+        for batch in &self.batches {
+            let slot = batch
+                .texture
+                .map_or(self.textures.len(), |texture| texture as usize);
+            let set = sets[slot].get_or_insert_with(|| {
+                let texture = self.textures.get(slot).unwrap_or(&self.blank);
+                descriptor_sets.transient(
+                    &pipeline.layout().set_layouts()[0],
+                    [
+                        WriteDescriptorSet::buffer(0, mvp_buffer.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, texture.clone(), sampler.clone()),
+                    ],
+                )
+            });
+            builder
+                .bind_descriptor_sets(
+                    pipeline.bind_point(),
+                    pipeline.layout().clone(),
+                    0,
+                    set.clone(),
+                )
+                .unwrap()
+                .draw(batch.count, 1, batch.start, 0)
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use vulkano::buffer::BufferContents;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorBindingFlags;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::layout::DescriptorSetLayoutBinding;
+use vulkano::descriptor_set::layout::DescriptorSetLayoutCreateInfo;
+use vulkano::descriptor_set::layout::DescriptorType;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::shader::ShaderStages;
+
+use super::descriptor_sets::DescriptorSets;
+use super::sampler::MipSampling;
+use super::sampler::SamplerDesc;
+
+/// Texture index of materials without that texture.
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// Set the bindless texture array is bound to, as `layout(set = 1, binding = 0) uniform
+/// sampler2D textures[]` in shaders; set 0 stays free for per-pass data.
+pub const BINDLESS_SET: u32 = 1;
+
+/// A `Material` as shaders read it, with textures as indices into the bindless array, e.g. in a
+/// storage buffer indexed by draw or pushed as constants.
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct BindlessMaterial {
+    pub base_color: [f32; 4],
+    /// `NO_TEXTURE` when the material has none.
+    pub base_color_texture: u32,
+    pub lightmap: u32,
+}
+
+/// Every texture in one variable-count descriptor array, so draws pick textures by index
+/// instead of binding a descriptor set each. Indices stay valid until their texture is
+/// removed and are then reused. Needs `VulkanConnection::descriptor_indexing_enabled`.
+///
+/// Each entry is a combined image sampler, so textures can have their own `SamplerDesc`; the
+/// rest use the default one.
+pub struct BindlessTextures {
+    device: Arc<Device>,
+    layout: Arc<DescriptorSetLayout>,
+    default_sampler: SamplerDesc,
+    /// Every sampler created so far. Textures share few distinct ones, so a list will do.
+    samplers: Vec<(SamplerDesc, Arc<Sampler>)>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    /// The view of each index and its sampler, `None` for the default one.
+    views: Vec<Option<(Arc<ImageView>, Option<SamplerDesc>)>>,
+    free: Vec<u32>,
+    /// Rebuilt by `descriptor_set` after textures change.
+    descriptor_set: Option<Arc<PersistentDescriptorSet>>,
+}
+impl BindlessTextures {
+    /// Upper bound on the array size; devices with lower limits get their limit.
+    pub const MAX_TEXTURES: u32 = 16 * 1024;
+
+    /// Allocates its set from `descriptor_sets`' allocator; variable counts don't fit its cache.
+    pub fn new(device: Arc<Device>, descriptor_sets: &DescriptorSets) -> Self {
+        let properties = device.physical_device().properties();
+        let capacity = Self::MAX_TEXTURES
+            .min(properties.max_per_stage_descriptor_sampled_images)
+            .min(properties.max_descriptor_set_sampled_images);
+        let layout = DescriptorSetLayout::new(
+            device.clone(),
+            DescriptorSetLayoutCreateInfo {
+                bindings: BTreeMap::from([(
+                    0,
+                    DescriptorSetLayoutBinding {
+                        binding_flags: DescriptorBindingFlags::PARTIALLY_BOUND
+                            | DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+                        descriptor_count: capacity,
+                        stages: ShaderStages::all_graphics() | ShaderStages::COMPUTE,
+                        ..DescriptorSetLayoutBinding::descriptor_type(
+                            DescriptorType::CombinedImageSampler,
+                        )
+                    },
+                )]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Self {
+            layout,
+            default_sampler: SamplerDesc::default(),
+            samplers: Vec::new(),
+            descriptor_set_allocator: descriptor_sets.allocator().clone(),
+            device,
+            views: Vec::new(),
+            free: Vec::new(),
+            descriptor_set: None,
+        }
+    }
+
+    pub fn layout(&self) -> Arc<DescriptorSetLayout> {
+        self.layout.clone()
+    }
+
+    /// Entries the array can hold.
+    pub fn capacity(&self) -> u32 {
+        self.layout.bindings()[&0].descriptor_count
+    }
+
+    pub fn len(&self) -> usize {
+        self.views.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Samples the textures without a sampler of their own with `sampling` from the next
+    /// `descriptor_set` on.
+    pub fn set_mip_sampling(&mut self, sampling: MipSampling) {
+        self.set_default_sampler(SamplerDesc {
+            mip: sampling,
+            ..self.default_sampler
+        });
+    }
+
+    pub fn default_sampler(&self) -> SamplerDesc {
+        self.default_sampler
+    }
+
+    /// Samples the textures without a sampler of their own with `desc` from the next
+    /// `descriptor_set` on.
+    pub fn set_default_sampler(&mut self, desc: SamplerDesc) {
+        self.default_sampler = desc;
+        self.descriptor_set = None;
+    }
+
+    /// Samples the texture at `index` with `desc`, or the default sampler for `None`. Returns
+    /// whether `index` holds a texture.
+    pub fn set_sampler(&mut self, index: u32, desc: Option<SamplerDesc>) -> bool {
+        let Some(Some((_, sampler))) = self.views.get_mut(index as usize) else {
+            return false;
+        };
+        *sampler = desc;
+        self.descriptor_set = None;
+        true
+    }
+
+    /// Adds `view` with the default sampler and returns its index, or `None` when the array
+    /// is full.
+    pub fn insert(&mut self, view: Arc<ImageView>) -> Option<u32> {
+        self.insert_with_sampler(view, None)
+    }
+
+    /// `insert`, sampled with `desc` instead of the default sampler where given.
+    pub fn insert_with_sampler(
+        &mut self,
+        view: Arc<ImageView>,
+        desc: Option<SamplerDesc>,
+    ) -> Option<u32> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if (self.views.len() as u32) < self.capacity() => {
+                self.views.push(None);
+                self.views.len() as u32 - 1
+            }
+            None => return None,
+        };
+        self.views[index as usize] = Some((view, desc));
+        self.descriptor_set = None;
+        Some(index)
+    }
+
+    /// Returns whether `index` held a texture.
+    pub fn remove(&mut self, index: u32) -> bool {
+        let Some(slot) = self.views.get_mut(index as usize) else {
+            return false;
+        };
+        if slot.take().is_none() {
+            return false;
+        }
+        self.free.push(index);
+        self.descriptor_set = None;
+        true
+    }
+
+    /// The array as set `BINDLESS_SET`, rebuilt after textures were added or removed. Sets
+    /// bound by earlier frames keep their textures alive until those frames finish.
+    pub fn descriptor_set(&mut self) -> Arc<PersistentDescriptorSet> {
+        if let Some(descriptor_set) = &self.descriptor_set {
+            return descriptor_set.clone();
+        }
+        let entries: Vec<_> = self
+            .views
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let (view, desc) = entry.clone()?;
+                Some((index as u32, view, desc.unwrap_or(self.default_sampler)))
+            })
+            .collect();
+        let writes: Vec<_> = entries
+            .into_iter()
+            .map(|(index, view, desc)| {
+                WriteDescriptorSet::image_view_sampler_array(0, index, [(view, self.sampler(desc))])
+            })
+            .collect();
+        let descriptor_set = PersistentDescriptorSet::new_variable(
+            &self.descriptor_set_allocator,
+            self.layout.clone(),
+            self.views.len().max(1) as u32,
+            writes,
+            [],
+        )
+        .unwrap();
+        self.descriptor_set = Some(descriptor_set.clone());
+        descriptor_set
+    }
+
+    /// The sampler of `desc`, created on first use.
+    pub fn sampler(&mut self, desc: SamplerDesc) -> Arc<Sampler> {
+        if let Some((_, sampler)) = self.samplers.iter().find(|(cached, _)| *cached == desc) {
+            return sampler.clone();
+        }
+        let sampler = desc.create_sampler(self.device.clone());
+        self.samplers.push((desc, sampler.clone()));
+        sampler
+    }
+
+    /// Layout for a pipeline of `stages` with the bindless array at `BINDLESS_SET` and the
+    /// other sets and push constants as the shaders declare them.
+    pub fn pipeline_layout(
+        &self,
+        device: Arc<Device>,
+        stages: &[PipelineShaderStageCreateInfo],
+    ) -> Arc<PipelineLayout> {
+        let mut create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(stages);
+        // The shaders' runtime array has no size; the real layout replaces it below.
+        if let Some(set) = create_info.set_layouts.get_mut(BINDLESS_SET as usize) {
+            set.bindings.clear();
+        }
+        let mut create_info = create_info
+            .into_pipeline_layout_create_info(device.clone())
+            .unwrap();
+        create_info.set_layouts[BINDLESS_SET as usize] = self.layout.clone();
+        PipelineLayout::new(device, create_info).unwrap()
+    }
+}
@@ -0,0 +1,71 @@
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::color_blend::BlendOp;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+
+/// How a pipeline's output is combined with what is already in the framebuffer. Everything
+/// but `Opaque` depends on what was drawn before, so draw such pipelines after the opaque
+/// ones, back to front where they overlap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Overwrites the framebuffer.
+    #[default]
+    Opaque,
+    /// Mixes by the output's alpha, for colors that aren't premultiplied, e.g. glass or fading
+    /// UI.
+    Alpha,
+    /// Like `Alpha` for colors already multiplied by their alpha, e.g. textures exported
+    /// premultiplied. Filters without dark fringes and can mix in additive light with alpha 0.
+    Premultiplied,
+    /// Adds the output weighted by its alpha, e.g. fire, sparks or glows. Order doesn't matter.
+    Additive,
+}
+impl BlendMode {
+    /// The blend equation of this mode, `None` for `Opaque`.
+    pub fn attachment_blend(self) -> Option<AttachmentBlend> {
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::Alpha => Some(AttachmentBlend::alpha()),
+            BlendMode::Premultiplied => Some(AttachmentBlend {
+                src_color_blend_factor: BlendFactor::One,
+                dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                color_blend_op: BlendOp::Add,
+                src_alpha_blend_factor: BlendFactor::One,
+                dst_alpha_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                alpha_blend_op: BlendOp::Add,
+            }),
+            BlendMode::Additive => Some(AttachmentBlend {
+                src_color_blend_factor: BlendFactor::SrcAlpha,
+                dst_color_blend_factor: BlendFactor::One,
+                color_blend_op: BlendOp::Add,
+                src_alpha_blend_factor: BlendFactor::Zero,
+                dst_alpha_blend_factor: BlendFactor::One,
+                alpha_blend_op: BlendOp::Add,
+            }),
+        }
+    }
+
+    /// Depth test of pipelines blending this way. Only `Opaque` writes depth: blended surfaces
+    /// are tested against the opaque scene but don't hide what is drawn behind them after.
+    pub fn depth_state(self) -> DepthState {
+        DepthState {
+            write_enable: self == BlendMode::Opaque,
+            compare_op: CompareOp::Less,
+        }
+    }
+
+    /// Blend state blending every one of `attachments` color attachments this way, e.g. for
+    /// `GraphicsPipelineCreateInfo::color_blend_state`.
+    pub fn color_blend_state(self, attachments: u32) -> ColorBlendState {
+        ColorBlendState::with_attachment_states(
+            attachments,
+            ColorBlendAttachmentState {
+                blend: self.attachment_blend(),
+                ..Default::default()
+            },
+        )
+    }
+}
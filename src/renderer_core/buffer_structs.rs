@@ -6,8 +6,9 @@ pub(crate) struct MyVertex {
     #[format(R32G32_SINT)]
     pub position: [i32; 2],
 
-    #[format(R8G8B8_UINT)]
-    pub color: [u8; 3],
+    /// Normalized RGBA: 255 reads as 1.0 in the shader.
+    #[format(R8G8B8A8_UNORM)]
+    pub color: [u8; 4],
 }
 
 #[derive(BufferContents)]
@@ -17,3 +18,302 @@ pub(crate) struct MVP {
     pub view: [[f32; 4]; 4],
     pub proj: [[f32; 4]; 4],
 }
+
+#[derive(BufferContents, Vertex)]
+#[repr(C)]
+pub(crate) struct SkinnedVertex {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+
+    #[format(R32G32B32A32_UINT)]
+    pub joints: [u32; 4],
+
+    #[format(R32G32B32A32_SFLOAT)]
+    pub weights: [f32; 4],
+
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+}
+
+/// Vertex of meshes from `Resources` and of `DynamicMesh`.
+#[derive(BufferContents, Vertex, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MeshVertex {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+}
+
+#[derive(BufferContents, Vertex)]
+#[repr(C)]
+pub(crate) struct LightmapVertex {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+
+    #[format(R32G32_SFLOAT)]
+    pub lightmap_uv: [f32; 2],
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct LightmapMaterial {
+    pub base_color: [f32; 4],
+}
+
+/// World-space box drawn by `vs_occlusion_box`; `w` is padding.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct OcclusionBox {
+    pub min: [f32; 4],
+    pub max: [f32; 4],
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct MorphInfo {
+    pub vertex_count: u32,
+    pub target_count: u32,
+}
+
+/// Simulation state of one GPU particle, laid out like `Particle` in `cs_particles`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct GpuParticle {
+    /// xyz position, w age in seconds.
+    pub position_age: [f32; 4],
+    /// xyz velocity, w lifetime in seconds.
+    pub velocity_lifetime: [f32; 4],
+}
+
+/// Push constants of `cs_particles`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct ParticleParams {
+    pub emitter_position: [f32; 4],
+    pub initial_velocity: [f32; 4],
+    pub velocity_spread: [f32; 4],
+    pub acceleration: [f32; 4],
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub lifetime: [f32; 2],
+    pub size: [f32; 2],
+    pub dt: f32,
+    pub time: f32,
+    pub particle_count: u32,
+    pub spawn_count: u32,
+}
+
+/// Binding 0 of `vs_multiview`, indexed by `gl_ViewIndex`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct StereoCameraUniform {
+    pub view: [[[f32; 4]; 4]; 2],
+    pub proj: [[[f32; 4]; 4]; 2],
+}
+
+/// Push constants of `vs_multiview`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct StereoDraw {
+    pub model: [[f32; 4]; 4],
+}
+
+/// Push constants of `vs_object_id`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct ObjectIdDraw {
+    pub model_view_projection: [[f32; 4]; 4],
+    pub id: u32,
+}
+
+/// Entry of `ObjectData`, read by `vs_material_objects` at `gl_InstanceIndex`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ObjectTransform {
+    pub model: [[f32; 4]; 4],
+}
+
+/// Push constants of `fs_material`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct MaterialParams {
+    pub base_color: [f32; 4],
+    /// Toward the light, normalized; w unused.
+    pub light_direction: [f32; 4],
+    pub base_color_texture: u32,
+}
+
+/// Push constants shared by `tcs_distance`, `tes_displaced` and `fs_displaced`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct DisplacementParams {
+    /// World space; w unused.
+    pub camera_position: [f32; 4],
+    pub base_color: [f32; 4],
+    pub displacement_scale: f32,
+    pub max_level: f32,
+    pub lod_distance: f32,
+}
+
+/// Push constants of `cs_skinning`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct SkinningParams {
+    pub vertex_count: u32,
+}
+
+/// Push constants of `cs_frustum_cull`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct CullParams {
+    pub planes: [[f32; 4]; 6],
+    pub object_count: u32,
+}
+
+/// Push constants of `cs_hi_z_cull`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct HiZCullParams {
+    pub view_projection: [[f32; 4]; 4],
+    pub object_count: u32,
+}
+
+/// Push constants of `cs_frustum_cull_bda`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct CullAddressParams {
+    pub planes: [[f32; 4]; 6],
+    pub object_count: u32,
+    pub padding: u32,
+    pub objects: u64,
+    pub draws: u64,
+    pub visible_count: u64,
+}
+
+/// Vertex of a meshlet mesh, read from a storage buffer by `vs_meshlet`.
+#[cfg(feature = "mesh_shader")]
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct MeshletVertex {
+    /// w unused.
+    pub position: [f32; 4],
+    /// w unused.
+    pub normal: [f32; 4],
+}
+
+/// Laid out like `Meshlet` in `vs_meshlet`.
+#[cfg(feature = "mesh_shader")]
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct GpuMeshlet {
+    pub bounding_sphere: [f32; 4],
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+}
+
+/// Push constants of `vs_meshlet`.
+#[cfg(feature = "mesh_shader")]
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct MeshletParams {
+    pub planes: [[f32; 4]; 6],
+}
+
+/// Push constants of `cs_ray_trace` and `rgen_ray_trace`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct RayTraceParams {
+    pub inverse_view_projection: [[f32; 4]; 4],
+    pub camera_position: [f32; 4],
+    /// Toward the light, normalized.
+    pub light_direction: [f32; 4],
+    pub sky_color: [f32; 4],
+}
+
+/// Push constants of `cs_ray_traced_ao`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct AoParams {
+    pub inverse_view_projection: [[f32; 4]; 4],
+    pub radius: f32,
+    pub ray_count: u32,
+    pub frame: u32,
+    pub bias: f32,
+    pub far_depth: f32,
+}
+
+/// Push constants of `cs_ray_traced_shadows`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct ShadowParams {
+    pub inverse_view_projection: [[f32; 4]; 4],
+    /// Point light position with w = 1, or the direction toward a directional light with w = 0.
+    pub light: [f32; 4],
+    pub source_radius: f32,
+    pub sample_count: u32,
+    pub frame: u32,
+    pub bias: f32,
+    pub far_depth: f32,
+}
+
+/// Push constants of `vs_water` and `fs_water`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct WaterParams {
+    pub center: [f32; 4],
+    pub camera_position: [f32; 4],
+    pub color: [f32; 4],
+    pub size: [f32; 2],
+    pub ripple_scale: f32,
+    pub ripple_strength: f32,
+    pub time: f32,
+}
+
+/// Vertex of 2D geometry, e.g. `TilemapRenderer` chunks, `NineSliceRenderer` panels and
+/// `Canvas` shapes, which leave `uv` unused.
+#[derive(BufferContents, Vertex, Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct Vertex2d {
+    #[format(R32G32_SFLOAT)]
+    pub position: [f32; 2],
+
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4],
+}
+
+/// One decal as `vs_decal` and `fs_decal` read it from their storage buffer.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct DecalInstance {
+    pub model: [[f32; 4]; 4],
+    pub world_to_decal: [[f32; 4]; 4],
+    pub atlas_rect: [f32; 4],
+    pub color: [f32; 4],
+}
+
+/// Push constants of `vs_volume` and `fs_volume`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct VolumeParams {
+    pub model: [[f32; 4]; 4],
+    pub color: [f32; 4],
+    pub density: f32,
+    pub threshold: f32,
+    pub steps: u32,
+}
@@ -0,0 +1,414 @@
+use std::f32::consts::PI;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use lyon::path::FillRule;
+use lyon::path::Path;
+use lyon::tessellation::BuffersBuilder;
+use lyon::tessellation::FillOptions;
+use lyon::tessellation::FillTessellator;
+use lyon::tessellation::FillVertex;
+use lyon::tessellation::StrokeOptions;
+use lyon::tessellation::StrokeTessellator;
+use lyon::tessellation::StrokeVertex;
+use lyon::tessellation::TessellationError;
+use lyon::tessellation::VertexBuffers;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use super::blend::BlendMode;
+use super::buffer_structs::Vertex2d;
+use super::descriptor_sets::DescriptorSets;
+use super::shaders;
+use super::uniform_ring::UniformRing;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// How far past a corner a sharp stroke join may reach, in stroke widths, before it's cut
+/// short.
+const MITER_LIMIT: f32 = 4.0;
+
+/// Immediate-mode 2D shapes, e.g. for charts and plots: call the shape functions every frame,
+/// then `record_draw`, then `clear`. Shapes are tessellated into triangles on the CPU and
+/// streamed through a `UniformRing`, so they cost nothing to set up and can change every
+/// frame.
+///
+/// Coordinates are in the same units as the projection, e.g. logical pixels with
+/// `UiScale::projection`, with y down. Later shapes are drawn over earlier ones. Strokes are
+/// centered on the outline. Arbitrary paths with curves, fill rules and stroke styles go
+/// through `fill_path` and `stroke_path`, tessellated with lyon.
+pub struct Canvas {
+    vertices: Vec<Vertex2d>,
+    fill_tessellator: FillTessellator,
+    stroke_tessellator: StrokeTessellator,
+    /// Reused between paths.
+    geometry: VertexBuffers<[f32; 2], u32>,
+    /// How far curves may stray from true circles, in units; smaller is smoother and costs
+    /// more vertices.
+    pub tolerance: f32,
+}
+impl Default for Canvas {
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            fill_tessellator: FillTessellator::new(),
+            stroke_tessellator: StrokeTessellator::new(),
+            geometry: VertexBuffers::new(),
+            tolerance: 0.25,
+        }
+    }
+}
+impl Canvas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub(crate) fn vertices(&self) -> &[Vertex2d] {
+        &self.vertices
+    }
+
+    pub(crate) fn vertices_mut(&mut self) -> &mut [Vertex2d] {
+        &mut self.vertices
+    }
+
+    pub fn fill_rect(&mut self, offset: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        self.fill_convex_polygon(&Self::rect_outline(offset, size), color);
+    }
+
+    pub fn stroke_rect(&mut self, offset: [f32; 2], size: [f32; 2], width: f32, color: [f32; 4]) {
+        self.stroke_polyline(&Self::rect_outline(offset, size), true, width, color);
+    }
+
+    pub fn fill_circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4]) {
+        let outline = self.circle_outline(center, radius);
+        self.fill_convex_polygon(&outline, color);
+    }
+
+    pub fn stroke_circle(&mut self, center: [f32; 2], radius: f32, width: f32, color: [f32; 4]) {
+        let outline = self.circle_outline(center, radius);
+        self.stroke_polyline(&outline, true, width, color);
+    }
+
+    /// `radius` shrinks to fit when it's more than half the width or height.
+    pub fn fill_rounded_rect(
+        &mut self,
+        offset: [f32; 2],
+        size: [f32; 2],
+        radius: f32,
+        color: [f32; 4],
+    ) {
+        let outline = self.rounded_rect_outline(offset, size, radius);
+        self.fill_convex_polygon(&outline, color);
+    }
+
+    pub fn stroke_rounded_rect(
+        &mut self,
+        offset: [f32; 2],
+        size: [f32; 2],
+        radius: f32,
+        width: f32,
+        color: [f32; 4],
+    ) {
+        let outline = self.rounded_rect_outline(offset, size, radius);
+        self.stroke_polyline(&outline, true, width, color);
+    }
+
+    pub fn line(&mut self, from: [f32; 2], to: [f32; 2], width: f32, color: [f32; 4]) {
+        self.stroke_polyline(&[from, to], false, width, color);
+    }
+
+    /// Fills the polygon through `points` as a triangle fan, which is only right for convex
+    /// polygons.
+    pub fn fill_convex_polygon(&mut self, points: &[[f32; 2]], color: [f32; 4]) {
+        if points.len() < 3 {
+            return;
+        }
+        for pair in points[1..].windows(2) {
+            self.triangle(points[0], pair[0], pair[1], color);
+        }
+    }
+
+    /// Strokes the lines through `points`, back to the first one if `closed`, with mitered
+    /// joins and ends cut flat at the first and last point, e.g. a line chart's series.
+    pub fn stroke_polyline(
+        &mut self,
+        points: &[[f32; 2]],
+        closed: bool,
+        width: f32,
+        color: [f32; 4],
+    ) {
+        let mut points = points.to_vec();
+        points.dedup();
+        if closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() < 2 || width <= 0.0 {
+            return;
+        }
+        let half = width * 0.5;
+        let count = points.len();
+        let normal = |from: [f32; 2], to: [f32; 2]| {
+            let [x, y] = [to[0] - from[0], to[1] - from[1]];
+            let length = (x * x + y * y).sqrt();
+            [-y / length, x / length]
+        };
+        // The offset from each point to the stroke's edges, along the bisector of the
+        // neighbouring segments' normals.
+        let offsets: Vec<[f32; 2]> = (0..count)
+            .map(|i| {
+                let previous = if i > 0 {
+                    Some(normal(points[i - 1], points[i]))
+                } else if closed {
+                    Some(normal(points[count - 1], points[0]))
+                } else {
+                    None
+                };
+                let next = if i + 1 < count {
+                    Some(normal(points[i], points[i + 1]))
+                } else if closed {
+                    Some(normal(points[count - 1], points[0]))
+                } else {
+                    None
+                };
+                let (a, b) = match (previous, next) {
+                    (Some(a), Some(b)) => (a, b),
+                    (Some(a), None) => (a, a),
+                    (None, Some(b)) => (b, b),
+                    (None, None) => unreachable!(),
+                };
+                let [x, y] = [a[0] + b[0], a[1] + b[1]];
+                let length = (x * x + y * y).sqrt();
+                if length < 1e-6 {
+                    // The line doubles back on itself.
+                    return [b[0] * half, b[1] * half];
+                }
+                let miter = [x / length, y / length];
+                let scale =
+                    (half / (miter[0] * b[0] + miter[1] * b[1])).min(half * MITER_LIMIT * 2.0);
+                [miter[0] * scale, miter[1] * scale]
+            })
+            .collect();
+
+        let segments = if closed { count } else { count - 1 };
+        for i in 0..segments {
+            let j = (i + 1) % count;
+            let [p, q] = [points[i], points[j]];
+            let [o, r] = [offsets[i], offsets[j]];
+            let p_outer = [p[0] + o[0], p[1] + o[1]];
+            let p_inner = [p[0] - o[0], p[1] - o[1]];
+            let q_outer = [q[0] + r[0], q[1] + r[1]];
+            let q_inner = [q[0] - r[0], q[1] - r[1]];
+            self.triangle(p_outer, q_outer, p_inner, color);
+            self.triangle(p_inner, q_outer, q_inner, color);
+        }
+    }
+
+    /// Fills `path`, e.g. built with `lyon::path::Path::builder`, closing open subpaths.
+    /// Curves are flattened to within `tolerance`. Nothing is drawn if it fails.
+    pub fn fill_path(
+        &mut self,
+        path: &Path,
+        fill_rule: FillRule,
+        color: [f32; 4],
+    ) -> Result<(), TessellationError> {
+        let options = FillOptions::tolerance(self.tolerance).with_fill_rule(fill_rule);
+        self.geometry.vertices.clear();
+        self.geometry.indices.clear();
+        self.fill_tessellator.tessellate_path(
+            path,
+            &options,
+            &mut BuffersBuilder::new(&mut self.geometry, |vertex: FillVertex| {
+                vertex.position().to_array()
+            }),
+        )?;
+        self.push_geometry(color);
+        Ok(())
+    }
+
+    /// Strokes `path` with `options`' width, joins and caps. Curves are flattened to within
+    /// `tolerance`, whatever `options` says. Nothing is drawn if it fails.
+    pub fn stroke_path(
+        &mut self,
+        path: &Path,
+        options: &StrokeOptions,
+        color: [f32; 4],
+    ) -> Result<(), TessellationError> {
+        let options = options.with_tolerance(self.tolerance);
+        self.geometry.vertices.clear();
+        self.geometry.indices.clear();
+        self.stroke_tessellator.tessellate_path(
+            path,
+            &options,
+            &mut BuffersBuilder::new(&mut self.geometry, |vertex: StrokeVertex| {
+                vertex.position().to_array()
+            }),
+        )?;
+        self.push_geometry(color);
+        Ok(())
+    }
+
+    /// Appends the indexed triangles of the last tessellated path.
+    fn push_geometry(&mut self, color: [f32; 4]) {
+        let geometry = &self.geometry;
+        self.vertices
+            .extend(geometry.indices.iter().map(|&index| Vertex2d {
+                position: geometry.vertices[index as usize],
+                uv: [0.0, 0.0],
+                color,
+            }));
+    }
+
+    fn triangle(&mut self, a: [f32; 2], b: [f32; 2], c: [f32; 2], color: [f32; 4]) {
+        self.vertices.extend([a, b, c].map(|position| Vertex2d {
+            position,
+            uv: [0.0, 0.0],
+            color,
+        }));
+    }
+
+    fn rect_outline(offset: [f32; 2], size: [f32; 2]) -> [[f32; 2]; 4] {
+        let [x, y] = offset;
+        let [right, bottom] = [x + size[0], y + size[1]];
+        [[x, y], [right, y], [right, bottom], [x, bottom]]
+    }
+
+    /// Enough segments for an arc of `radius` through `angle` to stay within `tolerance`.
+    fn arc_segments(&self, radius: f32, angle: f32) -> usize {
+        let tolerance = self.tolerance.max(1e-3);
+        if radius <= tolerance {
+            return 1;
+        }
+        let step = 2.0 * (1.0 - tolerance / radius).acos();
+        ((angle / step).ceil() as usize).clamp(1, 256)
+    }
+
+    fn circle_outline(&self, center: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+        let segments = self.arc_segments(radius, TAU).max(3);
+        (0..segments)
+            .map(|i| {
+                let angle = i as f32 / segments as f32 * TAU;
+                [
+                    center[0] + radius * angle.cos(),
+                    center[1] + radius * angle.sin(),
+                ]
+            })
+            .collect()
+    }
+
+    fn rounded_rect_outline(&self, offset: [f32; 2], size: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+        let radius = radius.min(size[0] * 0.5).min(size[1] * 0.5).max(0.0);
+        if radius == 0.0 {
+            return Self::rect_outline(offset, size).to_vec();
+        }
+        let [x, y] = offset;
+        let [right, bottom] = [x + size[0], y + size[1]];
+        let segments = self.arc_segments(radius, PI * 0.5);
+        // Clockwise on screen from the top-left corner, angles growing from +x towards +y.
+        let corners = [
+            ([x + radius, y + radius], PI),
+            ([right - radius, y + radius], PI * 1.5),
+            ([right - radius, bottom - radius], 0.0),
+            ([x + radius, bottom - radius], PI * 0.5),
+        ];
+        let mut outline = Vec::with_capacity(4 * (segments + 1));
+        for (center, start) in corners {
+            for i in 0..=segments {
+                let angle = start + i as f32 / segments as f32 * PI * 0.5;
+                outline.push([
+                    center[0] + radius * angle.cos(),
+                    center[1] + radius * angle.sin(),
+                ]);
+            }
+        }
+        outline
+    }
+
+    /// Alpha-blended, without depth testing.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_color_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            Vertex2d::per_vertex(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, mvp_buffer)],
+        )
+    }
+
+    /// Streams the shapes drawn since the last `clear` through `uniforms` and draws them.
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        uniforms: &UniformRing,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let vertex_buffer = uniforms.write_slice(&self.vertices);
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, vertex_buffer)
+            .unwrap()
+            .draw(self.vertices.len() as u32, 1, 0, 0)
+            .unwrap();
+    }
+}
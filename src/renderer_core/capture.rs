@@ -0,0 +1,377 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use image::ImageResult;
+use image::RgbaImage;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CopyImageToBufferInfo;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use crate::frame_diff::diff_images;
+use crate::frame_diff::FrameDiff;
+
+use super::compute::ComputeContext;
+use super::memory_stats::MemoryCategory;
+
+const COLOR_FORMAT: Format = Format::R8G8B8A8_UNORM;
+const DEPTH_FORMAT: Format = Format::D32_SFLOAT;
+/// Depth with a stencil component, for `CaptureSettings::stencil`. Unlike D24S8, every desktop
+/// vendor supports it.
+const DEPTH_STENCIL_FORMAT: Format = Format::D32_SFLOAT_S8_UINT;
+
+/// Quality options that change how a frame is rasterized, compared by `capture_diff`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaptureSettings {
+    /// MSAA sample count: 1, 2, 4 or 8.
+    pub samples: u32,
+    /// Depth cleared to 0 and tested with `Greater`. The projection must map near to 1 and far
+    /// to 0 to match.
+    pub reversed_z: bool,
+    pub clear_color: [f32; 4],
+    /// Gives the depth attachment a stencil component, cleared to 0, for materials with a
+    /// `StencilMode`.
+    pub stencil: bool,
+}
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            samples: 1,
+            reversed_z: false,
+            clear_color: [0.1, 0.1, 0.1, 1.0],
+            stencil: false,
+        }
+    }
+}
+impl CaptureSettings {
+    pub fn sample_count(&self) -> SampleCount {
+        SampleCount::try_from(self.samples).expect("unsupported sample count")
+    }
+
+    /// For the pipelines drawing into the capture.
+    pub fn multisample_state(&self) -> MultisampleState {
+        MultisampleState {
+            rasterization_samples: self.sample_count(),
+            ..Default::default()
+        }
+    }
+
+    pub fn depth_state(&self) -> DepthState {
+        DepthState {
+            write_enable: true,
+            compare_op: if self.reversed_z {
+                CompareOp::Greater
+            } else {
+                CompareOp::Less
+            },
+        }
+    }
+
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reversed_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn depth_format(&self) -> Format {
+        if self.stencil {
+            DEPTH_STENCIL_FORMAT
+        } else {
+            DEPTH_FORMAT
+        }
+    }
+
+    /// The clear value of the depth attachment, with the stencil cleared to 0 if it has one.
+    fn depth_clear(&self) -> ClearValue {
+        if self.stencil {
+            ClearValue::DepthStencil((self.depth_clear_value(), 0))
+        } else {
+            ClearValue::Depth(self.depth_clear_value())
+        }
+    }
+
+    /// Color and depth attachments with `samples` samples, plus a single-sampled color
+    /// attachment they are resolved into when multisampling.
+    pub fn render_pass(&self, device: Arc<Device>) -> Arc<RenderPass> {
+        if self.samples == 1 {
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        format: COLOR_FORMAT,
+                        samples: 1,
+                        load_op: Clear,
+                        store_op: Store,
+                    },
+                    depth: {
+                        format: self.depth_format(),
+                        samples: 1,
+                        load_op: Clear,
+                        store_op: DontCare,
+                    },
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth},
+                },
+            )
+            .unwrap()
+        } else {
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    multisampled_color: {
+                        format: COLOR_FORMAT,
+                        samples: self.samples,
+                        load_op: Clear,
+                        store_op: DontCare,
+                    },
+                    depth: {
+                        format: self.depth_format(),
+                        samples: self.samples,
+                        load_op: Clear,
+                        store_op: DontCare,
+                    },
+                    color: {
+                        format: COLOR_FORMAT,
+                        samples: 1,
+                        load_op: DontCare,
+                        store_op: Store,
+                    },
+                },
+                pass: {
+                    color: [multisampled_color],
+                    color_resolve: [color],
+                    depth_stencil: {depth},
+                },
+            )
+            .unwrap()
+        }
+    }
+}
+
+/// What the draw callback of a capture records into.
+pub struct CaptureTarget {
+    /// Pipelines must be created for this subpass, with `settings.multisample_state()`.
+    pub subpass: Subpass,
+    pub viewport: Viewport,
+    pub settings: CaptureSettings,
+}
+
+/// The same frame rendered with two settings profiles.
+pub struct CaptureComparison {
+    pub a: RgbaImage,
+    pub b: RgbaImage,
+    pub diff: FrameDiff,
+}
+impl CaptureComparison {
+    /// Writes `a.png`, `b.png` and `diff.png` into `directory`.
+    pub fn save(&self, directory: impl AsRef<Path>) -> ImageResult<()> {
+        let directory = directory.as_ref();
+        self.a.save(directory.join("a.png"))?;
+        self.b.save(directory.join("b.png"))?;
+        self.diff.image.save(directory.join("diff.png"))
+    }
+}
+
+/// Renders one frame offscreen and reads it back. `record` is called inside the render pass and
+/// records the scene's draws. Blocks until the GPU is done.
+pub fn capture_frame<F>(
+    context: &ComputeContext,
+    settings: &CaptureSettings,
+    extent: [u32; 2],
+    record: F,
+) -> RgbaImage
+where
+    F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &CaptureTarget),
+{
+    let render_pass = settings.render_pass(context.device());
+    let color = attachment(
+        context,
+        "capture color",
+        COLOR_FORMAT,
+        SampleCount::Sample1,
+        extent,
+        ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+    );
+    let depth = attachment(
+        context,
+        "capture depth",
+        settings.depth_format(),
+        settings.sample_count(),
+        extent,
+        ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+    );
+    let (attachments, clear_values) = if settings.samples == 1 {
+        (
+            vec![color.clone(), depth],
+            vec![
+                Some(settings.clear_color.into()),
+                Some(settings.depth_clear()),
+            ],
+        )
+    } else {
+        let multisampled_color = attachment(
+            context,
+            "capture multisampled color",
+            COLOR_FORMAT,
+            settings.sample_count(),
+            extent,
+            ImageUsage::COLOR_ATTACHMENT,
+        );
+        (
+            vec![multisampled_color, depth, color.clone()],
+            vec![
+                Some(settings.clear_color.into()),
+                Some(settings.depth_clear()),
+                None,
+            ],
+        )
+    };
+    let framebuffer = Framebuffer::new(
+        render_pass.clone(),
+        FramebufferCreateInfo {
+            attachments,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let readback = Buffer::new_slice::<u8>(
+        context.memory_allocator(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        (extent[0] * extent[1] * 4) as u64,
+    )
+    .unwrap();
+    context.memory_tracker().track_buffer(
+        MemoryCategory::Staging,
+        "capture readback",
+        readback.buffer(),
+    );
+
+    let target = CaptureTarget {
+        subpass: Subpass::from(render_pass, 0).unwrap(),
+        viewport: Viewport {
+            offset: [0.0, 0.0],
+            extent: [extent[0] as f32, extent[1] as f32],
+            depth_range: 0.0..=1.0,
+        },
+        settings: *settings,
+    };
+    context
+        .submit(sync::now(context.device()).boxed(), |builder| {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values,
+                        ..RenderPassBeginInfo::framebuffer(framebuffer)
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::Inline,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            record(builder, &target);
+            builder
+                .end_render_pass(SubpassEndInfo::default())
+                .unwrap()
+                .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                    color.image().clone(),
+                    readback.clone(),
+                ))
+                .unwrap();
+        })
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    let pixels = readback.read().unwrap().to_vec();
+    RgbaImage::from_raw(extent[0], extent[1], pixels).unwrap()
+}
+
+/// Renders the same frame with settings `a` and `b` and diffs the results, to check that a quality
+/// option changes what it should and nothing else. `record` is called once per profile.
+pub fn capture_diff<F>(
+    context: &ComputeContext,
+    extent: [u32; 2],
+    a: &CaptureSettings,
+    b: &CaptureSettings,
+    threshold: u8,
+    mut record: F,
+) -> CaptureComparison
+where
+    F: FnMut(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &CaptureTarget),
+{
+    let a = capture_frame(context, a, extent, &mut record);
+    let b = capture_frame(context, b, extent, &mut record);
+    let diff = diff_images(&a, &b, threshold);
+    CaptureComparison { a, b, diff }
+}
+
+fn attachment(
+    context: &ComputeContext,
+    label: &'static str,
+    format: Format,
+    samples: SampleCount,
+    extent: [u32; 2],
+    usage: ImageUsage,
+) -> Arc<ImageView> {
+    let image = Image::new(
+        context.memory_allocator(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            samples,
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    context
+        .memory_tracker()
+        .track_image(MemoryCategory::RenderTargets, label, &image);
+    ImageView::new_default(image).unwrap()
+}
@@ -0,0 +1,55 @@
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::pipeline::graphics::viewport::Scissor;
+
+/// A rectangle of the framebuffer, in pixels, that draws are clipped to, e.g. a scrolling UI
+/// panel or a minimap. Only pipelines with a dynamic scissor can be clipped, e.g. from
+/// `SpriteRenderer::get_clipped_pipeline`, and they need one set before their first draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClipRect {
+    pub offset: [u32; 2],
+    pub extent: [u32; 2],
+}
+impl ClipRect {
+    /// Clips nothing, whatever the framebuffer's size.
+    pub const UNCLIPPED: ClipRect = ClipRect {
+        offset: [0, 0],
+        extent: [i32::MAX as u32, i32::MAX as u32],
+    };
+
+    pub fn new(offset: [u32; 2], extent: [u32; 2]) -> Self {
+        Self { offset, extent }
+    }
+
+    /// The part inside both, e.g. for a panel nested in another. Empty when they don't
+    /// overlap.
+    pub fn intersect(&self, other: &ClipRect) -> ClipRect {
+        let start = [0, 1].map(|axis| self.offset[axis].max(other.offset[axis]));
+        let end = [0, 1].map(|axis| {
+            (self.offset[axis].saturating_add(self.extent[axis]))
+                .min(other.offset[axis].saturating_add(other.extent[axis]))
+        });
+        ClipRect {
+            offset: start,
+            extent: [0, 1].map(|axis| end[axis].saturating_sub(start[axis])),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extent[0] == 0 || self.extent[1] == 0
+    }
+
+    /// Clips the draws recorded after it, until the next clip rect.
+    pub fn record<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        builder
+            .set_scissor(0, [Scissor::from(*self)].into_iter().collect())
+            .unwrap();
+    }
+}
+impl From<ClipRect> for Scissor {
+    fn from(rect: ClipRect) -> Self {
+        Scissor {
+            offset: rect.offset,
+            extent: rect.extent,
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::shader::EntryPoint;
+use vulkano::sync::GpuFuture;
+use vulkano::sync::Sharing;
+
+use crate::device_capabilities::DeviceCapabilities;
+
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// Everything needed to create and run compute work: pipelines, storage resources, descriptor
+/// sets and dispatches. Work is either recorded into a frame's command buffer or submitted on its
+/// own with `submit`, which returns a future the frame can wait on. `submit_async` runs work on
+/// a separate compute queue, where the device has one, alongside the graphics queue.
+pub struct ComputeContext {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    async_queue: Option<Arc<Queue>>,
+    capabilities: DeviceCapabilities,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    descriptor_sets: DescriptorSets,
+    memory_tracker: MemoryTracker,
+}
+impl ComputeContext {
+    /// Creates its sets with `descriptor_sets`, e.g. `RendererCore::descriptor_sets`, so they
+    /// share the renderer's pools and cache.
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, descriptor_sets: &DescriptorSets) -> Self {
+        Self::with_async_queue(device, queue, None, descriptor_sets)
+    }
+
+    /// `new`, with `async_queue` for `submit_async`, e.g. `Queues::async_compute`.
+    /// Storage resources are then shared between its family and `queue`'s.
+    pub fn with_async_queue(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        async_queue: Option<Arc<Queue>>,
+        descriptor_sets: &DescriptorSets,
+    ) -> Self {
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        Self {
+            capabilities: DeviceCapabilities::new(&device),
+            device,
+            queue,
+            async_queue,
+            memory_allocator,
+            command_buffer_allocator,
+            descriptor_sets: descriptor_sets.clone(),
+            memory_tracker: MemoryTracker::new(),
+        }
+    }
+
+    pub fn device(&self) -> Arc<Device> {
+        self.device.clone()
+    }
+
+    /// The queue `submit` runs on.
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    /// What the device enabled, for passes to check before creating their pipelines.
+    pub fn capabilities(&self) -> &DeviceCapabilities {
+        &self.capabilities
+    }
+
+    pub fn memory_allocator(&self) -> Arc<StandardMemoryAllocator> {
+        self.memory_allocator.clone()
+    }
+
+    /// The storage buffers and images created with `create_storage_buffer` and
+    /// `create_storage_image`. Passes allocating on their own with `memory_allocator` can add
+    /// theirs.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory_tracker
+    }
+
+    /// Whether `submit_async` runs on its own queue rather than falling back to `submit`.
+    pub fn async_enabled(&self) -> bool {
+        self.async_queue.is_some()
+    }
+
+    /// Pipeline with a layout derived from the shader's own bindings and push constants.
+    pub fn create_pipeline(&self, entry_point: EntryPoint) -> Arc<ComputePipeline> {
+        let stage = PipelineShaderStageCreateInfo::new(entry_point);
+        let layout = PipelineLayout::new(
+            self.device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(self.device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+
+        ComputePipeline::new(
+            self.device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .expect("failed to create compute pipeline")
+    }
+
+    /// Device-local storage buffer of `len` elements. `extra_usage` adds e.g. `VERTEX_BUFFER` for
+    /// buffers that are drawn from afterwards. `label` names it in `memory_tracker`.
+    pub fn create_storage_buffer<T: BufferContents>(
+        &self,
+        label: &'static str,
+        len: u64,
+        extra_usage: BufferUsage,
+    ) -> Subbuffer<[T]> {
+        let buffer = Buffer::new_slice(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST | extra_usage,
+                sharing: self.sharing(),
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            len.max(1),
+        )
+        .unwrap();
+        self.memory_tracker
+            .track_buffer(MemoryCategory::Storage, label, buffer.buffer());
+        buffer
+    }
+
+    /// 2D storage image, also usable as a sampled image and as a copy source or destination.
+    /// `label` names it in `memory_tracker`.
+    pub fn create_storage_image(
+        &self,
+        label: &'static str,
+        format: Format,
+        extent: [u32; 2],
+    ) -> Arc<ImageView> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::STORAGE
+                    | ImageUsage::SAMPLED
+                    | ImageUsage::TRANSFER_SRC
+                    | ImageUsage::TRANSFER_DST,
+                sharing: self.sharing(),
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        self.memory_tracker
+            .track_image(MemoryCategory::Storage, label, &image);
+        ImageView::new_default(image).unwrap()
+    }
+
+    /// The shared sets `bind` and `bind_transient` create theirs with, for pipelines that aren't
+    /// vulkano compute pipelines.
+    pub fn descriptor_sets(&self) -> &DescriptorSets {
+        &self.descriptor_sets
+    }
+
+    /// Descriptor set 0 of `pipeline`, e.g. from `WriteDescriptorSet::buffer` and
+    /// `WriteDescriptorSet::image_view`. Cached like `DescriptorSets::cached`, so binding the
+    /// same resources again reuses the set.
+    pub fn bind(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<PersistentDescriptorSet> {
+        self.descriptor_sets
+            .cached(&pipeline.layout().set_layouts()[0], writes)
+    }
+
+    /// `bind` for the current frame only, like `DescriptorSets::transient`, for sets over
+    /// resources that change from frame to frame, e.g. per-frame uniforms or a rebuilt top
+    /// level, which would otherwise pile up in the cache.
+    pub fn bind_transient(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<PersistentDescriptorSet> {
+        self.descriptor_sets
+            .transient(&pipeline.layout().set_layouts()[0], writes)
+    }
+
+    /// Workgroup counts covering `size` invocations with the shader's `local_size`.
+    pub fn workgroups(size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+        std::array::from_fn(|i| size[i].div_ceil(local_size[i]))
+    }
+
+    /// Records a dispatch into `builder`. The builder inserts the barriers against earlier and
+    /// later commands that touch the same resources, including draws in the same frame.
+    pub fn record_dispatch<Pc: BufferContents>(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        push_constants: Option<Pc>,
+        group_counts: [u32; 3],
+    ) {
+        builder
+            .bind_pipeline_compute(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+        if let Some(push_constants) = push_constants {
+            builder
+                .push_constants(pipeline.layout().clone(), 0, push_constants)
+                .unwrap();
+        }
+        builder.dispatch(group_counts).unwrap();
+    }
+
+    /// Records compute work into its own command buffer and submits it after `after`. Join the
+    /// returned future with the frame's future so the frame waits for the results.
+    pub fn submit<F>(&self, after: Box<dyn GpuFuture>, record: F) -> Box<dyn GpuFuture>
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        record(&mut builder);
+        let command_buffer = builder.build().unwrap();
+
+        Box::new(
+            after
+                .then_execute(self.queue.clone(), command_buffer)
+                .unwrap(),
+        )
+    }
+
+    /// Records compute work like `submit`, but runs it on the async compute queue, so it
+    /// overlaps with the graphics queue's frames, e.g. particle simulation or culling for the
+    /// next frame. The work is flushed right away and the returned future signals a semaphore,
+    /// which graphics work chained after it waits on. Falls back to `submit` without an async
+    /// queue.
+    pub fn submit_async<F>(&self, after: Box<dyn GpuFuture>, record: F) -> Box<dyn GpuFuture>
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        let Some(queue) = &self.async_queue else {
+            return self.submit(after, record);
+        };
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        record(&mut builder);
+        let command_buffer = builder.build().unwrap();
+
+        // Work already submitted to another queue hands over through a semaphore.
+        let after = match after.queue() {
+            Some(after_queue) if after_queue != *queue => after.then_signal_semaphore().boxed(),
+            _ => after,
+        };
+        Box::new(
+            after
+                .then_execute(queue.clone(), command_buffer)
+                .unwrap()
+                .then_signal_semaphore_and_flush()
+                .unwrap(),
+        )
+    }
+
+    /// Concurrent sharing between the graphics and async compute families, so neither needs
+    /// ownership transfers.
+    fn sharing<S>(&self) -> Sharing<S>
+    where
+        S: FromIterator<u32> + IntoIterator<Item = u32>,
+    {
+        match &self.async_queue {
+            Some(async_queue)
+                if async_queue.queue_family_index() != self.queue.queue_family_index() =>
+            {
+                Sharing::Concurrent(
+                    [
+                        self.queue.queue_family_index(),
+                        async_queue.queue_family_index(),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )
+            }
+            _ => Sharing::Exclusive,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::CullMode;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::sync::HostAccessError;
+
+use super::blend::BlendMode;
+use super::buffer_structs::DecalInstance;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::storage_buffer::StorageBuffer;
+
+/// A grid of equally sized decal images in one texture, indexed in reading order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecalAtlas {
+    pub columns: u32,
+    pub rows: u32,
+}
+impl DecalAtlas {
+    /// The texture coordinates of image `index`, as `Decal::atlas_rect`.
+    pub fn rect(&self, index: u32) -> [f32; 4] {
+        let width = 1.0 / self.columns as f32;
+        let height = 1.0 / self.rows as f32;
+        [
+            (index % self.columns) as f32 * width,
+            (index / self.columns) as f32 * height,
+            width,
+            height,
+        ]
+    }
+}
+
+/// One decal, e.g. a bullet hole or a stain: an atlas image projected along the y axis of a
+/// box onto whatever surfaces lie inside it.
+#[derive(Clone, Copy, Debug)]
+pub struct Decal {
+    /// Maps the unit cube centered on the origin into the world. Keep the box thin along y, so
+    /// the image doesn't smear over surfaces parallel to it.
+    pub transform: Matrix4<f32>,
+    /// Offset and size of the image in the atlas' texture coordinates, e.g. from
+    /// `DecalAtlas::rect`.
+    pub atlas_rect: [f32; 4],
+    /// Multiplies the atlas image.
+    pub color: [f32; 4],
+    /// Seconds until the decal is removed, `f32::INFINITY` to keep it.
+    pub lifetime: f32,
+    /// Seconds at the end of `lifetime` over which it fades out.
+    pub fade_out: f32,
+}
+impl Default for Decal {
+    fn default() -> Self {
+        Self {
+            transform: Matrix4::identity(),
+            atlas_rect: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+            lifetime: f32::INFINITY,
+            fade_out: 1.0,
+        }
+    }
+}
+
+/// Box-projected decals drawn after the opaque geometry, reconstructing the surfaces behind
+/// each box from the depth buffer, so they follow any geometry without touching its meshes.
+/// Draw them in `RenderTargets::record_overlay_pass` sampling `RenderTargets::sampled_depth`.
+///
+/// At most `capacity` decals live at once; spawning more replaces the oldest.
+pub struct DecalRenderer {
+    decals: Vec<(Decal, f32)>,
+    capacity: usize,
+    instances: StorageBuffer<DecalInstance>,
+}
+impl DecalRenderer {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            decals: Vec::with_capacity(capacity),
+            capacity,
+            instances: StorageBuffer::new(
+                memory_allocator,
+                memory_tracker,
+                "decal instances",
+                capacity as u64,
+                BufferUsage::empty(),
+            ),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    pub fn spawn(&mut self, decal: Decal) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.decals.len() == self.capacity {
+            self.decals.remove(0);
+        }
+        self.decals.push((decal, 0.0));
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    /// Ages the decals by `dt` seconds, removing expired ones.
+    pub fn update(&mut self, dt: f32) {
+        for (_, age) in &mut self.decals {
+            *age += dt;
+        }
+        self.decals.retain(|(decal, age)| *age < decal.lifetime);
+    }
+
+    /// Writes the live decals, faded by age, for the next `record_draw`. Fails if the GPU is
+    /// still reading the previous upload.
+    pub fn upload(&mut self) -> Result<(), HostAccessError> {
+        let instances: Vec<DecalInstance> = self
+            .decals
+            .iter()
+            .map(|(decal, age)| {
+                let remaining = decal.lifetime - age;
+                let fade = if decal.fade_out > 0.0 {
+                    (remaining / decal.fade_out).min(1.0)
+                } else {
+                    1.0
+                };
+                let [r, g, b, a] = decal.color;
+                DecalInstance {
+                    model: decal.transform.into(),
+                    world_to_decal: decal
+                        .transform
+                        .try_inverse()
+                        .unwrap_or_else(Matrix4::zeros)
+                        .into(),
+                    atlas_rect: decal.atlas_rect,
+                    color: [r, g, b, a * fade],
+                }
+            })
+            .collect();
+        self.instances.upload(&instances)
+    }
+
+    /// Alpha-blends over the color attachments without depth, so the boxes are drawn also
+    /// with the camera inside them.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_decal::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_decal::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                // Back faces only, so every pixel is covered once, also from inside the box.
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::Front,
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(
+                    BlendMode::Alpha.color_blend_state(subpass.num_color_attachments()),
+                ),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds `mvp_buffer` at binding 0, of which only the view and projection are used,
+    /// `depth` at binding 1, `atlas` at binding 2 and the decals at binding 3. `depth` is read
+    /// per pixel, so it must match the pass' extent. Get the set again after `upload`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        depth: Arc<ImageView>,
+        atlas: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, depth, sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(2, atlas, sampler),
+                self.instances.write_descriptor(3),
+            ],
+        )
+    }
+
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        if self.instances.is_empty() {
+            return;
+        }
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .draw(36, self.instances.len(), 0, 0)
+            .unwrap();
+    }
+}
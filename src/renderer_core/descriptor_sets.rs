@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSetElements;
+use vulkano::device::Device;
+use vulkano::Handle;
+use vulkano::VulkanObject;
+
+/// Frames whose transient sets are kept alive, matching the frames the renderer keeps in flight.
+const FRAMES_IN_FLIGHT: usize = 2;
+
+/// A layout and everything written to it, as raw handles. Unique while the set it describes is
+/// alive, since the set keeps every resource in it from being destroyed and its handle reused.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SetKey {
+    layout: u64,
+    writes: Vec<u64>,
+}
+impl SetKey {
+    fn new(layout: &DescriptorSetLayout, writes: &[WriteDescriptorSet]) -> Self {
+        let mut key = Vec::new();
+        for write in writes {
+            let elements = write.elements();
+            key.extend([
+                write.binding() as u64,
+                write.first_array_element() as u64,
+                elements.len() as u64,
+            ]);
+            match elements {
+                WriteDescriptorSetElements::None(_) => {}
+                WriteDescriptorSetElements::Buffer(infos) => {
+                    for info in infos {
+                        key.extend([
+                            info.buffer.buffer().handle().as_raw(),
+                            info.buffer.offset(),
+                            info.range.start,
+                            info.range.end,
+                        ]);
+                    }
+                }
+                WriteDescriptorSetElements::BufferView(views) => {
+                    key.extend(views.iter().map(|view| view.handle().as_raw()));
+                }
+                WriteDescriptorSetElements::ImageView(infos) => {
+                    for info in infos {
+                        key.extend([info.image_view.handle().as_raw(), info.image_layout as u64]);
+                    }
+                }
+                WriteDescriptorSetElements::ImageViewSampler(infos) => {
+                    for (info, sampler) in infos {
+                        key.extend([
+                            info.image_view.handle().as_raw(),
+                            info.image_layout as u64,
+                            sampler.handle().as_raw(),
+                        ]);
+                    }
+                }
+                WriteDescriptorSetElements::Sampler(samplers) => {
+                    key.extend(samplers.iter().map(|sampler| sampler.handle().as_raw()));
+                }
+                WriteDescriptorSetElements::InlineUniformBlock(data) => {
+                    key.extend(data.iter().map(|&byte| byte as u64));
+                }
+                WriteDescriptorSetElements::AccelerationStructure(structures) => {
+                    key.extend(
+                        structures
+                            .iter()
+                            .map(|structure| structure.handle().as_raw()),
+                    );
+                }
+            }
+        }
+        Self {
+            layout: layout.handle().as_raw(),
+            writes: key,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SetsState {
+    cache: HashMap<SetKey, Arc<PersistentDescriptorSet>>,
+    frames: [Vec<Arc<PersistentDescriptorSet>>; FRAMES_IN_FLIGHT],
+    frame: usize,
+}
+
+/// One descriptor set allocator for the renderer and everything drawing with it, instead of a
+/// new allocator, and with it a new pool, per set.
+///
+/// `cached` sets are shared by every caller binding the same resources to the same layout, so
+/// the sets of meshes, materials and passes are written once rather than every frame.
+/// `transient` sets, e.g. over per-frame uniform buffers, are held until their frame slot comes
+/// around again and then freed together.
+///
+/// Clones share the allocator, cache and frame slots, so one can be handed to everything that
+/// creates sets for a renderer, e.g. its `ComputeContext`.
+#[derive(Clone)]
+pub struct DescriptorSets {
+    allocator: Arc<StandardDescriptorSetAllocator>,
+    state: Arc<Mutex<SetsState>>,
+}
+impl DescriptorSets {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            allocator: Arc::new(StandardDescriptorSetAllocator::new(
+                device,
+                Default::default(),
+            )),
+            state: Default::default(),
+        }
+    }
+
+    /// The shared allocator, for sets that need more than `cached` or `transient` offer, e.g.
+    /// variable descriptor counts.
+    pub fn allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.allocator
+    }
+
+    /// The set of `layout` with `writes`, created on first use. The cache keeps the bound
+    /// resources alive; `trim` lets go of sets nobody else holds.
+    pub fn cached(
+        &self,
+        layout: &Arc<DescriptorSetLayout>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let writes: Vec<_> = writes.into_iter().collect();
+        let key = SetKey::new(layout, &writes);
+        self.state
+            .lock()
+            .unwrap()
+            .cache
+            .entry(key)
+            .or_insert_with(|| {
+                PersistentDescriptorSet::new(&self.allocator, layout.clone(), writes, []).unwrap()
+            })
+            .clone()
+    }
+
+    /// A set of `layout` with `writes` for the current frame only.
+    pub fn transient(
+        &self,
+        layout: &Arc<DescriptorSetLayout>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let set =
+            PersistentDescriptorSet::new(&self.allocator, layout.clone(), writes, []).unwrap();
+        let mut state = self.state.lock().unwrap();
+        let frame = state.frame;
+        state.frames[frame].push(set.clone());
+        set
+    }
+
+    /// Moves on to the next frame slot, freeing the transient sets last created in it. Call
+    /// once per frame before recording.
+    pub fn next_frame(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.frame = (state.frame + 1) % FRAMES_IN_FLIGHT;
+        let frame = state.frame;
+        state.frames[frame].clear();
+    }
+
+    /// Drops cached sets held by nothing but the cache, e.g. after removing the meshes or
+    /// textures they bound, so their resources can be freed.
+    pub fn trim(&self) {
+        self.state
+            .lock()
+            .unwrap()
+            .cache
+            .retain(|_, set| Arc::strong_count(set) > 1);
+    }
+
+    /// Sets in the cache.
+    pub fn cached_count(&self) -> usize {
+        self.state.lock().unwrap().cache.len()
+    }
+}
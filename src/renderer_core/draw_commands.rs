@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+
+use super::frame_stats::FrameStats;
+
+/// One draw with everything it binds. Vertex buffers of any vertex type go in as bytes, e.g.
+/// `buffer.into_bytes()`, so draws of different layouts fit in one list.
+#[derive(Clone)]
+pub struct DrawCommand {
+    pub pipeline: Arc<GraphicsPipeline>,
+    /// Bound from set 0 up.
+    pub descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+    pub vertex_buffer: Subbuffer<[u8]>,
+    /// Draws indexed when set.
+    pub index_buffer: Option<Subbuffer<[u32]>>,
+    /// Vertices, or indices when indexed.
+    pub count: u32,
+    pub instance_count: u32,
+}
+impl DrawCommand {
+    /// Draws all of `vertex_buffer` once.
+    pub fn new<T: ?Sized>(
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+        vertex_buffer: Subbuffer<T>,
+    ) -> Self {
+        let vertex_buffer = vertex_buffer.into_bytes();
+        let stride = pipeline
+            .vertex_input_state()
+            .bindings
+            .get(&0)
+            .map_or(1, |binding| binding.stride.max(1));
+        Self {
+            count: (vertex_buffer.size() / stride as u64) as u32,
+            pipeline,
+            descriptor_sets,
+            vertex_buffer,
+            index_buffer: None,
+            instance_count: 1,
+        }
+    }
+
+    /// Draws all of `index_buffer` once.
+    pub fn indexed<T: ?Sized>(
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+        vertex_buffer: Subbuffer<T>,
+        index_buffer: Subbuffer<[u32]>,
+    ) -> Self {
+        Self {
+            count: index_buffer.len() as u32,
+            index_buffer: Some(index_buffer),
+            ..DrawCommand::new(pipeline, descriptor_sets, vertex_buffer)
+        }
+    }
+}
+
+/// Draws recorded in order within one subpass, each with its own pipeline, descriptor sets and
+/// vertex buffer. Only what changes from one draw to the next is bound again, so sort draws
+/// sharing state next to each other to save binds.
+#[derive(Clone, Default)]
+pub struct DrawCommands {
+    draws: Vec<DrawCommand>,
+}
+impl DrawCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, draw: DrawCommand) {
+        self.draws.push(draw);
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    pub fn draws(&self) -> &[DrawCommand] {
+        &self.draws
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    /// Records the draws into `builder`, which must be inside a render pass the pipelines were
+    /// made for. Nothing is assumed bound beforehand. Returns the draws and binds recorded.
+    pub fn record<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) -> FrameStats {
+        let mut stats = FrameStats::default();
+        let mut bound_pipeline: Option<&Arc<GraphicsPipeline>> = None;
+        let mut bound_sets: &[Arc<PersistentDescriptorSet>] = &[];
+        let mut bound_vertices: Option<&Subbuffer<[u8]>> = None;
+        let mut bound_indices: Option<&Subbuffer<[u32]>> = None;
+        for draw in &self.draws {
+            if !bound_pipeline.is_some_and(|pipeline| Arc::ptr_eq(pipeline, &draw.pipeline)) {
+                // Sets bound for another layout may be disturbed, so bind them again.
+                if !bound_pipeline
+                    .is_some_and(|pipeline| Arc::ptr_eq(pipeline.layout(), draw.pipeline.layout()))
+                {
+                    bound_sets = &[];
+                }
+                builder
+                    .bind_pipeline_graphics(draw.pipeline.clone())
+                    .unwrap();
+                bound_pipeline = Some(&draw.pipeline);
+                stats.pipeline_binds += 1;
+            }
+            let sets_bound = bound_sets.len() == draw.descriptor_sets.len()
+                && bound_sets
+                    .iter()
+                    .zip(&draw.descriptor_sets)
+                    .all(|(bound, set)| Arc::ptr_eq(bound, set));
+            if !sets_bound && !draw.descriptor_sets.is_empty() {
+                builder
+                    .bind_descriptor_sets(
+                        draw.pipeline.bind_point(),
+                        draw.pipeline.layout().clone(),
+                        0,
+                        draw.descriptor_sets.clone(),
+                    )
+                    .unwrap();
+                bound_sets = &draw.descriptor_sets;
+                stats.descriptor_set_binds += 1;
+            }
+            if bound_vertices != Some(&draw.vertex_buffer) {
+                builder
+                    .bind_vertex_buffers(0, draw.vertex_buffer.clone())
+                    .unwrap();
+                bound_vertices = Some(&draw.vertex_buffer);
+                stats.vertex_buffer_binds += 1;
+            }
+            match &draw.index_buffer {
+                Some(index_buffer) => {
+                    if bound_indices != Some(index_buffer) {
+                        builder.bind_index_buffer(index_buffer.clone()).unwrap();
+                        bound_indices = Some(index_buffer);
+                        stats.index_buffer_binds += 1;
+                    }
+                    builder
+                        .draw_indexed(draw.count, draw.instance_count, 0, 0, 0)
+                        .unwrap();
+                }
+                None => {
+                    builder.draw(draw.count, draw.instance_count, 0, 0).unwrap();
+                }
+            }
+            stats.add_draw(
+                draw.pipeline.input_assembly_state().topology,
+                draw.count,
+                draw.instance_count,
+            );
+        }
+        stats
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync::HostAccessError;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// Per-draw data, e.g. transforms and material indices, in one buffer that shaders read through
+/// its device address. Each draw gets `address_of` its entry as a push constant, declared as a
+/// `buffer_reference` in GLSL, so nothing is bound per draw. Needs
+/// `VulkanConnection::buffer_device_address_enabled`.
+///
+/// vulkano doesn't see reads through addresses, so it can't keep `upload` from overwriting data
+/// a frame in flight still reads; keep one buffer per frame in flight.
+pub struct DrawDataBuffer<T: BufferContents + Copy> {
+    buffer: Subbuffer<[T]>,
+    len: u32,
+}
+impl<T: BufferContents + Copy> DrawDataBuffer<T> {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        capacity: u64,
+    ) -> Self {
+        let buffer = Buffer::new_slice(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(MemoryCategory::Storage, "draw data", buffer.buffer());
+        Self { buffer, len: 0 }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Replaces the entries. Entries beyond the capacity are dropped.
+    pub fn upload(&mut self, data: &[T]) -> Result<(), HostAccessError> {
+        let mut dst = self.buffer.write()?;
+        let count = data.len().min(dst.len());
+        dst[..count].copy_from_slice(&data[..count]);
+        self.len = count as u32;
+        Ok(())
+    }
+
+    /// Device address of the first entry.
+    pub fn address(&self) -> u64 {
+        self.buffer.device_address().unwrap().get()
+    }
+
+    /// Device address of entry `index`, to push for its draw.
+    pub fn address_of(&self, index: u32) -> u64 {
+        self.address() + index as u64 * std::mem::size_of::<T>() as u64
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync::HostAccessError;
+
+use crate::bounds::Aabb;
+
+use super::buffer_structs::MeshVertex;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// Copies of the buffers, so the one written never is one a frame in flight still reads: two
+/// frames in flight plus the one being recorded.
+const BUFFERED_FRAMES: usize = 3;
+
+/// One frame's copy of the geometry.
+struct DynamicBuffers {
+    vertices: Subbuffer<[MeshVertex]>,
+    indices: Subbuffer<[u32]>,
+    vertex_count: u32,
+    index_count: u32,
+}
+
+/// Geometry rewritten from the CPU every frame, e.g. trails, ropes or plots. Each `update`
+/// writes the next of several host-visible buffer copies, so it doesn't wait for frames still
+/// drawing the previous geometry. Draw it with any `MeshVertex` pipeline, like meshes from
+/// `Resources`.
+pub struct DynamicMesh {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: MemoryTracker,
+    frames: Vec<DynamicBuffers>,
+    /// Index in `frames` of the last update.
+    current: usize,
+    bounds: Aabb,
+}
+impl DynamicMesh {
+    /// Room for `vertex_capacity` vertices and `index_capacity` indices. Updates with more
+    /// grow the buffers.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        vertex_capacity: u64,
+        index_capacity: u64,
+    ) -> Self {
+        let frames = (0..BUFFERED_FRAMES)
+            .map(|_| DynamicBuffers {
+                vertices: host_buffer(
+                    &memory_allocator,
+                    memory_tracker,
+                    BufferUsage::VERTEX_BUFFER,
+                    vertex_capacity,
+                ),
+                indices: host_buffer(
+                    &memory_allocator,
+                    memory_tracker,
+                    BufferUsage::INDEX_BUFFER,
+                    index_capacity,
+                ),
+                vertex_count: 0,
+                index_count: 0,
+            })
+            .collect();
+        Self {
+            memory_allocator,
+            memory_tracker: memory_tracker.clone(),
+            frames,
+            current: 0,
+            bounds: Aabb::EMPTY,
+        }
+    }
+
+    /// Replaces the geometry from this frame on, indexed triangles like a glTF primitive.
+    /// Fails if the GPU is still reading the buffers written `BUFFERED_FRAMES` updates ago,
+    /// i.e. when updating more than once per frame.
+    pub fn update(
+        &mut self,
+        vertices: &[MeshVertex],
+        indices: &[u32],
+    ) -> Result<(), HostAccessError> {
+        let next = (self.current + 1) % self.frames.len();
+        let frame = &mut self.frames[next];
+        if frame.vertices.len() < vertices.len() as u64 {
+            // Frames in flight keep the old buffer alive through their command buffers.
+            frame.vertices = host_buffer(
+                &self.memory_allocator,
+                &self.memory_tracker,
+                BufferUsage::VERTEX_BUFFER,
+                (vertices.len() as u64).next_power_of_two(),
+            );
+        }
+        if frame.indices.len() < indices.len() as u64 {
+            frame.indices = host_buffer(
+                &self.memory_allocator,
+                &self.memory_tracker,
+                BufferUsage::INDEX_BUFFER,
+                (indices.len() as u64).next_power_of_two(),
+            );
+        }
+        if !vertices.is_empty() {
+            frame.vertices.write()?[..vertices.len()].copy_from_slice(vertices);
+        }
+        if !indices.is_empty() {
+            frame.indices.write()?[..indices.len()].copy_from_slice(indices);
+        }
+        frame.vertex_count = vertices.len() as u32;
+        frame.index_count = indices.len() as u32;
+        self.current = next;
+        self.bounds = Aabb::from_points(vertices.iter().map(|vertex| &vertex.position));
+        Ok(())
+    }
+
+    /// Local bounds of the current geometry.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.frames[self.current].index_count
+    }
+
+    /// Binds the current geometry and records `instance_count` instances of it. The pipeline
+    /// and descriptor sets must already be bound. Empty geometry draws nothing.
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        instance_count: u32,
+        first_instance: u32,
+    ) {
+        let frame = &self.frames[self.current];
+        if frame.index_count == 0 {
+            return;
+        }
+        builder
+            .bind_vertex_buffers(0, frame.vertices.clone())
+            .unwrap()
+            .bind_index_buffer(frame.indices.clone())
+            .unwrap()
+            .draw_indexed(frame.index_count, instance_count, 0, 0, first_instance)
+            .unwrap();
+    }
+}
+
+fn host_buffer<T: BufferContents>(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    memory_tracker: &MemoryTracker,
+    usage: BufferUsage,
+    len: u64,
+) -> Subbuffer<[T]> {
+    let buffer = Buffer::new_slice(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        // Buffers can't be empty.
+        len.max(1),
+    )
+    .unwrap();
+    memory_tracker.track_buffer(MemoryCategory::Meshes, "dynamic mesh", buffer.buffer());
+    buffer
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::ops::Add;
+use std::ops::AddAssign;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+
+/// What recording a frame took, e.g. to see how much batching saves. Counts what went through
+/// the renderer's recorders, like `DrawCommands` and `MeshDraws`; draws recorded straight into
+/// a builder only count when added with `Renderer::add_frame_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    /// Of every instance, for triangle topologies.
+    pub triangles: u64,
+    pub pipeline_binds: u32,
+    /// Calls binding descriptor sets, however many sets each binds.
+    pub descriptor_set_binds: u32,
+    pub vertex_buffer_binds: u32,
+    pub index_buffer_binds: u32,
+}
+impl FrameStats {
+    /// Counts a draw of `count` vertices or indices, `instance_count` times.
+    pub fn add_draw(&mut self, topology: PrimitiveTopology, count: u32, instance_count: u32) {
+        self.draw_calls += 1;
+        self.triangles += FrameStats::triangles_of(topology, count) * instance_count as u64;
+    }
+
+    fn triangles_of(topology: PrimitiveTopology, count: u32) -> u64 {
+        let count = count as u64;
+        match topology {
+            PrimitiveTopology::TriangleList => count / 3,
+            PrimitiveTopology::TriangleStrip | PrimitiveTopology::TriangleFan => {
+                count.saturating_sub(2)
+            }
+            PrimitiveTopology::TriangleListWithAdjacency => count / 6,
+            PrimitiveTopology::TriangleStripWithAdjacency => (count / 2).saturating_sub(2),
+            _ => 0,
+        }
+    }
+
+    /// All binds together, what batching brings down.
+    pub fn binds(&self) -> u32 {
+        self.pipeline_binds
+            + self.descriptor_set_binds
+            + self.vertex_buffer_binds
+            + self.index_buffer_binds
+    }
+}
+impl Add for FrameStats {
+    type Output = FrameStats;
+
+    fn add(self, other: FrameStats) -> FrameStats {
+        FrameStats {
+            draw_calls: self.draw_calls + other.draw_calls,
+            triangles: self.triangles + other.triangles,
+            pipeline_binds: self.pipeline_binds + other.pipeline_binds,
+            descriptor_set_binds: self.descriptor_set_binds + other.descriptor_set_binds,
+            vertex_buffer_binds: self.vertex_buffer_binds + other.vertex_buffer_binds,
+            index_buffer_binds: self.index_buffer_binds + other.index_buffer_binds,
+        }
+    }
+}
+impl AddAssign for FrameStats {
+    fn add_assign(&mut self, other: FrameStats) {
+        *self = *self + other;
+    }
+}
+/// One line, e.g. for a debug overlay drawn with `TextRenderer`.
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} draws, {} triangles, {} pipelines, {} descriptor sets, {} vertex buffers, {} index buffers",
+            self.draw_calls,
+            self.triangles,
+            self.pipeline_binds,
+            self.descriptor_set_binds,
+            self.vertex_buffer_binds,
+            self.index_buffer_binds
+        )
+    }
+}
+
+/// `FrameStats` added to from several threads at once, e.g. while recording secondary command
+/// buffers in parallel.
+#[derive(Default)]
+pub(crate) struct FrameStatsCounter {
+    draw_calls: AtomicU32,
+    triangles: AtomicU64,
+    pipeline_binds: AtomicU32,
+    descriptor_set_binds: AtomicU32,
+    vertex_buffer_binds: AtomicU32,
+    index_buffer_binds: AtomicU32,
+}
+impl FrameStatsCounter {
+    pub(crate) fn add(&self, stats: FrameStats) {
+        self.draw_calls
+            .fetch_add(stats.draw_calls, Ordering::Relaxed);
+        self.triangles.fetch_add(stats.triangles, Ordering::Relaxed);
+        self.pipeline_binds
+            .fetch_add(stats.pipeline_binds, Ordering::Relaxed);
+        self.descriptor_set_binds
+            .fetch_add(stats.descriptor_set_binds, Ordering::Relaxed);
+        self.vertex_buffer_binds
+            .fetch_add(stats.vertex_buffer_binds, Ordering::Relaxed);
+        self.index_buffer_binds
+            .fetch_add(stats.index_buffer_binds, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> FrameStats {
+        FrameStats {
+            draw_calls: self.draw_calls.load(Ordering::Relaxed),
+            triangles: self.triangles.load(Ordering::Relaxed),
+            pipeline_binds: self.pipeline_binds.load(Ordering::Relaxed),
+            descriptor_set_binds: self.descriptor_set_binds.load(Ordering::Relaxed),
+            vertex_buffer_binds: self.vertex_buffer_binds.load(Ordering::Relaxed),
+            index_buffer_binds: self.index_buffer_binds.load(Ordering::Relaxed),
+        }
+    }
+}
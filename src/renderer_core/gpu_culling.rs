@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::DrawIndexedIndirectCommand;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::image::sampler::Sampler;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::sync::GpuFuture;
+use vulkano::sync::HostAccessError;
+
+use nalgebra::Matrix4;
+
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+
+use super::buffer_structs::CullAddressParams;
+use super::buffer_structs::CullParams;
+use super::buffer_structs::HiZCullParams;
+use super::compute::ComputeContext;
+use super::hi_z::HiZBuffer;
+use super::indirect::device_address_usage;
+use super::indirect::IndirectBuffer;
+use super::memory_stats::MemoryCategory;
+use super::shaders;
+
+/// Threads per workgroup of `cs_frustum_cull`.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// One indexed draw that the GPU may cull, laid out like `CullObject` in `cs_frustum_cull`.
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CullObject {
+    /// World-space center in xyz, radius in w.
+    pub bounding_sphere: [f32; 4],
+    pub index_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    /// Becomes the draw's `first_instance`, so the vertex shader can find the object's data
+    /// through `gl_InstanceIndex`.
+    pub instance: u32,
+}
+impl CullObject {
+    /// Uses the sphere enclosing `bounds`.
+    pub fn new(bounds: &Aabb, index_count: u32, first_index: u32, instance: u32) -> Self {
+        let center = bounds.center();
+        Self {
+            bounding_sphere: [center.x, center.y, center.z, bounds.half_extents().norm()],
+            index_count,
+            first_index,
+            vertex_offset: 0,
+            instance,
+        }
+    }
+}
+
+/// Frustum culling on the GPU. A compute pass tests every object's bounding sphere and packs the
+/// draws of the survivors into an indirect buffer, so the CPU cost no longer grows with the scene.
+/// With a `HiZBuffer`, it also skips objects hidden behind the depth it was built from.
+///
+/// Drawing uses `first_instance`, which needs the `draw_indirect_first_instance` feature.
+pub struct GpuFrustumCuller {
+    object_buffer: Subbuffer<[CullObject]>,
+    draws: IndirectBuffer<DrawIndexedIndirectCommand>,
+    visible_count: Subbuffer<[u32]>,
+    object_count: u32,
+}
+impl GpuFrustumCuller {
+    pub fn new(context: &ComputeContext, capacity: u64) -> Self {
+        let device_address = device_address_usage(&context.memory_allocator());
+        let object_buffer = Buffer::new_slice(
+            context.memory_allocator(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | device_address,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1),
+        )
+        .unwrap();
+        let memory_tracker = context.memory_tracker();
+        memory_tracker.track_buffer(
+            MemoryCategory::Storage,
+            "culling objects",
+            object_buffer.buffer(),
+        );
+        let draws = IndirectBuffer::new(context.memory_allocator(), memory_tracker, capacity);
+        let visible_count = Buffer::new_slice(
+            context.memory_allocator(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST | device_address,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            1,
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Storage,
+            "culling visible count",
+            visible_count.buffer(),
+        );
+
+        Self {
+            object_buffer,
+            draws,
+            visible_count,
+            object_count: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.object_buffer.len()
+    }
+
+    /// Replaces the objects culled from now on. Objects beyond the capacity are dropped.
+    /// Fails if the GPU is still reading the previous frame's objects.
+    pub fn upload_objects(&mut self, objects: &[CullObject]) -> Result<(), HostAccessError> {
+        let mut dst = self.object_buffer.write()?;
+        let count = objects.len().min(dst.len());
+        dst[..count].copy_from_slice(&objects[..count]);
+        self.object_count = count as u32;
+        self.draws.set_count(self.object_count);
+        Ok(())
+    }
+
+    /// Number of objects that passed the last completed cull, for statistics.
+    /// Fails while that cull is still running.
+    pub fn visible_count(&self) -> Result<u32, HostAccessError> {
+        Ok(self.visible_count.read()?[0])
+    }
+
+    pub fn get_pipeline(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_frustum_cull::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// Binds the objects, the output draws and the visible counter, matching `cs_frustum_cull`.
+    pub fn get_descriptor_set(
+        &self,
+        context: &ComputeContext,
+        pipeline: &Arc<ComputePipeline>,
+    ) -> Arc<PersistentDescriptorSet> {
+        context.bind(
+            pipeline,
+            [
+                WriteDescriptorSet::buffer(0, self.object_buffer.clone()),
+                WriteDescriptorSet::buffer(1, self.draws.buffer()),
+                WriteDescriptorSet::buffer(2, self.visible_count.clone()),
+            ],
+        )
+    }
+
+    /// Records the cull against `frustum`. Must be recorded outside of a render pass, before
+    /// `record_draw`.
+    pub fn record_cull(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        frustum: &Frustum,
+    ) {
+        // Culled objects leave zeroed commands behind, which draw nothing.
+        builder
+            .fill_buffer(self.draws.buffer().reinterpret::<[u32]>(), 0)
+            .unwrap()
+            .fill_buffer(self.visible_count.clone(), 0)
+            .unwrap();
+        ComputeContext::record_dispatch(
+            builder,
+            pipeline,
+            descriptor_set,
+            Some(CullParams {
+                planes: frustum.planes.map(Into::into),
+                object_count: self.object_count,
+            }),
+            ComputeContext::workgroups([self.object_count, 1, 1], [WORKGROUP_SIZE, 1, 1]),
+        );
+    }
+
+    /// `cs_hi_z_cull`, which also skips objects hidden behind the depth in a `HiZBuffer`.
+    pub fn get_pipeline_hi_z(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_hi_z_cull::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// Like `get_descriptor_set`, also binding `hi_z` at binding 3 for `get_pipeline_hi_z`.
+    pub fn get_descriptor_set_hi_z(
+        &self,
+        context: &ComputeContext,
+        pipeline: &Arc<ComputePipeline>,
+        hi_z: &HiZBuffer,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        context.bind(
+            pipeline,
+            [
+                WriteDescriptorSet::buffer(0, self.object_buffer.clone()),
+                WriteDescriptorSet::buffer(1, self.draws.buffer()),
+                WriteDescriptorSet::buffer(2, self.visible_count.clone()),
+                WriteDescriptorSet::image_view_sampler(3, hi_z.view(), sampler),
+            ],
+        )
+    }
+
+    /// Records the cull against the frustum of `view_projection` and the Hi-Z pyramid, with a
+    /// `get_pipeline_hi_z` pipeline. The pyramid is usually built from last frame's depth, so
+    /// `view_projection` should be last frame's too, and objects coming out from behind an
+    /// occluder appear a frame late. Must be recorded outside of a render pass, after
+    /// `HiZBuffer::record_build` and before `record_draw`.
+    pub fn record_cull_hi_z(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        view_projection: &Matrix4<f32>,
+    ) {
+        builder
+            .fill_buffer(self.draws.buffer().reinterpret::<[u32]>(), 0)
+            .unwrap()
+            .fill_buffer(self.visible_count.clone(), 0)
+            .unwrap();
+        ComputeContext::record_dispatch(
+            builder,
+            pipeline,
+            descriptor_set,
+            Some(HiZCullParams {
+                view_projection: (*view_projection).into(),
+                object_count: self.object_count,
+            }),
+            ComputeContext::workgroups([self.object_count, 1, 1], [WORKGROUP_SIZE, 1, 1]),
+        );
+    }
+
+    /// `cs_frustum_cull_bda`, which reads and writes the culler's buffers through device
+    /// addresses in its push constants and so needs no descriptor set. Needs
+    /// `VulkanConnection::buffer_device_address_enabled`.
+    pub fn get_pipeline_bda(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_frustum_cull_bda::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// Culls against `frustum` with a `get_pipeline_bda` pipeline after `after`, returning the
+    /// future the frame's draw must wait on, e.g. through `Renderer::chain_compute`.
+    ///
+    /// vulkano can't see accesses through addresses, so the clear and the cull are separate
+    /// submissions ordered by semaphores rather than commands in the frame's command buffer.
+    /// For the same reason, `upload_objects` must not run while a cull may still read them.
+    pub fn submit_cull_bda(
+        &self,
+        context: &ComputeContext,
+        after: Box<dyn GpuFuture>,
+        pipeline: Arc<ComputePipeline>,
+        frustum: &Frustum,
+    ) -> Box<dyn GpuFuture> {
+        let cleared = context.submit(after, |builder| {
+            builder
+                .fill_buffer(self.draws.buffer().reinterpret::<[u32]>(), 0)
+                .unwrap()
+                .fill_buffer(self.visible_count.clone(), 0)
+                .unwrap();
+        });
+        let params = CullAddressParams {
+            planes: frustum.planes.map(Into::into),
+            object_count: self.object_count,
+            padding: 0,
+            objects: self.object_buffer.device_address().unwrap().get(),
+            draws: self.draws.buffer().device_address().unwrap().get(),
+            visible_count: self.visible_count.device_address().unwrap().get(),
+        };
+        let culled = context.submit(cleared.then_signal_semaphore().boxed(), |builder| {
+            builder
+                .bind_pipeline_compute(pipeline.clone())
+                .unwrap()
+                .push_constants(pipeline.layout().clone(), 0, params)
+                .unwrap()
+                .dispatch(ComputeContext::workgroups(
+                    [self.object_count, 1, 1],
+                    [WORKGROUP_SIZE, 1, 1],
+                ))
+                .unwrap();
+        });
+        culled.then_signal_semaphore().boxed()
+    }
+
+    /// Records the surviving draws. The graphics pipeline, descriptor sets and the shared
+    /// vertex/index buffers must already be bound.
+    pub fn record_draw(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        self.draws.record_draw(builder);
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::DrawIndirectCommand;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+
+use super::buffer_structs::GpuParticle;
+use super::buffer_structs::ParticleParams;
+use super::compute::ComputeContext;
+use super::indirect::IndirectBuffer;
+use super::shaders;
+use super::sprites::SpriteInstance;
+
+/// Threads per workgroup of `cs_particles`.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// Emitter of a `GpuParticleSystem`. Curves are reduced to start/end pairs blended over the
+/// particle's life, since the whole simulation runs in the compute shader.
+#[derive(Clone, Debug)]
+pub struct GpuEmitterConfig {
+    pub position: Vector3<f32>,
+    /// Particles spawned per second.
+    pub rate: f32,
+    /// Lifetime in seconds, picked uniformly from the range.
+    pub lifetime: (f32, f32),
+    pub initial_velocity: Vector3<f32>,
+    /// Random velocity added per axis, picked uniformly from `-spread..spread`.
+    pub velocity_spread: Vector3<f32>,
+    pub acceleration: Vector3<f32>,
+    /// Size at birth and at death.
+    pub size: (f32, f32),
+    /// Color at birth and at death.
+    pub color: ([f32; 4], [f32; 4]),
+}
+impl Default for GpuEmitterConfig {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zeros(),
+            rate: 100_000.0,
+            lifetime: (1.0, 2.0),
+            initial_velocity: Vector3::new(0.0, 1.0, 0.0),
+            velocity_spread: Vector3::new(0.5, 0.2, 0.5),
+            acceleration: Vector3::zeros(),
+            size: (0.02, 0.0),
+            color: ([1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 0.0]),
+        }
+    }
+}
+
+/// Particles simulated entirely on the GPU. Each frame a compute pass ages, moves and respawns
+/// every particle, then writes the live ones as `SpriteInstance`s and their count straight into
+/// an indirect draw command, so the CPU never touches per-particle data. Drawn with the
+/// `SpriteRenderer` pipeline.
+pub struct GpuParticleSystem {
+    pub emitter: GpuEmitterConfig,
+    particle_buffer: Subbuffer<[GpuParticle]>,
+    instance_buffer: Subbuffer<[SpriteInstance]>,
+    indirect_buffer: IndirectBuffer<DrawIndirectCommand>,
+    spawn_counter: Subbuffer<[u32]>,
+    time: f32,
+    /// Fractional particles carried over between updates so low rates still emit.
+    spawn_accumulator: f32,
+    cleared: bool,
+}
+impl GpuParticleSystem {
+    pub fn new(context: &ComputeContext, emitter: GpuEmitterConfig, max_particles: u64) -> Self {
+        let particle_buffer =
+            context.create_storage_buffer("particles", max_particles, BufferUsage::empty());
+        let instance_buffer = context.create_storage_buffer(
+            "particle instances",
+            max_particles,
+            BufferUsage::VERTEX_BUFFER,
+        );
+        let mut indirect_buffer =
+            IndirectBuffer::new(context.memory_allocator(), context.memory_tracker(), 1);
+        indirect_buffer
+            .upload_commands(&[DrawIndirectCommand {
+                vertex_count: 6,
+                instance_count: 0,
+                first_vertex: 0,
+                first_instance: 0,
+            }])
+            .unwrap();
+        let spawn_counter =
+            context.create_storage_buffer("particle spawn counter", 1, BufferUsage::empty());
+
+        Self {
+            emitter,
+            particle_buffer,
+            instance_buffer,
+            indirect_buffer,
+            spawn_counter,
+            time: 0.0,
+            spawn_accumulator: 0.0,
+            cleared: false,
+        }
+    }
+
+    pub fn max_particles(&self) -> u64 {
+        self.particle_buffer.len()
+    }
+
+    pub fn get_compute_pipeline(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_particles::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// Binds the particle state, instance output, indirect command and spawn counter, matching `cs_particles`.
+    pub fn get_compute_descriptor_set(
+        &self,
+        context: &ComputeContext,
+        pipeline: &Arc<ComputePipeline>,
+    ) -> Arc<PersistentDescriptorSet> {
+        context.bind(
+            pipeline,
+            [
+                WriteDescriptorSet::buffer(0, self.particle_buffer.clone()),
+                WriteDescriptorSet::buffer(1, self.instance_buffer.clone()),
+                WriteDescriptorSet::buffer(2, self.indirect_buffer.buffer()),
+                WriteDescriptorSet::buffer(3, self.spawn_counter.clone()),
+            ],
+        )
+    }
+
+    /// Records the simulation step for `dt` seconds. Must be recorded outside of a render pass,
+    /// before `record_draw`.
+    pub fn record_update(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        dt: f32,
+    ) {
+        // Zeroed particles have an age and lifetime of 0, i.e. they are all dead.
+        if !self.cleared {
+            builder
+                .fill_buffer(self.particle_buffer.clone().reinterpret::<[u32]>(), 0)
+                .unwrap();
+            self.cleared = true;
+        }
+
+        self.time += dt;
+        self.spawn_accumulator += self.emitter.rate * dt;
+        let spawn_count = self.spawn_accumulator.floor();
+        self.spawn_accumulator -= spawn_count;
+
+        let particle_count = self.max_particles() as u32;
+        let emitter = &self.emitter;
+        let params = ParticleParams {
+            emitter_position: emitter.position.push(1.0).into(),
+            initial_velocity: emitter.initial_velocity.push(0.0).into(),
+            velocity_spread: emitter.velocity_spread.push(0.0).into(),
+            acceleration: emitter.acceleration.push(0.0).into(),
+            start_color: emitter.color.0,
+            end_color: emitter.color.1,
+            lifetime: [emitter.lifetime.0, emitter.lifetime.1],
+            size: [emitter.size.0, emitter.size.1],
+            dt,
+            time: self.time,
+            particle_count,
+            spawn_count: spawn_count as u32,
+        };
+
+        builder
+            .fill_buffer(
+                self.indirect_buffer
+                    .buffer()
+                    .reinterpret::<[u32]>()
+                    .slice(1..2),
+                0,
+            )
+            .unwrap()
+            .fill_buffer(self.spawn_counter.clone(), 0)
+            .unwrap();
+        ComputeContext::record_dispatch(
+            builder,
+            pipeline,
+            descriptor_set,
+            Some(params),
+            ComputeContext::workgroups([particle_count, 1, 1], [WORKGROUP_SIZE, 1, 1]),
+        );
+    }
+
+    /// Draws the particles written by the last `record_update` with a pipeline and descriptor set
+    /// from `SpriteRenderer`. The instance count comes from the GPU.
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.instance_buffer.clone())
+            .unwrap();
+        self.indirect_buffer.record_draw(builder);
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::max_mip_levels;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::image::view::ImageViewCreateInfo;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageSubresourceRange;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::ComputePipeline;
+
+use super::compute::ComputeContext;
+use super::memory_stats::MemoryCategory;
+use super::shaders;
+
+/// Threads per workgroup of `cs_hi_z_reduce`, in each direction.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// A hierarchical depth buffer: a mip chain where every texel holds the farthest depth of the
+/// texels it covers in the depth buffer. A few reads from the right level tell whether a
+/// screen rectangle is entirely behind what was drawn, e.g. for
+/// `GpuFrustumCuller::record_cull_hi_z`.
+///
+/// Built from a depth buffer with the usual `Less` test and 0..1 range; reversed depth would
+/// need the nearest depth instead.
+pub struct HiZBuffer {
+    pyramid: Arc<ImageView>,
+    /// One view per mip level, written by the reduction.
+    levels: Vec<Arc<ImageView>>,
+}
+impl HiZBuffer {
+    /// A pyramid for depth buffers of `extent`, with every mip level down to 1x1.
+    pub fn new(context: &ComputeContext, extent: [u32; 2]) -> Self {
+        let extent = [extent[0].max(1), extent[1].max(1), 1];
+        let image = Image::new(
+            context.memory_allocator(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32_SFLOAT,
+                extent,
+                mip_levels: max_mip_levels(extent),
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        context
+            .memory_tracker()
+            .track_image(MemoryCategory::Storage, "hi-z pyramid", &image);
+        let levels = (0..image.mip_levels())
+            .map(|level| {
+                ImageView::new(
+                    image.clone(),
+                    ImageViewCreateInfo {
+                        subresource_range: ImageSubresourceRange {
+                            mip_levels: level..level + 1,
+                            ..image.subresource_range()
+                        },
+                        ..ImageViewCreateInfo::from_image(&image)
+                    },
+                )
+                .unwrap()
+            })
+            .collect();
+        Self {
+            pyramid: ImageView::new_default(image).unwrap(),
+            levels,
+        }
+    }
+
+    /// All levels, for sampling with `texelFetch` at a level.
+    pub fn view(&self) -> Arc<ImageView> {
+        self.pyramid.clone()
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        let extent = self.pyramid.image().extent();
+        [extent[0], extent[1]]
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub fn get_pipeline(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_hi_z_reduce::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// One set per level, each reading the level above, or `depth` for the first, and writing
+    /// its own. `depth` must be a depth-aspect view of the pyramid's extent, e.g.
+    /// `RenderTargets::sampled_depth`. Get them again when the depth buffer is replaced.
+    pub fn get_descriptor_sets(
+        &self,
+        context: &ComputeContext,
+        pipeline: &Arc<ComputePipeline>,
+        depth: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Vec<Arc<PersistentDescriptorSet>> {
+        let sources = std::iter::once(depth).chain(self.levels.iter().cloned());
+        sources
+            .zip(&self.levels)
+            .map(|(source, destination)| {
+                context.bind(
+                    pipeline,
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, source, sampler.clone()),
+                        WriteDescriptorSet::image_view(1, destination.clone()),
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    /// Rebuilds the pyramid from the depth buffer, one dispatch per level. Must be recorded
+    /// outside of a render pass, after the pass that drew the depth; the builder inserts the
+    /// barriers between the levels.
+    pub fn record_build(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_sets: &[Arc<PersistentDescriptorSet>],
+    ) {
+        let [width, height] = self.extent();
+        for (level, descriptor_set) in descriptor_sets.iter().enumerate() {
+            let size = [(width >> level).max(1), (height >> level).max(1), 1];
+            ComputeContext::record_dispatch(
+                builder,
+                pipeline.clone(),
+                descriptor_set.clone(),
+                None::<u32>,
+                ComputeContext::workgroups(size, [WORKGROUP_SIZE, WORKGROUP_SIZE, 1]),
+            );
+        }
+    }
+}
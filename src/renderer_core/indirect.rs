@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::DrawIndexedIndirectCommand;
+use vulkano::command_buffer::DrawIndirectCommand;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::device::DeviceOwned;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync::HostAccessError;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// A command layout that can be drawn from an indirect buffer.
+pub trait IndirectCommand: BufferContents + Copy {
+    fn record(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        commands: Subbuffer<[Self]>,
+    );
+}
+impl IndirectCommand for DrawIndirectCommand {
+    fn record(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        commands: Subbuffer<[Self]>,
+    ) {
+        builder.draw_indirect(commands).unwrap();
+    }
+}
+impl IndirectCommand for DrawIndexedIndirectCommand {
+    fn record(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        commands: Subbuffer<[Self]>,
+    ) {
+        builder.draw_indexed_indirect(commands).unwrap();
+    }
+}
+
+/// Draw commands read by the GPU at draw time. Filled from the CPU with `upload_commands`, or by a
+/// compute shader that writes `buffer()` directly, which is what GPU-driven rendering builds on.
+///
+/// A non-zero `first_instance` needs the `draw_indirect_first_instance` feature.
+pub struct IndirectBuffer<C: IndirectCommand> {
+    buffer: Subbuffer<[C]>,
+    count: u32,
+}
+impl<C: IndirectCommand> IndirectBuffer<C> {
+    /// Room for `capacity` commands, all of which are drawn until `upload_commands` or
+    /// `set_count` says otherwise.
+    /// With `buffer_device_address` enabled, compute shaders can also write the commands
+    /// through the buffer's device address.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        capacity: u64,
+    ) -> Self {
+        let device_address = device_address_usage(&memory_allocator);
+        let buffer = Buffer::new_slice(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::TRANSFER_DST
+                    | device_address,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(MemoryCategory::Other, "indirect commands", buffer.buffer());
+        Self {
+            count: buffer.len() as u32,
+            buffer,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.buffer.len()
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Sets how many commands from the start of the buffer are drawn, e.g. after a compute pass
+    /// has written them.
+    pub fn set_count(&mut self, count: u32) {
+        self.count = count.min(self.capacity() as u32);
+    }
+
+    /// The whole buffer, for binding as a storage buffer or clearing with `fill_buffer`.
+    pub fn buffer(&self) -> Subbuffer<[C]> {
+        self.buffer.clone()
+    }
+
+    /// Replaces the commands drawn from now on. Commands beyond the capacity are dropped.
+    /// Fails if the GPU is still reading the previous frame's commands.
+    pub fn upload_commands(&mut self, commands: &[C]) -> Result<(), HostAccessError> {
+        let mut dst = self.buffer.write()?;
+        let count = commands.len().min(dst.len());
+        dst[..count].copy_from_slice(&commands[..count]);
+        self.count = count as u32;
+        Ok(())
+    }
+
+    /// Records the draws. The pipeline, descriptor sets and vertex/index buffers must already be
+    /// bound. Without the `multi_draw_indirect` feature each command is recorded as its own draw.
+    pub fn record_draw(&self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        if self.count == 0 {
+            return;
+        }
+        let commands = self.buffer.clone().slice(..self.count as u64);
+        if self.count == 1 || builder.device().enabled_features().multi_draw_indirect {
+            C::record(builder, commands);
+        } else {
+            for i in 0..self.count as u64 {
+                C::record(builder, commands.clone().slice(i..i + 1));
+            }
+        }
+    }
+}
+
+/// `SHADER_DEVICE_ADDRESS` where the device has `buffer_device_address` enabled, for buffers
+/// that may be accessed through addresses.
+pub(crate) fn device_address_usage(memory_allocator: &StandardMemoryAllocator) -> BufferUsage {
+    if memory_allocator
+        .device()
+        .enabled_features()
+        .buffer_device_address
+    {
+        BufferUsage::SHADER_DEVICE_ADDRESS
+    } else {
+        BufferUsage::empty()
+    }
+}
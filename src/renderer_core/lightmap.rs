@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::image::sampler::Filter;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::sampler::SamplerAddressMode;
+use vulkano::image::sampler::SamplerCreateInfo;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use crate::lightmap::LightmapMesh;
+
+use super::buffer_structs::LightmapMaterial;
+use super::buffer_structs::LightmapVertex;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::RendererCore;
+
+/// GPU copy of a mesh with baked lighting. The fragment shader multiplies the material's base
+/// color by the lightmap, so static lighting costs one texture read.
+pub struct LightmappedMesh {
+    vertex_buffer: Subbuffer<[LightmapVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+}
+impl LightmappedMesh {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        mesh: &LightmapMesh,
+    ) -> Self {
+        let vertices = mesh
+            .positions
+            .iter()
+            .zip(&mesh.normals)
+            .zip(&mesh.lightmap_uvs)
+            .map(|((&position, &normal), &lightmap_uv)| LightmapVertex {
+                position,
+                normal,
+                lightmap_uv,
+            });
+        let host_writable = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            vertices,
+        )
+        .unwrap();
+        let index_buffer = Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            host_writable,
+            mesh.indices.iter().copied(),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "lightmapped mesh vertices",
+            vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "lightmapped mesh indices",
+            index_buffer.buffer(),
+        );
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+        }
+    }
+
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_lightmap::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_lightmap::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+
+        RendererCore::build_pipeline(
+            device,
+            vs,
+            fs,
+            LightmapVertex::per_vertex(),
+            render_pass,
+            viewport,
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the lightmap, e.g. from `Resources::texture_view`,
+    /// with a clamped bilinear sampler at 1, matching `vs_lightmap` and `fs_lightmap`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        lightmap: Arc<ImageView>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let sampler = Sampler::new(
+            pipeline.device().clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let descriptor_set_layout = pipeline.layout().set_layouts()[0].clone();
+        // Each call makes its own sampler, so there is nothing to share in the cache.
+        PersistentDescriptorSet::new(
+            descriptor_sets.allocator(),
+            descriptor_set_layout,
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, lightmap, sampler),
+            ],
+            [],
+        )
+        .unwrap()
+    }
+
+    /// `base_color` is usually the mesh's `Material::base_color`.
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        base_color: [f32; 4],
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                LightmapMaterial { base_color },
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(self.index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::ops::BitOr;
+use std::ops::BitOrAssign;
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::color_blend::ColorComponents;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::shader::ShaderModule;
+
+use super::bindless::BindlessMaterial;
+use super::bindless::BindlessTextures;
+use super::blend::BlendMode;
+use super::buffer_structs::MaterialParams;
+use super::buffer_structs::MeshVertex;
+use super::buffer_structs::SkinnedVertex;
+use super::descriptor_sets::DescriptorSets;
+use super::object_data::ObjectData;
+use super::shaders;
+use super::stencil::StencilMode;
+
+/// Shader features a material is drawn with. Each combination is its own pipeline, built the
+/// first time it is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialFeatures(u32);
+impl MaterialFeatures {
+    pub const NONE: MaterialFeatures = MaterialFeatures(0);
+    /// Multiplies the base color by `Material::base_color_texture`, sampled with the mesh's
+    /// first UV set.
+    pub const TEXTURED: MaterialFeatures = MaterialFeatures(1 << 0);
+    /// Lambert shading from one directional light; unlit materials show their color as is.
+    pub const LIT: MaterialFeatures = MaterialFeatures(1 << 1);
+    /// Draws `SkinnedMesh`es instead of `Resources` meshes.
+    pub const SKINNED: MaterialFeatures = MaterialFeatures(1 << 2);
+    /// Blends by the color's alpha instead of overwriting. Sort such draws back to front.
+    pub const ALPHA_BLEND: MaterialFeatures = MaterialFeatures(1 << 3);
+    /// Blends colors already multiplied by their alpha, see `BlendMode::Premultiplied`.
+    pub const PREMULTIPLIED_BLEND: MaterialFeatures = MaterialFeatures(1 << 4);
+    /// Adds the color weighted by its alpha, see `BlendMode::Additive`.
+    pub const ADDITIVE_BLEND: MaterialFeatures = MaterialFeatures(1 << 5);
+    /// Reads each instance's model matrix from an `ObjectData`, so a whole draw list shares
+    /// one descriptor set from `MaterialPipelines::get_object_descriptor_set`. Ignored with
+    /// `SKINNED`.
+    pub const PER_OBJECT: MaterialFeatures = MaterialFeatures(1 << 6);
+    const BLEND_BITS: MaterialFeatures = MaterialFeatures(0b111 << 3);
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: MaterialFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: MaterialFeatures) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: MaterialFeatures) {
+        self.0 &= !other.0;
+    }
+
+    /// How the material blends, from its blend bit. With more than one set, the first of
+    /// alpha, premultiplied and additive wins.
+    pub fn blend_mode(self) -> BlendMode {
+        if self.contains(MaterialFeatures::ALPHA_BLEND) {
+            BlendMode::Alpha
+        } else if self.contains(MaterialFeatures::PREMULTIPLIED_BLEND) {
+            BlendMode::Premultiplied
+        } else if self.contains(MaterialFeatures::ADDITIVE_BLEND) {
+            BlendMode::Additive
+        } else {
+            BlendMode::Opaque
+        }
+    }
+
+    /// These features with `blend_mode` in place of any other.
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> MaterialFeatures {
+        self.remove(MaterialFeatures::BLEND_BITS);
+        self.insert(match blend_mode {
+            BlendMode::Opaque => MaterialFeatures::NONE,
+            BlendMode::Alpha => MaterialFeatures::ALPHA_BLEND,
+            BlendMode::Premultiplied => MaterialFeatures::PREMULTIPLIED_BLEND,
+            BlendMode::Additive => MaterialFeatures::ADDITIVE_BLEND,
+        });
+        self
+    }
+}
+impl BitOr for MaterialFeatures {
+    type Output = MaterialFeatures;
+
+    fn bitor(self, other: MaterialFeatures) -> MaterialFeatures {
+        MaterialFeatures(self.0 | other.0)
+    }
+}
+impl BitOrAssign for MaterialFeatures {
+    fn bitor_assign(&mut self, other: MaterialFeatures) {
+        self.insert(other);
+    }
+}
+
+/// Pipelines for subpass 0 of a render pass, one per `MaterialFeatures` combination and
+/// `StencilMode` in use. Where the render pass has depth, opaque pipelines test and write it and
+/// blended ones only test it, so draw the opaque batches of a `DrawList` before its
+/// transparent ones.
+/// All of them share `fs_material` and one of `vs_material`, `vs_material_skinned` and
+/// `vs_material_objects`, specialized per combination, so unused features cost nothing in the
+/// shader. Textures are read from the
+/// bindless array, so this needs `VulkanConnection::descriptor_indexing_enabled`, and every
+/// pipeline takes `Resources::bindless_descriptor_set` at `BINDLESS_SET`.
+pub struct MaterialPipelines {
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    viewport: Viewport,
+    vs: Arc<ShaderModule>,
+    vs_skinned: Arc<ShaderModule>,
+    vs_objects: Arc<ShaderModule>,
+    fs: Arc<ShaderModule>,
+    /// Shared by every permutation with the same vertex shader.
+    layout: Arc<PipelineLayout>,
+    skinned_layout: Arc<PipelineLayout>,
+    objects_layout: Arc<PipelineLayout>,
+    /// Keyed by features, stencil mode and whether the pipeline is for the depth pre-pass.
+    pipelines: HashMap<(MaterialFeatures, StencilMode, bool), Arc<GraphicsPipeline>>,
+    depth_prepass: bool,
+}
+impl MaterialPipelines {
+    /// `fs_material` specialization constant ids.
+    const TEXTURED_CONSTANT: u32 = 0;
+    const LIT_CONSTANT: u32 = 1;
+
+    pub fn new(
+        device: Arc<Device>,
+        bindless: &BindlessTextures,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Self {
+        let vs =
+            shaders::vs_material::load(device.clone()).expect("failed to create shader module");
+        let vs_skinned = shaders::vs_material_skinned::load(device.clone())
+            .expect("failed to create shader module");
+        let vs_objects = shaders::vs_material_objects::load(device.clone())
+            .expect("failed to create shader module");
+        let fs =
+            shaders::fs_material::load(device.clone()).expect("failed to create shader module");
+        let layout_of = |vs: &Arc<ShaderModule>| {
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs.entry_point("main").unwrap()),
+                PipelineShaderStageCreateInfo::new(fs.entry_point("main").unwrap()),
+            ];
+            bindless.pipeline_layout(device.clone(), &stages)
+        };
+        Self {
+            layout: layout_of(&vs),
+            skinned_layout: layout_of(&vs_skinned),
+            objects_layout: layout_of(&vs_objects),
+            device,
+            render_pass,
+            viewport,
+            vs,
+            vs_skinned,
+            vs_objects,
+            fs,
+            pipelines: HashMap::new(),
+            depth_prepass: false,
+        }
+    }
+
+    /// Points the pipelines at a new render pass or size, e.g. after the swapchain was
+    /// recreated. Cached pipelines are dropped and rebuilt as they are drawn again.
+    pub fn set_target(&mut self, render_pass: Arc<RenderPass>, viewport: Viewport) {
+        self.render_pass = render_pass;
+        self.viewport = viewport;
+        self.pipelines.clear();
+    }
+
+    /// The pipeline for `features`, built on first use.
+    pub fn get(&mut self, features: MaterialFeatures) -> Arc<GraphicsPipeline> {
+        self.get_masked(features, StencilMode::Disabled)
+    }
+
+    /// The pipeline for `features` using the stencil buffer as `stencil` says, e.g. a
+    /// material's `Material::stencil`, built on first use.
+    pub fn get_masked(
+        &mut self,
+        features: MaterialFeatures,
+        stencil: StencilMode,
+    ) -> Arc<GraphicsPipeline> {
+        self.get_cached(features, stencil, false)
+    }
+
+    /// Whether opaque pipelines expect a depth pre-pass, off by default. While on, opaque
+    /// pipelines test depth for less or equal without writing it, so each pixel is shaded
+    /// once, by the surface the pre-pass left in front. Worth it for expensive fragment
+    /// shading and much overdraw. Cached pipelines are dropped and rebuilt as they are drawn
+    /// again.
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        if depth_prepass != self.depth_prepass {
+            self.depth_prepass = depth_prepass;
+            self.pipelines.clear();
+        }
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    /// The depth pre-pass pipeline for `features`, built on first use: the same vertex shader
+    /// and layout without a fragment shader, writing nothing but depth. Draw every opaque
+    /// batch with these first, e.g. `DrawList::opaque_batches`, binding the same descriptor
+    /// sets as for the material pass, then all batches with `get` in the same subpass. Only
+    /// `SKINNED` and `PER_OBJECT` of `features` matter.
+    pub fn get_depth_only(&mut self, features: MaterialFeatures) -> Arc<GraphicsPipeline> {
+        let vertex_features = MaterialFeatures::SKINNED | MaterialFeatures::PER_OBJECT;
+        let features = MaterialFeatures(features.bits() & vertex_features.bits());
+        self.get_cached(features, StencilMode::Disabled, true)
+    }
+
+    fn get_cached(
+        &mut self,
+        features: MaterialFeatures,
+        stencil: StencilMode,
+        depth_only: bool,
+    ) -> Arc<GraphicsPipeline> {
+        let key = (features, stencil, depth_only);
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return pipeline.clone();
+        }
+        let pipeline = self.build_pipeline(features, stencil, depth_only);
+        self.pipelines.insert(key, pipeline.clone());
+        pipeline
+    }
+
+    /// Builds the pipelines of `features` ahead of time, e.g. during loading, so the first
+    /// frame using them does not hitch.
+    pub fn prewarm(&mut self, features: impl IntoIterator<Item = MaterialFeatures>) {
+        for features in features {
+            self.get(features);
+        }
+    }
+
+    /// Permutations built so far.
+    pub fn cached_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    /// Binds `mvp_buffer` at binding 0 for the pipelines without `SKINNED`. Skinned ones take
+    /// `SkinnedMesh::get_descriptor_set`, which also binds the joint matrices.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: &Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, mvp_buffer)],
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and `objects` at binding 1 for the pipelines with
+    /// `PER_OBJECT`, whose model matrix is `mvp_buffer`'s times the instance's. For this frame
+    /// only, like `objects`.
+    pub fn get_object_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: &Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        objects: &ObjectData,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.transient(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::buffer(1, objects.buffer()),
+            ],
+        )
+    }
+
+    /// Sets the material of the following draws, e.g. from `Resources::bindless_material`.
+    /// `light_direction` points toward the light and only matters with `LIT`.
+    pub fn push_material(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        material: &BindlessMaterial,
+        light_direction: &Vector3<f32>,
+    ) {
+        let light_direction = light_direction.normalize();
+        builder
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                MaterialParams {
+                    base_color: material.base_color,
+                    light_direction: [light_direction.x, light_direction.y, light_direction.z, 0.0],
+                    base_color_texture: material.base_color_texture,
+                },
+            )
+            .unwrap();
+    }
+
+    fn build_pipeline(
+        &self,
+        features: MaterialFeatures,
+        stencil: StencilMode,
+        depth_only: bool,
+    ) -> Arc<GraphicsPipeline> {
+        let skinned = features.contains(MaterialFeatures::SKINNED);
+        let (vs, vertex_description, layout) = if skinned {
+            (
+                &self.vs_skinned,
+                SkinnedVertex::per_vertex(),
+                &self.skinned_layout,
+            )
+        } else if features.contains(MaterialFeatures::PER_OBJECT) {
+            (
+                &self.vs_objects,
+                MeshVertex::per_vertex(),
+                &self.objects_layout,
+            )
+        } else {
+            (&self.vs, MeshVertex::per_vertex(), &self.layout)
+        };
+        let vs = vs.entry_point("main").unwrap();
+        let fs = self
+            .fs
+            .specialize(
+                [
+                    (
+                        Self::TEXTURED_CONSTANT,
+                        features.contains(MaterialFeatures::TEXTURED).into(),
+                    ),
+                    (
+                        Self::LIT_CONSTANT,
+                        features.contains(MaterialFeatures::LIT).into(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+        let vertex_input_state = vertex_description
+            .definition(&vs.info().input_interface)
+            .unwrap();
+        let mut stages = vec![PipelineShaderStageCreateInfo::new(vs)];
+        if !depth_only {
+            stages.push(PipelineShaderStageCreateInfo::new(fs));
+        }
+        let subpass = Subpass::from(self.render_pass.clone(), 0).unwrap();
+        let blend_mode = features.blend_mode();
+        let has_depth = subpass.subpass_desc().depth_stencil_attachment.is_some();
+        let writes_color = stencil.writes_color() && !depth_only;
+        // Masks drawn only into the stencil leave depth alone too.
+        let depth = (has_depth && stencil.writes_color()).then(|| {
+            if self.depth_prepass && blend_mode == BlendMode::Opaque && !depth_only {
+                DepthState {
+                    write_enable: false,
+                    compare_op: CompareOp::LessOrEqual,
+                }
+            } else {
+                blend_mode.depth_state()
+            }
+        });
+
+        GraphicsPipeline::new(
+            self.device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [self.viewport.clone()].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: (depth.is_some() || stencil.stencil_state().is_some()).then(
+                    || DepthStencilState {
+                        depth,
+                        stencil: stencil.stencil_state(),
+                        ..Default::default()
+                    },
+                ),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        blend: blend_mode.attachment_blend(),
+                        color_write_mask: if writes_color {
+                            ColorComponents::all()
+                        } else {
+                            ColorComponents::empty()
+                        },
+                        ..Default::default()
+                    },
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout.clone())
+            },
+        )
+        .unwrap()
+    }
+}
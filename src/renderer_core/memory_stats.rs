@@ -0,0 +1,433 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::Weak;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferUsage;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::Device;
+use vulkano::image::Image;
+use vulkano::memory::MemoryHeapFlags;
+use vulkano::Version;
+use vulkano::VulkanObject;
+
+/// What a tracked allocation is for, to see where memory goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    /// Vertex and index buffers.
+    Meshes,
+    Textures,
+    Uniforms,
+    /// Host-visible copies waiting to be uploaded.
+    Staging,
+    /// Storage buffers and images, e.g. of compute passes.
+    Storage,
+    /// Attachments drawn into, e.g. MSAA color and depth.
+    RenderTargets,
+    Other,
+}
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 7] = [
+        MemoryCategory::Meshes,
+        MemoryCategory::Textures,
+        MemoryCategory::Uniforms,
+        MemoryCategory::Staging,
+        MemoryCategory::Storage,
+        MemoryCategory::RenderTargets,
+        MemoryCategory::Other,
+    ];
+
+    /// The category of a buffer of `usage`, going by its most telling usage.
+    pub fn of_buffer_usage(usage: BufferUsage) -> MemoryCategory {
+        if usage.intersects(BufferUsage::VERTEX_BUFFER | BufferUsage::INDEX_BUFFER) {
+            MemoryCategory::Meshes
+        } else if usage.intersects(BufferUsage::UNIFORM_BUFFER) {
+            MemoryCategory::Uniforms
+        } else if usage.intersects(BufferUsage::STORAGE_BUFFER) {
+            MemoryCategory::Storage
+        } else if usage == BufferUsage::TRANSFER_SRC {
+            MemoryCategory::Staging
+        } else {
+            MemoryCategory::Other
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+enum Resource {
+    Buffer(Weak<Buffer>),
+    Image(Weak<Image>),
+}
+impl Resource {
+    fn is_alive(&self) -> bool {
+        match self {
+            Resource::Buffer(buffer) => buffer.strong_count() > 0,
+            Resource::Image(image) => image.strong_count() > 0,
+        }
+    }
+}
+
+struct Allocation {
+    resource: Resource,
+    category: MemoryCategory,
+    label: &'static str,
+    bytes: u64,
+    /// Where it was tracked from, while leak tracking is on.
+    backtrace: Option<Backtrace>,
+}
+
+/// A tracked buffer or image that outlived everything that should own it.
+#[derive(Debug)]
+pub struct LeakedResource {
+    pub label: &'static str,
+    pub category: MemoryCategory,
+    pub bytes: u64,
+    /// Where it was created, or `None` when leak tracking was off at the time.
+    pub backtrace: Option<String>,
+}
+
+/// Bytes held by the tracked buffers and images still alive, as of `MemoryTracker::usage`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    categories: [u64; MemoryCategory::ALL.len()],
+    pub buffer_bytes: u64,
+    pub image_bytes: u64,
+    pub buffers: u32,
+    pub images: u32,
+}
+impl MemoryUsage {
+    pub fn bytes(&self, category: MemoryCategory) -> u64 {
+        self.categories[category.index()]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buffer_bytes + self.image_bytes
+    }
+
+    /// Nonzero categories, largest first.
+    pub fn categories(&self) -> Vec<(MemoryCategory, u64)> {
+        let mut categories: Vec<_> = MemoryCategory::ALL
+            .into_iter()
+            .map(|category| (category, self.bytes(category)))
+            .filter(|(_, bytes)| *bytes > 0)
+            .collect();
+        categories.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        categories
+    }
+
+    /// Both together, e.g. of `RendererCore`, `Resources` and a `ComputeContext`.
+    pub fn combined(&self, other: &MemoryUsage) -> MemoryUsage {
+        let mut categories = self.categories;
+        for (bytes, other) in categories.iter_mut().zip(other.categories) {
+            *bytes += other;
+        }
+        MemoryUsage {
+            categories,
+            buffer_bytes: self.buffer_bytes + other.buffer_bytes,
+            image_bytes: self.image_bytes + other.image_bytes,
+            buffers: self.buffers + other.buffers,
+            images: self.images + other.images,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TrackerState {
+    allocations: Mutex<Allocations>,
+    leak_tracking: AtomicBool,
+}
+
+#[derive(Default)]
+struct Allocations {
+    /// Keyed by the resource's address, which can't be reused while its `Weak` is held.
+    by_address: HashMap<usize, Allocation>,
+    /// Size at which `insert` drops the dead entries, doubling what survives each time so
+    /// tracking stays amortized O(1) without `usage` or `leaks` ever being called.
+    prune_at: usize,
+}
+impl Allocations {
+    const MIN_PRUNE_AT: usize = 64;
+
+    fn prune(&mut self) {
+        self.by_address
+            .retain(|_, allocation| allocation.resource.is_alive());
+        self.prune_at = (self.by_address.len() * 2).max(Allocations::MIN_PRUNE_AT);
+    }
+}
+
+/// Counts the memory of buffers and images it is told about, by category, until they are
+/// dropped. Holds them weakly, so tracking never keeps anything alive. Clones share their
+/// allocations, so one tracker can be handed to everything that allocates for a renderer.
+///
+/// Counts what each resource needs, not the blocks the allocator reserved for it: allocators
+/// round up and keep freed blocks for reuse, so the driver's view in `MemoryBudget` is higher.
+#[derive(Clone, Default)]
+pub struct MemoryTracker {
+    state: Arc<TrackerState>,
+}
+impl MemoryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records where every resource tracked from now on was created, for `leaks`. Capturing
+    /// a backtrace per buffer is slow, so it's meant for debugging.
+    pub fn set_leak_tracking(&self, enabled: bool) {
+        self.state.leak_tracking.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn leak_tracking(&self) -> bool {
+        self.state.leak_tracking.load(Ordering::Relaxed)
+    }
+
+    /// `label` names what it's for in `leaks`, e.g. "staging buffer".
+    pub fn track_buffer(
+        &self,
+        category: MemoryCategory,
+        label: &'static str,
+        buffer: &Arc<Buffer>,
+    ) {
+        self.insert(
+            Arc::as_ptr(buffer) as usize,
+            Resource::Buffer(Arc::downgrade(buffer)),
+            category,
+            label,
+            buffer.memory_requirements().layout.size(),
+        );
+    }
+
+    /// Counts every plane of disjoint images.
+    pub fn track_image(&self, category: MemoryCategory, label: &'static str, image: &Arc<Image>) {
+        let bytes = image
+            .memory_requirements()
+            .iter()
+            .map(|requirements| requirements.layout.size())
+            .sum();
+        self.insert(
+            Arc::as_ptr(image) as usize,
+            Resource::Image(Arc::downgrade(image)),
+            category,
+            label,
+            bytes,
+        );
+    }
+
+    fn insert(
+        &self,
+        key: usize,
+        resource: Resource,
+        category: MemoryCategory,
+        label: &'static str,
+        bytes: u64,
+    ) {
+        let backtrace = self.leak_tracking().then(Backtrace::force_capture);
+        let mut allocations = self.state.allocations.lock().unwrap();
+        if allocations.by_address.len() >= allocations.prune_at {
+            allocations.prune();
+        }
+        allocations.by_address.insert(
+            key,
+            Allocation {
+                resource,
+                category,
+                label,
+                bytes,
+                backtrace,
+            },
+        );
+    }
+
+    fn alive_allocations(&self) -> MutexGuard<'_, Allocations> {
+        let mut allocations = self.state.allocations.lock().unwrap();
+        allocations.prune();
+        allocations
+    }
+
+    /// Everything tracked that is still alive. Call it once whatever owned the resources has
+    /// been dropped and the GPU is idle: anything left is kept alive by a stray `Arc`, e.g. a
+    /// clone stored in a cache or a command buffer never dropped.
+    pub fn leaks(&self) -> Vec<LeakedResource> {
+        self.alive_allocations()
+            .by_address
+            .values()
+            .map(|allocation| LeakedResource {
+                label: allocation.label,
+                category: allocation.category,
+                bytes: allocation.bytes,
+                backtrace: allocation
+                    .backtrace
+                    .as_ref()
+                    .map(|backtrace| backtrace.to_string()),
+            })
+            .collect()
+    }
+
+    /// Logs every one of `leaks` as a warning, with where it was created when known. Returns
+    /// how many there were.
+    pub fn report_leaks(&self) -> usize {
+        let leaks = self.leaks();
+        for leak in &leaks {
+            match &leak.backtrace {
+                Some(backtrace) => log::warn!(
+                    "leaked {} ({:?}, {} bytes), created at:\n{backtrace}",
+                    leak.label,
+                    leak.category,
+                    leak.bytes
+                ),
+                None => log::warn!(
+                    "leaked {} ({:?}, {} bytes)",
+                    leak.label,
+                    leak.category,
+                    leak.bytes
+                ),
+            }
+        }
+        leaks.len()
+    }
+
+    /// Of everything tracked that is still alive. Dropped resources count until the GPU work
+    /// using them has finished and released them.
+    pub fn usage(&self) -> MemoryUsage {
+        let allocations = self.alive_allocations();
+        let mut usage = MemoryUsage::default();
+        for allocation in allocations.by_address.values() {
+            usage.categories[allocation.category.index()] += allocation.bytes;
+            match allocation.resource {
+                Resource::Buffer(_) => {
+                    usage.buffer_bytes += allocation.bytes;
+                    usage.buffers += 1;
+                }
+                Resource::Image(_) => {
+                    usage.image_bytes += allocation.bytes;
+                    usage.images += 1;
+                }
+            }
+        }
+        usage
+    }
+}
+
+/// Reports the leaks of its trackers when dropped. Put it after everything owning their
+/// resources, e.g. as the last field of a struct, whose fields drop in order.
+pub struct LeakCheck {
+    trackers: Vec<MemoryTracker>,
+}
+impl LeakCheck {
+    /// Turns on leak tracking for each of `trackers`.
+    pub fn new(trackers: impl IntoIterator<Item = MemoryTracker>) -> Self {
+        let trackers: Vec<_> = trackers.into_iter().collect();
+        for tracker in &trackers {
+            tracker.set_leak_tracking(true);
+        }
+        Self { trackers }
+    }
+}
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let leaks: usize = self.trackers.iter().map(MemoryTracker::report_leaks).sum();
+        if leaks == 0 {
+            log::info!("no renderer resources leaked");
+        } else {
+            log::warn!("{leaks} renderer resources outlived their owners");
+        }
+    }
+}
+
+/// One memory heap as the driver sees it, across every process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub size: u64,
+    /// VRAM for device-local heaps.
+    pub device_local: bool,
+    /// How much this process can allocate from the heap before allocations start failing or
+    /// hurting performance. `None` without `VK_EXT_memory_budget`.
+    pub budget: Option<u64>,
+    /// How much this process has allocated from the heap, including what the renderer's
+    /// allocators reserved but haven't handed out. `None` without `VK_EXT_memory_budget`.
+    pub usage: Option<u64>,
+}
+
+/// Every heap of a device. Query it again whenever it's needed: budgets change as other
+/// applications allocate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub heaps: Vec<HeapBudget>,
+}
+impl MemoryBudget {
+    /// With budgets and usage where `device` enabled `VK_EXT_memory_budget`, only heap sizes
+    /// otherwise.
+    pub fn query(device: &Device) -> MemoryBudget {
+        let physical_device = device.physical_device();
+        let mut heaps: Vec<_> = physical_device
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .map(|heap| HeapBudget {
+                size: heap.size,
+                device_local: heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL),
+                budget: None,
+                usage: None,
+            })
+            .collect();
+        if device.enabled_extensions().ext_memory_budget {
+            let budget = Self::query_budget(physical_device);
+            for (index, heap) in heaps.iter_mut().enumerate() {
+                heap.budget = Some(budget.heap_budget[index]);
+                heap.usage = Some(budget.heap_usage[index]);
+            }
+        }
+        MemoryBudget { heaps }
+    }
+
+    /// vulkano doesn't expose the extension's properties, so they're read with the raw call.
+    fn query_budget(
+        physical_device: &PhysicalDevice,
+    ) -> ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT {
+        let mut budget = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = ash::vk::PhysicalDeviceMemoryProperties2 {
+            p_next: &mut budget as *mut _ as *mut std::ffi::c_void,
+            ..Default::default()
+        };
+        let instance = physical_device.instance();
+        let fns = instance.fns();
+        // SAFETY: `properties` only chains `budget`, which the enabled extension allows, and
+        // both outlive the call.
+        unsafe {
+            if instance.api_version() >= Version::V1_1 {
+                (fns.v1_1.get_physical_device_memory_properties2)(
+                    physical_device.handle(),
+                    &mut properties,
+                );
+            } else {
+                (fns.khr_get_physical_device_properties2
+                    .get_physical_device_memory_properties2_khr)(
+                    physical_device.handle(),
+                    &mut properties,
+                );
+            }
+        }
+        budget
+    }
+
+    /// Bytes in use in device-local heaps, or `None` without `VK_EXT_memory_budget`.
+    pub fn device_local_usage(&self) -> Option<u64> {
+        self.device_local_heaps().map(|heap| heap.usage).sum()
+    }
+
+    /// The budget of device-local heaps, or `None` without `VK_EXT_memory_budget`.
+    pub fn device_local_budget(&self) -> Option<u64> {
+        self.device_local_heaps().map(|heap| heap.budget).sum()
+    }
+
+    fn device_local_heaps(&self) -> impl Iterator<Item = &HeapBudget> {
+        self.heaps.iter().filter(|heap| heap.device_local)
+    }
+}
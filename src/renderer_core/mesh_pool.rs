@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+
+use super::buffer_structs::MeshVertex;
+use super::staging::StagingUploader;
+
+/// Frames after which retired ranges are reused: the frames in flight, plus the one being
+/// recorded when the mesh was removed.
+const RETIRE_FRAMES: u64 = 3;
+
+/// Free ranges of one buffer, sorted and merged, handed out first fit.
+struct FreeList {
+    free: Vec<Range<u64>>,
+}
+impl FreeList {
+    fn new(len: u64) -> Self {
+        Self {
+            free: std::iter::once(0..len).collect(),
+        }
+    }
+
+    fn allocate(&mut self, len: u64) -> Option<Range<u64>> {
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.end - range.start >= len)?;
+        let start = self.free[index].start;
+        self.free[index].start += len;
+        if self.free[index].is_empty() {
+            self.free.remove(index);
+        }
+        Some(start..start + len)
+    }
+
+    fn free(&mut self, range: Range<u64>) {
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+        // Merge with the neighbours it touches.
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    fn is_unused(&self, len: u64) -> bool {
+        matches!(self.free.as_slice(), [range] if *range == (0..len))
+    }
+}
+
+/// One vertex buffer and one index buffer that many meshes live in.
+struct MeshArena {
+    vertices: Subbuffer<[MeshVertex]>,
+    indices: Subbuffer<[u32]>,
+    free_vertices: FreeList,
+    free_indices: FreeList,
+}
+
+/// Where a mesh lives in the pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MeshAllocation {
+    pub arena: usize,
+    pub vertices: Range<u64>,
+    pub indices: Range<u64>,
+}
+
+/// Suballocates mesh vertex and index data from large device-local arenas instead of two
+/// buffers per mesh, so loading thousands of small meshes makes a handful of allocations, and
+/// meshes sharing an arena draw without rebinding buffers.
+pub(crate) struct MeshPool {
+    arenas: Vec<MeshArena>,
+    /// Ranges of removed meshes, with the frame they were removed in, until frames that may
+    /// still draw them have finished.
+    retired: VecDeque<(u64, MeshAllocation)>,
+    frame: u64,
+}
+impl MeshPool {
+    /// Vertices per arena; larger meshes get an arena of their own size.
+    const VERTEX_ARENA_LEN: u64 = 256 * 1024;
+    const INDEX_ARENA_LEN: u64 = 1024 * 1024;
+
+    pub fn new() -> Self {
+        Self {
+            arenas: Vec::new(),
+            retired: VecDeque::new(),
+            frame: 0,
+        }
+    }
+
+    /// Room for `vertex_count` vertices and `index_count` indices in one arena, creating an
+    /// arena if none has both.
+    pub fn allocate(
+        &mut self,
+        staging: &StagingUploader,
+        vertex_count: u64,
+        index_count: u64,
+    ) -> MeshAllocation {
+        for (arena, slot) in self.arenas.iter_mut().enumerate() {
+            let Some(vertices) = slot.free_vertices.allocate(vertex_count) else {
+                continue;
+            };
+            let Some(indices) = slot.free_indices.allocate(index_count) else {
+                slot.free_vertices.free(vertices);
+                continue;
+            };
+            return MeshAllocation {
+                arena,
+                vertices,
+                indices,
+            };
+        }
+
+        let vertex_len = Self::VERTEX_ARENA_LEN.max(vertex_count);
+        let index_len = Self::INDEX_ARENA_LEN.max(index_count);
+        let mut arena = MeshArena {
+            vertices: staging.device_buffer(
+                "mesh pool vertices",
+                BufferUsage::VERTEX_BUFFER,
+                vertex_len,
+            ),
+            indices: staging.device_buffer(
+                "mesh pool indices",
+                BufferUsage::INDEX_BUFFER,
+                index_len,
+            ),
+            free_vertices: FreeList::new(vertex_len),
+            free_indices: FreeList::new(index_len),
+        };
+        let allocation = MeshAllocation {
+            arena: self.arenas.len(),
+            vertices: arena.free_vertices.allocate(vertex_count).unwrap(),
+            indices: arena.free_indices.allocate(index_count).unwrap(),
+        };
+        self.arenas.push(arena);
+        allocation
+    }
+
+    pub fn vertex_buffer(&self, allocation: &MeshAllocation) -> Subbuffer<[MeshVertex]> {
+        self.arenas[allocation.arena]
+            .vertices
+            .clone()
+            .slice(allocation.vertices.clone())
+    }
+
+    pub fn index_buffer(&self, allocation: &MeshAllocation) -> Subbuffer<[u32]> {
+        self.arenas[allocation.arena]
+            .indices
+            .clone()
+            .slice(allocation.indices.clone())
+    }
+
+    /// The whole buffers of `arena`, for binding once for every mesh in it.
+    pub fn arena_buffers(&self, arena: usize) -> (Subbuffer<[MeshVertex]>, Subbuffer<[u32]>) {
+        let arena = &self.arenas[arena];
+        (arena.vertices.clone(), arena.indices.clone())
+    }
+
+    /// Frees the ranges of a removed mesh once the frames that may draw it have finished.
+    pub fn retire(&mut self, allocation: MeshAllocation) {
+        self.retired.push_back((self.frame, allocation));
+    }
+
+    /// Reuses the ranges retired long enough ago. Call once per frame.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        while let Some((frame, _)) = self.retired.front() {
+            if frame + RETIRE_FRAMES > self.frame {
+                break;
+            }
+            let (_, allocation) = self.retired.pop_front().unwrap();
+            let arena = &mut self.arenas[allocation.arena];
+            arena.free_vertices.free(allocation.vertices);
+            arena.free_indices.free(allocation.indices);
+        }
+    }
+
+    pub fn arena_count(&self) -> usize {
+        self.arenas.len()
+    }
+
+    /// Arenas no mesh uses anymore.
+    pub fn unused_arena_count(&self) -> usize {
+        self.arenas
+            .iter()
+            .filter(|arena| {
+                arena.free_vertices.is_unused(arena.vertices.len())
+                    && arena.free_indices.is_unused(arena.indices.len())
+            })
+            .count()
+    }
+}
@@ -0,0 +1,602 @@
+use std::mem;
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ash::vk;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBufferInheritanceInfo;
+use vulkano::command_buffer::CommandBufferInheritanceRenderPassInfo;
+use vulkano::command_buffer::CommandBufferInheritanceRenderPassType;
+use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::SecondaryCommandBufferAbstract;
+use vulkano::command_buffer::SecondaryCommandBufferResourcesUsage;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::device::Queue;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::shader::spirv;
+use vulkano::shader::ShaderModule;
+use vulkano::shader::ShaderModuleCreateInfo;
+use vulkano::shader::ShaderStages;
+use vulkano::ValidationError;
+use vulkano::VulkanObject;
+
+use crate::frustum::Frustum;
+use crate::gltf_loader::GltfPrimitive;
+use crate::meshlet::Meshlets;
+use crate::meshlet::MAX_MESHLET_TRIANGLES;
+
+use super::buffer_structs::GpuMeshlet;
+use super::buffer_structs::MeshletParams;
+use super::buffer_structs::MeshletVertex;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+
+/// Task and mesh stages, compiled by the build script since vulkano-shaders has no such kinds.
+const TASK_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/meshlet.task.spv"));
+const MESH_SPIRV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/meshlet.mesh.spv"));
+/// Meshlets one task shader workgroup culls, one per invocation.
+const MESHLETS_PER_TASK: u32 = 32;
+
+/// GPU copy of a primitive split into meshlets, in the storage buffer layout the task/mesh
+/// shaders read. Drawn by a `MeshShaderPipeline` where `DeviceCapabilities::mesh_shader` is set;
+/// elsewhere `vs_meshlet` pulls the same data with one instance per meshlet, culling each
+/// meshlet against the frustum on its way.
+pub struct MeshletMesh {
+    vertex_buffer: Subbuffer<[MeshletVertex]>,
+    meshlet_buffer: Subbuffer<[GpuMeshlet]>,
+    meshlet_vertex_buffer: Subbuffer<[u32]>,
+    triangle_buffer: Subbuffer<[u32]>,
+}
+impl MeshletMesh {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        primitive: &GltfPrimitive,
+    ) -> Self {
+        let meshlets = Meshlets::build(&primitive.positions, &primitive.indices);
+        let host_writable = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+        let storage = || BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        };
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            storage(),
+            host_writable.clone(),
+            primitive.positions.iter().zip(&primitive.normals).map(
+                |(&[x, y, z], &[nx, ny, nz])| MeshletVertex {
+                    position: [x, y, z, 1.0],
+                    normal: [nx, ny, nz, 0.0],
+                },
+            ),
+        )
+        .unwrap();
+        let meshlet_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            storage(),
+            host_writable.clone(),
+            meshlets.meshlets.iter().map(|meshlet| GpuMeshlet {
+                bounding_sphere: meshlet
+                    .bounds
+                    .center()
+                    .push(meshlet.bounds.half_extents().norm())
+                    .into(),
+                vertex_offset: meshlet.vertex_offset,
+                vertex_count: meshlet.vertex_count,
+                triangle_offset: meshlet.triangle_offset,
+                triangle_count: meshlet.triangle_count,
+            }),
+        )
+        .unwrap();
+        let meshlet_vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            storage(),
+            host_writable.clone(),
+            meshlets.vertices.iter().copied(),
+        )
+        .unwrap();
+        let triangle_buffer = Buffer::from_iter(
+            memory_allocator,
+            storage(),
+            host_writable,
+            meshlets
+                .triangles
+                .iter()
+                .map(|&[a, b, c]| u32::from_le_bytes([a, b, c, 0])),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "meshlet vertices",
+            vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(MemoryCategory::Meshes, "meshlets", meshlet_buffer.buffer());
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "meshlet vertex indices",
+            meshlet_vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "meshlet triangles",
+            triangle_buffer.buffer(),
+        );
+
+        Self {
+            vertex_buffer,
+            meshlet_buffer,
+            meshlet_vertex_buffer,
+            triangle_buffer,
+        }
+    }
+
+    pub fn meshlet_count(&self) -> u32 {
+        self.meshlet_buffer.len() as u32
+    }
+
+    /// The `vs_meshlet` fallback for devices without mesh shaders. Reads no vertex buffers;
+    /// everything comes from the descriptor set.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_meshlet::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the meshlet buffers at 1 to 4, for the layout of
+    /// either `get_pipeline` or a `MeshShaderPipeline`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        layout: &Arc<PipelineLayout>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &layout.set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::buffer(1, self.vertex_buffer.clone()),
+                WriteDescriptorSet::buffer(2, self.meshlet_buffer.clone()),
+                WriteDescriptorSet::buffer(3, self.meshlet_vertex_buffer.clone()),
+                WriteDescriptorSet::buffer(4, self.triangle_buffer.clone()),
+            ],
+        )
+    }
+
+    /// Meshlets outside `frustum`, in world space, are dropped by the vertex shader.
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        frustum: &Frustum,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                MeshletParams {
+                    planes: frustum.planes.map(Into::into),
+                },
+            )
+            .unwrap()
+            .draw(MAX_MESHLET_TRIANGLES as u32 * 3, self.meshlet_count(), 0, 0)
+            .unwrap();
+    }
+
+    /// Records the task/mesh draw for the next frame, for `Renderer::queue_draws`. The task
+    /// shader drops meshlets outside `frustum`, in world space.
+    pub fn record_mesh_tasks(
+        &self,
+        pipeline: &Arc<MeshShaderPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        frustum: &Frustum,
+    ) -> Arc<MeshTaskCommands> {
+        let params = MeshletParams {
+            planes: frustum.planes.map(Into::into),
+        };
+        let group_count = self.meshlet_count().div_ceil(MESHLETS_PER_TASK);
+        let device = pipeline.device.handle();
+        let fns = pipeline.device.fns();
+        let pool = pipeline.pool.lock().unwrap();
+        let mut command_buffer = vk::CommandBuffer::null();
+        let inheritance_info = vk::CommandBufferInheritanceInfo {
+            render_pass: pipeline.subpass.render_pass().handle(),
+            subpass: pipeline.subpass.index(),
+            ..Default::default()
+        };
+        unsafe {
+            (fns.v1_0.allocate_command_buffers)(
+                device,
+                &vk::CommandBufferAllocateInfo {
+                    command_pool: *pool,
+                    level: vk::CommandBufferLevel::SECONDARY,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                },
+                &mut command_buffer,
+            )
+            .result()
+            .unwrap();
+            (fns.v1_0.begin_command_buffer)(
+                command_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                        | vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    p_inheritance_info: &inheritance_info,
+                    ..Default::default()
+                },
+            )
+            .result()
+            .unwrap();
+            (fns.v1_0.cmd_bind_pipeline)(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.handle,
+            );
+            (fns.v1_0.cmd_bind_descriptor_sets)(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.layout.handle(),
+                0,
+                1,
+                &descriptor_set.handle(),
+                0,
+                ptr::null(),
+            );
+            (fns.v1_0.cmd_push_constants)(
+                command_buffer,
+                pipeline.layout.handle(),
+                vk::ShaderStageFlags::TASK_EXT,
+                0,
+                mem::size_of::<MeshletParams>() as u32,
+                ptr::addr_of!(params).cast(),
+            );
+            (fns.ext_mesh_shader.cmd_draw_mesh_tasks_ext)(command_buffer, group_count, 1, 1);
+            (fns.v1_0.end_command_buffer)(command_buffer)
+                .result()
+                .unwrap();
+        }
+
+        Arc::new(MeshTaskCommands {
+            pipeline: pipeline.clone(),
+            handle: command_buffer,
+            inheritance_info: CommandBufferInheritanceInfo {
+                render_pass: Some(CommandBufferInheritanceRenderPassType::BeginRenderPass(
+                    CommandBufferInheritanceRenderPassInfo {
+                        subpass: pipeline.subpass.clone(),
+                        framebuffer: None,
+                    },
+                )),
+                ..Default::default()
+            },
+            resources_usage: SecondaryCommandBufferResourcesUsage::default(),
+            recorded: AtomicBool::new(false),
+            _descriptor_set: descriptor_set,
+        })
+    }
+}
+
+/// Task, mesh and fragment stages drawing a `MeshletMesh`, built with raw calls since vulkano
+/// can't create pipelines without a vertex stage. `try_new` returns `None` where
+/// `DeviceCapabilities::mesh_shader` isn't set; use `MeshletMesh::get_pipeline` there.
+pub struct MeshShaderPipeline {
+    device: Arc<Device>,
+    handle: vk::Pipeline,
+    layout: Arc<PipelineLayout>,
+    subpass: Subpass,
+    /// For `MeshTaskCommands`, locked while recording and freeing them.
+    pool: Mutex<vk::CommandPool>,
+}
+impl MeshShaderPipeline {
+    /// `queue` is the one the frames are submitted to.
+    pub fn try_new(
+        queue: &Arc<Queue>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Option<Arc<Self>> {
+        let device = queue.device().clone();
+        if !device.enabled_extensions().ext_mesh_shader {
+            return None;
+        }
+        let load = |bytes| {
+            let words = spirv::bytes_to_words(bytes).unwrap();
+            unsafe { ShaderModule::new(device.clone(), ShaderModuleCreateInfo::new(&words)) }
+                .expect("failed to create shader module")
+        };
+        let task = load(TASK_SPIRV);
+        let mesh = load(MESH_SPIRV);
+        let fs = shaders::fs::load(device.clone()).expect("failed to create shader module");
+        let stages = [&task, &mesh, &fs]
+            .map(|module| PipelineShaderStageCreateInfo::new(module.entry_point("main").unwrap()));
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        debug_assert!(layout
+            .push_constant_ranges()
+            .iter()
+            .all(|range| range.stages == ShaderStages::TASK));
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        let entry_name = c"main";
+        let stage_infos = [
+            (vk::ShaderStageFlags::TASK_EXT, &task),
+            (vk::ShaderStageFlags::MESH_EXT, &mesh),
+            (vk::ShaderStageFlags::FRAGMENT, &fs),
+        ]
+        .map(|(stage, module)| vk::PipelineShaderStageCreateInfo {
+            stage,
+            module: module.handle(),
+            p_name: entry_name.as_ptr(),
+            ..Default::default()
+        });
+        let viewports = [vk::Viewport {
+            x: viewport.offset[0],
+            y: viewport.offset[1],
+            width: viewport.extent[0],
+            height: viewport.extent[1],
+            min_depth: *viewport.depth_range.start(),
+            max_depth: *viewport.depth_range.end(),
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D {
+                x: viewport.offset[0] as i32,
+                y: viewport.offset[1] as i32,
+            },
+            extent: vk::Extent2D {
+                width: viewport.extent[0] as u32,
+                height: viewport.extent[1] as u32,
+            },
+        }];
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            p_viewports: viewports.as_ptr(),
+            scissor_count: 1,
+            p_scissors: scissors.as_ptr(),
+            ..Default::default()
+        };
+        // The same fixed-function state as the `vs_meshlet` pipeline's defaults.
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: subpass
+                .num_samples()
+                .map_or(vk::SampleCountFlags::TYPE_1, Into::into),
+            ..Default::default()
+        };
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
+        let attachments = vec![
+            vk::PipelineColorBlendAttachmentState {
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+                ..Default::default()
+            };
+            subpass.num_color_attachments() as usize
+        ];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            ..Default::default()
+        };
+        let create_info = vk::GraphicsPipelineCreateInfo {
+            stage_count: stage_infos.len() as u32,
+            p_stages: stage_infos.as_ptr(),
+            p_viewport_state: &viewport_state,
+            p_rasterization_state: &rasterization_state,
+            p_multisample_state: &multisample_state,
+            p_depth_stencil_state: &depth_stencil_state,
+            p_color_blend_state: &color_blend_state,
+            layout: layout.handle(),
+            render_pass: subpass.render_pass().handle(),
+            subpass: subpass.index(),
+            ..Default::default()
+        };
+
+        let fns = &device.fns().v1_0;
+        let mut handle = vk::Pipeline::null();
+        let mut pool = vk::CommandPool::null();
+        unsafe {
+            (fns.create_graphics_pipelines)(
+                device.handle(),
+                vk::PipelineCache::null(),
+                1,
+                &create_info,
+                ptr::null(),
+                &mut handle,
+            )
+            .result()
+            .unwrap();
+            (fns.create_command_pool)(
+                device.handle(),
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                    queue_family_index: queue.queue_family_index(),
+                    ..Default::default()
+                },
+                ptr::null(),
+                &mut pool,
+            )
+            .result()
+            .unwrap();
+        }
+
+        Some(Arc::new(Self {
+            device,
+            handle,
+            layout,
+            subpass,
+            pool: Mutex::new(pool),
+        }))
+    }
+
+    /// For `MeshletMesh::get_descriptor_set`.
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+}
+impl Drop for MeshShaderPipeline {
+    fn drop(&mut self) {
+        let device = self.device.handle();
+        let fns = &self.device.fns().v1_0;
+        unsafe {
+            (fns.destroy_command_pool)(device, *self.pool.get_mut().unwrap(), ptr::null());
+            (fns.destroy_pipeline)(device, self.handle, ptr::null());
+        }
+    }
+}
+
+/// One frame's draw of a `MeshletMesh` through a `MeshShaderPipeline`, as a secondary command
+/// buffer the frame's primary can execute. Executed at most once.
+pub struct MeshTaskCommands {
+    pipeline: Arc<MeshShaderPipeline>,
+    handle: vk::CommandBuffer,
+    inheritance_info: CommandBufferInheritanceInfo,
+    /// Empty: the buffers read are only written when a mesh is created.
+    resources_usage: SecondaryCommandBufferResourcesUsage,
+    recorded: AtomicBool,
+    /// Keeps the meshlet buffers alive until the frame is done.
+    _descriptor_set: Arc<PersistentDescriptorSet>,
+}
+impl Drop for MeshTaskCommands {
+    fn drop(&mut self) {
+        let pool = self.pipeline.pool.lock().unwrap();
+        let fns = &self.pipeline.device.fns().v1_0;
+        unsafe {
+            (fns.free_command_buffers)(self.pipeline.device.handle(), *pool, 1, &self.handle);
+        }
+    }
+}
+unsafe impl VulkanObject for MeshTaskCommands {
+    type Handle = vk::CommandBuffer;
+
+    fn handle(&self) -> Self::Handle {
+        self.handle
+    }
+}
+unsafe impl DeviceOwned for MeshTaskCommands {
+    fn device(&self) -> &Arc<Device> {
+        &self.pipeline.device
+    }
+}
+unsafe impl SecondaryCommandBufferAbstract for MeshTaskCommands {
+    fn usage(&self) -> CommandBufferUsage {
+        CommandBufferUsage::OneTimeSubmit
+    }
+
+    fn inheritance_info(&self) -> &CommandBufferInheritanceInfo {
+        &self.inheritance_info
+    }
+
+    fn lock_record(&self) -> Result<(), Box<ValidationError>> {
+        if self.recorded.swap(true, Ordering::AcqRel) {
+            return Err(Box::new(ValidationError {
+                problem: "the mesh task commands were already executed by a frame".into(),
+                ..Default::default()
+            }));
+        }
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn resources_usage(&self) -> &SecondaryCommandBufferResourcesUsage {
+        &self.resources_usage
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+use crate::gltf_loader::GltfPrimitive;
+
+use super::buffer_structs::MeshVertex;
+use super::buffer_structs::MorphInfo;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::uniform_ring::UniformRing;
+use super::RendererCore;
+
+/// GPU copy of a glTF primitive with morph targets. The targets are blended in the vertex
+/// shader, so only the weights change from frame to frame, streamed through a `UniformRing`.
+pub struct MorphedMesh {
+    vertex_buffer: Subbuffer<[MeshVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+    delta_buffer: Subbuffer<[[f32; 4]]>,
+    /// All zero until the first `upload_weights`, then the last frame's weights.
+    weight_buffer: Subbuffer<[f32]>,
+    vertex_count: u32,
+    target_count: u32,
+    bounds: Aabb,
+}
+impl MorphedMesh {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        primitive: &GltfPrimitive,
+    ) -> Self {
+        let vertices = primitive
+            .positions
+            .iter()
+            .zip(&primitive.normals)
+            .enumerate()
+            .map(|(i, (&position, &normal))| MeshVertex {
+                position,
+                normal,
+                uv: primitive.uvs.get(i).copied().unwrap_or([0.0; 2]),
+            });
+        // Laid out as the shader expects: per target, per vertex, position delta then normal delta.
+        let mut deltas: Vec<[f32; 4]> = primitive
+            .morph_targets
+            .iter()
+            .flat_map(|target| {
+                target
+                    .position_deltas
+                    .iter()
+                    .zip(&target.normal_deltas)
+                    .flat_map(|(p, n)| [[p[0], p[1], p[2], 0.0], [n[0], n[1], n[2], 0.0]])
+            })
+            .collect();
+        // Storage buffers cannot be empty, so meshes without targets still get one dummy entry.
+        if deltas.is_empty() {
+            deltas.push([0.0; 4]);
+        }
+        let host_writable = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+        let target_count = primitive.morph_targets.len();
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            vertices,
+        )
+        .unwrap();
+        let index_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            primitive.indices.iter().copied(),
+        )
+        .unwrap();
+        let delta_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            deltas,
+        )
+        .unwrap();
+        let weight_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            host_writable,
+            (0..target_count.max(1)).map(|_| 0.0),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "morphed mesh vertices",
+            vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "morphed mesh indices",
+            index_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Storage,
+            "morph target deltas",
+            delta_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Storage,
+            "morph weights",
+            weight_buffer.buffer(),
+        );
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            delta_buffer,
+            weight_buffer,
+            vertex_count: primitive.positions.len() as u32,
+            target_count: target_count as u32,
+            bounds: primitive.bounds(),
+        }
+    }
+
+    /// Local bounds covering every blend of the morph targets.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Writes this frame's morph target weights into `uniforms`, e.g. from
+    /// `AnimationPlayer::sample_morph_weights`, so frames still in flight keep reading theirs.
+    /// Missing weights are zero. Get the frame's descriptor set afterwards, which binds them.
+    pub fn upload_weights(&mut self, uniforms: &UniformRing, weights: &[f32]) {
+        let weights: Vec<f32> = (0..self.weight_buffer.len() as usize)
+            .map(|i| weights.get(i).copied().unwrap_or(0.0))
+            .collect();
+        self.weight_buffer = uniforms.write_slice(&weights);
+    }
+
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_morph::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+
+        RendererCore::build_pipeline(
+            device,
+            vs,
+            fs,
+            MeshVertex::per_vertex(),
+            render_pass,
+            viewport,
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0, the target deltas at 1 and the weights at 2, matching `vs_morph`.
+    /// The weights move every frame, so get it every frame after `upload_weights`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.transient(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::buffer(1, self.delta_buffer.clone()),
+                WriteDescriptorSet::buffer(2, self.weight_buffer.clone()),
+            ],
+        )
+    }
+
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                MorphInfo {
+                    vertex_count: self.vertex_count,
+                    target_count: self.target_count,
+                },
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(self.index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap();
+    }
+
+    /// Records the draw only if the mesh, placed by `model_matrix`, intersects `frustum`.
+    /// Returns whether it did.
+    pub fn record_draw_if_visible<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        frustum: &Frustum,
+        model_matrix: &Matrix4<f32>,
+    ) -> bool {
+        let visible = frustum.intersects_aabb(&self.bounds.transform(model_matrix));
+        if visible {
+            self.record_draw(builder, pipeline, descriptor_set);
+        }
+        visible
+    }
+}
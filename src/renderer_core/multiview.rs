@@ -0,0 +1,397 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::view::ImageViewCreateInfo;
+use vulkano::image::view::ImageViewType;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageSubresourceRange;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::AttachmentDescription;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentReference;
+use vulkano::render_pass::AttachmentStoreOp;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::RenderPassCreateInfo;
+use vulkano::render_pass::Subpass;
+use vulkano::render_pass::SubpassDescription;
+use vulkano::sync::HostAccessError;
+
+use super::buffer_structs::MeshVertex;
+use super::buffer_structs::StereoCameraUniform;
+use super::buffer_structs::StereoDraw;
+use super::compute::ComputeContext;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::shaders;
+
+const DEPTH_FORMAT: Format = Format::D32_SFLOAT;
+
+/// Both eyes in the subpass's view mask, and rendered from nearly the same position, which lets
+/// implementations share work between them.
+const VIEW_MASK: u32 = 0b11;
+
+/// View and projection of each eye, left first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoCamera {
+    pub views: [Matrix4<f32>; 2],
+    pub projections: [Matrix4<f32>; 2],
+}
+impl StereoCamera {
+    /// Eyes `ipd` apart along the head's x axis, centered on `head_view`, sharing `projection`.
+    /// Headsets report an asymmetric projection per eye; set `projections` from those instead.
+    pub fn from_head(head_view: &Matrix4<f32>, ipd: f32, projection: Matrix4<f32>) -> Self {
+        let eye = |side: f32| {
+            Matrix4::new_translation(&Vector3::new(-side * ipd / 2.0, 0.0, 0.0)) * head_view
+        };
+        Self {
+            views: [eye(-1.0), eye(1.0)],
+            projections: [projection; 2],
+        }
+    }
+}
+
+/// A two-layer color and depth target both eyes are drawn into in one pass with
+/// `VK_KHR_multiview`: every draw is broadcast to both layers, with `gl_ViewIndex` picking the
+/// eye's camera. Groundwork for VR output, which hands each layer to the headset's compositor.
+/// Needs `DeviceCapabilities::multiview`.
+pub struct StereoTarget {
+    render_pass: Arc<RenderPass>,
+    framebuffer: Arc<Framebuffer>,
+    color: Arc<ImageView>,
+    camera_buffer: Subbuffer<StereoCameraUniform>,
+}
+impl StereoTarget {
+    pub const VIEW_COUNT: u32 = 2;
+
+    /// `extent` is per eye. Panics without multiview; see `try_new`.
+    pub fn new(context: &ComputeContext, format: Format, extent: [u32; 2]) -> Self {
+        Self::try_new(context, format, extent).expect("stereo targets need multiview")
+    }
+
+    /// `None` where the device has no multiview, e.g. to draw each eye in its own pass.
+    pub fn try_new(context: &ComputeContext, format: Format, extent: [u32; 2]) -> Option<Self> {
+        if !context.capabilities().multiview {
+            return None;
+        }
+        let render_pass = RenderPass::new(
+            context.device(),
+            RenderPassCreateInfo {
+                attachments: vec![
+                    AttachmentDescription {
+                        format,
+                        samples: SampleCount::Sample1,
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::Store,
+                        initial_layout: ImageLayout::Undefined,
+                        final_layout: ImageLayout::ColorAttachmentOptimal,
+                        ..Default::default()
+                    },
+                    AttachmentDescription {
+                        format: DEPTH_FORMAT,
+                        samples: SampleCount::Sample1,
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::DontCare,
+                        initial_layout: ImageLayout::Undefined,
+                        final_layout: ImageLayout::DepthStencilAttachmentOptimal,
+                        ..Default::default()
+                    },
+                ],
+                subpasses: vec![SubpassDescription {
+                    view_mask: VIEW_MASK,
+                    color_attachments: vec![Some(AttachmentReference {
+                        attachment: 0,
+                        layout: ImageLayout::ColorAttachmentOptimal,
+                        ..Default::default()
+                    })],
+                    depth_stencil_attachment: Some(AttachmentReference {
+                        attachment: 1,
+                        layout: ImageLayout::DepthStencilAttachmentOptimal,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                correlated_view_masks: vec![VIEW_MASK],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let layered = |format, usage, label| {
+            let image = Image::new(
+                context.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: [extent[0], extent[1], 1],
+                    array_layers: Self::VIEW_COUNT,
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            context
+                .memory_tracker()
+                .track_image(MemoryCategory::RenderTargets, label, &image);
+            ImageView::new_default(image).unwrap()
+        };
+        let color = layered(
+            format,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+            "stereo color",
+        );
+        let depth = layered(
+            DEPTH_FORMAT,
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+            "stereo depth",
+        );
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![color.clone(), depth],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let camera_buffer = Buffer::new_sized(
+            context.memory_allocator(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        context.memory_tracker().track_buffer(
+            MemoryCategory::Uniforms,
+            "stereo cameras",
+            camera_buffer.buffer(),
+        );
+
+        Some(Self {
+            render_pass,
+            framebuffer,
+            color,
+            camera_buffer,
+        })
+    }
+
+    pub fn render_pass(&self) -> Arc<RenderPass> {
+        self.render_pass.clone()
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        let extent = self.color.image().extent();
+        [extent[0], extent[1]]
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let [width, height] = self.extent();
+        Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    /// Both eyes as one array view, e.g. for sampling in a distortion pass.
+    pub fn color(&self) -> Arc<ImageView> {
+        self.color.clone()
+    }
+
+    /// One eye's layer of the color target, 0 for the left eye.
+    pub fn eye(&self, eye: u32) -> Arc<ImageView> {
+        let image = self.color.image().clone();
+        ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2d,
+                subresource_range: ImageSubresourceRange {
+                    array_layers: eye..eye + 1,
+                    ..image.subresource_range()
+                },
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Fails if the GPU is still reading the previous frame's cameras.
+    pub fn set_camera(&self, camera: &StereoCamera) -> Result<(), HostAccessError> {
+        *self.camera_buffer.write()? = StereoCameraUniform {
+            view: camera.views.map(Into::into),
+            proj: camera.projections.map(Into::into),
+        };
+        Ok(())
+    }
+
+    /// Draws `MeshVertex` meshes, e.g. through `Resources::record_draw_mesh`, shaded with a
+    /// fixed light.
+    pub fn get_pipeline(&self, device: Arc<Device>) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_multiview::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_multiview::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let vertex_input_state = MeshVertex::per_vertex()
+            .definition(&vs.info().input_interface)
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(self.render_pass.clone(), 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [self.viewport()].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds the eyes' cameras at binding 0, matching `vs_multiview`.
+    pub fn get_descriptor_set(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, self.camera_buffer.clone())],
+        )
+    }
+
+    /// Begins the pass, binds `pipeline` and `descriptor_set`, lets `record` draw and ends the
+    /// pass. Use `push_model` before each draw.
+    pub fn record_pass<F>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        clear_color: [f32; 4],
+        record: F,
+    ) where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(clear_color.into()), Some(1.0.into())],
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+        record(builder);
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+    }
+
+    /// Sets the model matrix of the following draws.
+    pub fn push_model(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        model: &Matrix4<f32>,
+    ) {
+        builder
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                StereoDraw {
+                    model: (*model).into(),
+                },
+            )
+            .unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+use vulkano::sync::HostAccessError;
+
+use super::blend::BlendMode;
+use super::buffer_structs::Vertex2d;
+use super::clip_rect::ClipRect;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// Vertices of one panel: three by three quads of two triangles.
+pub(crate) const PANEL_VERTICES: u64 = 54;
+
+/// Where a panel texture is cut into a 3x3 grid: its corners keep their size, its edges
+/// stretch along their length and its center stretches both ways, so borders stay crisp
+/// however a button or window is sized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NineSlice {
+    /// Size of the texture, in texels.
+    pub texture_size: [u32; 2],
+    /// Widths of the left and right border and heights of the top and bottom one, in texels.
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+impl NineSlice {
+    /// Borders of `border` texels on every side.
+    pub fn uniform(texture_size: [u32; 2], border: u32) -> Self {
+        Self {
+            texture_size,
+            left: border,
+            right: border,
+            top: border,
+            bottom: border,
+        }
+    }
+}
+
+/// One panel to draw, in the same units as the projection, e.g. logical pixels with
+/// `UiScale::projection`, with y down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Panel {
+    /// Top-left corner.
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+    /// Units per border texel, e.g. `1.0 / UiScale::factor()` for borders a screen pixel per
+    /// texel thick. Borders shrink evenly when the panel is smaller than both together.
+    pub border_scale: f32,
+}
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            size: [0.0, 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+            border_scale: 1.0,
+        }
+    }
+}
+
+/// Draws up to `capacity` nine-slice panels of one texture per frame with a single draw.
+pub struct NineSliceRenderer {
+    vertex_buffer: Subbuffer<[Vertex2d]>,
+    vertex_count: u32,
+}
+impl NineSliceRenderer {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        capacity: u64,
+    ) -> Self {
+        let vertex_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1) * PANEL_VERTICES,
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "nine-slice vertices",
+            vertex_buffer.buffer(),
+        );
+        Self {
+            vertex_buffer,
+            vertex_count: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.vertex_buffer.len() / PANEL_VERTICES
+    }
+
+    /// Replaces the panels drawn, all cut by `slice`. Panels past `capacity` are dropped.
+    /// Fails if the GPU is still reading the previous panels.
+    pub fn upload_panels(
+        &mut self,
+        slice: &NineSlice,
+        panels: &[Panel],
+    ) -> Result<(), HostAccessError> {
+        let mut dst = self.vertex_buffer.write()?;
+        let count = panels.len().min(self.capacity() as usize);
+        for (panel, vertices) in panels[..count]
+            .iter()
+            .zip(dst.chunks_exact_mut(PANEL_VERTICES as usize))
+        {
+            Self::panel_vertices(slice, panel, vertices);
+        }
+        self.vertex_count = (count as u64 * PANEL_VERTICES) as u32;
+        Ok(())
+    }
+
+    pub(crate) fn panel_vertices(slice: &NineSlice, panel: &Panel, vertices: &mut [Vertex2d]) {
+        let [width, height] = slice.texture_size.map(|size| size.max(1) as f32);
+        let borders = |start: u32, end: u32, size: f32| {
+            let [start, end] = [start, end].map(|border| border as f32 * panel.border_scale);
+            let shrink = (size / (start + end)).min(1.0);
+            [start * shrink, end * shrink]
+        };
+        let [left, right] = borders(slice.left, slice.right, panel.size[0]);
+        let [top, bottom] = borders(slice.top, slice.bottom, panel.size[1]);
+        let [x, y] = panel.offset;
+        let xs = [x, x + left, x + panel.size[0] - right, x + panel.size[0]];
+        let ys = [y, y + top, y + panel.size[1] - bottom, y + panel.size[1]];
+        let us = [
+            0.0,
+            slice.left as f32 / width,
+            1.0 - slice.right as f32 / width,
+            1.0,
+        ];
+        let vs = [
+            0.0,
+            slice.top as f32 / height,
+            1.0 - slice.bottom as f32 / height,
+            1.0,
+        ];
+
+        let mut vertices = vertices.iter_mut();
+        for row in 0..3 {
+            for column in 0..3 {
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (0, 1), (1, 0), (1, 1)] {
+                    *vertices.next().unwrap() = Vertex2d {
+                        position: [xs[column + dx], ys[row + dy]],
+                        uv: [us[column + dx], vs[row + dy]],
+                        color: panel.color,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Alpha-blended, for panels with rounded corners or shadows.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        NineSliceRenderer::build_pipeline(device, render_pass, viewport, false)
+    }
+
+    /// Like `get_pipeline`, for panels clipped to a `ClipRect`, e.g. inside a scrolling list.
+    /// Draw with `record_draw_clipped`.
+    pub fn get_clipped_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        NineSliceRenderer::build_pipeline(device, render_pass, viewport, true)
+    }
+
+    fn build_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        clipped: bool,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            Vertex2d::per_vertex(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                dynamic_scissor: clipped,
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the panel texture at binding 1. `sampler` should
+    /// clamp to the edge, so borders don't bleed into each other.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        texture: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, texture, sampler),
+            ],
+        )
+    }
+
+    /// Draws with a pipeline from `get_clipped_pipeline`, only inside `clip_rect`.
+    pub fn record_draw_clipped<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        clip_rect: ClipRect,
+    ) {
+        if clip_rect.is_empty() {
+            return;
+        }
+        clip_rect.record(builder);
+        self.record_draw(builder, pipeline, descriptor_set);
+    }
+
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .draw(self.vertex_count, 1, 0, 0)
+            .unwrap();
+    }
+}
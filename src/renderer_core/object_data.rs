@@ -0,0 +1,78 @@
+use nalgebra::Matrix4;
+use vulkano::buffer::Subbuffer;
+
+use crate::draw_list::DrawBatch;
+use crate::draw_list::DrawList;
+use crate::draw_list::RenderObject;
+use crate::handles::MeshId;
+
+use super::buffer_structs::ObjectTransform;
+use super::uniform_ring::UniformRing;
+
+/// One frame's per-object data, in a storage buffer the vertex shader indexes with
+/// `gl_InstanceIndex`, so every object gets its own model matrix while the whole frame binds
+/// one descriptor set, e.g. through `MaterialPipelines::get_object_descriptor_set` with
+/// `MaterialFeatures::PER_OBJECT`. A draw of the objects from entry `first` on passes `first`
+/// as its first instance. Written to the `UniformRing`, so build it anew every frame.
+pub struct ObjectData {
+    transforms: Subbuffer<[ObjectTransform]>,
+    len: u32,
+}
+impl ObjectData {
+    /// The objects' model matrices, indexed in the order given.
+    pub fn new(uniforms: &UniformRing, models: impl IntoIterator<Item = Matrix4<f32>>) -> Self {
+        let mut transforms: Vec<ObjectTransform> = models
+            .into_iter()
+            .map(|model| ObjectTransform {
+                model: model.into(),
+            })
+            .collect();
+        let len = transforms.len() as u32;
+        // Buffers can't be empty; nothing reads the padding entry.
+        if transforms.is_empty() {
+            transforms.push(ObjectTransform {
+                model: Matrix4::identity().into(),
+            });
+        }
+        Self {
+            transforms: uniforms.write_slice(&transforms),
+            len,
+        }
+    }
+
+    /// The world matrices of `objects` in `draw_list` item order, so each batch is one draw
+    /// from `batch_draws`.
+    pub fn from_draw_list(
+        uniforms: &UniformRing,
+        draw_list: &DrawList,
+        objects: &[RenderObject],
+    ) -> Self {
+        Self::new(
+            uniforms,
+            draw_list
+                .items()
+                .iter()
+                .map(|item| objects[item.object as usize].world_matrix),
+        )
+    }
+
+    /// Draws of `batches` for `MeshDraws::record_draw_meshes`, each starting at its first
+    /// item's entry of an `ObjectData` built by `from_draw_list`.
+    pub fn batch_draws(batches: &[DrawBatch]) -> impl Iterator<Item = (MeshId, u32, u32)> + '_ {
+        batches
+            .iter()
+            .map(|batch| (batch.mesh, batch.item_count, batch.first_item))
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn buffer(&self) -> Subbuffer<[ObjectTransform]> {
+        self.transforms.clone()
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::color_blend::ColorComponents;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::query::QueryControlFlags;
+use vulkano::query::QueryPool;
+use vulkano::query::QueryPoolCreateInfo;
+use vulkano::query::QueryResultFlags;
+use vulkano::query::QueryType;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::Validated;
+use vulkano::VulkanError;
+
+use crate::draw_list::RenderObject;
+
+use super::buffer_structs::OcclusionBox;
+use super::descriptor_sets::DescriptorSets;
+use super::shaders;
+
+/// Hardware occlusion queries against the bounding boxes of expensive objects. Results arrive a
+/// frame late: an object hidden last frame is skipped this frame, but its box is still tested,
+/// so it reappears one frame after it comes back into view.
+///
+/// Per frame: `update_results`, hand `occluded` to `DrawList::set_occlusion_results`, build the
+/// draw list, `record_reset` before the render pass, draw the occluders, then `record_queries`
+/// for `DrawList::occlusion_candidates` in the same subpass. The subpass needs a depth attachment.
+pub struct OcclusionCuller {
+    query_pool: Arc<QueryPool>,
+    /// Object indices queried in the last recorded frame.
+    queried: Vec<u32>,
+    occluded: Vec<bool>,
+    results: Vec<u64>,
+}
+impl OcclusionCuller {
+    /// Room for objects with indices below `capacity`; objects beyond it are never culled.
+    pub fn new(device: Arc<Device>, capacity: u32) -> Self {
+        let query_pool = QueryPool::new(
+            device,
+            QueryPoolCreateInfo {
+                query_count: capacity.max(1),
+                ..QueryPoolCreateInfo::query_type(QueryType::Occlusion)
+            },
+        )
+        .unwrap();
+        Self {
+            queried: Vec::new(),
+            occluded: vec![false; query_pool.query_count() as usize],
+            // A sample count and an availability value per query.
+            results: vec![0; query_pool.query_count() as usize * 2],
+            query_pool,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.query_pool.query_count()
+    }
+
+    /// Per object index, whether its box had no visible samples in the last finished query.
+    pub fn occluded(&self) -> &[bool] {
+        &self.occluded
+    }
+
+    /// Picks up the results of the last recorded frame without waiting for the GPU. Objects whose
+    /// queries have not finished yet keep their previous result; objects that were not queried
+    /// count as visible.
+    pub fn update_results(&mut self) -> Result<(), Validated<VulkanError>> {
+        if self.queried.is_empty() {
+            // Also keeps the pool from being read before it was ever reset.
+            self.occluded.fill(false);
+            return Ok(());
+        }
+        let mut was_queried = vec![false; self.occluded.len()];
+        for &object in &self.queried {
+            was_queried[object as usize] = true;
+        }
+        self.query_pool.get_results(
+            0..self.capacity(),
+            &mut self.results,
+            QueryResultFlags::WITH_AVAILABILITY,
+        )?;
+        for (object, occluded) in self.occluded.iter_mut().enumerate() {
+            if !was_queried[object] {
+                *occluded = false;
+            } else if self.results[object * 2 + 1] != 0 {
+                *occluded = self.results[object * 2] == 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bounding boxes tested with the depth test only: no color or depth writes, no face culling.
+    /// `depth_compare_op` must match the scene's, e.g. `Greater` with reversed Z.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        depth_compare_op: CompareOp,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_occlusion_box::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_occlusion_box::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: depth_compare_op,
+                    }),
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        color_write_mask: ColorComponents::empty(),
+                        ..Default::default()
+                    },
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds `mvp_buffer` at binding 0, matching `vs_occlusion_box`. Its model matrix is ignored.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, mvp_buffer)],
+        )
+    }
+
+    /// Must be recorded outside of a render pass, after `update_results` and before
+    /// `record_queries`.
+    pub fn record_reset(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.queried.clear();
+        // SAFETY: the pool's queries are only ever begun by `record_queries`, in command buffers
+        // recorded after this reset and submitted to the same queue.
+        unsafe {
+            builder
+                .reset_query_pool(self.query_pool.clone(), 0..self.capacity())
+                .unwrap();
+        }
+    }
+
+    /// Draws the bounding box of every candidate, each inside its own query. Record after the
+    /// occluders so their depth is in place.
+    pub fn record_queries(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        objects: &[RenderObject],
+        candidates: &[u32],
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+        for &object in candidates {
+            if object >= self.capacity() {
+                continue;
+            }
+            let bounds = &objects[object as usize].bounds;
+            // SAFETY: `record_reset` reset every query of the pool earlier in this frame, and
+            // each object is queried at most once per frame.
+            unsafe {
+                builder
+                    .begin_query(self.query_pool.clone(), object, QueryControlFlags::empty())
+                    .unwrap();
+            }
+            builder
+                .push_constants(
+                    pipeline.layout().clone(),
+                    0,
+                    OcclusionBox {
+                        min: bounds.min.push(0.0).into(),
+                        max: bounds.max.push(0.0).into(),
+                    },
+                )
+                .unwrap()
+                .draw(36, 1, 0, 0)
+                .unwrap()
+                .end_query(self.query_pool.clone(), object)
+                .unwrap();
+            self.queried.push(object);
+        }
+    }
+}
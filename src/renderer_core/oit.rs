@@ -0,0 +1,418 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::color_blend::BlendOp;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexBufferDescription;
+use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::AttachmentDescription;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentReference;
+use vulkano::render_pass::AttachmentStoreOp;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::RenderPassCreateInfo;
+use vulkano::render_pass::Subpass;
+use vulkano::render_pass::SubpassDescription;
+use vulkano::shader::EntryPoint;
+
+use super::blend::BlendMode;
+use super::compute::ComputeContext;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::shaders;
+
+/// Sums of weighted, premultiplied colors and of weights.
+const ACCUMULATION_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+/// Product of the transparent surfaces' `1 - alpha`, how much of the opaque scene shows.
+const REVEALAGE_FORMAT: Format = Format::R8_UNORM;
+
+/// Weighted blended order-independent transparency, for scenes where sorting fails, e.g.
+/// intersecting glass or dense particles and foliage. Transparent surfaces are drawn in any
+/// order into two accumulation targets, then composited over the opaque scene in one
+/// fullscreen draw. Overlaps are approximate: nearer surfaces weigh more, but strongly
+/// colored layers blend towards their average.
+///
+/// Per frame, after the opaque pass: `record_accumulation` with pipelines from `get_pipeline`,
+/// then `record_composite` in a pass drawing over the opaque color, e.g.
+/// `RenderTargets::record_overlay_pass`. Fragment shaders of accumulation pipelines write
+/// two outputs for a color `c` of straight alpha at a weight `w`:
+///
+/// ```glsl
+/// layout(location = 0) out vec4 accumulation; // vec4(c.rgb * c.a, c.a) * w
+/// layout(location = 1) out float revealage;   // c.a
+///
+/// float w = clamp(pow(min(1.0, c.a * 10.0) + 0.01, 3.0) * 1e8
+///     * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+/// ```
+pub struct WeightedOit {
+    render_pass: Arc<RenderPass>,
+    framebuffer: Arc<Framebuffer>,
+    accumulation: Arc<ImageView>,
+    revealage: Arc<ImageView>,
+}
+impl WeightedOit {
+    /// Panics where the device can't blend the targets differently; see `try_new`.
+    pub fn new(context: &ComputeContext, depth: Arc<ImageView>) -> Self {
+        Self::try_new(context, depth).expect("independent blending is unsupported")
+    }
+
+    /// Accumulates over `depth`, the opaque pass' depth attachment, e.g. `RenderTargets::depth`,
+    /// which is tested but not written and must be in the shader read-only layout between
+    /// passes, as `RenderTargets` leaves it. The targets match its extent. `None` without
+    /// `DeviceCapabilities::independent_blend`.
+    pub fn try_new(context: &ComputeContext, depth: Arc<ImageView>) -> Option<Self> {
+        if !context.capabilities().independent_blend {
+            return None;
+        }
+        let color_attachment = |format| AttachmentDescription {
+            format,
+            samples: SampleCount::Sample1,
+            load_op: AttachmentLoadOp::Clear,
+            store_op: AttachmentStoreOp::Store,
+            initial_layout: ImageLayout::Undefined,
+            final_layout: ImageLayout::ShaderReadOnlyOptimal,
+            ..Default::default()
+        };
+        let color_reference = |attachment| {
+            Some(AttachmentReference {
+                attachment,
+                layout: ImageLayout::ColorAttachmentOptimal,
+                ..Default::default()
+            })
+        };
+        let render_pass = RenderPass::new(
+            context.device(),
+            RenderPassCreateInfo {
+                attachments: vec![
+                    color_attachment(ACCUMULATION_FORMAT),
+                    color_attachment(REVEALAGE_FORMAT),
+                    AttachmentDescription {
+                        format: depth.format(),
+                        samples: SampleCount::Sample1,
+                        load_op: AttachmentLoadOp::Load,
+                        store_op: AttachmentStoreOp::Store,
+                        initial_layout: ImageLayout::ShaderReadOnlyOptimal,
+                        final_layout: ImageLayout::ShaderReadOnlyOptimal,
+                        ..Default::default()
+                    },
+                ],
+                subpasses: vec![SubpassDescription {
+                    color_attachments: vec![color_reference(0), color_reference(1)],
+                    depth_stencil_attachment: Some(AttachmentReference {
+                        attachment: 2,
+                        layout: ImageLayout::DepthStencilReadOnlyOptimal,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let [width, height, _] = depth.image().extent();
+        let target = |format, label| {
+            let image = Image::new(
+                context.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: [width, height, 1],
+                    usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            context
+                .memory_tracker()
+                .track_image(MemoryCategory::RenderTargets, label, &image);
+            ImageView::new_default(image).unwrap()
+        };
+        let accumulation = target(ACCUMULATION_FORMAT, "oit accumulation");
+        let revealage = target(REVEALAGE_FORMAT, "oit revealage");
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![accumulation.clone(), revealage.clone(), depth],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        Some(Self {
+            render_pass,
+            framebuffer,
+            accumulation,
+            revealage,
+        })
+    }
+
+    /// The accumulation pass.
+    pub fn render_pass(&self) -> Arc<RenderPass> {
+        self.render_pass.clone()
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let [width, height] = self.framebuffer.extent();
+        Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    /// Adds up the first output and multiplies the destination revealage by one minus the
+    /// second, for `GraphicsPipelineCreateInfo::color_blend_state` of accumulation pipelines.
+    pub fn color_blend_state() -> ColorBlendState {
+        let additive = AttachmentBlend {
+            src_color_blend_factor: BlendFactor::One,
+            dst_color_blend_factor: BlendFactor::One,
+            color_blend_op: BlendOp::Add,
+            src_alpha_blend_factor: BlendFactor::One,
+            dst_alpha_blend_factor: BlendFactor::One,
+            alpha_blend_op: BlendOp::Add,
+        };
+        let revealing = AttachmentBlend {
+            src_color_blend_factor: BlendFactor::Zero,
+            dst_color_blend_factor: BlendFactor::OneMinusSrcColor,
+            color_blend_op: BlendOp::Add,
+            src_alpha_blend_factor: BlendFactor::Zero,
+            dst_alpha_blend_factor: BlendFactor::OneMinusSrcAlpha,
+            alpha_blend_op: BlendOp::Add,
+        };
+        ColorBlendState {
+            attachments: [additive, revealing]
+                .into_iter()
+                .map(|blend| ColorBlendAttachmentState {
+                    blend: Some(blend),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Tested against the opaque scene, never written, so transparent surfaces don't hide
+    /// each other.
+    pub fn depth_stencil_state() -> DepthStencilState {
+        DepthStencilState {
+            depth: Some(DepthState {
+                write_enable: false,
+                compare_op: CompareOp::Less,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Pipeline for the accumulation pass, drawing both faces, for shaders writing the outputs
+    /// described on `WeightedOit` and reading vertex buffers of `vertex_description`.
+    pub fn get_pipeline(
+        &self,
+        device: Arc<Device>,
+        vs: EntryPoint,
+        fs: EntryPoint,
+        vertex_description: VertexBufferDescription,
+    ) -> Arc<GraphicsPipeline> {
+        let vertex_input_state = vertex_description
+            .definition(&vs.info().input_interface)
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(self.render_pass.clone(), 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [self.viewport()].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(WeightedOit::depth_stencil_state()),
+                color_blend_state: Some(WeightedOit::color_blend_state()),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Begins the accumulation pass with nothing accumulated, lets `record` draw the
+    /// transparent surfaces and ends the pass.
+    pub fn record_accumulation<F>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        record: F,
+    ) where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![
+                        Some(ClearValue::Float([0.0; 4])),
+                        Some(ClearValue::Float([1.0, 0.0, 0.0, 0.0])),
+                        None,
+                    ],
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        record(builder);
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+    }
+
+    /// Alpha-blends the accumulated surfaces over subpass 0 of `render_pass`, whose extent
+    /// must match the targets'.
+    pub fn get_composite_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_fullscreen::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_oit_composite::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(
+                    BlendMode::Alpha.color_blend_state(subpass.num_color_attachments()),
+                ),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds the accumulation target at binding 0 and the revealage at binding 1.
+    pub fn get_composite_descriptor_set(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    self.accumulation.clone(),
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(1, self.revealage.clone(), sampler),
+            ],
+        )
+    }
+
+    /// After `record_accumulation`, inside the pass the composite pipeline was made for.
+    pub fn record_composite(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .draw(3, 1, 0, 0)
+            .unwrap();
+    }
+}
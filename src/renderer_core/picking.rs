@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::BufferImageCopy;
+use vulkano::command_buffer::CopyImageToBufferInfo;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use super::buffer_structs::MeshVertex;
+use super::buffer_structs::ObjectIdDraw;
+use super::compute::ComputeContext;
+use super::memory_stats::MemoryCategory;
+use super::shaders;
+
+const ID_FORMAT: Format = Format::R32_UINT;
+const DEPTH_FORMAT: Format = Format::D32_SFLOAT;
+
+/// Id of pixels no object covers.
+const NO_OBJECT: u32 = u32::MAX;
+
+/// Something drawn into an `IdBuffer`, numbered however the app likes, e.g. by its index in a
+/// list of nodes. `u32::MAX` is reserved for empty pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(pub u32);
+
+/// An offscreen `R32_UINT` target each object is drawn into with its id, so the id under a
+/// pixel, e.g. the cursor, can be read back. Exact for any geometry the GPU can draw, at the
+/// cost of a round trip to the GPU per pick.
+pub struct IdBuffer {
+    framebuffer: Arc<Framebuffer>,
+    ids: Arc<Image>,
+    pipeline: Arc<GraphicsPipeline>,
+    readback: Subbuffer<[u32]>,
+    /// The last `render`, which picks wait for.
+    rendered: Option<Box<dyn GpuFuture>>,
+}
+impl IdBuffer {
+    pub fn new(context: &ComputeContext, extent: [u32; 2]) -> Self {
+        let device = context.device();
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                ids: {
+                    format: ID_FORMAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                depth: {
+                    format: DEPTH_FORMAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+            },
+            pass: {
+                color: [ids],
+                depth_stencil: {depth},
+            },
+        )
+        .unwrap();
+
+        let attachment = |format, usage, label| {
+            let image = Image::new(
+                context.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: [extent[0], extent[1], 1],
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            context
+                .memory_tracker()
+                .track_image(MemoryCategory::RenderTargets, label, &image);
+            image
+        };
+        let ids = attachment(
+            ID_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+            "picking ids",
+        );
+        let depth = attachment(
+            DEPTH_FORMAT,
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+            "picking depth",
+        );
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![
+                    ImageView::new_default(ids.clone()).unwrap(),
+                    ImageView::new_default(depth).unwrap(),
+                ],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let readback = Buffer::new_slice(
+            context.memory_allocator(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            1,
+        )
+        .unwrap();
+        context.memory_tracker().track_buffer(
+            MemoryCategory::Staging,
+            "picking readback",
+            readback.buffer(),
+        );
+
+        Self {
+            pipeline: IdBuffer::get_pipeline(context, render_pass, extent),
+            framebuffer,
+            ids,
+            readback,
+            rendered: None,
+        }
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        let extent = self.ids.extent();
+        [extent[0], extent[1]]
+    }
+
+    /// Draws the ids: begins the pass, binds the `MeshVertex` pipeline, lets `record` draw with
+    /// `push_object` before each draw, e.g. through `Resources::record_draw_mesh`, and submits.
+    pub fn render<F>(&mut self, context: &ComputeContext, record: F)
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, &Arc<GraphicsPipeline>),
+    {
+        let after = self
+            .rendered
+            .take()
+            .unwrap_or_else(|| sync::now(context.device()).boxed());
+        self.rendered = Some(context.submit(after, |builder| {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![
+                            Some(ClearValue::Uint([NO_OBJECT, 0, 0, 0])),
+                            Some(1.0.into()),
+                        ],
+                        ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::Inline,
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+                .bind_pipeline_graphics(self.pipeline.clone())
+                .unwrap();
+            record(builder, &self.pipeline);
+            builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+        }));
+    }
+
+    /// Sets the transform and id of the following draws.
+    pub fn push_object(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        model_view_projection: &Matrix4<f32>,
+        id: ObjectId,
+    ) {
+        builder
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                ObjectIdDraw {
+                    model_view_projection: (*model_view_projection).into(),
+                    id: id.0,
+                },
+            )
+            .unwrap();
+    }
+
+    /// The object at `position` in the last `render`, in pixels from the top left. Waits for the
+    /// GPU to finish rendering and copy the pixel back.
+    pub fn pick(&mut self, context: &ComputeContext, position: [u32; 2]) -> Option<ObjectId> {
+        let extent = self.extent();
+        if position[0] >= extent[0] || position[1] >= extent[1] {
+            return None;
+        }
+        let after = self.rendered.take()?;
+        let region = BufferImageCopy {
+            image_subresource: self.ids.subresource_layers(),
+            image_offset: [position[0], position[1], 0],
+            image_extent: [1, 1, 1],
+            ..Default::default()
+        };
+        context
+            .submit(after, |builder| {
+                builder
+                    .copy_image_to_buffer(CopyImageToBufferInfo {
+                        regions: [region].into_iter().collect(),
+                        ..CopyImageToBufferInfo::image_buffer(
+                            self.ids.clone(),
+                            self.readback.clone(),
+                        )
+                    })
+                    .unwrap();
+            })
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        // Everything has finished, so later picks need not wait again.
+        self.rendered = Some(sync::now(context.device()).boxed());
+
+        let id = self.readback.read().unwrap()[0];
+        (id != NO_OBJECT).then_some(ObjectId(id))
+    }
+
+    fn get_pipeline(
+        context: &ComputeContext,
+        render_pass: Arc<RenderPass>,
+        extent: [u32; 2],
+    ) -> Arc<GraphicsPipeline> {
+        let device = context.device();
+        let vs = shaders::vs_object_id::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_object_id::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let vertex_input_state = MeshVertex::per_vertex()
+            .definition(&vs.info().input_interface)
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [Viewport {
+                        offset: [0.0, 0.0],
+                        extent: [extent[0] as f32, extent[1] as f32],
+                        depth_range: 0.0..=1.0,
+                    }]
+                    .into_iter()
+                    .collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                // Ids can't be blended.
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+}
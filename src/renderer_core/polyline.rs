@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use lyon::math::point;
+use lyon::path::LineCap;
+use lyon::path::LineJoin;
+use lyon::path::Path;
+use lyon::tessellation::StrokeOptions;
+use nalgebra::Matrix4;
+use nalgebra::Orthographic3;
+use nalgebra::Vector4;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use super::buffer_structs::MVP;
+use super::canvas::Canvas;
+use super::descriptor_sets::DescriptorSets;
+use super::uniform_ring::UniformRing;
+
+/// Clip-space w below which points count as behind the camera, where the projection blows up.
+const NEAR_W: f32 = 1e-4;
+
+/// A line through world-space points that stays `width` pixels wide however far away it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<[f32; 3]>,
+    /// Joins the last point back to the first.
+    pub closed: bool,
+    /// In pixels.
+    pub width: f32,
+    pub color: [f32; 4],
+    /// Miter joins past four widths long fall back to bevels.
+    pub join: LineJoin,
+    /// Of both ends of open polylines.
+    pub cap: LineCap,
+}
+impl Default for Polyline {
+    fn default() -> Self {
+        Self {
+            points: Vec::new(),
+            closed: false,
+            width: 1.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+        }
+    }
+}
+
+/// Draws thick `Polyline`s with proper joins and caps, e.g. plots, measurements and selection
+/// outlines, which Vulkan's line primitives can't: wide lines are optional and have neither.
+///
+/// `update` projects the points into pixels and tessellates the lines there, so call it
+/// whenever the camera, the viewport or the polylines change, which for a moving camera is
+/// every frame. Lines are drawn over the scene, without depth testing; segments reaching
+/// behind the camera are cut where they leave the view.
+pub struct PolylineRenderer {
+    polylines: Vec<Polyline>,
+    canvas: Canvas,
+    viewport_size: [f32; 2],
+}
+impl Default for PolylineRenderer {
+    fn default() -> Self {
+        Self {
+            polylines: Vec::new(),
+            canvas: Canvas::new(),
+            viewport_size: [1.0, 1.0],
+        }
+    }
+}
+impl PolylineRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, polyline: Polyline) {
+        self.polylines.push(polyline);
+    }
+
+    pub fn clear(&mut self) {
+        self.polylines.clear();
+        self.canvas.clear();
+    }
+
+    pub fn polylines(&self) -> &[Polyline] {
+        &self.polylines
+    }
+
+    pub fn polylines_mut(&mut self) -> &mut [Polyline] {
+        &mut self.polylines
+    }
+
+    /// Tessellates the polylines as seen through `view_projection` over a viewport of
+    /// `viewport_size` pixels.
+    pub fn update(&mut self, view_projection: &Matrix4<f32>, viewport_size: [f32; 2]) {
+        self.viewport_size = viewport_size;
+        self.canvas.clear();
+        for polyline in &self.polylines {
+            if polyline.width <= 0.0 {
+                continue;
+            }
+            let path = Self::project(polyline, view_projection, viewport_size);
+            let options = StrokeOptions::default()
+                .with_line_width(polyline.width)
+                .with_line_join(polyline.join)
+                .with_line_cap(polyline.cap);
+            if let Err(error) = self.canvas.stroke_path(&path, &options, polyline.color) {
+                log::warn!("failed to tessellate a polyline: {error}");
+            }
+        }
+    }
+
+    /// The polyline in pixels, split where it passes behind the camera.
+    fn project(
+        polyline: &Polyline,
+        view_projection: &Matrix4<f32>,
+        viewport_size: [f32; 2],
+    ) -> Path {
+        let to_clip = |position: [f32; 3]| {
+            view_projection * Vector4::new(position[0], position[1], position[2], 1.0)
+        };
+        let to_pixels = |clip: Vector4<f32>| {
+            point(
+                (clip.x / clip.w * 0.5 + 0.5) * viewport_size[0],
+                (clip.y / clip.w * 0.5 + 0.5) * viewport_size[1],
+            )
+        };
+        let clip: Vec<Vector4<f32>> = polyline.points.iter().copied().map(to_clip).collect();
+        let count = clip.len();
+        let whole = clip.iter().all(|position| position.w >= NEAR_W);
+        // A closed polyline in view is one loop; otherwise each stretch in front of the camera
+        // is its own open line.
+        let close = polyline.closed && whole && count > 2;
+        let segments = if polyline.closed && count > 2 {
+            count
+        } else {
+            count.saturating_sub(1)
+        };
+
+        let mut builder = Path::builder();
+        if close {
+            builder.begin(to_pixels(clip[0]));
+            for &position in &clip[1..] {
+                builder.line_to(to_pixels(position));
+            }
+            builder.end(true);
+            return builder.build();
+        }
+        let mut open = false;
+        for i in 0..segments {
+            let mut start = clip[i];
+            let mut end = clip[(i + 1) % count];
+            if start.w < NEAR_W && end.w < NEAR_W {
+                continue;
+            }
+            // Cut the part behind the camera off.
+            let cut_end = end.w < NEAR_W;
+            if start.w < NEAR_W || cut_end {
+                let cut = start + (end - start) * ((NEAR_W - start.w) / (end.w - start.w));
+                if start.w < NEAR_W {
+                    start = cut;
+                } else {
+                    end = cut;
+                }
+            }
+            if !open {
+                builder.begin(to_pixels(start));
+                open = true;
+            }
+            builder.line_to(to_pixels(end));
+            if cut_end {
+                builder.end(false);
+                open = false;
+            }
+        }
+        if open {
+            builder.end(false);
+        }
+        builder.build()
+    }
+
+    /// The `Canvas` pipeline.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        Canvas::get_pipeline(device, render_pass, viewport)
+    }
+
+    /// Draws the lines tessellated by the last `update`, binding a pixel projection of its
+    /// viewport size from `uniforms`.
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        descriptor_sets: &mut DescriptorSets,
+        uniforms: &UniformRing,
+        pipeline: Arc<GraphicsPipeline>,
+    ) {
+        if self.canvas.is_empty() {
+            return;
+        }
+        let [width, height] = self.viewport_size;
+        let identity: [[f32; 4]; 4] = Matrix4::<f32>::identity().into();
+        let mvp_buffer = uniforms.write(MVP {
+            model: identity,
+            view: identity,
+            proj: Orthographic3::new(0.0, width, 0.0, height, -1.0, 1.0)
+                .to_homogeneous()
+                .into(),
+        });
+        let descriptor_set = descriptor_sets.transient(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, mvp_buffer)],
+        );
+        self.canvas
+            .record_draw(builder, uniforms, pipeline, descriptor_set);
+    }
+}
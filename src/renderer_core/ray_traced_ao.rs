@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::ClearColorImageInfo;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::sampler::SamplerAddressMode;
+use vulkano::image::sampler::SamplerCreateInfo;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::ComputePipeline;
+
+use super::buffer_structs::AoParams;
+use super::compute::ComputeContext;
+use super::ray_tracing::RayTracingScene;
+use super::shaders;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Ray-traced ambient occlusion from a G-buffer: per pixel, `ray_count` short cosine-weighted
+/// rays against the scene's top level, written as the unoccluded fraction to an `R32_SFLOAT`
+/// image. A higher-quality replacement for screen-space AO, which misses occluders off screen or
+/// behind the visible surface. Needs `VulkanConnection::ray_query_enabled`.
+pub struct RayTracedAo {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+    frame: u32,
+    inverse_view_projection: Matrix4<f32>,
+    /// World space length of the AO rays; occluders further away don't darken.
+    pub radius: f32,
+    /// Rays per pixel per frame. The noise is per frame, so a temporal filter can accumulate.
+    pub ray_count: u32,
+    /// Offset of ray origins along the normal, against self-intersection.
+    pub bias: f32,
+    /// Depth of cleared pixels, which are left unoccluded. 1 unless the projection reverses depth.
+    pub far_depth: f32,
+}
+impl RayTracedAo {
+    /// Panics without ray queries; see `try_new`.
+    pub fn new(context: &ComputeContext) -> Self {
+        Self::try_new(context).expect("ray-traced AO needs ray queries")
+    }
+
+    /// `None` where the device has no ray queries, e.g. to fall back to screen space AO.
+    pub fn try_new(context: &ComputeContext) -> Option<Self> {
+        if !context.capabilities().ray_query {
+            return None;
+        }
+        let entry_point = shaders::cs_ray_traced_ao::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let sampler = Sampler::new(
+            context.device(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Some(Self {
+            pipeline: context.create_pipeline(entry_point),
+            sampler,
+            frame: 0,
+            inverse_view_projection: Matrix4::identity(),
+            radius: 1.0,
+            ray_count: 4,
+            bias: 1e-3,
+            far_depth: 1.0,
+        })
+    }
+
+    /// The camera the G-buffer was rendered with.
+    pub fn set_camera(&mut self, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
+        self.inverse_view_projection = (projection * view)
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+    }
+
+    /// An AO image of `extent` for `record` to write.
+    pub fn create_target(context: &ComputeContext, extent: [u32; 2]) -> Arc<ImageView> {
+        context.create_storage_image("ray-traced ao", Format::R32_SFLOAT, extent)
+    }
+
+    /// Builds the scene's top level if needed and traces AO for every pixel of `target`. `depth`
+    /// and `normals` are sampled views of the G-buffer at the same size, the latter with world
+    /// space normals in rgb. Clears `target` to 1 when the scene has nothing to trace.
+    pub fn record(
+        &mut self,
+        context: &ComputeContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        scene: &mut RayTracingScene,
+        depth: Arc<ImageView>,
+        normals: Arc<ImageView>,
+        target: Arc<ImageView>,
+    ) {
+        self.frame = self.frame.wrapping_add(1);
+        if !scene.record_build(context, builder) {
+            builder
+                .clear_color_image(ClearColorImageInfo {
+                    clear_value: [1.0; 4].into(),
+                    ..ClearColorImageInfo::image(target.image().clone())
+                })
+                .unwrap();
+            return;
+        }
+        let extent = target.image().extent();
+        let descriptor_set = context.bind_transient(
+            &self.pipeline,
+            [
+                WriteDescriptorSet::acceleration_structure(0, scene.tlas().unwrap()),
+                WriteDescriptorSet::image_view_sampler(1, depth, self.sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(2, normals, self.sampler.clone()),
+                WriteDescriptorSet::image_view(3, target),
+            ],
+        );
+        ComputeContext::record_dispatch(
+            builder,
+            self.pipeline.clone(),
+            descriptor_set,
+            Some(AoParams {
+                inverse_view_projection: self.inverse_view_projection.into(),
+                radius: self.radius,
+                ray_count: self.ray_count,
+                frame: self.frame,
+                bias: self.bias,
+                far_depth: self.far_depth,
+            }),
+            ComputeContext::workgroups(
+                [extent[0], extent[1], 1],
+                [WORKGROUP_SIZE, WORKGROUP_SIZE, 1],
+            ),
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::ClearColorImageInfo;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::sampler::SamplerAddressMode;
+use vulkano::image::sampler::SamplerCreateInfo;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::ComputePipeline;
+
+use super::buffer_structs::ShadowParams;
+use super::compute::ComputeContext;
+use super::ray_tracing::RayTracingScene;
+use super::shaders;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// A light as far as its shadows are concerned. A source size of 0 gives hard shadows, anything
+/// larger soft ones with penumbrae that widen away from the occluder.
+#[derive(Clone, Copy, Debug)]
+pub enum ShadowLight {
+    Directional {
+        /// The direction the light travels in.
+        direction: Vector3<f32>,
+        /// Half the angle the source covers in the sky, in radians; the sun's is about 0.0047.
+        angular_radius: f32,
+    },
+    Point {
+        position: Vector3<f32>,
+        /// Radius of the emitting sphere.
+        radius: f32,
+    },
+}
+impl ShadowLight {
+    fn params(&self) -> ([f32; 4], f32) {
+        match *self {
+            ShadowLight::Directional {
+                direction,
+                angular_radius,
+            } => ((-direction.normalize()).push(0.0).into(), angular_radius),
+            ShadowLight::Point { position, radius } => (position.push(1.0).into(), radius),
+        }
+    }
+}
+
+/// Per-light shadow masks traced with ray queries from a G-buffer, as an alternative to shadow
+/// maps on GPUs with `VulkanConnection::ray_query_enabled`: no resolution, bias or cascade
+/// tuning, and soft shadows from the light's real size. Each mask is an `R32_SFLOAT` image of
+/// the fraction of the light each pixel sees, to multiply into that light's contribution.
+pub struct RayTracedShadows {
+    pipeline: Arc<ComputePipeline>,
+    sampler: Arc<Sampler>,
+    frame: u32,
+    inverse_view_projection: Matrix4<f32>,
+    /// Rays per pixel per frame for lights with a size. The noise is per frame, so a temporal
+    /// filter can accumulate.
+    pub sample_count: u32,
+    /// Offset of ray origins along the normal, against self-intersection.
+    pub bias: f32,
+    /// Depth of cleared pixels, which are left lit. 1 unless the projection reverses depth.
+    pub far_depth: f32,
+}
+impl RayTracedShadows {
+    /// Panics without ray queries; see `try_new`.
+    pub fn new(context: &ComputeContext) -> Self {
+        Self::try_new(context).expect("ray-traced shadows need ray queries")
+    }
+
+    /// `None` where the device has no ray queries, e.g. to fall back to shadow maps.
+    pub fn try_new(context: &ComputeContext) -> Option<Self> {
+        if !context.capabilities().ray_query {
+            return None;
+        }
+        let entry_point = shaders::cs_ray_traced_shadows::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let sampler = Sampler::new(
+            context.device(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Some(Self {
+            pipeline: context.create_pipeline(entry_point),
+            sampler,
+            frame: 0,
+            inverse_view_projection: Matrix4::identity(),
+            sample_count: 4,
+            bias: 1e-3,
+            far_depth: 1.0,
+        })
+    }
+
+    /// The camera the G-buffer was rendered with.
+    pub fn set_camera(&mut self, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
+        self.inverse_view_projection = (projection * view)
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+    }
+
+    /// A shadow mask of `extent` for `record` to write.
+    pub fn create_target(context: &ComputeContext, extent: [u32; 2]) -> Arc<ImageView> {
+        context.create_storage_image("ray-traced shadow mask", Format::R32_SFLOAT, extent)
+    }
+
+    /// Builds the scene's top level if needed and traces one mask per light into its target.
+    /// `depth` and `normals` are sampled views of the G-buffer at the targets' size, the latter
+    /// with world space normals in rgb. Clears the targets to 1 when the scene has nothing to
+    /// trace.
+    pub fn record(
+        &mut self,
+        context: &ComputeContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        scene: &mut RayTracingScene,
+        depth: Arc<ImageView>,
+        normals: Arc<ImageView>,
+        lights: &[(ShadowLight, Arc<ImageView>)],
+    ) {
+        self.frame = self.frame.wrapping_add(1);
+        if !scene.record_build(context, builder) {
+            for (_, target) in lights {
+                builder
+                    .clear_color_image(ClearColorImageInfo {
+                        clear_value: [1.0; 4].into(),
+                        ..ClearColorImageInfo::image(target.image().clone())
+                    })
+                    .unwrap();
+            }
+            return;
+        }
+        let tlas = scene.tlas().unwrap();
+
+        for (light, target) in lights {
+            let extent = target.image().extent();
+            let descriptor_set = context.bind_transient(
+                &self.pipeline,
+                [
+                    WriteDescriptorSet::acceleration_structure(0, tlas.clone()),
+                    WriteDescriptorSet::image_view_sampler(1, depth.clone(), self.sampler.clone()),
+                    WriteDescriptorSet::image_view_sampler(
+                        2,
+                        normals.clone(),
+                        self.sampler.clone(),
+                    ),
+                    WriteDescriptorSet::image_view(3, target.clone()),
+                ],
+            );
+            let (light, source_radius) = light.params();
+            ComputeContext::record_dispatch(
+                builder,
+                self.pipeline.clone(),
+                descriptor_set,
+                Some(ShadowParams {
+                    inverse_view_projection: self.inverse_view_projection.into(),
+                    light,
+                    source_radius,
+                    sample_count: self.sample_count,
+                    frame: self.frame,
+                    bias: self.bias,
+                    far_depth: self.far_depth,
+                }),
+                ComputeContext::workgroups(
+                    [extent[0], extent[1], 1],
+                    [WORKGROUP_SIZE, WORKGROUP_SIZE, 1],
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use slotmap::SecondaryMap;
+use vulkano::acceleration_structure::AccelerationStructure;
+use vulkano::acceleration_structure::AccelerationStructureBuildGeometryInfo;
+use vulkano::acceleration_structure::AccelerationStructureBuildRangeInfo;
+use vulkano::acceleration_structure::AccelerationStructureBuildType;
+use vulkano::acceleration_structure::AccelerationStructureCreateInfo;
+use vulkano::acceleration_structure::AccelerationStructureGeometries;
+use vulkano::acceleration_structure::AccelerationStructureGeometryInstancesData;
+use vulkano::acceleration_structure::AccelerationStructureGeometryTrianglesData;
+use vulkano::acceleration_structure::AccelerationStructureInstance;
+use vulkano::acceleration_structure::AccelerationStructureType;
+use vulkano::acceleration_structure::BuildAccelerationStructureFlags;
+use vulkano::acceleration_structure::BuildAccelerationStructureMode;
+use vulkano::acceleration_structure::GeometryFlags;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::IndexBuffer;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::BlitImageInfo;
+use vulkano::command_buffer::ClearColorImageInfo;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::Image;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::DeviceLayout;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+use vulkano::Packed24_8;
+
+use crate::gltf_loader::GltfPrimitive;
+use crate::handles::MeshId;
+
+use super::buffer_structs::RayTraceParams;
+use super::compute::ComputeContext;
+use super::memory_stats::MemoryCategory;
+use super::ray_tracing_pipeline::RayTracingPipeline;
+use super::shaders;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Bottom-level acceleration structure of a mesh, plus the geometry the trace shader shades with.
+struct BottomLevel {
+    structure: Arc<AccelerationStructure>,
+    positions: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+/// Every mesh's vertices and indices in one pair of storage buffers, since the trace shader can
+/// only tell meshes apart by the instance's custom index.
+struct Geometry {
+    positions: Subbuffer<[[f32; 4]]>,
+    indices: Subbuffer<[u32]>,
+    /// Per custom index, the first index and first vertex of its mesh.
+    slots: Subbuffer<[[u32; 2]]>,
+    slot_of: SecondaryMap<MeshId, u32>,
+}
+
+/// Acceleration structures for hardware ray tracing: one bottom level per mesh and a top level
+/// over the current instances, rebuilt when they change. Needs
+/// `VulkanConnection::ray_query_enabled`.
+pub struct RayTracingScene {
+    meshes: SecondaryMap<MeshId, BottomLevel>,
+    geometry: Option<Geometry>,
+    instances: Vec<(MeshId, Matrix4<f32>)>,
+    tlas: Option<Arc<AccelerationStructure>>,
+    tlas_dirty: bool,
+    /// Bottom levels referenced by the last two top levels, which frames in flight may still
+    /// trace after their meshes were removed.
+    in_use: [Vec<Arc<AccelerationStructure>>; 2],
+}
+impl Default for RayTracingScene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RayTracingScene {
+    pub fn new() -> Self {
+        Self {
+            meshes: SecondaryMap::new(),
+            geometry: None,
+            instances: Vec::new(),
+            tlas: None,
+            tlas_dirty: true,
+            in_use: Default::default(),
+        }
+    }
+
+    /// Builds the bottom level of `id` and waits for it, so later top-level builds can reference
+    /// it. Replaces any earlier structure of `id`.
+    pub fn add_mesh(&mut self, context: &ComputeContext, id: MeshId, primitive: &GltfPrimitive) {
+        if primitive.indices.is_empty() {
+            return;
+        }
+        let input_usage = BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+            | BufferUsage::SHADER_DEVICE_ADDRESS;
+        let vertex_buffer = host_buffer(
+            context,
+            "blas vertices",
+            input_usage,
+            primitive.positions.iter().copied(),
+        );
+        let index_buffer = host_buffer(
+            context,
+            "blas indices",
+            input_usage,
+            primitive.indices.iter().copied(),
+        );
+        let triangles = AccelerationStructureGeometryTrianglesData {
+            flags: GeometryFlags::OPAQUE,
+            vertex_data: Some(vertex_buffer.into_bytes()),
+            vertex_stride: std::mem::size_of::<[f32; 3]>() as u32,
+            max_vertex: primitive.positions.len() as u32 - 1,
+            index_data: Some(IndexBuffer::U32(index_buffer)),
+            ..AccelerationStructureGeometryTrianglesData::new(Format::R32G32B32_SFLOAT)
+        };
+
+        let mut structure = None;
+        context
+            .submit(sync::now(context.device()).boxed(), |builder| {
+                structure = Some(record_build(
+                    context,
+                    builder,
+                    AccelerationStructureType::BottomLevel,
+                    AccelerationStructureGeometries::Triangles(vec![triangles]),
+                    primitive.indices.len() as u32 / 3,
+                ));
+            })
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        self.meshes.insert(
+            id,
+            BottomLevel {
+                structure: structure.unwrap(),
+                positions: primitive.positions.clone(),
+                indices: primitive.indices.clone(),
+            },
+        );
+        self.geometry = None;
+        self.tlas_dirty = true;
+    }
+
+    pub fn remove_mesh(&mut self, id: MeshId) -> bool {
+        let removed = self.meshes.remove(id).is_some();
+        if removed {
+            self.geometry = None;
+            self.tlas_dirty = true;
+        }
+        removed
+    }
+
+    pub fn contains_mesh(&self, id: MeshId) -> bool {
+        self.meshes.contains_key(id)
+    }
+
+    /// Meshes to trace and their world matrices. Meshes without a bottom level are skipped.
+    pub fn set_instances(&mut self, instances: impl IntoIterator<Item = (MeshId, Matrix4<f32>)>) {
+        self.instances.clear();
+        self.instances.extend(instances);
+        self.tlas_dirty = true;
+    }
+
+    /// The top level as of the last `record_build`.
+    pub fn tlas(&self) -> Option<Arc<AccelerationStructure>> {
+        self.tlas.clone()
+    }
+
+    /// Records a top-level build if meshes or instances changed since the last one. Must be
+    /// recorded outside of a render pass. Returns whether there is anything to trace.
+    pub fn record_build(
+        &mut self,
+        context: &ComputeContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> bool {
+        if !self.tlas_dirty {
+            return self.tlas.is_some();
+        }
+        self.tlas_dirty = false;
+        self.in_use.swap(0, 1);
+        self.in_use[0].clear();
+        if self.meshes.is_empty() {
+            self.tlas = None;
+            return false;
+        }
+        let geometry = self
+            .geometry
+            .get_or_insert_with(|| Geometry::new(context, &self.meshes));
+
+        let instances: Vec<_> = self
+            .instances
+            .iter()
+            .filter_map(|(mesh, world_matrix)| {
+                let structure = &self.meshes.get(*mesh)?.structure;
+                self.in_use[0].push(structure.clone());
+                Some(AccelerationStructureInstance {
+                    transform: std::array::from_fn(|row| {
+                        std::array::from_fn(|column| world_matrix[(row, column)])
+                    }),
+                    instance_custom_index_and_mask: Packed24_8::new(geometry.slot_of[*mesh], 0xff),
+                    acceleration_structure_reference: structure.device_address().get(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        if instances.is_empty() {
+            self.tlas = None;
+            return false;
+        }
+
+        let instance_count = instances.len() as u32;
+        let instance_buffer = host_buffer(
+            context,
+            "tlas instances",
+            BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+                | BufferUsage::SHADER_DEVICE_ADDRESS,
+            instances,
+        );
+        self.tlas = Some(record_build(
+            context,
+            builder,
+            AccelerationStructureType::TopLevel,
+            AccelerationStructureGeometries::Instances(
+                AccelerationStructureGeometryInstancesData::new(instance_buffer.into()),
+            ),
+            instance_count,
+        ));
+        true
+    }
+}
+
+impl Geometry {
+    fn new(context: &ComputeContext, meshes: &SecondaryMap<MeshId, BottomLevel>) -> Self {
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        let mut slots = Vec::new();
+        let mut slot_of = SecondaryMap::new();
+        for (id, mesh) in meshes {
+            slot_of.insert(id, slots.len() as u32);
+            slots.push([indices.len() as u32, positions.len() as u32]);
+            positions.extend(mesh.positions.iter().map(|&[x, y, z]| [x, y, z, 1.0]));
+            indices.extend_from_slice(&mesh.indices);
+        }
+        Self {
+            positions: host_buffer(
+                context,
+                "ray tracing positions",
+                BufferUsage::STORAGE_BUFFER,
+                positions,
+            ),
+            indices: host_buffer(
+                context,
+                "ray tracing indices",
+                BufferUsage::STORAGE_BUFFER,
+                indices,
+            ),
+            slots: host_buffer(
+                context,
+                "ray tracing mesh slots",
+                BufferUsage::STORAGE_BUFFER,
+                slots,
+            ),
+            slot_of,
+        }
+    }
+}
+
+/// How `RayTracedOutput` traces its rays.
+enum Tracer {
+    /// Raygen, miss and closest-hit stages, where `DeviceCapabilities::ray_tracing_pipeline`
+    /// is set.
+    Pipeline(Arc<RayTracingPipeline>),
+    /// `cs_ray_trace` with ray queries otherwise.
+    Query(Arc<ComputePipeline>),
+}
+
+/// Ray-traced frames: primary rays against the scene's top level, Lambert shading from the hit
+/// triangle's normal and one shadow ray toward the sun. Traced by a ray tracing pipeline where
+/// the device has one, else by ray queries from a compute shader.
+pub struct RayTracedOutput {
+    scene: RayTracingScene,
+    tracer: Tracer,
+    target: Option<Arc<ImageView>>,
+    inverse_view_projection: Matrix4<f32>,
+    camera_position: Vector3<f32>,
+    /// Direction toward the light.
+    pub light_direction: Vector3<f32>,
+    pub sky_color: [f32; 3],
+}
+impl RayTracedOutput {
+    /// Panics without ray queries; see `try_new`.
+    pub fn new(context: &ComputeContext) -> Self {
+        Self::try_new(context).expect("ray-traced output needs ray queries")
+    }
+
+    /// `None` where the device has no ray queries.
+    pub fn try_new(context: &ComputeContext) -> Option<Self> {
+        if !context.capabilities().ray_query {
+            return None;
+        }
+        let tracer = if context.capabilities().ray_tracing_pipeline {
+            Tracer::Pipeline(RayTracingPipeline::new(context.queue()))
+        } else {
+            let entry_point = shaders::cs_ray_trace::load(context.device())
+                .expect("failed to create shader module")
+                .entry_point("main")
+                .unwrap();
+            Tracer::Query(context.create_pipeline(entry_point))
+        };
+        Some(Self {
+            scene: RayTracingScene::new(),
+            tracer,
+            target: None,
+            inverse_view_projection: Matrix4::identity(),
+            camera_position: Vector3::zeros(),
+            light_direction: Vector3::new(0.3, 1.0, 0.2).normalize(),
+            sky_color: [0.5, 0.7, 1.0],
+        })
+    }
+
+    pub fn scene(&self) -> &RayTracingScene {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut RayTracingScene {
+        &mut self.scene
+    }
+
+    pub fn set_camera(&mut self, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
+        self.inverse_view_projection = (projection * view)
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+        self.camera_position = view
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .fixed_view::<3, 1>(0, 3)
+            .into_owned();
+    }
+
+    /// Builds the top level if needed, traces a frame the size of `image` and blits it there.
+    /// `image` needs `TRANSFER_DST` usage. Clears it to the sky color when nothing is traced.
+    pub fn record(
+        &mut self,
+        context: &ComputeContext,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: Arc<Image>,
+    ) {
+        let extent = [image.extent()[0], image.extent()[1]];
+        let target = match &self.target {
+            Some(target) if target.image().extent()[..2] == extent => target.clone(),
+            _ => self
+                .target
+                .insert(context.create_storage_image(
+                    "ray-traced output",
+                    Format::R8G8B8A8_UNORM,
+                    extent,
+                ))
+                .clone(),
+        };
+
+        if !self.scene.record_build(context, builder) {
+            let [r, g, b] = self.sky_color;
+            builder
+                .clear_color_image(ClearColorImageInfo {
+                    clear_value: [r, g, b, 1.0].into(),
+                    ..ClearColorImageInfo::image(image)
+                })
+                .unwrap();
+            return;
+        }
+        let geometry = self.scene.geometry.as_ref().unwrap();
+        let writes = [
+            WriteDescriptorSet::acceleration_structure(0, self.scene.tlas.clone().unwrap()),
+            WriteDescriptorSet::image_view(1, target.clone()),
+            WriteDescriptorSet::buffer(2, geometry.positions.clone()),
+            WriteDescriptorSet::buffer(3, geometry.indices.clone()),
+            WriteDescriptorSet::buffer(4, geometry.slots.clone()),
+        ];
+        let params = RayTraceParams {
+            inverse_view_projection: self.inverse_view_projection.into(),
+            camera_position: self.camera_position.push(1.0).into(),
+            light_direction: self.light_direction.normalize().push(0.0).into(),
+            sky_color: [self.sky_color[0], self.sky_color[1], self.sky_color[2], 1.0],
+        };
+        match &self.tracer {
+            Tracer::Pipeline(pipeline) => {
+                let descriptor_set = context
+                    .descriptor_sets()
+                    .transient(&pipeline.layout().set_layouts()[0], writes);
+                // Leaves the target in the layout `record_trace` expects, as far as vulkano's
+                // tracking of it goes.
+                builder
+                    .clear_color_image(ClearColorImageInfo::image(target.image().clone()))
+                    .unwrap();
+                builder
+                    .execute_commands(pipeline.record_trace(target.image(), descriptor_set, params))
+                    .unwrap();
+            }
+            Tracer::Query(pipeline) => {
+                let descriptor_set = context.bind_transient(pipeline, writes);
+                ComputeContext::record_dispatch(
+                    builder,
+                    pipeline.clone(),
+                    descriptor_set,
+                    Some(params),
+                    ComputeContext::workgroups(
+                        [extent[0], extent[1], 1],
+                        [WORKGROUP_SIZE, WORKGROUP_SIZE, 1],
+                    ),
+                );
+            }
+        }
+        builder
+            .blit_image(BlitImageInfo::images(target.image().clone(), image))
+            .unwrap();
+    }
+}
+
+fn host_buffer<T: BufferContents, I>(
+    context: &ComputeContext,
+    label: &'static str,
+    usage: BufferUsage,
+    data: I,
+) -> Subbuffer<[T]>
+where
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    let buffer = Buffer::from_iter(
+        context.memory_allocator(),
+        BufferCreateInfo {
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data,
+    )
+    .unwrap();
+    context.memory_tracker().track_buffer(
+        MemoryCategory::of_buffer_usage(usage),
+        label,
+        buffer.buffer(),
+    );
+    buffer
+}
+
+/// Creates an acceleration structure sized for `geometries` and records its build.
+fn record_build(
+    context: &ComputeContext,
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ty: AccelerationStructureType,
+    geometries: AccelerationStructureGeometries,
+    primitive_count: u32,
+) -> Arc<AccelerationStructure> {
+    let device = context.device();
+    let mut build_info = AccelerationStructureBuildGeometryInfo {
+        flags: BuildAccelerationStructureFlags::PREFER_FAST_TRACE,
+        mode: BuildAccelerationStructureMode::Build,
+        ..AccelerationStructureBuildGeometryInfo::new(geometries)
+    };
+    let sizes = device
+        .acceleration_structure_build_sizes(
+            AccelerationStructureBuildType::Device,
+            &build_info,
+            &[primitive_count],
+        )
+        .unwrap();
+
+    let storage = Buffer::new_slice::<u8>(
+        context.memory_allocator(),
+        BufferCreateInfo {
+            usage: BufferUsage::ACCELERATION_STRUCTURE_STORAGE | BufferUsage::SHADER_DEVICE_ADDRESS,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+        sizes.acceleration_structure_size,
+    )
+    .unwrap();
+    context.memory_tracker().track_buffer(
+        MemoryCategory::Storage,
+        "acceleration structure",
+        storage.buffer(),
+    );
+    // SAFETY: the storage buffer is only ever accessed through the acceleration structure.
+    let structure = unsafe {
+        AccelerationStructure::new(
+            device.clone(),
+            AccelerationStructureCreateInfo {
+                ty,
+                ..AccelerationStructureCreateInfo::new(storage)
+            },
+        )
+    }
+    .unwrap();
+
+    // Buffers are aligned to at most 64 bytes, less than some devices want for scratch memory,
+    // so over-allocate and start at the first aligned address.
+    let alignment = device
+        .physical_device()
+        .properties()
+        .min_acceleration_structure_scratch_offset_alignment
+        .unwrap_or(1) as u64;
+    let scratch_buffer = Buffer::new(
+        context.memory_allocator(),
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..Default::default()
+        },
+        DeviceLayout::from_size_alignment(sizes.build_scratch_size + alignment, 1).unwrap(),
+    )
+    .unwrap();
+    context.memory_tracker().track_buffer(
+        MemoryCategory::Storage,
+        "acceleration structure scratch",
+        &scratch_buffer,
+    );
+    let scratch = Subbuffer::new(scratch_buffer);
+    let address = scratch.device_address().unwrap().get();
+    let offset = address.next_multiple_of(alignment) - address;
+    build_info.dst_acceleration_structure = Some(structure.clone());
+    build_info.scratch_data = Some(scratch.slice(offset..offset + sizes.build_scratch_size));
+
+    // SAFETY: the inputs come from meshes with in-range indices, and top levels only reference
+    // bottom levels that `add_mesh` finished building and that the scene keeps alive.
+    unsafe {
+        builder
+            .build_acceleration_structure(
+                build_info,
+                [AccelerationStructureBuildRangeInfo {
+                    primitive_count,
+                    primitive_offset: 0,
+                    first_vertex: 0,
+                    transform_offset: 0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap();
+    }
+    structure
+}
@@ -0,0 +1,534 @@
+use std::mem;
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use ash::vk;
+use vulkano::command_buffer::CommandBufferInheritanceInfo;
+use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::SecondaryCommandBufferAbstract;
+use vulkano::command_buffer::SecondaryCommandBufferResourcesUsage;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::device::Queue;
+use vulkano::image::Image;
+use vulkano::memory::MemoryPropertyFlags;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::ValidationError;
+use vulkano::VulkanObject;
+
+use super::buffer_structs::RayTraceParams;
+use super::shaders;
+
+/// Shader groups in the order the binding table lists them.
+const RAYGEN_GROUP: usize = 0;
+const MISS_GROUPS: [usize; 2] = [1, 2];
+const HIT_GROUP: usize = 3;
+
+/// Raygen, miss and closest-hit stages tracing the same frames as `cs_ray_trace`, with their
+/// shader binding table. Built with raw calls since vulkano has no ray tracing pipeline objects.
+/// Only created where `DeviceCapabilities::ray_tracing_pipeline` is set.
+pub(crate) struct RayTracingPipeline {
+    device: Arc<Device>,
+    handle: vk::Pipeline,
+    layout: Arc<PipelineLayout>,
+    raygen_region: vk::StridedDeviceAddressRegionKHR,
+    miss_region: vk::StridedDeviceAddressRegionKHR,
+    hit_region: vk::StridedDeviceAddressRegionKHR,
+    /// Holds the regions above. Raw too, since vulkano has no shader binding table usage.
+    table_buffer: vk::Buffer,
+    table_memory: vk::DeviceMemory,
+    /// For `TraceRaysCommands`, locked while recording and freeing them.
+    pool: Mutex<vk::CommandPool>,
+}
+impl RayTracingPipeline {
+    /// `queue` is the one the traced frames are submitted to.
+    pub fn new(queue: &Arc<Queue>) -> Arc<Self> {
+        let device = queue.device().clone();
+        let stages = [
+            shaders::rgen_ray_trace::load(device.clone()),
+            shaders::rmiss_ray_trace::load(device.clone()),
+            shaders::rmiss_shadow::load(device.clone()),
+            shaders::rchit_ray_trace::load(device.clone()),
+        ]
+        .map(|module| {
+            let module = module.expect("failed to create shader module");
+            PipelineShaderStageCreateInfo::new(module.entry_point("main").unwrap())
+        });
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+
+        let entry_name = c"main";
+        let stage_infos = [
+            vk::ShaderStageFlags::RAYGEN_KHR,
+            vk::ShaderStageFlags::MISS_KHR,
+            vk::ShaderStageFlags::MISS_KHR,
+            vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        ]
+        .into_iter()
+        .zip(&stages)
+        .map(|(stage, info)| vk::PipelineShaderStageCreateInfo {
+            stage,
+            module: info.entry_point.module().handle(),
+            p_name: entry_name.as_ptr(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+        // Each stage its own group, in stage order; the hit group only has a closest-hit shader.
+        let groups = [RAYGEN_GROUP, MISS_GROUPS[0], MISS_GROUPS[1], HIT_GROUP].map(|group| {
+            let stage = group as u32;
+            if group == HIT_GROUP {
+                vk::RayTracingShaderGroupCreateInfoKHR {
+                    ty: vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP,
+                    general_shader: vk::SHADER_UNUSED_KHR,
+                    closest_hit_shader: stage,
+                    any_hit_shader: vk::SHADER_UNUSED_KHR,
+                    intersection_shader: vk::SHADER_UNUSED_KHR,
+                    ..Default::default()
+                }
+            } else {
+                vk::RayTracingShaderGroupCreateInfoKHR {
+                    ty: vk::RayTracingShaderGroupTypeKHR::GENERAL,
+                    general_shader: stage,
+                    closest_hit_shader: vk::SHADER_UNUSED_KHR,
+                    any_hit_shader: vk::SHADER_UNUSED_KHR,
+                    intersection_shader: vk::SHADER_UNUSED_KHR,
+                    ..Default::default()
+                }
+            }
+        });
+        let create_info = vk::RayTracingPipelineCreateInfoKHR {
+            stage_count: stage_infos.len() as u32,
+            p_stages: stage_infos.as_ptr(),
+            group_count: groups.len() as u32,
+            p_groups: groups.as_ptr(),
+            // The shadow ray is traced from the raygen shader too, not from the hit shader.
+            max_pipeline_ray_recursion_depth: 1,
+            layout: layout.handle(),
+            ..Default::default()
+        };
+
+        let properties = device.physical_device().properties();
+        let handle_size = properties.shader_group_handle_size.unwrap();
+        let handle_alignment = properties.shader_group_handle_alignment.unwrap();
+        let base_alignment = properties.shader_group_base_alignment.unwrap();
+        let mut handle = vk::Pipeline::null();
+        let mut handles = vec![0; groups.len() * handle_size as usize];
+        let mut pool = vk::CommandPool::null();
+        unsafe {
+            (device
+                .fns()
+                .khr_ray_tracing_pipeline
+                .create_ray_tracing_pipelines_khr)(
+                device.handle(),
+                vk::DeferredOperationKHR::null(),
+                vk::PipelineCache::null(),
+                1,
+                &create_info,
+                ptr::null(),
+                &mut handle,
+            )
+            .result()
+            .unwrap();
+            (device
+                .fns()
+                .khr_ray_tracing_pipeline
+                .get_ray_tracing_shader_group_handles_khr)(
+                device.handle(),
+                handle,
+                0,
+                groups.len() as u32,
+                handles.len(),
+                handles.as_mut_ptr().cast(),
+            )
+            .result()
+            .unwrap();
+            (device.fns().v1_0.create_command_pool)(
+                device.handle(),
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                    queue_family_index: queue.queue_family_index(),
+                    ..Default::default()
+                },
+                ptr::null(),
+                &mut pool,
+            )
+            .result()
+            .unwrap();
+        }
+
+        // Raygen, misses and hit each start a region at the base alignment, with their entries
+        // at the handle alignment within.
+        let stride = (handle_size as u64).next_multiple_of(handle_alignment as u64);
+        let region_size = |count: u64| (count * stride).next_multiple_of(base_alignment as u64);
+        let regions = [
+            (RAYGEN_GROUP..RAYGEN_GROUP + 1, region_size(1)),
+            (MISS_GROUPS[0]..MISS_GROUPS[1] + 1, region_size(2)),
+            (HIT_GROUP..HIT_GROUP + 1, region_size(1)),
+        ];
+        let table_size: u64 = regions.iter().map(|(_, size)| size).sum();
+
+        let mut table = vec![0; table_size as usize];
+        let mut region_offsets = [0; 3];
+        let mut region_offset = 0;
+        for ((groups, size), offset) in regions.iter().zip(&mut region_offsets) {
+            for (entry, group) in groups.clone().enumerate() {
+                let start = (region_offset + entry as u64 * stride) as usize;
+                let handle = group * handle_size as usize..(group + 1) * handle_size as usize;
+                table[start..start + handle_size as usize].copy_from_slice(&handles[handle]);
+            }
+            *offset = region_offset;
+            region_offset += size;
+        }
+        let (table_buffer, table_memory, address) =
+            create_binding_table(&device, &table, base_alignment as u64);
+        let [raygen_region, miss_region, hit_region] =
+            std::array::from_fn(|i| vk::StridedDeviceAddressRegionKHR {
+                device_address: address + region_offsets[i],
+                stride,
+                size: regions[i].1,
+            });
+
+        Arc::new(Self {
+            device,
+            handle,
+            layout,
+            // The raygen region's size must equal its stride.
+            raygen_region: vk::StridedDeviceAddressRegionKHR {
+                stride: raygen_region.size,
+                ..raygen_region
+            },
+            miss_region,
+            hit_region,
+            table_buffer,
+            table_memory,
+            pool: Mutex::new(pool),
+        })
+    }
+
+    /// For the descriptor set with the same bindings as `cs_ray_trace`'s.
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+
+    /// Records tracing a frame into `target`, which vulkano last left in the transfer
+    /// destination layout, e.g. by clearing it, and leaves it there for a blit. Waits for
+    /// acceleration structure builds recorded before it. Execute it outside of a render pass.
+    pub fn record_trace(
+        self: &Arc<Self>,
+        target: &Image,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        params: RayTraceParams,
+    ) -> Arc<TraceRaysCommands> {
+        let device = self.device.handle();
+        let fns = self.device.fns();
+        let pool = self.pool.lock().unwrap();
+        let mut command_buffer = vk::CommandBuffer::null();
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let barrier =
+            |old_layout, new_layout, src_access_mask, dst_access_mask| vk::ImageMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: target.handle(),
+                subresource_range,
+                ..Default::default()
+            };
+        let built = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            dst_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+            ..Default::default()
+        };
+        let before = barrier(
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+        // vulkano's own barrier before the blit starts from the clear, which this one chains to.
+        let after = barrier(
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        );
+        let [width, height, _] = target.extent();
+        let inheritance_info = vk::CommandBufferInheritanceInfo::default();
+        unsafe {
+            (fns.v1_0.allocate_command_buffers)(
+                device,
+                &vk::CommandBufferAllocateInfo {
+                    command_pool: *pool,
+                    level: vk::CommandBufferLevel::SECONDARY,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                },
+                &mut command_buffer,
+            )
+            .result()
+            .unwrap();
+            (fns.v1_0.begin_command_buffer)(
+                command_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    p_inheritance_info: &inheritance_info,
+                    ..Default::default()
+                },
+            )
+            .result()
+            .unwrap();
+            (fns.v1_0.cmd_pipeline_barrier)(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::DependencyFlags::empty(),
+                1,
+                &built,
+                0,
+                ptr::null(),
+                1,
+                &before,
+            );
+            (fns.v1_0.cmd_bind_pipeline)(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.handle,
+            );
+            (fns.v1_0.cmd_bind_descriptor_sets)(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.layout.handle(),
+                0,
+                1,
+                &descriptor_set.handle(),
+                0,
+                ptr::null(),
+            );
+            (fns.v1_0.cmd_push_constants)(
+                command_buffer,
+                self.layout.handle(),
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                0,
+                mem::size_of::<RayTraceParams>() as u32,
+                ptr::addr_of!(params).cast(),
+            );
+            (fns.khr_ray_tracing_pipeline.cmd_trace_rays_khr)(
+                command_buffer,
+                &self.raygen_region,
+                &self.miss_region,
+                &self.hit_region,
+                &vk::StridedDeviceAddressRegionKHR::default(),
+                width,
+                height,
+                1,
+            );
+            (fns.v1_0.cmd_pipeline_barrier)(
+                command_buffer,
+                vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                0,
+                ptr::null(),
+                0,
+                ptr::null(),
+                1,
+                &after,
+            );
+            (fns.v1_0.end_command_buffer)(command_buffer)
+                .result()
+                .unwrap();
+        }
+
+        Arc::new(TraceRaysCommands {
+            pipeline: self.clone(),
+            handle: command_buffer,
+            inheritance_info: CommandBufferInheritanceInfo::default(),
+            resources_usage: SecondaryCommandBufferResourcesUsage::default(),
+            recorded: AtomicBool::new(false),
+            _descriptor_set: descriptor_set,
+        })
+    }
+}
+impl Drop for RayTracingPipeline {
+    fn drop(&mut self) {
+        let device = self.device.handle();
+        let fns = &self.device.fns().v1_0;
+        unsafe {
+            (fns.destroy_command_pool)(device, *self.pool.get_mut().unwrap(), ptr::null());
+            (fns.destroy_pipeline)(device, self.handle, ptr::null());
+            (fns.destroy_buffer)(device, self.table_buffer, ptr::null());
+            (fns.free_memory)(device, self.table_memory, ptr::null());
+        }
+    }
+}
+
+/// A host-visible buffer with `table` at an address aligned to `alignment`, which is returned.
+fn create_binding_table(
+    device: &Device,
+    table: &[u8],
+    alignment: u64,
+) -> (vk::Buffer, vk::DeviceMemory, u64) {
+    let fns = device.fns();
+    let mut buffer = vk::Buffer::null();
+    let mut memory = vk::DeviceMemory::null();
+    let mut requirements = vk::MemoryRequirements::default();
+    let mut mapped = ptr::null_mut();
+    // Over-allocated to start at the first aligned address, like the scratch buffers.
+    let size = table.len() as u64 + alignment;
+    unsafe {
+        (fns.v1_0.create_buffer)(
+            device.handle(),
+            &vk::BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                ..Default::default()
+            },
+            ptr::null(),
+            &mut buffer,
+        )
+        .result()
+        .unwrap();
+        (fns.v1_0.get_buffer_memory_requirements)(device.handle(), buffer, &mut requirements);
+        let memory_type_index = device
+            .physical_device()
+            .memory_properties()
+            .memory_types
+            .iter()
+            .enumerate()
+            .position(|(index, memory_type)| {
+                requirements.memory_type_bits & (1 << index) != 0
+                    && memory_type.property_flags.contains(
+                        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+                    )
+            })
+            .expect("no host-visible memory for the shader binding table")
+            as u32;
+        let flags_info = vk::MemoryAllocateFlagsInfo {
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            ..Default::default()
+        };
+        (fns.v1_0.allocate_memory)(
+            device.handle(),
+            &vk::MemoryAllocateInfo {
+                p_next: ptr::addr_of!(flags_info).cast(),
+                allocation_size: requirements.size,
+                memory_type_index,
+                ..Default::default()
+            },
+            ptr::null(),
+            &mut memory,
+        )
+        .result()
+        .unwrap();
+        (fns.v1_0.bind_buffer_memory)(device.handle(), buffer, memory, 0)
+            .result()
+            .unwrap();
+
+        let address = (fns.v1_2.get_buffer_device_address)(
+            device.handle(),
+            &vk::BufferDeviceAddressInfo {
+                buffer,
+                ..Default::default()
+            },
+        );
+        let offset = address.next_multiple_of(alignment) - address;
+        (fns.v1_0.map_memory)(
+            device.handle(),
+            memory,
+            0,
+            vk::WHOLE_SIZE,
+            vk::MemoryMapFlags::empty(),
+            &mut mapped,
+        )
+        .result()
+        .unwrap();
+        ptr::copy_nonoverlapping(
+            table.as_ptr(),
+            mapped.cast::<u8>().add(offset as usize),
+            table.len(),
+        );
+        (fns.v1_0.unmap_memory)(device.handle(), memory);
+        (buffer, memory, address + offset)
+    }
+}
+
+/// One frame's trace through a `RayTracingPipeline`, as a secondary command buffer the frame's
+/// primary can execute. Executed at most once.
+pub(crate) struct TraceRaysCommands {
+    pipeline: Arc<RayTracingPipeline>,
+    handle: vk::CommandBuffer,
+    inheritance_info: CommandBufferInheritanceInfo,
+    /// Empty: `record_trace` synchronizes the target and the top level itself.
+    resources_usage: SecondaryCommandBufferResourcesUsage,
+    recorded: AtomicBool,
+    /// Keeps the target, top level and geometry alive until the frame is done.
+    _descriptor_set: Arc<PersistentDescriptorSet>,
+}
+impl Drop for TraceRaysCommands {
+    fn drop(&mut self) {
+        let pool = self.pipeline.pool.lock().unwrap();
+        let fns = &self.pipeline.device.fns().v1_0;
+        unsafe {
+            (fns.free_command_buffers)(self.pipeline.device.handle(), *pool, 1, &self.handle);
+        }
+    }
+}
+unsafe impl VulkanObject for TraceRaysCommands {
+    type Handle = vk::CommandBuffer;
+
+    fn handle(&self) -> Self::Handle {
+        self.handle
+    }
+}
+unsafe impl DeviceOwned for TraceRaysCommands {
+    fn device(&self) -> &Arc<Device> {
+        &self.pipeline.device
+    }
+}
+unsafe impl SecondaryCommandBufferAbstract for TraceRaysCommands {
+    fn usage(&self) -> CommandBufferUsage {
+        CommandBufferUsage::OneTimeSubmit
+    }
+
+    fn inheritance_info(&self) -> &CommandBufferInheritanceInfo {
+        &self.inheritance_info
+    }
+
+    fn lock_record(&self) -> Result<(), Box<ValidationError>> {
+        if self.recorded.swap(true, Ordering::AcqRel) {
+            return Err(Box::new(ValidationError {
+                problem: "the trace was already executed by a frame".into(),
+                ..Default::default()
+            }));
+        }
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn resources_usage(&self) -> &SecondaryCommandBufferResourcesUsage {
+        &self.resources_usage
+    }
+}
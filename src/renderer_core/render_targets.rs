@@ -0,0 +1,441 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::format::NumericFormat;
+use vulkano::image::view::ImageView;
+use vulkano::image::view::ImageViewCreateInfo;
+use vulkano::image::Image;
+use vulkano::image::ImageAspects;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageSubresourceRange;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::render_pass::AttachmentDescription;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentReference;
+use vulkano::render_pass::AttachmentStoreOp;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::RenderPassCreateInfo;
+use vulkano::render_pass::SubpassDescription;
+
+use super::blend::BlendMode;
+use super::compute::ComputeContext;
+use super::memory_stats::MemoryCategory;
+
+/// What a pass does with an attachment's contents: `load` when the pass begins and `store`
+/// when it ends. `Load` keeps what earlier passes drew, e.g. for an overlay pass on top of the
+/// scene; `DontCare` saves bandwidth when every pixel is overwritten anyway or the result is
+/// discarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttachmentOps {
+    pub load: AttachmentLoadOp,
+    pub store: AttachmentStoreOp,
+}
+impl AttachmentOps {
+    pub const CLEAR: AttachmentOps = AttachmentOps {
+        load: AttachmentLoadOp::Clear,
+        store: AttachmentStoreOp::Store,
+    };
+    pub const LOAD: AttachmentOps = AttachmentOps {
+        load: AttachmentLoadOp::Load,
+        store: AttachmentStoreOp::Store,
+    };
+    pub const DONT_CARE: AttachmentOps = AttachmentOps {
+        load: AttachmentLoadOp::DontCare,
+        store: AttachmentStoreOp::Store,
+    };
+
+    /// The layout the attachment starts the pass in: `Undefined` unless its contents are
+    /// loaded, so the previous ones needn't be kept.
+    pub(super) fn initial_layout(self, layout: ImageLayout) -> ImageLayout {
+        if self.load == AttachmentLoadOp::Load {
+            layout
+        } else {
+            ImageLayout::Undefined
+        }
+    }
+}
+impl Default for AttachmentOps {
+    fn default() -> Self {
+        AttachmentOps::CLEAR
+    }
+}
+
+/// One color attachment of `RenderTargets`, written by the fragment shader output at its
+/// index, e.g. `layout(location = 1) out vec4 normal`.
+#[derive(Clone, Copy, Debug)]
+pub struct ColorTarget {
+    pub format: Format,
+    pub blend: BlendMode,
+    pub ops: AttachmentOps,
+    /// What the attachment is cleared to when the pass begins with `AttachmentLoadOp::Clear`.
+    pub clear: ClearValue,
+}
+impl ColorTarget {
+    /// An opaque target of `format`, cleared to zero.
+    pub fn new(format: Format) -> Self {
+        let clear = match format.numeric_format_color() {
+            Some(NumericFormat::UINT) => ClearValue::Uint([0; 4]),
+            Some(NumericFormat::SINT) => ClearValue::Int([0; 4]),
+            _ => ClearValue::Float([0.0; 4]),
+        };
+        Self {
+            format,
+            blend: BlendMode::Opaque,
+            ops: AttachmentOps::CLEAR,
+            clear,
+        }
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_ops(mut self, ops: AttachmentOps) -> Self {
+        self.ops = ops;
+        self
+    }
+
+    pub fn with_clear(mut self, clear: impl Into<ClearValue>) -> Self {
+        self.clear = clear.into();
+        self
+    }
+}
+
+/// An offscreen pass with several color attachments written at once, each with its own format
+/// and blend mode, e.g. a G-buffer of albedo, normals and material ids for deferred shading.
+/// Attachments are sampled or copied from after the pass. Pipelines drawing into it take
+/// `color_blend_state` and subpass 0 of `render_pass`.
+///
+/// A second, overlay pass draws over the color attachments again while sampling the depth the
+/// first one left, e.g. for decals.
+pub struct RenderTargets {
+    render_pass: Arc<RenderPass>,
+    framebuffer: Arc<Framebuffer>,
+    overlay_render_pass: Arc<RenderPass>,
+    overlay_framebuffer: Arc<Framebuffer>,
+    targets: Vec<ColorTarget>,
+    colors: Vec<Arc<ImageView>>,
+    depth: Option<Arc<ImageView>>,
+    sampled_depth: Option<Arc<ImageView>>,
+}
+impl RenderTargets {
+    /// Panics where the device can't draw `targets` at once; see `try_new`.
+    pub fn new(
+        context: &ComputeContext,
+        extent: [u32; 2],
+        targets: &[ColorTarget],
+        depth_format: Option<Format>,
+    ) -> Self {
+        Self::try_new(context, extent, targets, depth_format)
+            .expect("too many color targets, or independent blending is unsupported")
+    }
+
+    /// `None` for more targets than `DeviceCapabilities::max_color_attachments`, or targets
+    /// blending differently without `DeviceCapabilities::independent_blend`.
+    pub fn try_new(
+        context: &ComputeContext,
+        extent: [u32; 2],
+        targets: &[ColorTarget],
+        depth_format: Option<Format>,
+    ) -> Option<Self> {
+        let capabilities = context.capabilities();
+        let independent = targets
+            .windows(2)
+            .any(|pair| pair[0].blend != pair[1].blend);
+        if targets.len() > capabilities.max_color_attachments as usize
+            || (independent && !capabilities.independent_blend)
+        {
+            return None;
+        }
+
+        let color_attachment = |target: &ColorTarget, ops: AttachmentOps| AttachmentDescription {
+            format: target.format,
+            samples: SampleCount::Sample1,
+            load_op: ops.load,
+            store_op: ops.store,
+            initial_layout: ops.initial_layout(ImageLayout::ShaderReadOnlyOptimal),
+            final_layout: ImageLayout::ShaderReadOnlyOptimal,
+            ..Default::default()
+        };
+        let mut attachments: Vec<AttachmentDescription> = targets
+            .iter()
+            .map(|target| color_attachment(target, target.ops))
+            .collect();
+        let color_attachments: Vec<Option<AttachmentReference>> = (0..targets.len() as u32)
+            .map(|attachment| {
+                Some(AttachmentReference {
+                    attachment,
+                    layout: ImageLayout::ColorAttachmentOptimal,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let depth_stencil_attachment = depth_format.map(|format| {
+            attachments.push(AttachmentDescription {
+                format,
+                samples: SampleCount::Sample1,
+                load_op: AttachmentLoadOp::Clear,
+                store_op: AttachmentStoreOp::Store,
+                initial_layout: ImageLayout::Undefined,
+                final_layout: ImageLayout::ShaderReadOnlyOptimal,
+                ..Default::default()
+            });
+            AttachmentReference {
+                attachment: targets.len() as u32,
+                layout: ImageLayout::DepthStencilAttachmentOptimal,
+                ..Default::default()
+            }
+        });
+        let render_pass = RenderPass::new(
+            context.device(),
+            RenderPassCreateInfo {
+                attachments,
+                subpasses: vec![SubpassDescription {
+                    color_attachments: color_attachments.clone(),
+                    depth_stencil_attachment,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let overlay_render_pass = RenderPass::new(
+            context.device(),
+            RenderPassCreateInfo {
+                attachments: targets
+                    .iter()
+                    .map(|target| color_attachment(target, AttachmentOps::LOAD))
+                    .collect(),
+                subpasses: vec![SubpassDescription {
+                    color_attachments,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let attachment = |format, usage, label| {
+            let image = Image::new(
+                context.memory_allocator(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: [extent[0], extent[1], 1],
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            context
+                .memory_tracker()
+                .track_image(MemoryCategory::RenderTargets, label, &image);
+            ImageView::new_default(image).unwrap()
+        };
+        let colors: Vec<Arc<ImageView>> = targets
+            .iter()
+            .map(|target| {
+                attachment(
+                    target.format,
+                    ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                    "render target color",
+                )
+            })
+            .collect();
+        let depth = depth_format.map(|format| {
+            attachment(
+                format,
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
+                "render target depth",
+            )
+        });
+        // Samplers read a single aspect, which a view of a depth/stencil format has two of.
+        let sampled_depth = depth.as_ref().map(|depth| {
+            let image = depth.image().clone();
+            ImageView::new(
+                image.clone(),
+                ImageViewCreateInfo {
+                    subresource_range: ImageSubresourceRange {
+                        aspects: ImageAspects::DEPTH,
+                        ..image.subresource_range()
+                    },
+                    ..ImageViewCreateInfo::from_image(&image)
+                },
+            )
+            .unwrap()
+        });
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: colors.iter().cloned().chain(depth.clone()).collect(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let overlay_framebuffer = Framebuffer::new(
+            overlay_render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: colors.clone(),
+                extent,
+                layers: 1,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        Some(Self {
+            render_pass,
+            framebuffer,
+            overlay_render_pass,
+            overlay_framebuffer,
+            targets: targets.to_vec(),
+            colors,
+            depth,
+            sampled_depth,
+        })
+    }
+
+    pub fn render_pass(&self) -> Arc<RenderPass> {
+        self.render_pass.clone()
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        let extent = self.framebuffer.extent();
+        [extent[0], extent[1]]
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let [width, height] = self.extent();
+        Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    pub fn targets(&self) -> &[ColorTarget] {
+        &self.targets
+    }
+
+    /// The color attachment at `index`, in the order of the targets passed to `new`.
+    pub fn color(&self, index: usize) -> Arc<ImageView> {
+        self.colors[index].clone()
+    }
+
+    /// The overlay pass, loading the color attachments and without depth. Pipelines drawing
+    /// into it take `color_blend_state` and subpass 0 of it.
+    pub fn overlay_render_pass(&self) -> Arc<RenderPass> {
+        self.overlay_render_pass.clone()
+    }
+
+    /// The depth attachment, with every aspect of its format.
+    pub fn depth(&self) -> Option<Arc<ImageView>> {
+        self.depth.clone()
+    }
+
+    /// The depth aspect of the depth attachment alone, which can be sampled after the pass
+    /// whatever the format, e.g. by pipelines drawing in the overlay pass.
+    pub fn sampled_depth(&self) -> Option<Arc<ImageView>> {
+        self.sampled_depth.clone()
+    }
+
+    /// Blend state with each attachment blending as its target asks, for
+    /// `GraphicsPipelineCreateInfo::color_blend_state`.
+    pub fn color_blend_state(&self) -> ColorBlendState {
+        ColorBlendState {
+            attachments: self
+                .targets
+                .iter()
+                .map(|target| ColorBlendAttachmentState {
+                    blend: target.blend.attachment_blend(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Begins the pass, clearing the attachments whose targets clear, lets `record` draw and
+    /// ends the pass.
+    pub fn record_pass<F>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        record: F,
+    ) where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        let mut clear_values: Vec<Option<ClearValue>> = self
+            .targets
+            .iter()
+            .map(|target| (target.ops.load == AttachmentLoadOp::Clear).then_some(target.clear))
+            .collect();
+        if let Some(depth) = &self.depth {
+            clear_values.push(Some(if depth.format().numeric_format_stencil().is_some() {
+                ClearValue::DepthStencil((1.0, 0))
+            } else {
+                ClearValue::Depth(1.0)
+            }));
+        }
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values,
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        record(builder);
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+    }
+
+    /// Begins the overlay pass, after `record_pass`, lets `record` draw over the color
+    /// attachments and ends the pass.
+    pub fn record_overlay_pass<F>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        record: F,
+    ) where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None; self.targets.len()],
+                    ..RenderPassBeginInfo::framebuffer(self.overlay_framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        record(builder);
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+    }
+}
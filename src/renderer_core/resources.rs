@@ -0,0 +1,1046 @@
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use slotmap::SecondaryMap;
+use slotmap::SlotMap;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::format::CompressionType;
+use vulkano::format::Format;
+use vulkano::format::FormatFeatures;
+use vulkano::image::max_mip_levels;
+use vulkano::image::view::ImageView;
+use vulkano::image::view::ImageViewCreateInfo;
+use vulkano::image::view::ImageViewType;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::sync::future::FenceSignalFuture;
+use vulkano::sync::GpuFuture;
+
+use crate::bounds::Aabb;
+use crate::compressed_texture::CompressedTexture;
+use crate::gltf_loader::GltfPrimitive;
+use crate::handles::MaterialId;
+use crate::handles::MeshId;
+use crate::handles::TextureArrayId;
+use crate::handles::TextureId;
+use crate::handles::VolumeTextureId;
+
+use super::bindless::BindlessMaterial;
+use super::bindless::BindlessTextures;
+use super::bindless::NO_TEXTURE;
+use super::buffer_structs::MeshVertex;
+use super::descriptor_sets::DescriptorSets;
+use super::frame_stats::FrameStats;
+use super::frame_stats::FrameStatsCounter;
+use super::material_pipelines::MaterialFeatures;
+use super::memory_stats::MemoryTracker;
+use super::memory_stats::MemoryUsage;
+use super::mesh_pool::MeshAllocation;
+use super::mesh_pool::MeshPool;
+use super::sampler::MipSampling;
+use super::sampler::SamplerDesc;
+use super::staging::StagingUploader;
+use super::stencil::StencilMode;
+
+struct Mesh {
+    /// The mesh's range of its arena's buffers.
+    vertex_buffer: Subbuffer<[MeshVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+    allocation: MeshAllocation,
+    bounds: Aabb,
+}
+
+struct Texture {
+    view: Arc<ImageView>,
+    /// Index in `Resources::bindless`, if it has one and wasn't full.
+    bindless_index: Option<u32>,
+}
+
+/// A resource whose copy to the GPU has been submitted but may not have finished.
+enum Staged {
+    Mesh(MeshId, Mesh),
+    Texture(TextureId, Arc<ImageView>),
+}
+
+/// The resources of one `process_uploads`, made ready once the fence of their copies signals.
+struct UploadBatch {
+    fence: FenceSignalFuture<Box<dyn GpuFuture>>,
+    resources: Vec<Staged>,
+}
+
+/// A resource whose handle may be handed out before its data reaches the GPU.
+enum Slot<T> {
+    Pending,
+    Ready(T),
+}
+impl<T> Slot<T> {
+    fn ready(&self) -> Option<&T> {
+        match self {
+            Slot::Pending => None,
+            Slot::Ready(resource) => Some(resource),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    meshes: SlotMap<MeshId, Slot<Mesh>>,
+    textures: SlotMap<TextureId, Slot<Texture>>,
+    materials: SlotMap<MaterialId, Material>,
+}
+
+/// Creation work queued by a `ResourceLoader` for the render thread.
+enum UploadData {
+    Mesh(MeshId, GltfPrimitive),
+    Texture {
+        id: TextureId,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    },
+    CompressedTexture(TextureId, CompressedTexture),
+}
+
+struct Upload {
+    data: UploadData,
+    priority: UploadPriority,
+}
+impl Upload {
+    /// Bytes copied to the GPU.
+    fn size(&self) -> u64 {
+        match &self.data {
+            UploadData::Mesh(_, primitive) => {
+                (primitive.positions.len() * std::mem::size_of::<MeshVertex>()
+                    + primitive.indices.len() * std::mem::size_of::<u32>()) as u64
+            }
+            UploadData::Texture { rgba, .. } => rgba.len() as u64,
+            UploadData::CompressedTexture(_, texture) => texture.size(),
+        }
+    }
+}
+
+/// How queued uploads compete for the per-frame transfer budget.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UploadPriority {
+    /// Uploaded in queue order while the frame's budget lasts.
+    #[default]
+    Normal,
+    /// Uploaded in the next `process_uploads` regardless of the budget, e.g. the player's
+    /// weapon or anything the camera is about to see up close.
+    Critical,
+}
+
+/// What one `Resources::process_uploads` call did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// Resources whose copies were submitted. They become ready once the GPU has run them,
+    /// which the next call checks.
+    pub uploaded: usize,
+    pub uploaded_bytes: u64,
+    /// Uploads left for later frames.
+    pub pending: usize,
+    pub pending_bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    pub base_color: [f32; 4],
+    pub base_color_texture: Option<TextureId>,
+    /// Baked static lighting, sampled with the mesh's lightmap UVs by `LightmappedMesh`.
+    pub lightmap: Option<TextureId>,
+    /// Shader features to draw with, selecting the pipeline in `MaterialPipelines`.
+    pub features: MaterialFeatures,
+    /// How draws of the material mask or are masked by others, selecting the pipeline in
+    /// `MaterialPipelines` with `features`.
+    pub stencil: StencilMode,
+}
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            lightmap: None,
+            features: MaterialFeatures::NONE,
+            stencil: StencilMode::Disabled,
+        }
+    }
+}
+
+/// Creates resources from any thread. Handles are returned at once; meshes and textures stay
+/// pending, and draw nothing, until the render thread has uploaded them in
+/// `Resources::process_uploads`.
+#[derive(Clone)]
+pub struct ResourceLoader {
+    device: Arc<Device>,
+    slots: Arc<Mutex<Slots>>,
+    uploads: mpsc::Sender<Upload>,
+}
+impl ResourceLoader {
+    pub fn create_mesh(&self, primitive: GltfPrimitive) -> MeshId {
+        self.create_mesh_with_priority(primitive, UploadPriority::Normal)
+    }
+
+    pub fn create_mesh_with_priority(
+        &self,
+        primitive: GltfPrimitive,
+        priority: UploadPriority,
+    ) -> MeshId {
+        let id = lock(&self.slots).meshes.insert(Slot::Pending);
+        self.send(UploadData::Mesh(id, primitive), priority);
+        id
+    }
+
+    /// Tightly packed sRGB RGBA8 pixels, as for `Resources::create_texture`.
+    pub fn create_texture(&self, width: u32, height: u32, rgba: Vec<u8>) -> TextureId {
+        self.create_texture_with_priority(width, height, rgba, UploadPriority::Normal)
+    }
+
+    pub fn create_texture_with_priority(
+        &self,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+        priority: UploadPriority,
+    ) -> TextureId {
+        assert_eq!(
+            rgba.len(),
+            (width * height * 4) as usize,
+            "texture data does not match its size"
+        );
+        let id = lock(&self.slots).textures.insert(Slot::Pending);
+        self.send(
+            UploadData::Texture {
+                id,
+                width,
+                height,
+                rgba,
+            },
+            priority,
+        );
+        id
+    }
+
+    /// Texture data copied as is, e.g. from `CompressedTexture::load_ktx2`. `None` when the
+    /// device can't sample its format; decode it to RGBA8 instead.
+    pub fn create_compressed_texture(&self, texture: CompressedTexture) -> Option<TextureId> {
+        self.create_compressed_texture_with_priority(texture, UploadPriority::Normal)
+    }
+
+    pub fn create_compressed_texture_with_priority(
+        &self,
+        texture: CompressedTexture,
+        priority: UploadPriority,
+    ) -> Option<TextureId> {
+        if !texture_format_supported(&self.device, texture.format) {
+            return None;
+        }
+        let id = lock(&self.slots).textures.insert(Slot::Pending);
+        self.send(UploadData::CompressedTexture(id, texture), priority);
+        Some(id)
+    }
+
+    /// Materials need no upload and are usable immediately.
+    pub fn create_material(&self, material: Material) -> MaterialId {
+        lock(&self.slots).materials.insert(material)
+    }
+
+    fn send(&self, data: UploadData, priority: UploadPriority) {
+        // The receiver lives as long as the renderer; after that nobody can draw the resource
+        // anyway.
+        let _ = self.uploads.send(Upload { data, priority });
+    }
+}
+
+fn lock(slots: &Mutex<Slots>) -> MutexGuard<'_, Slots> {
+    slots.lock().expect("resource slots poisoned")
+}
+
+/// Mesh buffers locked for recording draws. It can be shared between threads recording
+/// secondary command buffers, but blocks loaders and `Resources` from creating or removing
+/// resources while held, so drop it once recording is done.
+pub struct MeshDraws<'a> {
+    slots: MutexGuard<'a, Slots>,
+    mesh_pool: &'a MeshPool,
+    /// Of every thread recording with it.
+    stats: FrameStatsCounter,
+}
+impl MeshDraws<'_> {
+    /// The draws and buffer binds recorded with it so far. Meshes are triangle lists.
+    pub fn stats(&self) -> FrameStats {
+        self.stats.get()
+    }
+
+    /// Like `Resources::record_draw_mesh`.
+    pub fn record_draw_mesh<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        id: MeshId,
+        instance_count: u32,
+        first_instance: u32,
+    ) {
+        let Some(mesh) = self.slots.meshes.get(id).and_then(Slot::ready) else {
+            return;
+        };
+        builder
+            .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(mesh.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(
+                mesh.index_buffer.len() as u32,
+                instance_count,
+                0,
+                0,
+                first_instance,
+            )
+            .unwrap();
+        let mut stats = FrameStats {
+            vertex_buffer_binds: 1,
+            index_buffer_binds: 1,
+            ..Default::default()
+        };
+        stats.add_draw(
+            PrimitiveTopology::TriangleList,
+            mesh.index_buffer.len() as u32,
+            instance_count,
+        );
+        self.stats.add(stats);
+    }
+
+    /// Like `Resources::record_draw_meshes`.
+    pub fn record_draw_meshes<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        draws: impl IntoIterator<Item = (MeshId, u32, u32)>,
+    ) {
+        let mut stats = FrameStats::default();
+        let mut bound_arena = None;
+        for (id, instance_count, first_instance) in draws {
+            let Some(mesh) = self.slots.meshes.get(id).and_then(Slot::ready) else {
+                continue;
+            };
+            let allocation = &mesh.allocation;
+            if bound_arena != Some(allocation.arena) {
+                let (vertices, indices) = self.mesh_pool.arena_buffers(allocation.arena);
+                builder
+                    .bind_vertex_buffers(0, vertices)
+                    .unwrap()
+                    .bind_index_buffer(indices)
+                    .unwrap();
+                bound_arena = Some(allocation.arena);
+                stats.vertex_buffer_binds += 1;
+                stats.index_buffer_binds += 1;
+            }
+            builder
+                .draw_indexed(
+                    mesh.index_buffer.len() as u32,
+                    instance_count,
+                    allocation.indices.start as u32,
+                    allocation.vertices.start as i32,
+                    first_instance,
+                )
+                .unwrap();
+            stats.add_draw(
+                PrimitiveTopology::TriangleList,
+                mesh.index_buffer.len() as u32,
+                instance_count,
+            );
+        }
+        self.stats.add(stats);
+    }
+}
+
+/// Whether textures of `format` can be copied to and sampled. Block-compressed formats also
+/// need their feature enabled, e.g. `texture_compression_bc` for BCn.
+fn texture_format_supported(device: &Device, format: Format) -> bool {
+    let features = device.enabled_features();
+    let compression_enabled = match format.compression() {
+        None => true,
+        Some(CompressionType::BC) => features.texture_compression_bc,
+        Some(CompressionType::ETC2 | CompressionType::EAC) => features.texture_compression_etc2,
+        Some(CompressionType::ASTC_LDR) => features.texture_compression_astc_ldr,
+        Some(_) => false,
+    };
+    compression_enabled
+        && device
+            .physical_device()
+            .format_properties(format)
+            .is_ok_and(|properties| {
+                properties
+                    .optimal_tiling_features
+                    .contains(FormatFeatures::SAMPLED_IMAGE | FormatFeatures::TRANSFER_DST)
+            })
+}
+
+/// Owns every mesh, texture and material and hands out handles to them. Removing a resource
+/// only drops the renderer's reference; command buffers still in flight keep the GPU memory
+/// alive until they finish.
+pub struct Resources {
+    device: Arc<Device>,
+    staging: StagingUploader,
+    /// Vertex and index arenas every mesh is suballocated from.
+    mesh_pool: MeshPool,
+    /// Copies staged by the current `process_uploads`.
+    staged: Vec<Staged>,
+    /// Submitted batches whose copies may still be running.
+    in_flight: Vec<UploadBatch>,
+    slots: Arc<Mutex<Slots>>,
+    uploads: mpsc::Receiver<Upload>,
+    /// Uploads received from loaders that did not fit into earlier frames' budgets.
+    pending: VecDeque<Upload>,
+    upload_budget: u64,
+    loader: ResourceLoader,
+    /// Every ready texture, where the device supports descriptor indexing.
+    bindless: Option<BindlessTextures>,
+    /// Samplers set for textures, pending or ready; the others use the default sampler.
+    texture_samplers: SecondaryMap<TextureId, SamplerDesc>,
+    /// 2D array views. They are created and written on the render thread only, so they have no
+    /// pending state.
+    texture_arrays: SlotMap<TextureArrayId, Arc<ImageView>>,
+    /// 3D views, created on the render thread like `texture_arrays`.
+    volume_textures: SlotMap<VolumeTextureId, Arc<ImageView>>,
+}
+impl Resources {
+    /// Default of `upload_budget`: enough for a few large textures per frame without stalling.
+    pub const DEFAULT_UPLOAD_BUDGET: u64 = 32 * 1024 * 1024;
+
+    /// Bindless textures, where the device has them, are allocated from `descriptor_sets`,
+    /// e.g. `RendererCore::descriptor_sets`.
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, descriptor_sets: &DescriptorSets) -> Self {
+        Self::with_upload_queue(device, queue.clone(), queue, descriptor_sets)
+    }
+
+    /// Copies data on `upload_queue`, e.g. `Queues::transfer`, for drawing on
+    /// `queue`.
+    pub fn with_upload_queue(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        upload_queue: Arc<Queue>,
+        descriptor_sets: &DescriptorSets,
+    ) -> Self {
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let slots = Arc::new(Mutex::new(Slots::default()));
+        let (sender, uploads) = mpsc::channel();
+        let bindless = device
+            .enabled_features()
+            .descriptor_binding_variable_descriptor_count
+            .then(|| BindlessTextures::new(device.clone(), descriptor_sets));
+        Self {
+            device: device.clone(),
+            staging: StagingUploader::new(upload_queue, memory_allocator, queue),
+            mesh_pool: MeshPool::new(),
+            staged: Vec::new(),
+            in_flight: Vec::new(),
+            loader: ResourceLoader {
+                device,
+                slots: slots.clone(),
+                uploads: sender,
+            },
+            slots,
+            uploads,
+            pending: VecDeque::new(),
+            upload_budget: Self::DEFAULT_UPLOAD_BUDGET,
+            bindless,
+            texture_samplers: SecondaryMap::new(),
+            texture_arrays: SlotMap::with_key(),
+            volume_textures: SlotMap::with_key(),
+        }
+    }
+
+    /// Memory of every mesh arena, texture and staging buffer still alive, by category.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.staging.memory_tracker().usage()
+    }
+
+    /// What `memory_usage` counts, e.g. for a `LeakCheck`.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        self.staging.memory_tracker()
+    }
+
+    /// A handle for creating resources from other threads.
+    pub fn loader(&self) -> ResourceLoader {
+        self.loader.clone()
+    }
+
+    pub fn upload_budget(&self) -> u64 {
+        self.upload_budget
+    }
+
+    /// Bytes `process_uploads` copies per frame, so a burst of streamed assets is spread over
+    /// several frames instead of stalling one. Critical uploads ignore it, and one upload per
+    /// frame always goes through so assets larger than the budget still arrive.
+    pub fn set_upload_budget(&mut self, bytes: u64) {
+        self.upload_budget = bytes;
+    }
+
+    /// Uploads critical resources queued by loaders, then the rest in queue order until the
+    /// frame's budget is spent, all in one submission. Resources of earlier calls whose copies
+    /// have finished become ready first. Call once per frame on the render thread, before
+    /// recording draws.
+    pub fn process_uploads(&mut self) -> UploadStats {
+        self.mesh_pool.next_frame();
+        self.finish_uploads();
+        self.pending.extend(self.uploads.try_iter());
+        let mut stats = UploadStats::default();
+
+        let (critical, normal): (VecDeque<_>, VecDeque<_>) = self
+            .pending
+            .drain(..)
+            .partition(|upload| upload.priority == UploadPriority::Critical);
+        for upload in critical {
+            let size = upload.size();
+            if self.upload(upload.data) {
+                stats.uploaded += 1;
+                stats.uploaded_bytes += size;
+            }
+        }
+        self.pending = normal;
+        while let Some(upload) = self.pending.front() {
+            let size = upload.size();
+            if stats.uploaded_bytes > 0 && stats.uploaded_bytes + size > self.upload_budget {
+                break;
+            }
+            let upload = self.pending.pop_front().unwrap();
+            if self.upload(upload.data) {
+                stats.uploaded += 1;
+                stats.uploaded_bytes += size;
+            }
+        }
+
+        if let Some(copies) = self.staging.flush() {
+            self.in_flight.push(UploadBatch {
+                fence: copies.then_signal_fence_and_flush().unwrap(),
+                resources: std::mem::take(&mut self.staged),
+            });
+        }
+
+        stats.pending = self.pending.len();
+        stats.pending_bytes = self.pending.iter().map(Upload::size).sum();
+        stats
+    }
+
+    /// Makes the resources of finished batches ready, unless they were removed meanwhile.
+    fn finish_uploads(&mut self) {
+        let (finished, in_flight) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|batch: &UploadBatch| batch.fence.is_signaled().unwrap());
+        self.in_flight = in_flight;
+        for resource in finished.into_iter().flat_map(|batch| batch.resources) {
+            match resource {
+                Staged::Mesh(id, mesh) => match lock(&self.slots).meshes.get_mut(id) {
+                    Some(slot) => *slot = Slot::Ready(mesh),
+                    None => self.mesh_pool.retire(mesh.allocation),
+                },
+                Staged::Texture(id, view) => {
+                    if !lock(&self.slots).textures.contains_key(id) {
+                        continue;
+                    }
+                    let texture = self.texture(view, self.texture_samplers.get(id).copied());
+                    if let Some(slot) = lock(&self.slots).textures.get_mut(id) {
+                        *slot = Slot::Ready(texture);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves a queued mesh past the budget in the next `process_uploads`. Returns whether it
+    /// was still queued.
+    pub fn prioritize_mesh(&mut self, id: MeshId) -> bool {
+        self.prioritize(|data| matches!(data, UploadData::Mesh(mesh, _) if *mesh == id))
+    }
+
+    pub fn prioritize_texture(&mut self, id: TextureId) -> bool {
+        self.prioritize(
+            |data| matches!(data, UploadData::Texture { id: texture, .. } if *texture == id),
+        )
+    }
+
+    fn prioritize(&mut self, matches: impl Fn(&UploadData) -> bool) -> bool {
+        self.pending.extend(self.uploads.try_iter());
+        match self.pending.iter_mut().find(|upload| matches(&upload.data)) {
+            Some(upload) => {
+                upload.priority = UploadPriority::Critical;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stages the resource's copy for this call's batch. Returns false if the resource was
+    /// removed while it was queued.
+    fn upload(&mut self, data: UploadData) -> bool {
+        match data {
+            UploadData::Mesh(id, primitive) => {
+                if !lock(&self.slots).meshes.contains_key(id) {
+                    return false;
+                }
+                let mesh = self.upload_mesh(&primitive);
+                self.staged.push(Staged::Mesh(id, mesh));
+            }
+            UploadData::Texture {
+                id,
+                width,
+                height,
+                rgba,
+            } => {
+                if !lock(&self.slots).textures.contains_key(id) {
+                    return false;
+                }
+                let view = self.upload_texture(width, height, &rgba);
+                self.staged.push(Staged::Texture(id, view));
+            }
+            UploadData::CompressedTexture(id, texture) => {
+                if !lock(&self.slots).textures.contains_key(id) {
+                    return false;
+                }
+                let view = self.upload_compressed_texture(&texture);
+                self.staged.push(Staged::Texture(id, view));
+            }
+        }
+        true
+    }
+
+    /// Uploads the mesh and waits for the copy to finish.
+    pub fn create_mesh(&mut self, primitive: &GltfPrimitive) -> MeshId {
+        let mesh = self.upload_mesh(primitive);
+        self.staging.flush_and_wait();
+        lock(&self.slots).meshes.insert(Slot::Ready(mesh))
+    }
+
+    /// Uploads tightly packed sRGB RGBA8 pixels and waits for the copy to finish.
+    pub fn create_texture(&mut self, width: u32, height: u32, rgba: &[u8]) -> TextureId {
+        assert_eq!(
+            rgba.len(),
+            (width * height * 4) as usize,
+            "texture data does not match its size"
+        );
+        let view = self.upload_texture(width, height, rgba);
+        self.staging.flush_and_wait();
+        let texture = self.texture(view, None);
+        lock(&self.slots).textures.insert(Slot::Ready(texture))
+    }
+
+    /// Uploads texture data as is, e.g. from `CompressedTexture::load_ktx2`, and waits for the
+    /// copy to finish. `None` when the device can't sample its format; decode it to RGBA8
+    /// instead.
+    pub fn create_compressed_texture(&mut self, texture: &CompressedTexture) -> Option<TextureId> {
+        if !self.texture_format_supported(texture.format) {
+            return None;
+        }
+        let view = self.upload_compressed_texture(texture);
+        self.staging.flush_and_wait();
+        let texture = self.texture(view, None);
+        Some(lock(&self.slots).textures.insert(Slot::Ready(texture)))
+    }
+
+    /// Whether `create_compressed_texture` accepts textures in `format`, e.g. to pick between
+    /// BC7 and a decoded fallback.
+    pub fn texture_format_supported(&self, format: Format) -> bool {
+        texture_format_supported(&self.device, format)
+    }
+
+    /// An sRGB RGBA8 texture array of `layers` layers with full mip chains, e.g. a sprite
+    /// sheet or terrain layers, sampled as `sampler2DArray`. The layers hold no data until
+    /// written with `write_texture_array_layer`.
+    pub fn create_texture_array(&mut self, width: u32, height: u32, layers: u32) -> TextureArrayId {
+        let extent = [width, height, 1];
+        let image = self.staging.empty_image(
+            "texture array",
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8G8B8A8_SRGB,
+                extent,
+                array_layers: layers,
+                mip_levels: max_mip_levels(extent),
+                usage: ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+        );
+        // A single layer would otherwise get a plain 2D view.
+        let view = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap();
+        self.texture_arrays.insert(view)
+    }
+
+    /// Uploads tightly packed sRGB RGBA8 pixels into one layer, regenerates its mips and waits
+    /// for the copy to finish. Frames still in flight must not be sampling the layer. Returns
+    /// whether the array existed.
+    pub fn write_texture_array_layer(
+        &mut self,
+        id: TextureArrayId,
+        layer: u32,
+        rgba: &[u8],
+    ) -> bool {
+        let Some(view) = self.texture_arrays.get(id) else {
+            return false;
+        };
+        let image = view.image().clone();
+        let [width, height, _] = image.extent();
+        assert!(layer < image.array_layers(), "layer out of range");
+        assert_eq!(
+            rgba.len(),
+            (width * height * 4) as usize,
+            "texture data does not match its size"
+        );
+        self.staging.write_layer(&image, layer, rgba);
+        self.staging.flush_and_wait();
+        true
+    }
+
+    /// Array view for binding the texture array to a `sampler2DArray` descriptor.
+    pub fn texture_array_view(&self, id: TextureArrayId) -> Option<Arc<ImageView>> {
+        self.texture_arrays.get(id).cloned()
+    }
+
+    pub fn remove_texture_array(&mut self, id: TextureArrayId) -> bool {
+        self.texture_arrays.remove(id).is_some()
+    }
+
+    /// A 3D texture of tightly packed `texels`, slice after slice, e.g. `R8_UNORM` or
+    /// `R16_UNORM` densities from a scan for `VolumeRenderer`, and waits for the copy to finish.
+    /// `None` when the device can't sample `format`.
+    pub fn create_volume_texture(
+        &mut self,
+        extent: [u32; 3],
+        format: Format,
+        texels: &[u8],
+    ) -> Option<VolumeTextureId> {
+        assert_eq!(
+            texels.len() as u64,
+            extent.iter().map(|&size| size as u64).product::<u64>() * format.block_size(),
+            "texture data does not match its size"
+        );
+        if !self.texture_format_supported(format) {
+            return None;
+        }
+        let image = self.staging.image(
+            "volume texture",
+            ImageCreateInfo {
+                image_type: ImageType::Dim3d,
+                format,
+                extent,
+                usage: ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            texels,
+        );
+        self.staging.flush_and_wait();
+        Some(
+            self.volume_textures
+                .insert(ImageView::new_default(image).unwrap()),
+        )
+    }
+
+    /// 3D view for binding the texture to a `sampler3D` descriptor.
+    pub fn volume_texture_view(&self, id: VolumeTextureId) -> Option<Arc<ImageView>> {
+        self.volume_textures.get(id).cloned()
+    }
+
+    pub fn remove_volume_texture(&mut self, id: VolumeTextureId) -> bool {
+        self.volume_textures.remove(id).is_some()
+    }
+
+    pub fn create_material(&mut self, material: Material) -> MaterialId {
+        lock(&self.slots).materials.insert(material)
+    }
+
+    /// Returns whether the mesh existed.
+    pub fn remove_mesh(&mut self, id: MeshId) -> bool {
+        let Some(slot) = lock(&self.slots).meshes.remove(id) else {
+            return false;
+        };
+        if let Slot::Ready(mesh) = slot {
+            self.mesh_pool.retire(mesh.allocation);
+        }
+        true
+    }
+
+    pub fn remove_texture(&mut self, id: TextureId) -> bool {
+        let Some(slot) = lock(&self.slots).textures.remove(id) else {
+            return false;
+        };
+        self.texture_samplers.remove(id);
+        if let Slot::Ready(texture) = slot {
+            self.release_bindless_index(&texture);
+        }
+        true
+    }
+
+    pub fn remove_material(&mut self, id: MaterialId) -> bool {
+        lock(&self.slots).materials.remove(id).is_some()
+    }
+
+    /// Whether the mesh has been uploaded and can be drawn.
+    pub fn is_mesh_ready(&self, id: MeshId) -> bool {
+        lock(&self.slots)
+            .meshes
+            .get(id)
+            .is_some_and(|slot| slot.ready().is_some())
+    }
+
+    pub fn is_texture_ready(&self, id: TextureId) -> bool {
+        lock(&self.slots)
+            .textures
+            .get(id)
+            .is_some_and(|slot| slot.ready().is_some())
+    }
+
+    /// Local bounds of the mesh, for building `RenderObject`s. `None` while it is pending.
+    pub fn mesh_bounds(&self, id: MeshId) -> Option<Aabb> {
+        let slots = lock(&self.slots);
+        slots.meshes.get(id)?.ready().map(|mesh| mesh.bounds)
+    }
+
+    pub fn material(&self, id: MaterialId) -> Option<Material> {
+        lock(&self.slots).materials.get(id).copied()
+    }
+
+    /// Returns whether the material existed.
+    pub fn set_material(&mut self, id: MaterialId, material: Material) -> bool {
+        match lock(&self.slots).materials.get_mut(id) {
+            Some(slot) => {
+                *slot = material;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// View for binding the texture to a sampler descriptor. `None` while it is pending.
+    pub fn texture_view(&self, id: TextureId) -> Option<Arc<ImageView>> {
+        let slots = lock(&self.slots);
+        slots
+            .textures
+            .get(id)?
+            .ready()
+            .map(|texture| texture.view.clone())
+    }
+
+    /// Index of the texture in the bindless array. `None` while it is pending, or without
+    /// descriptor indexing.
+    pub fn texture_index(&self, id: TextureId) -> Option<u32> {
+        let slots = lock(&self.slots);
+        slots.textures.get(id)?.ready()?.bindless_index
+    }
+
+    /// The material with its textures as bindless indices, `NO_TEXTURE` for missing or pending
+    /// ones. `None` for stale handles.
+    pub fn bindless_material(&self, id: MaterialId) -> Option<BindlessMaterial> {
+        let material = self.material(id)?;
+        let index = |texture: Option<TextureId>| {
+            texture
+                .and_then(|texture| self.texture_index(texture))
+                .unwrap_or(NO_TEXTURE)
+        };
+        Some(BindlessMaterial {
+            base_color: material.base_color,
+            base_color_texture: index(material.base_color_texture),
+            lightmap: index(material.lightmap),
+        })
+    }
+
+    /// The bindless texture array, for pipeline layouts. `None` without descriptor indexing.
+    pub fn bindless_textures(&self) -> Option<&BindlessTextures> {
+        self.bindless.as_ref()
+    }
+
+    /// How textures in the bindless array are sampled across their mip levels.
+    pub fn set_mip_sampling(&mut self, sampling: MipSampling) {
+        if let Some(bindless) = &mut self.bindless {
+            bindless.set_mip_sampling(sampling);
+        }
+    }
+
+    /// Sampler of the textures without one of their own, e.g. with anisotropy from the
+    /// graphics settings.
+    pub fn set_default_sampler(&mut self, desc: SamplerDesc) {
+        if let Some(bindless) = &mut self.bindless {
+            bindless.set_default_sampler(desc);
+        }
+    }
+
+    /// Samples the texture with `desc`, or the default sampler for `None`. Also works while it
+    /// is pending. Returns whether the texture existed.
+    pub fn set_texture_sampler(&mut self, id: TextureId, desc: Option<SamplerDesc>) -> bool {
+        let slots = lock(&self.slots);
+        let Some(slot) = slots.textures.get(id) else {
+            return false;
+        };
+        match desc {
+            Some(desc) => self.texture_samplers.insert(id, desc),
+            None => self.texture_samplers.remove(id),
+        };
+        if let (Some(bindless), Some(index)) = (
+            &mut self.bindless,
+            slot.ready().and_then(|texture| texture.bindless_index),
+        ) {
+            bindless.set_sampler(index, desc);
+        }
+        true
+    }
+
+    /// The sampler the texture is drawn with, for binding its view outside the bindless
+    /// array. `None` for stale handles.
+    pub fn texture_sampler(&self, id: TextureId) -> Option<SamplerDesc> {
+        if !lock(&self.slots).textures.contains_key(id) {
+            return None;
+        }
+        Some(self.texture_samplers.get(id).copied().unwrap_or_else(|| {
+            self.bindless
+                .as_ref()
+                .map_or_else(SamplerDesc::default, BindlessTextures::default_sampler)
+        }))
+    }
+
+    /// Every ready texture as one descriptor set, to bind once per frame at `BINDLESS_SET`.
+    pub fn bindless_descriptor_set(&mut self) -> Option<Arc<PersistentDescriptorSet>> {
+        self.bindless.as_mut().map(BindlessTextures::descriptor_set)
+    }
+
+    fn release_bindless_index(&mut self, texture: &Texture) {
+        if let (Some(bindless), Some(index)) = (&mut self.bindless, texture.bindless_index) {
+            bindless.remove(index);
+        }
+    }
+
+    pub fn device(&self) -> Arc<Device> {
+        self.device.clone()
+    }
+
+    /// Binds the mesh's buffers and records `instance_count` instances of it, e.g. one
+    /// `DrawBatch`. The pipeline and descriptor sets must already be bound. Stale handles and
+    /// pending meshes draw nothing.
+    pub fn record_draw_mesh<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        id: MeshId,
+        instance_count: u32,
+        first_instance: u32,
+    ) {
+        self.mesh_draws()
+            .record_draw_mesh(builder, id, instance_count, first_instance);
+    }
+
+    /// Records many meshes like `record_draw_mesh`, binding the buffers only when a mesh is in
+    /// a different arena than the one before, so runs of small meshes share one bind. Each
+    /// draw is a mesh, its instance count and its first instance.
+    pub fn record_draw_meshes<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        draws: impl IntoIterator<Item = (MeshId, u32, u32)>,
+    ) {
+        self.mesh_draws().record_draw_meshes(builder, draws);
+    }
+
+    /// Every mesh, locked for recording draws, e.g. from several threads at once with
+    /// `RendererCore::record_draw_list_parallel`.
+    pub fn mesh_draws(&self) -> MeshDraws<'_> {
+        MeshDraws {
+            slots: lock(&self.slots),
+            mesh_pool: &self.mesh_pool,
+            stats: FrameStatsCounter::default(),
+        }
+    }
+
+    /// Device-local arenas meshes are suballocated from, and how many of them no mesh uses.
+    pub fn mesh_arena_stats(&self) -> (usize, usize) {
+        (
+            self.mesh_pool.arena_count(),
+            self.mesh_pool.unused_arena_count(),
+        )
+    }
+
+    /// Stages the mesh's data into a range of a mesh arena, which holds it once the staging
+    /// uploader's next flush has run.
+    fn upload_mesh(&mut self, primitive: &GltfPrimitive) -> Mesh {
+        let vertices: Vec<MeshVertex> = primitive
+            .positions
+            .iter()
+            .zip(&primitive.normals)
+            .enumerate()
+            .map(|(i, (&position, &normal))| MeshVertex {
+                position,
+                normal,
+                uv: primitive.uvs.get(i).copied().unwrap_or([0.0; 2]),
+            })
+            .collect();
+        let allocation = self.mesh_pool.allocate(
+            &self.staging,
+            vertices.len() as u64,
+            primitive.indices.len() as u64,
+        );
+        let vertex_buffer = self.mesh_pool.vertex_buffer(&allocation);
+        let index_buffer = self.mesh_pool.index_buffer(&allocation);
+        self.staging.write_buffer(vertex_buffer.clone(), vertices);
+        self.staging
+            .write_buffer(index_buffer.clone(), primitive.indices.iter().copied());
+
+        Mesh {
+            vertex_buffer,
+            index_buffer,
+            allocation,
+            bounds: primitive.bounds(),
+        }
+    }
+
+    /// Stages the texture like `upload_mesh`, with its full mip chain.
+    fn upload_texture(&mut self, width: u32, height: u32, rgba: &[u8]) -> Arc<ImageView> {
+        let image = self.staging.image_with_mips(
+            "texture",
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8G8B8A8_SRGB,
+                extent: [width, height, 1],
+                usage: ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            rgba,
+        );
+        ImageView::new_default(image).unwrap()
+    }
+
+    /// Stages the texture's levels like `upload_mesh`. Compressed formats can't be blitted, so
+    /// textures without mips stay without them.
+    fn upload_compressed_texture(&mut self, texture: &CompressedTexture) -> Arc<ImageView> {
+        let image = self.staging.image_with_levels(
+            "compressed texture",
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: texture.format,
+                extent: [texture.extent[0], texture.extent[1], 1],
+                usage: ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            &texture.levels,
+        );
+        ImageView::new_default(image).unwrap()
+    }
+
+    /// A texture of an uploaded view, added to the bindless array with `sampler`.
+    fn texture(&mut self, view: Arc<ImageView>, sampler: Option<SamplerDesc>) -> Texture {
+        Texture {
+            bindless_index: self
+                .bindless
+                .as_mut()
+                .and_then(|bindless| bindless.insert_with_sampler(view.clone(), sampler)),
+            view,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::image::sampler::Filter;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::sampler::SamplerAddressMode;
+use vulkano::image::sampler::SamplerCreateInfo;
+use vulkano::image::sampler::SamplerMipmapMode;
+use vulkano::image::sampler::LOD_CLAMP_NONE;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+
+use crate::device_capabilities::DeviceCapabilities;
+
+/// How a sampler picks mip levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MipSampling {
+    /// Added to the computed level; positive values blur, negative ones sharpen at the cost of
+    /// shimmering. Clamped to the device's `max_sampler_lod_bias`.
+    pub lod_bias: f32,
+    /// Finest level sampled, e.g. above 0 to cap texture detail on low settings.
+    pub min_lod: f32,
+    /// Coarsest level sampled; `LOD_CLAMP_NONE` for the whole chain.
+    pub max_lod: f32,
+}
+impl Default for MipSampling {
+    fn default() -> Self {
+        Self {
+            lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: LOD_CLAMP_NONE,
+        }
+    }
+}
+
+/// Everything a texture is sampled with. The default is trilinear and repeating.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: SamplerMipmapMode,
+    /// Wrap mode on every axis.
+    pub address_mode: SamplerAddressMode,
+    /// Maximum anisotropy, e.g. 16.0 for textures seen at grazing angles. Clamped to the
+    /// device's limit, and ignored where `DeviceCapabilities::max_anisotropy` is `None`.
+    pub anisotropy: Option<f32>,
+    /// Makes it a depth comparison sampler, for `sampler2DShadow` in shaders.
+    pub compare: Option<CompareOp>,
+    pub mip: MipSampling,
+}
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode: SamplerAddressMode::Repeat,
+            anisotropy: None,
+            compare: None,
+            mip: MipSampling::default(),
+        }
+    }
+}
+impl SamplerDesc {
+    /// Unfiltered texels, e.g. for pixel art.
+    pub fn nearest() -> Self {
+        Self {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mipmap_mode: SamplerMipmapMode::Nearest,
+            ..Default::default()
+        }
+    }
+
+    /// Filtered depth comparisons against a shadow map, clamped to its edges.
+    pub fn shadow() -> Self {
+        Self {
+            address_mode: SamplerAddressMode::ClampToEdge,
+            compare: Some(CompareOp::LessOrEqual),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_anisotropy(self, max_anisotropy: f32) -> Self {
+        Self {
+            anisotropy: Some(max_anisotropy),
+            ..self
+        }
+    }
+
+    pub fn create_sampler(&self, device: Arc<Device>) -> Arc<Sampler> {
+        let max_bias = device.physical_device().properties().max_sampler_lod_bias;
+        let anisotropy = self
+            .anisotropy
+            .zip(DeviceCapabilities::new(&device).max_anisotropy)
+            .map(|(anisotropy, max)| anisotropy.clamp(1.0, max));
+        Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: self.mag_filter,
+                min_filter: self.min_filter,
+                mipmap_mode: self.mipmap_mode,
+                address_mode: [self.address_mode; 3],
+                mip_lod_bias: self.mip.lod_bias.clamp(-max_bias, max_bias),
+                anisotropy,
+                compare: self.compare,
+                lod: self.mip.min_lod..=self.mip.max_lod.max(self.mip.min_lod),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+}
@@ -5,9 +5,9 @@ pub mod vs {
                 #version 460
     
                 layout(location = 0) in ivec2 position;
-                layout(location = 1) in uvec3 color;
+                layout(location = 1) in vec4 color;
 
-                layout(location = 0) out vec3 v_color;
+                layout(location = 0) out vec4 v_color;
 
                 layout(binding = 0) uniform UniformBufferObject {
                     mat4 model;
@@ -17,7 +17,7 @@ pub mod vs {
 
                 void main() {
                     gl_Position = mvp.proj * mvp.view * mvp.model * vec4(position, 0.0, 1.0);
-                    v_color = color/255.0;
+                    v_color = color;
                 }
             ",
     }
@@ -31,10 +31,2167 @@ pub mod fs {
     
                 layout(location = 0) out vec4 f_color;
 
-                layout(location = 0) in vec3 v_color;                
+                layout(location = 0) in vec4 v_color;
+
+                void main() {
+                    f_color = v_color;
+                }
+            ",
+    }
+}
+
+pub mod vs_skinned {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in uvec4 joints;
+                layout(location = 3) in vec4 weights;
+
+                layout(location = 0) out vec3 v_color;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(binding = 1) readonly buffer JointMatrices {
+                    mat4 joints[];
+                } joint_matrices;
+
+                void main() {
+                    mat4 skin = weights.x * joint_matrices.joints[joints.x]
+                        + weights.y * joint_matrices.joints[joints.y]
+                        + weights.z * joint_matrices.joints[joints.z]
+                        + weights.w * joint_matrices.joints[joints.w];
+                    gl_Position = mvp.proj * mvp.view * mvp.model * skin * vec4(position, 1.0);
+                    // No lighting yet, so visualize the skinned normal instead.
+                    v_color = normalize(mat3(skin) * normal) * 0.5 + 0.5;
+                }
+            ",
+    }
+}
+
+pub mod vs_morph {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+
+                layout(location = 0) out vec3 v_color;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                // Deltas of target t for vertex v start at (t * vertex_count + v) * 2:
+                // the position delta followed by the normal delta.
+                layout(binding = 1) readonly buffer MorphDeltas {
+                    vec4 deltas[];
+                } morph_deltas;
+
+                layout(binding = 2) readonly buffer MorphWeights {
+                    float weights[];
+                } morph_weights;
+
+                layout(push_constant) uniform MorphInfo {
+                    uint vertex_count;
+                    uint target_count;
+                } morph_info;
+
+                void main() {
+                    vec3 morphed_position = position;
+                    vec3 morphed_normal = normal;
+                    for (uint t = 0; t < morph_info.target_count; t++) {
+                        float weight = morph_weights.weights[t];
+                        uint base = (t * morph_info.vertex_count + uint(gl_VertexIndex)) * 2;
+                        morphed_position += weight * morph_deltas.deltas[base].xyz;
+                        morphed_normal += weight * morph_deltas.deltas[base + 1].xyz;
+                    }
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(morphed_position, 1.0);
+                    v_color = normalize(morphed_normal) * 0.5 + 0.5;
+                }
+            ",
+    }
+}
+
+pub mod vs_lightmap {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in vec2 lightmap_uv;
+
+                layout(location = 0) out vec2 v_lightmap_uv;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                void main() {
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(position, 1.0);
+                    v_lightmap_uv = lightmap_uv;
+                }
+            ",
+    }
+}
+
+pub mod fs_lightmap {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec2 v_lightmap_uv;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler2D lightmap;
+
+                layout(push_constant) uniform LightmapMaterial {
+                    vec4 base_color;
+                } material;
+
+                void main() {
+                    vec3 irradiance = texture(lightmap, v_lightmap_uv).rgb;
+                    f_color = vec4(material.base_color.rgb * irradiance, material.base_color.a);
+                }
+            ",
+    }
+}
+
+pub mod vs_material {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in vec2 uv;
+
+                layout(location = 0) out vec3 v_normal;
+                layout(location = 1) out vec2 v_uv;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                // Bit-identical depth in the pre-pass and the material pass.
+                invariant gl_Position;
+
+                void main() {
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(position, 1.0);
+                    v_normal = mat3(mvp.model) * normal;
+                    v_uv = uv;
+                }
+            ",
+    }
+}
+
+pub mod vs_material_objects {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in vec2 uv;
+
+                layout(location = 0) out vec3 v_normal;
+                layout(location = 1) out vec2 v_uv;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                struct ObjectTransform {
+                    mat4 model;
+                };
+
+                // One entry per object; instances of a draw start at its first instance.
+                layout(binding = 1) readonly buffer ObjectTransforms {
+                    ObjectTransform objects[];
+                } object_transforms;
+
+                // Bit-identical depth in the pre-pass and the material pass.
+                invariant gl_Position;
+
+                void main() {
+                    mat4 model = mvp.model * object_transforms.objects[gl_InstanceIndex].model;
+                    gl_Position = mvp.proj * mvp.view * model * vec4(position, 1.0);
+                    v_normal = mat3(model) * normal;
+                    v_uv = uv;
+                }
+            ",
+    }
+}
+
+pub mod vs_material_skinned {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in uvec4 joints;
+                layout(location = 3) in vec4 weights;
+                layout(location = 4) in vec2 uv;
+
+                layout(location = 0) out vec3 v_normal;
+                layout(location = 1) out vec2 v_uv;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(binding = 1) readonly buffer JointMatrices {
+                    mat4 joints[];
+                } joint_matrices;
+
+                // Bit-identical depth in the pre-pass and the material pass.
+                invariant gl_Position;
+
+                void main() {
+                    mat4 skin = weights.x * joint_matrices.joints[joints.x]
+                        + weights.y * joint_matrices.joints[joints.y]
+                        + weights.z * joint_matrices.joints[joints.z]
+                        + weights.w * joint_matrices.joints[joints.w];
+                    gl_Position = mvp.proj * mvp.view * mvp.model * skin * vec4(position, 1.0);
+                    v_normal = mat3(mvp.model) * mat3(skin) * normal;
+                    v_uv = uv;
+                }
+            ",
+    }
+}
+
+pub mod fs_material {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+                #extension GL_EXT_nonuniform_qualifier : require
+
+                // Set per permutation by `MaterialPipelines`; branches on them compile away.
+                layout(constant_id = 0) const bool TEXTURED = false;
+                layout(constant_id = 1) const bool LIT = false;
+
+                const uint NO_TEXTURE = 0xFFFFFFFFu;
+                const float AMBIENT = 0.1;
+
+                layout(location = 0) in vec3 v_normal;
+                layout(location = 1) in vec2 v_uv;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(set = 1, binding = 0) uniform sampler2D textures[];
+
+                layout(push_constant) uniform MaterialParams {
+                    vec4 base_color;
+                    vec4 light_direction;
+                    uint base_color_texture;
+                } material;
+
+                void main() {
+                    vec4 color = material.base_color;
+                    if (TEXTURED && material.base_color_texture != NO_TEXTURE) {
+                        color *= texture(textures[nonuniformEXT(material.base_color_texture)], v_uv);
+                    }
+                    if (LIT) {
+                        float diffuse = max(dot(normalize(v_normal), material.light_direction.xyz), 0.0);
+                        color.rgb *= AMBIENT + (1.0 - AMBIENT) * diffuse;
+                    }
+                    f_color = color;
+                }
+            ",
+    }
+}
+
+pub mod vs_patch {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+                layout(location = 2) in vec2 uv;
+
+                layout(location = 0) out vec3 v_position;
+                layout(location = 1) out vec3 v_normal;
+                layout(location = 2) out vec2 v_uv;
+
+                // Tessellated and projected later, so this only hands the control points on.
+                void main() {
+                    v_position = position;
+                    v_normal = normal;
+                    v_uv = uv;
+                }
+            ",
+    }
+}
+
+pub mod tcs_distance {
+    vulkano_shaders::shader! {
+        ty: "tess_ctrl",
+        src: "
+                #version 460
+
+                layout(vertices = 3) out;
+
+                layout(location = 0) in vec3 v_position[];
+                layout(location = 1) in vec3 v_normal[];
+                layout(location = 2) in vec2 v_uv[];
+
+                layout(location = 0) out vec3 c_position[];
+                layout(location = 1) out vec3 c_normal[];
+                layout(location = 2) out vec2 c_uv[];
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(push_constant) uniform DisplacementParams {
+                    vec4 camera_position;
+                    vec4 base_color;
+                    float displacement_scale;
+                    float max_level;
+                    float lod_distance;
+                } params;
+
+                // Full detail up to `lod_distance` from the camera, halving with each doubling
+                // of the distance beyond it. Shared edges get the same level from both sides,
+                // so neighbouring patches don't crack.
+                float edge_level(int a, int b) {
+                    vec3 midpoint = (v_position[a] + v_position[b]) * 0.5;
+                    vec3 world = (mvp.model * vec4(midpoint, 1.0)).xyz;
+                    float distance = max(length(world - params.camera_position.xyz), 0.001);
+                    return clamp(params.max_level * params.lod_distance / distance, 1.0, params.max_level);
+                }
+
+                void main() {
+                    c_position[gl_InvocationID] = v_position[gl_InvocationID];
+                    c_normal[gl_InvocationID] = v_normal[gl_InvocationID];
+                    c_uv[gl_InvocationID] = v_uv[gl_InvocationID];
+                    if (gl_InvocationID == 0) {
+                        // Outer level i is the edge opposite vertex i.
+                        gl_TessLevelOuter[0] = edge_level(1, 2);
+                        gl_TessLevelOuter[1] = edge_level(2, 0);
+                        gl_TessLevelOuter[2] = edge_level(0, 1);
+                        gl_TessLevelInner[0] = max(max(gl_TessLevelOuter[0], gl_TessLevelOuter[1]), gl_TessLevelOuter[2]);
+                    }
+                }
+            ",
+    }
+}
+
+pub mod tes_displaced {
+    vulkano_shaders::shader! {
+        ty: "tess_eval",
+        src: "
+                #version 460
+
+                layout(triangles, equal_spacing, ccw) in;
+
+                layout(location = 0) in vec3 c_position[];
+                layout(location = 1) in vec3 c_normal[];
+                layout(location = 2) in vec2 c_uv[];
+
+                layout(location = 0) out vec3 e_normal;
+                layout(location = 1) out vec2 e_uv;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(binding = 1) uniform sampler2D heightmap;
+
+                layout(push_constant) uniform DisplacementParams {
+                    vec4 camera_position;
+                    vec4 base_color;
+                    float displacement_scale;
+                    float max_level;
+                    float lod_distance;
+                } params;
+
+                void main() {
+                    vec3 b = gl_TessCoord;
+                    vec3 position = b.x * c_position[0] + b.y * c_position[1] + b.z * c_position[2];
+                    vec3 normal = normalize(b.x * c_normal[0] + b.y * c_normal[1] + b.z * c_normal[2]);
+                    vec2 uv = b.x * c_uv[0] + b.y * c_uv[1] + b.z * c_uv[2];
+                    float height = textureLod(heightmap, uv, 0.0).r;
+                    position += normal * height * params.displacement_scale;
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(position, 1.0);
+                    e_normal = mat3(mvp.model) * normal;
+                    e_uv = uv;
+                }
+            ",
+    }
+}
+
+pub mod fs_displaced {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 e_normal;
+                layout(location = 1) in vec2 e_uv;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(push_constant) uniform DisplacementParams {
+                    vec4 camera_position;
+                    vec4 base_color;
+                    float displacement_scale;
+                    float max_level;
+                    float lod_distance;
+                } params;
+
+                void main() {
+                    vec3 light = normalize(vec3(0.3, 1.0, 0.2));
+                    float diffuse = max(dot(normalize(e_normal), light), 0.0);
+                    f_color = vec4(params.base_color.rgb * (0.2 + 0.8 * diffuse), params.base_color.a);
+                }
+            ",
+    }
+}
+
+pub mod vs_multiview {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+                #extension GL_EXT_multiview : require
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec3 normal;
+
+                layout(location = 0) out vec3 v_normal;
+
+                layout(binding = 0) uniform StereoCameraUniform {
+                    mat4 view[2];
+                    mat4 proj[2];
+                } camera;
+
+                layout(push_constant) uniform StereoDraw {
+                    mat4 model;
+                } draw;
 
                 void main() {
-                    f_color = vec4(v_color, 1.0);
+                    gl_Position = camera.proj[gl_ViewIndex] * camera.view[gl_ViewIndex]
+                        * draw.model * vec4(position, 1.0);
+                    v_normal = mat3(draw.model) * normal;
+                }
+            ",
+    }
+}
+
+pub mod fs_multiview {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 v_normal;
+
+                layout(location = 0) out vec4 f_color;
+
+                void main() {
+                    vec3 light = normalize(vec3(0.3, 1.0, 0.2));
+                    float diffuse = max(dot(normalize(v_normal), light), 0.0);
+                    f_color = vec4(vec3(0.2 + 0.8 * diffuse), 1.0);
+                }
+            ",
+    }
+}
+
+pub mod vs_object_id {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+
+                layout(location = 0) flat out uint v_id;
+
+                layout(push_constant) uniform ObjectIdDraw {
+                    mat4 model_view_projection;
+                    uint id;
+                } draw;
+
+                void main() {
+                    gl_Position = draw.model_view_projection * vec4(position, 1.0);
+                    v_id = draw.id;
+                }
+            ",
+    }
+}
+
+pub mod fs_object_id {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) flat in uint v_id;
+
+                layout(location = 0) out uint f_id;
+
+                void main() {
+                    f_id = v_id;
+                }
+            ",
+    }
+}
+
+pub mod vs_occlusion_box {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(push_constant) uniform OcclusionBox {
+                    vec4 min;
+                    vec4 max;
+                } box;
+
+                // Two triangles per face of the unit cube.
+                const int INDICES[36] = int[](
+                    0, 2, 1, 1, 2, 3,  4, 5, 6, 5, 7, 6,
+                    0, 1, 4, 1, 5, 4,  2, 6, 3, 3, 6, 7,
+                    0, 4, 2, 2, 4, 6,  1, 3, 5, 3, 7, 5
+                );
+
+                void main() {
+                    int corner = INDICES[gl_VertexIndex];
+                    vec3 t = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+                    vec3 world = mix(box.min.xyz, box.max.xyz, t);
+                    gl_Position = mvp.proj * mvp.view * vec4(world, 1.0);
+                }
+            ",
+    }
+}
+
+pub mod vs_volume {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(push_constant) uniform VolumeParams {
+                    mat4 model;
+                    vec4 color;
+                    float density;
+                    float threshold;
+                    uint steps;
+                } params;
+
+                // Position on the unit cube the volume fills, and the camera in the same space.
+                layout(location = 0) out vec3 v_position;
+                layout(location = 1) flat out vec3 v_eye;
+
+                // Two triangles per face of the unit cube.
+                const int INDICES[36] = int[](
+                    0, 2, 1, 1, 2, 3,  4, 5, 6, 5, 7, 6,
+                    0, 1, 4, 1, 5, 4,  2, 6, 3, 3, 6, 7,
+                    0, 4, 2, 2, 4, 6,  1, 3, 5, 3, 7, 5
+                );
+
+                void main() {
+                    int corner = INDICES[gl_VertexIndex];
+                    vec3 t = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+                    mat4 model_view = mvp.view * params.model;
+                    v_position = t;
+                    v_eye = (inverse(model_view) * vec4(0.0, 0.0, 0.0, 1.0)).xyz;
+                    gl_Position = mvp.proj * model_view * vec4(t, 1.0);
+                }
+            ",
+    }
+}
+
+pub mod fs_volume {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 v_position;
+                layout(location = 1) flat in vec3 v_eye;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler3D volume;
+
+                layout(push_constant) uniform VolumeParams {
+                    mat4 model;
+                    vec4 color;
+                    float density;
+                    float threshold;
+                    uint steps;
+                } params;
+
+                void main() {
+                    vec3 to_fragment = v_position - v_eye;
+                    float fragment_t = length(to_fragment);
+                    vec3 dir = to_fragment / fragment_t;
+
+                    // Where the view ray enters and leaves the unit cube.
+                    vec3 t0 = (vec3(0.0) - v_eye) / dir;
+                    vec3 t1 = (vec3(1.0) - v_eye) / dir;
+                    vec3 near = min(t0, t1);
+                    vec3 far = max(t0, t1);
+                    float t_enter = max(max(near.x, near.y), max(near.z, 0.0));
+                    float t_exit = min(min(far.x, far.y), far.z);
+
+                    // Both sides of the cube are rasterized; only the faces where the ray leaves
+                    // march, so each pixel is composited once, also with the camera inside.
+                    if (fragment_t < 0.5 * (t_enter + t_exit)) {
+                        discard;
+                    }
+
+                    // A fixed step, so the look doesn't change with the view angle.
+                    float step_length = sqrt(3.0) / float(max(params.steps, 1));
+                    vec4 accumulated = vec4(0.0);
+                    for (float t = t_enter + 0.5 * step_length; t < t_exit; t += step_length) {
+                        float density = texture(volume, v_eye + dir * t).r;
+                        if (density < params.threshold) {
+                            continue;
+                        }
+                        // Front to back, with Beer-Lambert absorption over the step.
+                        float alpha = 1.0 - exp(-density * params.density * step_length);
+                        accumulated.rgb += (1.0 - accumulated.a) * alpha * params.color.rgb;
+                        accumulated.a += (1.0 - accumulated.a) * alpha;
+                        if (accumulated.a > 0.99) {
+                            break;
+                        }
+                    }
+                    if (accumulated.a <= 0.0) {
+                        discard;
+                    }
+                    f_color = vec4(accumulated.rgb / accumulated.a, accumulated.a * params.color.a);
+                }
+            ",
+    }
+}
+
+pub mod vs_textured_2d {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec2 position;
+                layout(location = 1) in vec2 uv;
+                layout(location = 2) in vec4 color;
+
+                layout(location = 0) out vec2 v_uv;
+                layout(location = 1) out vec4 v_color;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                void main() {
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(position, 0.0, 1.0);
+                    v_uv = uv;
+                    v_color = color;
+                }
+            ",
+    }
+}
+
+pub mod fs_textured_2d {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec2 v_uv;
+                layout(location = 1) in vec4 v_color;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler2D image;
+
+                void main() {
+                    // Negative coordinates mark untextured shapes batched with textured quads.
+                    vec4 texel = texture(image, v_uv);
+                    f_color = (v_uv.x < 0.0 ? vec4(1.0) : texel) * v_color;
+                }
+            ",
+    }
+}
+
+pub mod fs_color_2d {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 1) in vec4 v_color;
+
+                layout(location = 0) out vec4 f_color;
+
+                void main() {
+                    f_color = v_color;
+                }
+            ",
+    }
+}
+
+pub mod vs_decal {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                struct Decal {
+                    mat4 model;
+                    mat4 world_to_decal;
+                    vec4 atlas_rect;
+                    vec4 color;
+                };
+
+                layout(std430, binding = 3) readonly buffer Decals {
+                    Decal decals[];
+                };
+
+                layout(location = 0) flat out uint v_decal;
+                layout(location = 1) flat out mat4 v_clip_to_world;
+
+                // Two triangles per face of the unit cube.
+                const int INDICES[36] = int[](
+                    0, 2, 1, 1, 2, 3,  4, 5, 6, 5, 7, 6,
+                    0, 1, 4, 1, 5, 4,  2, 6, 3, 3, 6, 7,
+                    0, 4, 2, 2, 4, 6,  1, 3, 5, 3, 7, 5
+                );
+
+                void main() {
+                    int corner = INDICES[gl_VertexIndex];
+                    vec3 t = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) - 0.5;
+                    v_decal = gl_InstanceIndex;
+                    v_clip_to_world = inverse(mvp.proj * mvp.view);
+                    gl_Position = mvp.proj * mvp.view * decals[gl_InstanceIndex].model * vec4(t, 1.0);
+                }
+            ",
+    }
+}
+
+pub mod fs_decal {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) flat in uint v_decal;
+                layout(location = 1) flat in mat4 v_clip_to_world;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler2D depth;
+                layout(binding = 2) uniform sampler2D atlas;
+
+                struct Decal {
+                    mat4 model;
+                    mat4 world_to_decal;
+                    vec4 atlas_rect;
+                    vec4 color;
+                };
+
+                layout(std430, binding = 3) readonly buffer Decals {
+                    Decal decals[];
+                };
+
+                void main() {
+                    // The scene surface behind this fragment, back in world space.
+                    ivec2 pixel = ivec2(gl_FragCoord.xy);
+                    float scene_depth = texelFetch(depth, pixel, 0).r;
+                    vec2 ndc = (gl_FragCoord.xy / vec2(textureSize(depth, 0))) * 2.0 - 1.0;
+                    vec4 world = v_clip_to_world * vec4(ndc, scene_depth, 1.0);
+                    world /= world.w;
+
+                    // Only surfaces inside the decal's box are covered.
+                    Decal decal = decals[v_decal];
+                    vec3 local = (decal.world_to_decal * world).xyz;
+                    if (any(greaterThan(abs(local), vec3(0.5)))) {
+                        discard;
+                    }
+
+                    // Projected along the box's y axis.
+                    vec2 uv = decal.atlas_rect.xy + (local.xz + 0.5) * decal.atlas_rect.zw;
+                    f_color = texture(atlas, uv) * decal.color;
+                }
+            ",
+    }
+}
+
+pub mod vs_fullscreen {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                // One triangle covering the viewport, clipped to it.
+                void main() {
+                    vec2 corner = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                    gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+                }
+            ",
+    }
+}
+
+pub mod fs_oit_composite {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 0) uniform sampler2D accumulation;
+                layout(binding = 1) uniform sampler2D revealage;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_FragCoord.xy);
+                    float revealed = texelFetch(revealage, pixel, 0).r;
+                    if (revealed >= 1.0) {
+                        discard;
+                    }
+                    vec4 accumulated = texelFetch(accumulation, pixel, 0);
+                    // Sums of many bright surfaces overflow half floats.
+                    if (isinf(max(max(accumulated.r, accumulated.g), accumulated.b))) {
+                        accumulated.rgb = vec3(accumulated.a);
+                    }
+                    vec3 average = accumulated.rgb / max(accumulated.a, 1e-5);
+                    f_color = vec4(average, 1.0 - revealed);
+                }
+            ",
+    }
+}
+
+pub mod vs_water {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                layout(push_constant) uniform WaterParams {
+                    vec4 center;
+                    vec4 camera_position;
+                    vec4 color;
+                    vec2 size;
+                    float ripple_scale;
+                    float ripple_strength;
+                    float time;
+                } params;
+
+                layout(location = 0) out vec3 v_world;
+                layout(location = 1) out vec4 v_clip;
+
+                // Two triangles spanning the surface, in units of its half size.
+                const vec2 CORNERS[6] = vec2[](
+                    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0),
+                    vec2(-1.0, 1.0), vec2(1.0, -1.0), vec2(1.0, 1.0)
+                );
+
+                void main() {
+                    vec2 corner = CORNERS[gl_VertexIndex] * 0.5 * params.size;
+                    v_world = params.center.xyz + vec3(corner.x, 0.0, corner.y);
+                    v_clip = mvp.proj * mvp.view * vec4(v_world, 1.0);
+                    gl_Position = v_clip;
+                }
+            ",
+    }
+}
+
+pub mod fs_water {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 v_world;
+                layout(location = 1) in vec4 v_clip;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler2D reflection;
+                layout(binding = 2) uniform sampler2D refraction;
+                layout(binding = 3) uniform sampler2D normal_map;
+
+                layout(push_constant) uniform WaterParams {
+                    vec4 center;
+                    vec4 camera_position;
+                    vec4 color;
+                    vec2 size;
+                    float ripple_scale;
+                    float ripple_strength;
+                    float time;
+                } params;
+
+                // Reflectance of water seen head on.
+                const float F0 = 0.02;
+
+                void main() {
+                    // Two layers of the normal map scrolling across each other, so the ripples
+                    // don't visibly repeat or slide as one.
+                    vec2 uv = v_world.xz / params.ripple_scale;
+                    vec3 a = texture(normal_map, uv + vec2(0.03, 0.01) * params.time).xyz;
+                    vec3 b = texture(normal_map, uv * 1.7 - vec2(0.02, 0.035) * params.time).xyz;
+                    vec3 tangent_normal = normalize((a + b) * 2.0 - 2.0);
+                    // Tangent space z is up, along the surface's normal.
+                    vec3 normal = normalize(tangent_normal.xzy);
+
+                    // The targets were drawn from this view and its mirror image, so both are
+                    // sampled where this fragment is on screen, shifted by the ripples.
+                    vec2 screen = v_clip.xy / v_clip.w * 0.5 + 0.5;
+                    vec2 offset = tangent_normal.xy * params.ripple_strength;
+                    vec3 reflected = texture(reflection, clamp(screen + offset, 0.001, 0.999)).rgb;
+                    vec3 refracted = texture(refraction, clamp(screen - offset, 0.001, 0.999)).rgb;
+                    refracted = mix(refracted, params.color.rgb, params.color.a);
+
+                    vec3 to_eye = normalize(params.camera_position.xyz - v_world);
+                    float fresnel = F0 + (1.0 - F0) * pow(1.0 - max(dot(normal, to_eye), 0.0), 5.0);
+                    f_color = vec4(mix(refracted, reflected, fresnel), 1.0);
+                }
+            ",
+    }
+}
+
+#[cfg(feature = "mesh_shader")]
+pub mod vs_meshlet {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) out vec3 v_color;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                struct MeshletVertex {
+                    vec4 position;
+                    vec4 normal;
+                };
+
+                struct Meshlet {
+                    vec4 bounding_sphere;
+                    uint vertex_offset;
+                    uint vertex_count;
+                    uint triangle_offset;
+                    uint triangle_count;
+                };
+
+                layout(binding = 1) readonly buffer Vertices {
+                    MeshletVertex vertices[];
+                };
+
+                layout(binding = 2) readonly buffer Meshlets {
+                    Meshlet meshlets[];
+                };
+
+                layout(binding = 3) readonly buffer MeshletVertices {
+                    uint meshlet_vertices[];
+                };
+
+                // Three byte-sized corners per triangle.
+                layout(binding = 4) readonly buffer Triangles {
+                    uint triangles[];
+                };
+
+                layout(push_constant) uniform MeshletParams {
+                    vec4 planes[6];
+                } params;
+
+                // One instance per meshlet, pulling its triangles the way a mesh shader would.
+                void main() {
+                    Meshlet meshlet = meshlets[gl_InstanceIndex];
+                    uint triangle = gl_VertexIndex / 3;
+
+                    vec3 center = (mvp.model * vec4(meshlet.bounding_sphere.xyz, 1.0)).xyz;
+                    float scale = max(
+                        length(mvp.model[0].xyz),
+                        max(length(mvp.model[1].xyz), length(mvp.model[2].xyz))
+                    );
+                    float radius = meshlet.bounding_sphere.w * scale;
+                    bool visible = triangle < meshlet.triangle_count;
+                    for (int i = 0; i < 6; i++) {
+                        if (dot(params.planes[i].xyz, center) + params.planes[i].w < -radius) {
+                            visible = false;
+                        }
+                    }
+                    // Unused slots and culled meshlets collapse onto a point outside the clip volume.
+                    if (!visible) {
+                        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+                        v_color = vec3(0.0);
+                        return;
+                    }
+
+                    uint corners = triangles[meshlet.triangle_offset + triangle];
+                    uint corner = (corners >> (8 * (gl_VertexIndex % 3))) & 0xFF;
+                    MeshletVertex vertex = vertices[meshlet_vertices[meshlet.vertex_offset + corner]];
+                    gl_Position = mvp.proj * mvp.view * mvp.model * vec4(vertex.position.xyz, 1.0);
+                    // No lighting yet: tint each meshlet so the clusters show, shaded by the normal.
+                    vec3 tint = fract(float(gl_InstanceIndex) * vec3(0.754877, 0.569840, 0.362211));
+                    float shade = normalize(mat3(mvp.model) * vertex.normal.xyz).y * 0.25 + 0.75;
+                    v_color = (tint * 0.6 + 0.4) * shade;
+                }
+            ",
+    }
+}
+
+pub mod fs_occlusion_box {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                // Only the depth test matters; color writes are masked off.
+                void main() {
+                }
+            ",
+    }
+}
+
+pub mod vs_sprite {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec3 position;
+                layout(location = 1) in vec2 size;
+                layout(location = 2) in float rotation;
+                layout(location = 3) in vec4 color;
+                layout(location = 4) in uint layer;
+
+                layout(location = 0) out vec4 v_color;
+                layout(location = 1) out vec2 v_uv;
+                layout(location = 2) flat out uint v_layer;
+
+                layout(binding = 0) uniform UniformBufferObject {
+                    mat4 model;
+                    mat4 view;
+                    mat4 proj;
+                } mvp;
+
+                const vec2 CORNERS[6] = vec2[](
+                    vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+                    vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+                );
+
+                void main() {
+                    vec2 corner = CORNERS[gl_VertexIndex % 6];
+                    float c = cos(rotation);
+                    float s = sin(rotation);
+                    vec2 offset = mat2(c, s, -s, c) * (corner * size);
+
+                    // The camera's right and up axes are the first two rows of the view matrix.
+                    vec3 right = vec3(mvp.view[0][0], mvp.view[1][0], mvp.view[2][0]);
+                    vec3 up = vec3(mvp.view[0][1], mvp.view[1][1], mvp.view[2][1]);
+                    vec4 world = mvp.model * vec4(position, 1.0);
+                    world.xyz += right * offset.x + up * offset.y;
+
+                    gl_Position = mvp.proj * mvp.view * world;
+                    v_color = color;
+                    v_uv = corner + 0.5;
+                    v_layer = layer;
+                }
+            ",
+    }
+}
+
+pub mod fs_sprite {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec4 v_color;
+                layout(location = 1) in vec2 v_uv;
+
+                layout(location = 0) out vec4 f_color;
+
+                void main() {
+                    if (v_color.a <= 0.0) {
+                        discard;
+                    }
+                    f_color = v_color;
+                }
+            ",
+    }
+}
+
+pub mod fs_sprite_array {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+                #version 460
+
+                layout(location = 0) in vec4 v_color;
+                layout(location = 1) in vec2 v_uv;
+                layout(location = 2) flat in uint v_layer;
+
+                layout(location = 0) out vec4 f_color;
+
+                layout(binding = 1) uniform sampler2DArray sheet;
+
+                void main() {
+                    // Texture rows go down, the quad's up.
+                    vec4 color = v_color * texture(sheet, vec3(v_uv.x, 1.0 - v_uv.y, v_layer));
+                    if (color.a <= 0.0) {
+                        discard;
+                    }
+                    f_color = color;
+                }
+            ",
+    }
+}
+
+pub mod cs_particles {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+                #version 460
+
+                layout(local_size_x = 256, local_size_y = 1, local_size_z = 1) in;
+
+                struct Particle {
+                    vec4 position_age;
+                    vec4 velocity_lifetime;
+                };
+
+                layout(binding = 0) buffer Particles {
+                    Particle particles[];
+                };
+
+                // Tightly packed `SpriteInstance`s: position, size, rotation, color, layer.
+                layout(binding = 1) writeonly buffer Instances {
+                    float instances[];
+                };
+
+                layout(binding = 2) buffer DrawCommand {
+                    uint vertex_count;
+                    uint instance_count;
+                    uint first_vertex;
+                    uint first_instance;
+                } draw;
+
+                layout(binding = 3) buffer SpawnCounter {
+                    uint spawned;
+                };
+
+                layout(push_constant) uniform ParticleParams {
+                    vec4 emitter_position;
+                    vec4 initial_velocity;
+                    vec4 velocity_spread;
+                    vec4 acceleration;
+                    vec4 start_color;
+                    vec4 end_color;
+                    vec2 lifetime;
+                    vec2 size;
+                    float dt;
+                    float time;
+                    uint particle_count;
+                    uint spawn_count;
+                } params;
+
+                const uint INSTANCE_STRIDE = 11;
+
+                uint hash(uint x) {
+                    x ^= x >> 16;
+                    x *= 0x7feb352du;
+                    x ^= x >> 15;
+                    x *= 0x846ca68bu;
+                    x ^= x >> 16;
+                    return x;
+                }
+
+                float random(inout uint seed) {
+                    seed = hash(seed);
+                    return float(seed >> 8) / 16777216.0;
+                }
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= params.particle_count) {
+                        return;
+                    }
+
+                    Particle p = particles[index];
+                    p.position_age.w += params.dt;
+                    if (p.position_age.w >= p.velocity_lifetime.w) {
+                        // Dead particles respawn while this frame's spawn budget lasts.
+                        if (atomicAdd(spawned, 1) >= params.spawn_count) {
+                            particles[index] = p;
+                            return;
+                        }
+                        uint seed = hash(index ^ floatBitsToUint(params.time));
+                        vec3 jitter = vec3(random(seed), random(seed), random(seed)) * 2.0 - 1.0;
+                        float lifetime = mix(params.lifetime.x, params.lifetime.y, random(seed));
+                        p.position_age = vec4(params.emitter_position.xyz, 0.0);
+                        p.velocity_lifetime = vec4(
+                            params.initial_velocity.xyz + jitter * params.velocity_spread.xyz,
+                            max(lifetime, 1e-6)
+                        );
+                    } else {
+                        p.velocity_lifetime.xyz += params.acceleration.xyz * params.dt;
+                        p.position_age.xyz += p.velocity_lifetime.xyz * params.dt;
+                    }
+                    particles[index] = p;
+
+                    // Compact the live particles so the indirect draw only covers them.
+                    float life = p.position_age.w / p.velocity_lifetime.w;
+                    float size = mix(params.size.x, params.size.y, life);
+                    vec4 color = mix(params.start_color, params.end_color, life);
+                    uint base = atomicAdd(draw.instance_count, 1) * INSTANCE_STRIDE;
+                    instances[base + 0] = p.position_age.x;
+                    instances[base + 1] = p.position_age.y;
+                    instances[base + 2] = p.position_age.z;
+                    instances[base + 3] = size;
+                    instances[base + 4] = size;
+                    instances[base + 5] = 0.0;
+                    instances[base + 6] = color.r;
+                    instances[base + 7] = color.g;
+                    instances[base + 8] = color.b;
+                    instances[base + 9] = color.a;
+                    // Layer 0; its bits are those of 0.0.
+                    instances[base + 10] = 0.0;
+                }
+            ",
+    }
+}
+
+pub mod cs_skinning {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                // Tightly packed `SkinnedVertex`es: position, normal, joints, weights, uv.
+                layout(binding = 0) readonly buffer BindPose {
+                    float bind_pose[];
+                };
+
+                layout(binding = 1) readonly buffer JointMatrices {
+                    mat4 joints[];
+                } joint_matrices;
+
+                // Tightly packed `MeshVertex`es: position, normal, uv.
+                layout(binding = 2) writeonly buffer Skinned {
+                    float skinned[];
+                };
+
+                layout(push_constant) uniform SkinningParams {
+                    uint vertex_count;
+                } params;
+
+                const uint BIND_POSE_STRIDE = 16;
+                const uint SKINNED_STRIDE = 8;
+
+                void main() {
+                    uint i = gl_GlobalInvocationID.x;
+                    if (i >= params.vertex_count) {
+                        return;
+                    }
+                    uint src = i * BIND_POSE_STRIDE;
+                    vec3 position = vec3(bind_pose[src], bind_pose[src + 1], bind_pose[src + 2]);
+                    vec3 normal = vec3(bind_pose[src + 3], bind_pose[src + 4], bind_pose[src + 5]);
+                    mat4 skin = mat4(0.0);
+                    for (uint j = 0; j < 4; j++) {
+                        uint joint = floatBitsToUint(bind_pose[src + 6 + j]);
+                        skin += bind_pose[src + 10 + j] * joint_matrices.joints[joint];
+                    }
+                    position = (skin * vec4(position, 1.0)).xyz;
+                    normal = normalize(mat3(skin) * normal);
+
+                    uint dst = i * SKINNED_STRIDE;
+                    skinned[dst] = position.x;
+                    skinned[dst + 1] = position.y;
+                    skinned[dst + 2] = position.z;
+                    skinned[dst + 3] = normal.x;
+                    skinned[dst + 4] = normal.y;
+                    skinned[dst + 5] = normal.z;
+                    skinned[dst + 6] = bind_pose[src + 14];
+                    skinned[dst + 7] = bind_pose[src + 15];
+                }
+            ",
+    }
+}
+
+pub mod cs_frustum_cull {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct CullObject {
+                    vec4 bounding_sphere;
+                    uint index_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint instance;
+                };
+
+                struct DrawCommand {
+                    uint index_count;
+                    uint instance_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint first_instance;
+                };
+
+                layout(binding = 0) readonly buffer Objects {
+                    CullObject objects[];
+                };
+
+                layout(binding = 1) writeonly buffer Draws {
+                    DrawCommand draws[];
+                };
+
+                layout(binding = 2) buffer VisibleCount {
+                    uint visible_count;
+                };
+
+                layout(push_constant) uniform CullParams {
+                    vec4 planes[6];
+                    uint object_count;
+                } params;
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= params.object_count) {
+                        return;
+                    }
+
+                    CullObject object = objects[index];
+                    vec3 center = object.bounding_sphere.xyz;
+                    float radius = object.bounding_sphere.w;
+                    for (int i = 0; i < 6; i++) {
+                        if (dot(params.planes[i].xyz, center) + params.planes[i].w < -radius) {
+                            return;
+                        }
+                    }
+
+                    // Survivors are packed at the front; the slots after them stay zeroed no-op draws.
+                    uint slot = atomicAdd(visible_count, 1);
+                    draws[slot] = DrawCommand(
+                        object.index_count,
+                        1,
+                        object.first_index,
+                        object.vertex_offset,
+                        object.instance
+                    );
+                }
+            ",
+    }
+}
+
+pub mod cs_frustum_cull_bda {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        src: "
+                #version 460
+                #extension GL_EXT_buffer_reference : require
+                #extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct CullObject {
+                    vec4 bounding_sphere;
+                    uint index_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint instance;
+                };
+
+                struct DrawCommand {
+                    uint index_count;
+                    uint instance_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint first_instance;
+                };
+
+                layout(buffer_reference, std430, buffer_reference_align = 16) readonly buffer Objects {
+                    CullObject objects[];
+                };
+
+                layout(buffer_reference, std430, buffer_reference_align = 4) writeonly buffer Draws {
+                    DrawCommand draws[];
+                };
+
+                layout(buffer_reference, std430, buffer_reference_align = 4) buffer VisibleCount {
+                    uint visible_count;
+                };
+
+                // Same as cs_frustum_cull, with the buffers passed as device addresses instead
+                // of descriptors.
+                layout(push_constant) uniform CullAddressParams {
+                    vec4 planes[6];
+                    uint object_count;
+                    uint padding;
+                    Objects objects;
+                    Draws draws;
+                    VisibleCount visible_count;
+                } params;
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= params.object_count) {
+                        return;
+                    }
+
+                    CullObject object = params.objects.objects[index];
+                    vec3 center = object.bounding_sphere.xyz;
+                    float radius = object.bounding_sphere.w;
+                    for (int i = 0; i < 6; i++) {
+                        if (dot(params.planes[i].xyz, center) + params.planes[i].w < -radius) {
+                            return;
+                        }
+                    }
+
+                    uint slot = atomicAdd(params.visible_count.visible_count, 1);
+                    params.draws.draws[slot] = DrawCommand(
+                        object.index_count,
+                        1,
+                        object.first_index,
+                        object.vertex_offset,
+                        object.instance
+                    );
+                }
+            ",
+    }
+}
+
+pub mod cs_hi_z_reduce {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+                #version 460
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                // The depth buffer for level 0, the level above otherwise.
+                layout(binding = 0) uniform sampler2D source;
+                layout(binding = 1, r32f) uniform writeonly image2D destination;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    ivec2 size = imageSize(destination);
+                    if (any(greaterThanEqual(pixel, size))) {
+                        return;
+                    }
+
+                    // The farthest depth of every source texel the pixel overlaps, three wide
+                    // below odd-sized levels so no texel is skipped.
+                    ivec2 source_size = textureSize(source, 0);
+                    ivec2 first = pixel * source_size / size;
+                    ivec2 last = max(((pixel + 1) * source_size + size - 1) / size, first + 1);
+                    float depth = 0.0;
+                    for (int y = first.y; y < last.y; y++) {
+                        for (int x = first.x; x < last.x; x++) {
+                            depth = max(depth, texelFetch(source, ivec2(x, y), 0).r);
+                        }
+                    }
+                    imageStore(destination, pixel, vec4(depth));
+                }
+            ",
+    }
+}
+
+pub mod cs_hi_z_cull {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+                #version 460
+
+                layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+                struct CullObject {
+                    vec4 bounding_sphere;
+                    uint index_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint instance;
+                };
+
+                struct DrawCommand {
+                    uint index_count;
+                    uint instance_count;
+                    uint first_index;
+                    int vertex_offset;
+                    uint first_instance;
+                };
+
+                layout(binding = 0) readonly buffer Objects {
+                    CullObject objects[];
+                };
+
+                layout(binding = 1) writeonly buffer Draws {
+                    DrawCommand draws[];
+                };
+
+                layout(binding = 2) buffer VisibleCount {
+                    uint visible_count;
+                };
+
+                layout(binding = 3) uniform sampler2D hi_z;
+
+                layout(push_constant) uniform HiZCullParams {
+                    mat4 view_projection;
+                    uint object_count;
+                } params;
+
+                bool in_frustum(vec3 center, float radius) {
+                    mat4 m = transpose(params.view_projection);
+                    vec4 planes[6] = vec4[](
+                        m[3] + m[0], m[3] - m[0], m[3] + m[1], m[3] - m[1], m[2], m[3] - m[2]
+                    );
+                    for (int i = 0; i < 6; i++) {
+                        vec4 plane = planes[i] / length(planes[i].xyz);
+                        if (dot(plane.xyz, center) + plane.w < -radius) {
+                            return false;
+                        }
+                    }
+                    return true;
+                }
+
+                // Whether the sphere is behind what the pyramid saw, testing its nearest depth
+                // against the farthest occluder depth over its screen rectangle.
+                bool occluded(vec3 center, float radius) {
+                    vec2 uv_min = vec2(1.0);
+                    vec2 uv_max = vec2(0.0);
+                    float nearest = 1.0;
+                    for (int i = 0; i < 8; i++) {
+                        vec3 corner = center + radius * vec3(
+                            (i & 1) != 0 ? 1.0 : -1.0,
+                            (i & 2) != 0 ? 1.0 : -1.0,
+                            (i & 4) != 0 ? 1.0 : -1.0
+                        );
+                        vec4 clip = params.view_projection * vec4(corner, 1.0);
+                        // Reaching behind the camera: never hidden.
+                        if (clip.w <= 0.0) {
+                            return false;
+                        }
+                        vec3 ndc = clip.xyz / clip.w;
+                        uv_min = min(uv_min, ndc.xy * 0.5 + 0.5);
+                        uv_max = max(uv_max, ndc.xy * 0.5 + 0.5);
+                        nearest = min(nearest, ndc.z);
+                    }
+                    uv_min = clamp(uv_min, 0.0, 1.0);
+                    uv_max = clamp(uv_max, 0.0, 1.0);
+
+                    // The level where the rectangle spans at most two texels each way.
+                    vec2 size = vec2(textureSize(hi_z, 0));
+                    vec2 extent = (uv_max - uv_min) * size;
+                    int levels = textureQueryLevels(hi_z);
+                    int level = clamp(int(ceil(log2(max(max(extent.x, extent.y), 1.0)))), 0, levels - 1);
+                    ivec2 level_size = textureSize(hi_z, level);
+                    ivec2 first = ivec2(uv_min * vec2(level_size));
+                    ivec2 last = min(ivec2(uv_max * vec2(level_size)), level_size - 1);
+                    float farthest = 0.0;
+                    for (int y = first.y; y <= min(last.y, first.y + 1); y++) {
+                        for (int x = first.x; x <= min(last.x, first.x + 1); x++) {
+                            farthest = max(farthest, texelFetch(hi_z, ivec2(x, y), level).r);
+                        }
+                    }
+                    return nearest > farthest;
+                }
+
+                void main() {
+                    uint index = gl_GlobalInvocationID.x;
+                    if (index >= params.object_count) {
+                        return;
+                    }
+
+                    CullObject object = objects[index];
+                    vec3 center = object.bounding_sphere.xyz;
+                    float radius = object.bounding_sphere.w;
+                    if (!in_frustum(center, radius) || occluded(center, radius)) {
+                        return;
+                    }
+
+                    // Survivors are packed at the front; the slots after them stay zeroed no-op draws.
+                    uint slot = atomicAdd(visible_count, 1);
+                    draws[slot] = DrawCommand(
+                        object.index_count,
+                        1,
+                        object.first_index,
+                        object.vertex_offset,
+                        object.instance
+                    );
+                }
+            ",
+    }
+}
+
+pub mod cs_ray_trace {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(binding = 0) uniform accelerationStructureEXT tlas;
+
+                layout(binding = 1, rgba8) uniform writeonly image2D output_image;
+
+                layout(binding = 2) readonly buffer Positions {
+                    vec4 positions[];
+                };
+
+                layout(binding = 3) readonly buffer Indices {
+                    uint indices[];
+                };
+
+                // Per instance custom index: first index and first vertex of its mesh.
+                layout(binding = 4) readonly buffer Slots {
+                    uvec2 slots[];
+                };
+
+                layout(push_constant) uniform RayTraceParams {
+                    mat4 inverse_view_projection;
+                    vec4 camera_position;
+                    vec4 light_direction;
+                    vec4 sky_color;
+                } params;
+
+                const float MAX_DISTANCE = 10000.0;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    ivec2 size = imageSize(output_image);
+                    if (pixel.x >= size.x || pixel.y >= size.y) {
+                        return;
+                    }
+
+                    // Any depth works for the direction, whichever way the projection maps depth.
+                    vec2 ndc = (vec2(pixel) + 0.5) / vec2(size) * 2.0 - 1.0;
+                    vec4 target = params.inverse_view_projection * vec4(ndc, 0.5, 1.0);
+                    vec3 origin = params.camera_position.xyz;
+                    vec3 direction = normalize(target.xyz / target.w - origin);
+
+                    rayQueryEXT query;
+                    rayQueryInitializeEXT(
+                        query, tlas, gl_RayFlagsOpaqueEXT, 0xFF, origin, 0.0, direction, MAX_DISTANCE
+                    );
+                    while (rayQueryProceedEXT(query)) {}
+
+                    vec3 color = params.sky_color.rgb;
+                    if (rayQueryGetIntersectionTypeEXT(query, true)
+                            == gl_RayQueryCommittedIntersectionTriangleEXT) {
+                        uvec2 slot = slots[rayQueryGetIntersectionInstanceCustomIndexEXT(query, true)];
+                        uint first = slot.x + 3 * rayQueryGetIntersectionPrimitiveIndexEXT(query, true);
+                        vec3 a = positions[slot.y + indices[first]].xyz;
+                        vec3 b = positions[slot.y + indices[first + 1]].xyz;
+                        vec3 c = positions[slot.y + indices[first + 2]].xyz;
+                        mat3 object_to_world = mat3(rayQueryGetIntersectionObjectToWorldEXT(query, true));
+                        vec3 normal = normalize(transpose(inverse(object_to_world)) * cross(b - a, c - a));
+                        if (dot(normal, direction) > 0.0) {
+                            normal = -normal;
+                        }
+                        vec3 hit = origin + direction * rayQueryGetIntersectionTEXT(query, true);
+
+                        vec3 light = params.light_direction.xyz;
+                        float diffuse = max(dot(normal, light), 0.0);
+                        if (diffuse > 0.0) {
+                            rayQueryEXT shadow;
+                            rayQueryInitializeEXT(
+                                shadow,
+                                tlas,
+                                gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+                                0xFF,
+                                hit + normal * 1e-3,
+                                0.0,
+                                light,
+                                MAX_DISTANCE
+                            );
+                            while (rayQueryProceedEXT(shadow)) {}
+                            if (rayQueryGetIntersectionTypeEXT(shadow, true)
+                                    != gl_RayQueryCommittedIntersectionNoneEXT) {
+                                diffuse = 0.0;
+                            }
+                        }
+                        color = vec3(0.8) * (diffuse + 0.3 * params.sky_color.rgb);
+                    }
+                    imageStore(output_image, pixel, vec4(color, 1.0));
+                }
+            ",
+    }
+}
+
+pub mod rgen_ray_trace {
+    vulkano_shaders::shader! {
+        ty: "raygen",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_tracing : require
+
+                layout(binding = 0) uniform accelerationStructureEXT tlas;
+
+                layout(binding = 1, rgba8) uniform writeonly image2D output_image;
+
+                layout(push_constant) uniform RayTraceParams {
+                    mat4 inverse_view_projection;
+                    vec4 camera_position;
+                    vec4 light_direction;
+                    vec4 sky_color;
+                } params;
+
+                // Filled by `rchit_ray_trace`, or given a negative distance by `rmiss_ray_trace`.
+                struct HitPayload {
+                    vec3 normal;
+                    float distance;
+                };
+
+                layout(location = 0) rayPayloadEXT HitPayload hit;
+
+                // Cleared by `rmiss_shadow`.
+                layout(location = 1) rayPayloadEXT bool shadowed;
+
+                const float MAX_DISTANCE = 10000.0;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_LaunchIDEXT.xy);
+
+                    // Any depth works for the direction, whichever way the projection maps depth.
+                    vec2 ndc = (vec2(pixel) + 0.5) / vec2(gl_LaunchSizeEXT.xy) * 2.0 - 1.0;
+                    vec4 target = params.inverse_view_projection * vec4(ndc, 0.5, 1.0);
+                    vec3 origin = params.camera_position.xyz;
+                    vec3 direction = normalize(target.xyz / target.w - origin);
+
+                    traceRayEXT(
+                        tlas, gl_RayFlagsOpaqueEXT, 0xFF, 0, 0, 0, origin, 0.0, direction, MAX_DISTANCE, 0
+                    );
+
+                    vec3 color = params.sky_color.rgb;
+                    if (hit.distance >= 0.0) {
+                        vec3 position = origin + direction * hit.distance;
+                        vec3 light = params.light_direction.xyz;
+                        float diffuse = max(dot(hit.normal, light), 0.0);
+                        if (diffuse > 0.0) {
+                            shadowed = true;
+                            traceRayEXT(
+                                tlas,
+                                gl_RayFlagsOpaqueEXT
+                                    | gl_RayFlagsTerminateOnFirstHitEXT
+                                    | gl_RayFlagsSkipClosestHitShaderEXT,
+                                0xFF,
+                                0,
+                                0,
+                                1,
+                                position + hit.normal * 1e-3,
+                                0.0,
+                                light,
+                                MAX_DISTANCE,
+                                1
+                            );
+                            if (shadowed) {
+                                diffuse = 0.0;
+                            }
+                        }
+                        color = vec3(0.8) * (diffuse + 0.3 * params.sky_color.rgb);
+                    }
+                    imageStore(output_image, pixel, vec4(color, 1.0));
+                }
+            ",
+    }
+}
+
+pub mod rmiss_ray_trace {
+    vulkano_shaders::shader! {
+        ty: "miss",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_tracing : require
+
+                struct HitPayload {
+                    vec3 normal;
+                    float distance;
+                };
+
+                layout(location = 0) rayPayloadInEXT HitPayload hit;
+
+                void main() {
+                    hit.distance = -1.0;
+                }
+            ",
+    }
+}
+
+pub mod rmiss_shadow {
+    vulkano_shaders::shader! {
+        ty: "miss",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_tracing : require
+
+                layout(location = 1) rayPayloadInEXT bool shadowed;
+
+                void main() {
+                    shadowed = false;
+                }
+            ",
+    }
+}
+
+pub mod rchit_ray_trace {
+    vulkano_shaders::shader! {
+        ty: "closesthit",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_tracing : require
+
+                layout(binding = 2) readonly buffer Positions {
+                    vec4 positions[];
+                };
+
+                layout(binding = 3) readonly buffer Indices {
+                    uint indices[];
+                };
+
+                // Per instance custom index: first index and first vertex of its mesh.
+                layout(binding = 4) readonly buffer Slots {
+                    uvec2 slots[];
+                };
+
+                struct HitPayload {
+                    vec3 normal;
+                    float distance;
+                };
+
+                layout(location = 0) rayPayloadInEXT HitPayload hit;
+
+                void main() {
+                    uvec2 slot = slots[gl_InstanceCustomIndexEXT];
+                    uint first = slot.x + 3 * gl_PrimitiveID;
+                    vec3 a = positions[slot.y + indices[first]].xyz;
+                    vec3 b = positions[slot.y + indices[first + 1]].xyz;
+                    vec3 c = positions[slot.y + indices[first + 2]].xyz;
+                    mat3 object_to_world = mat3(gl_ObjectToWorldEXT);
+                    vec3 normal = normalize(transpose(inverse(object_to_world)) * cross(b - a, c - a));
+                    if (dot(normal, gl_WorldRayDirectionEXT) > 0.0) {
+                        normal = -normal;
+                    }
+                    hit.normal = normal;
+                    hit.distance = gl_HitTEXT;
+                }
+            ",
+    }
+}
+
+pub mod cs_ray_traced_ao {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(binding = 0) uniform accelerationStructureEXT tlas;
+
+                layout(binding = 1) uniform sampler2D depth_buffer;
+
+                // World space normals in rgb, unnormalized is fine.
+                layout(binding = 2) uniform sampler2D normal_buffer;
+
+                layout(binding = 3, r32f) uniform writeonly image2D ao_image;
+
+                layout(push_constant) uniform AoParams {
+                    mat4 inverse_view_projection;
+                    float radius;
+                    uint ray_count;
+                    uint frame;
+                    float bias;
+                    float far_depth;
+                } params;
+
+                uint hash(uint x) {
+                    x ^= x >> 16;
+                    x *= 0x7feb352dU;
+                    x ^= x >> 15;
+                    x *= 0x846ca68bU;
+                    x ^= x >> 16;
+                    return x;
+                }
+
+                float random(inout uint state) {
+                    state = hash(state);
+                    return float(state) / 4294967296.0;
+                }
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    ivec2 size = imageSize(ao_image);
+                    if (pixel.x >= size.x || pixel.y >= size.y) {
+                        return;
+                    }
+
+                    float depth = texelFetch(depth_buffer, pixel, 0).r;
+                    vec3 normal = texelFetch(normal_buffer, pixel, 0).rgb;
+                    if (depth == params.far_depth || dot(normal, normal) == 0.0) {
+                        imageStore(ao_image, pixel, vec4(1.0));
+                        return;
+                    }
+                    normal = normalize(normal);
+
+                    vec2 ndc = (vec2(pixel) + 0.5) / vec2(size) * 2.0 - 1.0;
+                    vec4 world = params.inverse_view_projection * vec4(ndc, depth, 1.0);
+                    vec3 origin = world.xyz / world.w + normal * params.bias;
+
+                    // Basis around the normal for cosine weighted hemisphere directions.
+                    vec3 helper = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+                    vec3 tangent = normalize(cross(helper, normal));
+                    vec3 bitangent = cross(normal, tangent);
+
+                    uint state = hash(uint(pixel.x) + hash(uint(pixel.y) + hash(params.frame)));
+                    uint visible = 0;
+                    for (uint i = 0; i < params.ray_count; i++) {
+                        float u = random(state);
+                        float phi = 6.28318530718 * random(state);
+                        float r = sqrt(u);
+                        vec3 direction = tangent * (r * cos(phi))
+                            + bitangent * (r * sin(phi))
+                            + normal * sqrt(1.0 - u);
+
+                        rayQueryEXT query;
+                        rayQueryInitializeEXT(
+                            query,
+                            tlas,
+                            gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+                            0xFF,
+                            origin,
+                            0.0,
+                            direction,
+                            params.radius
+                        );
+                        while (rayQueryProceedEXT(query)) {}
+                        if (rayQueryGetIntersectionTypeEXT(query, true)
+                                == gl_RayQueryCommittedIntersectionNoneEXT) {
+                            visible++;
+                        }
+                    }
+                    imageStore(ao_image, pixel, vec4(float(visible) / float(max(params.ray_count, 1))));
+                }
+            ",
+    }
+}
+pub mod cs_ray_traced_shadows {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        vulkan_version: "1.2",
+        spirv_version: "1.4",
+        src: "
+                #version 460
+                #extension GL_EXT_ray_query : require
+
+                layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+                layout(binding = 0) uniform accelerationStructureEXT tlas;
+
+                layout(binding = 1) uniform sampler2D depth_buffer;
+
+                // World space normals in rgb, unnormalized is fine.
+                layout(binding = 2) uniform sampler2D normal_buffer;
+
+                layout(binding = 3, r32f) uniform writeonly image2D shadow_mask;
+
+                layout(push_constant) uniform ShadowParams {
+                    mat4 inverse_view_projection;
+                    // Point light position with w = 1, or the direction toward a directional
+                    // light with w = 0.
+                    vec4 light;
+                    // Sphere radius of a point light, angular radius of a directional one.
+                    float source_radius;
+                    uint sample_count;
+                    uint frame;
+                    float bias;
+                    float far_depth;
+                } params;
+
+                const float MAX_DISTANCE = 10000.0;
+
+                uint hash(uint x) {
+                    x ^= x >> 16;
+                    x *= 0x7feb352dU;
+                    x ^= x >> 15;
+                    x *= 0x846ca68bU;
+                    x ^= x >> 16;
+                    return x;
+                }
+
+                float random(inout uint state) {
+                    state = hash(state);
+                    return float(state) / 4294967296.0;
+                }
+
+                // Random unit vector within angle acos(cos_max) around axis.
+                vec3 sample_cone(vec3 axis, float cos_max, inout uint state) {
+                    float cos_theta = mix(1.0, cos_max, random(state));
+                    float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+                    float phi = 6.28318530718 * random(state);
+                    vec3 helper = abs(axis.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+                    vec3 tangent = normalize(cross(helper, axis));
+                    vec3 bitangent = cross(axis, tangent);
+                    return tangent * (sin_theta * cos(phi))
+                        + bitangent * (sin_theta * sin(phi))
+                        + axis * cos_theta;
+                }
+
+                bool occluded(vec3 origin, vec3 direction, float max_distance) {
+                    rayQueryEXT query;
+                    rayQueryInitializeEXT(
+                        query,
+                        tlas,
+                        gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+                        0xFF,
+                        origin,
+                        0.0,
+                        direction,
+                        max_distance
+                    );
+                    while (rayQueryProceedEXT(query)) {}
+                    return rayQueryGetIntersectionTypeEXT(query, true)
+                        != gl_RayQueryCommittedIntersectionNoneEXT;
+                }
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                    ivec2 size = imageSize(shadow_mask);
+                    if (pixel.x >= size.x || pixel.y >= size.y) {
+                        return;
+                    }
+
+                    float depth = texelFetch(depth_buffer, pixel, 0).r;
+                    vec3 normal = texelFetch(normal_buffer, pixel, 0).rgb;
+                    if (depth == params.far_depth || dot(normal, normal) == 0.0) {
+                        imageStore(shadow_mask, pixel, vec4(1.0));
+                        return;
+                    }
+                    normal = normalize(normal);
+
+                    vec2 ndc = (vec2(pixel) + 0.5) / vec2(size) * 2.0 - 1.0;
+                    vec4 world = params.inverse_view_projection * vec4(ndc, depth, 1.0);
+                    vec3 position = world.xyz / world.w;
+                    vec3 origin = position + normal * params.bias;
+
+                    bool point_light = params.light.w != 0.0;
+                    vec3 to_light = point_light
+                        ? params.light.xyz - position
+                        : params.light.xyz;
+                    if (dot(normal, to_light) <= 0.0) {
+                        imageStore(shadow_mask, pixel, vec4(0.0));
+                        return;
+                    }
+
+                    // One ray toward the center when the source has no size, for hard shadows.
+                    uint samples = params.source_radius > 0.0 ? max(params.sample_count, 1) : 1;
+                    uint state = hash(uint(pixel.x) + hash(uint(pixel.y) + hash(params.frame)));
+                    uint lit = 0;
+                    for (uint i = 0; i < samples; i++) {
+                        vec3 direction;
+                        float max_distance;
+                        if (point_light) {
+                            float distance_to_light = length(to_light);
+                            float sin_max = min(params.source_radius / distance_to_light, 1.0);
+                            direction = samples == 1
+                                ? to_light / distance_to_light
+                                : sample_cone(
+                                    to_light / distance_to_light,
+                                    sqrt(1.0 - sin_max * sin_max),
+                                    state
+                                );
+                            max_distance = max(distance_to_light - params.source_radius, 0.0);
+                        } else {
+                            direction = samples == 1
+                                ? normalize(to_light)
+                                : sample_cone(normalize(to_light), cos(params.source_radius), state);
+                            max_distance = MAX_DISTANCE;
+                        }
+                        if (!occluded(origin, direction, max_distance)) {
+                            lit++;
+                        }
+                    }
+                    imageStore(shadow_mask, pixel, vec4(float(lit) / float(samples)));
                 }
             ",
     }
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use crate::animation::Skin;
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+use crate::gltf_loader::GltfPrimitive;
+
+use super::buffer_structs::MeshVertex;
+use super::buffer_structs::SkinnedVertex;
+use super::buffer_structs::SkinningParams;
+use super::compute::ComputeContext;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::uniform_ring::UniformRing;
+use super::RendererCore;
+
+/// `cs_skinning` workgroup size.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// GPU copy of a skinned glTF primitive together with the storage buffer holding its joint matrices.
+///
+/// Skinned in the vertex shader by default. With `enable_compute_skinning`, a compute pass can
+/// instead write the posed vertices into a buffer once per frame, which any `MeshVertex`
+/// pipeline then draws, e.g. a shadow pass and a G-buffer pass, without skinning again.
+pub struct SkinnedMesh {
+    vertex_buffer: Subbuffer<[SkinnedVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+    /// The bind pose until the first `upload_joint_matrices`, then the last frame's matrices.
+    joint_buffer: Subbuffer<[[[f32; 4]; 4]]>,
+    /// Posed vertices written by `record_skinning`, once compute skinning is enabled.
+    skinned_buffer: Option<Subbuffer<[MeshVertex]>>,
+    /// Bind-pose bounds.
+    bounds: Aabb,
+}
+impl SkinnedMesh {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        primitive: &GltfPrimitive,
+        skin: &Skin,
+    ) -> Self {
+        let vertices = primitive
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| SkinnedVertex {
+                position,
+                normal: primitive.normals[i],
+                joints: primitive.joints.get(i).copied().unwrap_or([0; 4]),
+                weights: primitive
+                    .weights
+                    .get(i)
+                    .copied()
+                    .unwrap_or([1.0, 0.0, 0.0, 0.0]),
+                uv: primitive.uvs.get(i).copied().unwrap_or([0.0; 2]),
+            });
+        let host_writable = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                // Also read by `cs_skinning`.
+                usage: BufferUsage::VERTEX_BUFFER | BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            vertices,
+        )
+        .unwrap();
+        let index_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            host_writable.clone(),
+            primitive.indices.iter().copied(),
+        )
+        .unwrap();
+        let joint_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            host_writable,
+            skin.joints
+                .iter()
+                .map(|_| Matrix4::<f32>::identity().into()),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "skinned mesh vertices",
+            vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "skinned mesh indices",
+            index_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Storage,
+            "skinned mesh bind pose",
+            joint_buffer.buffer(),
+        );
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            joint_buffer,
+            skinned_buffer: None,
+            bounds: primitive.bounds(),
+        }
+    }
+
+    /// Allocates the buffer `record_skinning` writes the posed vertices into.
+    pub fn enable_compute_skinning(&mut self, context: &ComputeContext) {
+        if self.skinned_buffer.is_none() {
+            self.skinned_buffer = Some(context.create_storage_buffer(
+                "skinned vertices",
+                self.vertex_buffer.len(),
+                BufferUsage::VERTEX_BUFFER,
+            ));
+        }
+    }
+
+    /// The posed vertices, once compute skinning is enabled.
+    pub fn skinned_vertices(&self) -> Option<Subbuffer<[MeshVertex]>> {
+        self.skinned_buffer.clone()
+    }
+
+    /// Writes this frame's joint matrices into `uniforms`, so frames still in flight keep
+    /// reading theirs. Call once per frame after posing the skeleton, then get the frame's
+    /// descriptor sets, which bind the new matrices.
+    pub fn upload_joint_matrices(
+        &mut self,
+        uniforms: &UniformRing,
+        skin: &Skin,
+        world_matrices: &[Matrix4<f32>],
+    ) {
+        let joints = skin.joint_matrices(world_matrices);
+        if !joints.is_empty() {
+            self.joint_buffer = uniforms.write_slice(&joints);
+        }
+    }
+
+    /// World bounds of the posed mesh. Every skinned vertex is a weighted average of the vertex
+    /// moved by each of its joints, so the union of the bind-pose box moved by every joint holds it.
+    pub fn pose_bounds(&self, skin: &Skin, world_matrices: &[Matrix4<f32>]) -> Aabb {
+        skin.joint_matrices(world_matrices)
+            .into_iter()
+            .fold(Aabb::EMPTY, |bounds, joint_matrix| {
+                bounds.union(&self.bounds.transform(&Matrix4::from(joint_matrix)))
+            })
+    }
+
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_skinned::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+
+        RendererCore::build_pipeline(
+            device,
+            vs,
+            fs,
+            SkinnedVertex::per_vertex(),
+            render_pass,
+            viewport,
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the joint matrices at binding 1, matching `vs_skinned`.
+    /// The matrices move every frame, so get it every frame after `upload_joint_matrices`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.transient(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::buffer(1, self.joint_buffer.clone()),
+            ],
+        )
+    }
+
+    pub fn get_skinning_pipeline(context: &ComputeContext) -> Arc<ComputePipeline> {
+        let cs = shaders::cs_skinning::load(context.device())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        context.create_pipeline(cs)
+    }
+
+    /// Binds the bind pose, joint matrices and posed vertices, matching `cs_skinning`. Get it
+    /// every frame after `upload_joint_matrices`, like `get_descriptor_set`. Panics unless
+    /// compute skinning is enabled.
+    pub fn get_skinning_descriptor_set(
+        &self,
+        context: &ComputeContext,
+        pipeline: &Arc<ComputePipeline>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let skinned_buffer = self
+            .skinned_buffer
+            .clone()
+            .expect("compute skinning isn't enabled");
+        context.bind_transient(
+            pipeline,
+            [
+                WriteDescriptorSet::buffer(0, self.vertex_buffer.clone()),
+                WriteDescriptorSet::buffer(1, self.joint_buffer.clone()),
+                WriteDescriptorSet::buffer(2, skinned_buffer),
+            ],
+        )
+    }
+
+    /// Poses the vertices with the uploaded joint matrices. Record once per frame after
+    /// `upload_joint_matrices`, outside of a render pass and before `record_draw_skinned`.
+    pub fn record_skinning(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        let vertex_count = self.vertex_buffer.len() as u32;
+        ComputeContext::record_dispatch(
+            builder,
+            pipeline,
+            descriptor_set,
+            Some(SkinningParams { vertex_count }),
+            ComputeContext::workgroups([vertex_count, 1, 1], [WORKGROUP_SIZE, 1, 1]),
+        );
+    }
+
+    /// Draws the vertices posed by the last `record_skinning` with any `MeshVertex` pipeline,
+    /// e.g. from `MaterialPipelines` without `SKINNED`, whose descriptor sets must be bound.
+    /// Draws nothing unless compute skinning is enabled.
+    pub fn record_draw_skinned<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        let Some(skinned_buffer) = &self.skinned_buffer else {
+            return;
+        };
+        builder
+            .bind_vertex_buffers(0, skinned_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(self.index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap();
+    }
+
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(self.index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap();
+    }
+
+    /// Records the draw only if `world_bounds`, e.g. from `pose_bounds`, intersects `frustum`.
+    /// Returns whether it did.
+    pub fn record_draw_if_visible<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        frustum: &Frustum,
+        world_bounds: &Aabb,
+    ) -> bool {
+        let visible = frustum.intersects_aabb(world_bounds);
+        if visible {
+            self.record_draw(builder, pipeline, descriptor_set);
+        }
+        visible
+    }
+}
@@ -0,0 +1,75 @@
+use nalgebra::Matrix4;
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+/// A camera drawn into a rectangle of the frame, e.g. one player's half in split screen or one
+/// pane of an editor's quad view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenView {
+    /// Top left corner and size as fractions of the frame, so layouts survive resizes.
+    pub offset: [f32; 2],
+    pub extent: [f32; 2],
+    pub view: Matrix4<f32>,
+    /// `None` keeps the core's pixel projection, sized to the rectangle.
+    pub projection: Option<Matrix4<f32>>,
+}
+impl ScreenView {
+    pub fn new(offset: [f32; 2], extent: [f32; 2]) -> Self {
+        Self {
+            offset,
+            extent,
+            view: Matrix4::identity(),
+            projection: None,
+        }
+    }
+
+    /// Left and right halves, for two players side by side.
+    pub fn split_vertical() -> [ScreenView; 2] {
+        [
+            Self::new([0.0, 0.0], [0.5, 1.0]),
+            Self::new([0.5, 0.0], [0.5, 1.0]),
+        ]
+    }
+
+    /// Top and bottom halves.
+    pub fn split_horizontal() -> [ScreenView; 2] {
+        [
+            Self::new([0.0, 0.0], [1.0, 0.5]),
+            Self::new([0.0, 0.5], [1.0, 0.5]),
+        ]
+    }
+
+    /// Four quarters in reading order, as editors lay out their orthographic and perspective
+    /// views.
+    pub fn quad() -> [ScreenView; 4] {
+        [
+            Self::new([0.0, 0.0], [0.5, 0.5]),
+            Self::new([0.5, 0.0], [0.5, 0.5]),
+            Self::new([0.0, 0.5], [0.5, 0.5]),
+            Self::new([0.5, 0.5], [0.5, 0.5]),
+        ]
+    }
+
+    /// The rectangle in a frame of `frame_extent`, snapped to whole pixels so neighbouring
+    /// views neither overlap nor leave gaps.
+    pub fn viewport(&self, frame_extent: [u32; 2]) -> Viewport {
+        let start = [0, 1].map(|axis| (self.offset[axis] * frame_extent[axis] as f32).round());
+        let end = [0, 1].map(|axis| {
+            ((self.offset[axis] + self.extent[axis]) * frame_extent[axis] as f32).round()
+        });
+        Viewport {
+            offset: start,
+            // Vulkan rejects empty viewports.
+            extent: [0, 1].map(|axis| (end[axis] - start[axis]).max(1.0)),
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    /// Whether a position in frame pixels, e.g. the cursor, falls in this view.
+    pub fn contains(&self, frame_extent: [u32; 2], position: [f32; 2]) -> bool {
+        let viewport = self.viewport(frame_extent);
+        (0..2).all(|axis| {
+            (viewport.offset[axis]..viewport.offset[axis] + viewport.extent[axis])
+                .contains(&position[axis])
+        })
+    }
+}
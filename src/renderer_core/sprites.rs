@@ -0,0 +1,338 @@
+use std::sync::Arc;
+
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+use vulkano::shader::ShaderModule;
+
+use super::blend::BlendMode;
+use super::clip_rect::ClipRect;
+use super::descriptor_sets::DescriptorSets;
+use super::shaders;
+use super::uniform_ring::UniformRing;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// One camera-facing quad. The quad is generated in the vertex shader, so this is the only
+/// vertex data a sprite needs.
+#[derive(BufferContents, Vertex, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SpriteInstance {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+
+    #[format(R32G32_SFLOAT)]
+    pub size: [f32; 2],
+
+    /// Rotation around the view axis, in radians.
+    #[format(R32_SFLOAT)]
+    pub rotation: f32,
+
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4],
+
+    /// Layer of the sprite sheet drawn by the textured pipeline; ignored by the untextured one.
+    #[format(R32_UINT)]
+    pub layer: u32,
+}
+
+/// What a `Flipbook` does after its last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Holds the last frame.
+    Once,
+    /// Starts over from the first frame.
+    #[default]
+    Loop,
+    /// Plays backwards to the first frame, then forwards again.
+    PingPong,
+}
+
+/// A frame sequence of consecutive layers of a sprite sheet, e.g. an explosion or a walk cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flipbook {
+    pub first_layer: u32,
+    pub frame_count: u32,
+    /// Frames per second.
+    pub frame_rate: f32,
+    pub loop_mode: LoopMode,
+}
+impl Flipbook {
+    /// Seconds until the last frame ends.
+    pub fn duration(&self) -> f32 {
+        self.frame_count as f32 / self.frame_rate
+    }
+
+    /// The sheet layer shown `time` seconds after the start.
+    pub fn layer_at(&self, time: f32) -> u32 {
+        if self.frame_count <= 1 || self.frame_rate <= 0.0 {
+            return self.first_layer;
+        }
+        let frame = (time.max(0.0) * self.frame_rate) as u32;
+        let last = self.frame_count - 1;
+        let frame = match self.loop_mode {
+            LoopMode::Once => frame.min(last),
+            LoopMode::Loop => frame % self.frame_count,
+            LoopMode::PingPong => {
+                // The end frames aren't repeated when turning around.
+                let cycle = frame % (2 * last);
+                if cycle > last {
+                    2 * last - cycle
+                } else {
+                    cycle
+                }
+            }
+        };
+        self.first_layer + frame
+    }
+
+    /// Whether a `Once` flipbook has shown its last frame for `time` seconds past its start.
+    /// Looping ones never finish.
+    pub fn is_finished(&self, time: f32) -> bool {
+        self.loop_mode == LoopMode::Once && time >= self.duration()
+    }
+}
+
+/// Plays a `Flipbook` for one sprite, advanced by the frame's delta time.
+#[derive(Clone, Copy, Debug)]
+pub struct FlipbookPlayer {
+    pub flipbook: Flipbook,
+    time: f32,
+    pub speed: f32,
+}
+impl FlipbookPlayer {
+    pub fn new(flipbook: Flipbook) -> Self {
+        Self {
+            flipbook,
+            time: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Moves the playhead by `dt` seconds of wall time, scaled by `speed`.
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt * self.speed;
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn layer(&self) -> u32 {
+        self.flipbook.layer_at(self.time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.flipbook.is_finished(self.time)
+    }
+
+    /// Sets `sprite`'s sheet layer to the current frame, for the textured pipelines.
+    pub fn apply(&self, sprite: &mut SpriteInstance) {
+        sprite.layer = self.layer();
+    }
+}
+
+/// Draws up to `capacity` sprites per frame with a single instanced draw.
+pub struct SpriteRenderer {
+    /// Streamed through a `UniformRing`, so frames still drawing older instances keep theirs.
+    instance_buffer: Option<Subbuffer<[SpriteInstance]>>,
+    instance_count: u32,
+    capacity: u64,
+}
+impl SpriteRenderer {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            instance_buffer: None,
+            instance_count: 0,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Replaces the sprites drawn from now on, written through `uniforms`. Sprites beyond the
+    /// capacity are dropped.
+    pub fn upload_instances(&mut self, uniforms: &UniformRing, instances: &[SpriteInstance]) {
+        let count = instances.len().min(self.capacity as usize);
+        self.instance_buffer = (count > 0).then(|| uniforms.write_slice(&instances[..count]));
+        self.instance_count = count as u32;
+    }
+
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let fs = shaders::fs_sprite::load(device.clone()).expect("failed to create shader module");
+        SpriteRenderer::build_pipeline(device, fs, render_pass, viewport, false, BlendMode::Opaque)
+    }
+
+    /// Like `get_pipeline`, but multiplies each sprite's color by its `layer` of a sprite sheet
+    /// texture array, e.g. from `Resources::texture_array_view`.
+    pub fn get_textured_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let fs =
+            shaders::fs_sprite_array::load(device.clone()).expect("failed to create shader module");
+        SpriteRenderer::build_pipeline(device, fs, render_pass, viewport, false, BlendMode::Opaque)
+    }
+
+    /// Like `get_pipeline`, for sprites clipped to a `ClipRect`, e.g. inside a UI panel. Draw
+    /// with `record_draw_clipped`.
+    pub fn get_clipped_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let fs = shaders::fs_sprite::load(device.clone()).expect("failed to create shader module");
+        SpriteRenderer::build_pipeline(device, fs, render_pass, viewport, true, BlendMode::Opaque)
+    }
+
+    /// `get_textured_pipeline` for sprites clipped to a `ClipRect`.
+    pub fn get_clipped_textured_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let fs =
+            shaders::fs_sprite_array::load(device.clone()).expect("failed to create shader module");
+        SpriteRenderer::build_pipeline(device, fs, render_pass, viewport, true, BlendMode::Opaque)
+    }
+
+    /// Any sprite pipeline: `textured` like `get_textured_pipeline`, `clipped` like
+    /// `get_clipped_pipeline`, blending with `blend`, e.g. `BlendMode::Alpha` for cut-out UI
+    /// or `BlendMode::Additive` for glowing particles.
+    pub fn get_blended_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        textured: bool,
+        clipped: bool,
+        blend: BlendMode,
+    ) -> Arc<GraphicsPipeline> {
+        let fs = if textured {
+            shaders::fs_sprite_array::load(device.clone())
+        } else {
+            shaders::fs_sprite::load(device.clone())
+        }
+        .expect("failed to create shader module");
+        SpriteRenderer::build_pipeline(device, fs, render_pass, viewport, clipped, blend)
+    }
+
+    fn build_pipeline(
+        device: Arc<Device>,
+        fs: Arc<ShaderModule>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        clipped: bool,
+        blend: BlendMode,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_sprite::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = fs.entry_point("main").unwrap();
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            SpriteInstance::per_instance(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                dynamic_scissor: clipped,
+                blend,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0; the sprite shader billboards using its view matrix.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [WriteDescriptorSet::buffer(0, mvp_buffer)],
+        )
+    }
+
+    /// Binds `mvp_buffer` like `get_descriptor_set` and the sprite sheet at binding 1, for the
+    /// textured pipeline.
+    pub fn get_textured_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        sheet: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, sheet, sampler),
+            ],
+        )
+    }
+
+    /// Draws with a pipeline from `get_clipped_pipeline` or `get_clipped_textured_pipeline`,
+    /// only inside `clip_rect`.
+    pub fn record_draw_clipped<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        clip_rect: ClipRect,
+    ) {
+        if clip_rect.is_empty() {
+            return;
+        }
+        clip_rect.record(builder);
+        self.record_draw(builder, pipeline, descriptor_set);
+    }
+
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        let Some(instance_buffer) = &self.instance_buffer else {
+            return;
+        };
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, instance_buffer.clone())
+            .unwrap()
+            .draw(6, self.instance_count, 0, 0)
+            .unwrap();
+    }
+}
@@ -0,0 +1,487 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::BlitImageInfo;
+use vulkano::command_buffer::BufferImageCopy;
+use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::CopyBufferInfo;
+use vulkano::command_buffer::CopyBufferToImageInfo;
+use vulkano::command_buffer::ImageBlit;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::device::DeviceOwned;
+use vulkano::device::Queue;
+use vulkano::device::QueueFlags;
+use vulkano::format::FormatFeatures;
+use vulkano::image::max_mip_levels;
+use vulkano::image::mip_level_extent;
+use vulkano::image::sampler::Filter;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageSubresourceLayers;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+use vulkano::sync::Sharing;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+enum StagedCopy {
+    Buffer(CopyBufferInfo),
+    Image(CopyBufferToImageInfo),
+}
+
+/// Creates device-local buffers and images whose data goes through host-visible staging
+/// buffers, so shaders read them from VRAM instead of across the bus on discrete cards. Copies
+/// are only queued; `flush` records them all into one command buffer.
+pub struct StagingUploader {
+    queue: Arc<Queue>,
+    /// Where the resources are used. Also blits mip chains when `queue` can't.
+    consumer_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    copies: Vec<StagedCopy>,
+    /// Images whose levels below the first are filled from it after the copies, and the array
+    /// layers to fill.
+    mipmapped: Vec<(Arc<Image>, Range<u32>)>,
+    pending_bytes: u64,
+    /// The upload and consumer families when they differ, so created resources are shared
+    /// between them.
+    concurrent_families: Option<[u32; 2]>,
+    memory_tracker: MemoryTracker,
+}
+impl StagingUploader {
+    /// Copies on `queue` into resources used on `consumer_queue`, e.g. a transfer queue
+    /// filling meshes for the graphics queue.
+    pub fn new(
+        queue: Arc<Queue>,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        consumer_queue: Arc<Queue>,
+    ) -> Self {
+        let upload_family = queue.queue_family_index();
+        let consumer_family = consumer_queue.queue_family_index();
+        Self {
+            concurrent_families: (upload_family != consumer_family)
+                .then_some([upload_family, consumer_family]),
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                queue.device().clone(),
+                Default::default(),
+            ),
+            queue,
+            consumer_queue,
+            memory_allocator,
+            copies: Vec::new(),
+            mipmapped: Vec::new(),
+            pending_bytes: 0,
+            memory_tracker: MemoryTracker::new(),
+        }
+    }
+
+    /// Every buffer and image the uploader created, staging buffers included, by category.
+    /// Buffers are categorized by their usage and images count as textures.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory_tracker
+    }
+
+    /// A device-local buffer with `usage` that will hold `data` once the next `flush` has run
+    /// on the GPU. `data` must not be empty. `label` names it in `memory_tracker`, as it does
+    /// for every buffer and image created here.
+    pub fn buffer_from_iter<T, I>(
+        &mut self,
+        label: &'static str,
+        usage: BufferUsage,
+        data: I,
+    ) -> Subbuffer<[T]>
+    where
+        T: BufferContents,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let staging = self.staging_buffer(data);
+        let buffer = self.device_buffer(label, usage, staging.len());
+        self.queue_buffer_copy(staging, buffer.clone());
+        buffer
+    }
+
+    /// An empty device-local buffer of `len` elements with `usage`, to be filled by
+    /// `write_buffer`, e.g. an arena many meshes are suballocated from.
+    pub fn device_buffer<T: BufferContents>(
+        &self,
+        label: &'static str,
+        usage: BufferUsage,
+        len: u64,
+    ) -> Subbuffer<[T]> {
+        let buffer = Buffer::new_slice(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: usage | BufferUsage::TRANSFER_DST,
+                sharing: self.sharing(),
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            len,
+        )
+        .unwrap();
+        self.memory_tracker.track_buffer(
+            MemoryCategory::of_buffer_usage(usage),
+            label,
+            buffer.buffer(),
+        );
+        buffer
+    }
+
+    /// Queues a copy of `data` into `dst`, e.g. a range of a `device_buffer`, for the next
+    /// `flush`. `data` must have exactly as many elements as `dst`.
+    pub fn write_buffer<T, I>(&mut self, dst: Subbuffer<[T]>, data: I)
+    where
+        T: BufferContents,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let staging = self.staging_buffer(data);
+        assert_eq!(
+            staging.len(),
+            dst.len(),
+            "data does not fit the buffer range"
+        );
+        self.queue_buffer_copy(staging, dst);
+    }
+
+    /// A device-local image that will hold `texels`, tightly packed in its format, once the
+    /// next `flush` has run on the GPU. Only the first mip level and layer are written.
+    pub fn image(
+        &mut self,
+        label: &'static str,
+        create_info: ImageCreateInfo,
+        texels: &[u8],
+    ) -> Arc<Image> {
+        let staging = self.staging_buffer(texels.iter().copied());
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                usage: create_info.usage | ImageUsage::TRANSFER_DST,
+                sharing: self.sharing(),
+                ..create_info
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        self.memory_tracker
+            .track_image(MemoryCategory::Textures, label, &image);
+        self.pending_bytes += staging.size();
+        self.copies
+            .push(StagedCopy::Image(CopyBufferToImageInfo::buffer_image(
+                staging,
+                image.clone(),
+            )));
+        image
+    }
+
+    /// `image` with the full mip chain, each level downsampled from the one above on the GPU
+    /// once the first has been copied. `create_info.mip_levels` is ignored.
+    pub fn image_with_mips(
+        &mut self,
+        label: &'static str,
+        create_info: ImageCreateInfo,
+        texels: &[u8],
+    ) -> Arc<Image> {
+        let image = self.image(
+            label,
+            ImageCreateInfo {
+                mip_levels: max_mip_levels(create_info.extent),
+                usage: create_info.usage | ImageUsage::TRANSFER_SRC,
+                ..create_info
+            },
+            texels,
+        );
+        if image.mip_levels() > 1 {
+            self.mipmapped
+                .push((image.clone(), 0..image.array_layers()));
+        }
+        image
+    }
+
+    /// A device-local image without data, e.g. an array whose layers are filled one by one
+    /// with `write_layer`.
+    pub fn empty_image(&self, label: &'static str, create_info: ImageCreateInfo) -> Arc<Image> {
+        let mut usage = create_info.usage | ImageUsage::TRANSFER_DST;
+        if create_info.mip_levels > 1 {
+            usage |= ImageUsage::TRANSFER_SRC;
+        }
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                usage,
+                sharing: self.sharing(),
+                ..create_info
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        self.memory_tracker
+            .track_image(MemoryCategory::Textures, label, &image);
+        image
+    }
+
+    /// Copies `texels`, tightly packed, into the first mip level of `layer` of an image from
+    /// `empty_image`, and fills the layer's other levels from it, once the next `flush` has run
+    /// on the GPU. The layer must not be read by work still in flight.
+    pub fn write_layer(&mut self, image: &Arc<Image>, layer: u32, texels: &[u8]) {
+        let staging = self.staging_buffer(texels.iter().copied());
+        let mut copy = CopyBufferToImageInfo::buffer_image(staging.clone(), image.clone());
+        copy.regions[0].image_subresource.array_layers = layer..layer + 1;
+        self.pending_bytes += staging.size();
+        self.copies.push(StagedCopy::Image(copy));
+        if image.mip_levels() > 1 {
+            self.mipmapped.push((image.clone(), layer..layer + 1));
+        }
+    }
+
+    /// A device-local image that will hold `levels`, the tightly packed data of each mip level
+    /// from the first, once the next `flush` has run on the GPU. For data that already has its
+    /// mips, e.g. block-compressed textures, which can't be blitted.
+    pub fn image_with_levels(
+        &mut self,
+        label: &'static str,
+        create_info: ImageCreateInfo,
+        levels: &[Vec<u8>],
+    ) -> Arc<Image> {
+        let extent = create_info.extent;
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                mip_levels: levels.len() as u32,
+                usage: create_info.usage | ImageUsage::TRANSFER_DST,
+                sharing: self.sharing(),
+                ..create_info
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        self.memory_tracker
+            .track_image(MemoryCategory::Textures, label, &image);
+        let staging = self.staging_buffer(levels.concat());
+        let layers = image.subresource_layers();
+        let mut buffer_offset = 0;
+        let regions = levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let region = BufferImageCopy {
+                    buffer_offset,
+                    image_subresource: ImageSubresourceLayers {
+                        mip_level: level as u32,
+                        ..layers.clone()
+                    },
+                    image_extent: mip_level_extent(extent, level as u32).unwrap(),
+                    ..Default::default()
+                };
+                buffer_offset += data.len() as u64;
+                region
+            })
+            .collect();
+        self.pending_bytes += staging.size();
+        self.copies.push(StagedCopy::Image(CopyBufferToImageInfo {
+            regions,
+            ..CopyBufferToImageInfo::buffer_image(staging, image.clone())
+        }));
+        image
+    }
+
+    /// Bytes queued since the last `flush`.
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.copies.is_empty()
+    }
+
+    /// Submits every queued copy in one command buffer, followed by the mip chain blits. Blits
+    /// need a graphics queue, so from a transfer queue they go to the consumer queue after a
+    /// semaphore. The staging buffers live until the copies have finished. `None` when nothing
+    /// was queued.
+    pub fn flush(&mut self) -> Option<Box<dyn GpuFuture>> {
+        if self.copies.is_empty() {
+            return None;
+        }
+        let mut builder = self.command_buffer_builder(&self.queue);
+        for copy in self.copies.drain(..) {
+            match copy {
+                StagedCopy::Buffer(info) => builder.copy_buffer(info).unwrap(),
+                StagedCopy::Image(info) => builder.copy_buffer_to_image(info).unwrap(),
+            };
+        }
+        self.pending_bytes = 0;
+
+        let blits_on_upload_queue = Self::can_blit(&self.queue);
+        if blits_on_upload_queue {
+            for (image, layers) in self.mipmapped.drain(..) {
+                record_mip_chain(&mut builder, image, layers);
+            }
+        }
+        let copies = sync::now(self.queue.device().clone())
+            .then_execute(self.queue.clone(), builder.build().unwrap())
+            .unwrap()
+            .boxed();
+        if self.mipmapped.is_empty() {
+            return Some(copies);
+        }
+
+        let mut builder = self.command_buffer_builder(&self.consumer_queue);
+        for (image, layers) in self.mipmapped.drain(..) {
+            record_mip_chain(&mut builder, image, layers);
+        }
+        Some(
+            copies
+                .then_signal_semaphore()
+                .then_execute(self.consumer_queue.clone(), builder.build().unwrap())
+                .unwrap()
+                .boxed(),
+        )
+    }
+
+    /// Submits the queued copies and blocks until they are done.
+    pub fn flush_and_wait(&mut self) {
+        if let Some(future) = self.flush() {
+            future
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+        }
+    }
+
+    fn queue_buffer_copy<T: BufferContents>(&mut self, src: Subbuffer<[T]>, dst: Subbuffer<[T]>) {
+        self.pending_bytes += src.size();
+        self.copies
+            .push(StagedCopy::Buffer(CopyBufferInfo::buffers(src, dst)));
+    }
+
+    fn command_buffer_builder(
+        &self,
+        queue: &Arc<Queue>,
+    ) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
+        AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap()
+    }
+
+    fn can_blit(queue: &Queue) -> bool {
+        let family = queue.queue_family_index() as usize;
+        queue.device().physical_device().queue_family_properties()[family]
+            .queue_flags
+            .intersects(QueueFlags::GRAPHICS)
+    }
+
+    /// Concurrent sharing spares the queue family ownership transfers exclusive resources
+    /// would need.
+    fn sharing<S>(&self) -> Sharing<S>
+    where
+        S: FromIterator<u32> + IntoIterator<Item = u32>,
+    {
+        match self.concurrent_families {
+            Some(families) => Sharing::Concurrent(families.into_iter().collect()),
+            None => Sharing::Exclusive,
+        }
+    }
+
+    fn staging_buffer<T, I>(&self, data: I) -> Subbuffer<[T]>
+    where
+        T: BufferContents,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let staging = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data,
+        )
+        .unwrap();
+        self.memory_tracker.track_buffer(
+            MemoryCategory::Staging,
+            "staging buffer",
+            staging.buffer(),
+        );
+        staging
+    }
+}
+
+/// Fills every mip level of `image`'s `array_layers` below the first by blitting each level
+/// into the next, halving the extent. Filters linearly where the format allows it.
+fn record_mip_chain(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    image: Arc<Image>,
+    array_layers: Range<u32>,
+) {
+    let filter = if image
+        .format_features()
+        .intersects(FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR)
+    {
+        Filter::Linear
+    } else {
+        Filter::Nearest
+    };
+    let extent = image.extent();
+    let layers = ImageSubresourceLayers {
+        array_layers,
+        ..image.subresource_layers()
+    };
+    for level in 1..image.mip_levels() {
+        let src = mip_level_extent(extent, level - 1).unwrap();
+        let dst = mip_level_extent(extent, level).unwrap();
+        builder
+            .blit_image(BlitImageInfo {
+                regions: [ImageBlit {
+                    src_subresource: ImageSubresourceLayers {
+                        mip_level: level - 1,
+                        ..layers.clone()
+                    },
+                    src_offsets: [[0; 3], src],
+                    dst_subresource: ImageSubresourceLayers {
+                        mip_level: level,
+                        ..layers.clone()
+                    },
+                    dst_offsets: [[0; 3], dst],
+                    ..Default::default()
+                }]
+                .into(),
+                filter,
+                ..BlitImageInfo::images(image.clone(), image.clone())
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::StencilOp;
+use vulkano::pipeline::graphics::depth_stencil::StencilOpState;
+use vulkano::pipeline::graphics::depth_stencil::StencilOps;
+use vulkano::pipeline::graphics::depth_stencil::StencilState;
+
+/// How a material uses the stencil buffer, to mask where other draws land, e.g. a portal that
+/// shows another scene or a UI panel with a rounded shape. Masks are drawn first with `Write`
+/// or `WriteOnly`, then the masked content with `Inside` or `Outside` the same reference.
+/// Anything but `Disabled` needs a depth attachment with a stencil component, e.g. with
+/// `CaptureSettings::stencil`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StencilMode {
+    /// Ignores the stencil buffer.
+    #[default]
+    Disabled,
+    /// Draws as usual and sets the stencil to `reference` wherever it does.
+    Write { reference: u8 },
+    /// Sets the stencil to `reference` without drawing any color, e.g. an invisible mask
+    /// shape.
+    WriteOnly { reference: u8 },
+    /// Draws only where the stencil is `reference`.
+    Inside { reference: u8 },
+    /// Draws only where the stencil isn't `reference`.
+    Outside { reference: u8 },
+}
+impl StencilMode {
+    /// The stencil state of pipelines drawing with this mode, `None` when `Disabled`.
+    pub fn stencil_state(self) -> Option<StencilState> {
+        let (reference, compare_op, pass_op) = match self {
+            StencilMode::Disabled => return None,
+            StencilMode::Write { reference } | StencilMode::WriteOnly { reference } => {
+                (reference, CompareOp::Always, StencilOp::Replace)
+            }
+            StencilMode::Inside { reference } => (reference, CompareOp::Equal, StencilOp::Keep),
+            StencilMode::Outside { reference } => (reference, CompareOp::NotEqual, StencilOp::Keep),
+        };
+        let face = StencilOpState {
+            ops: StencilOps {
+                fail_op: StencilOp::Keep,
+                pass_op,
+                depth_fail_op: StencilOp::Keep,
+                compare_op,
+            },
+            compare_mask: u8::MAX.into(),
+            write_mask: u8::MAX.into(),
+            reference: reference.into(),
+        };
+        Some(StencilState {
+            front: face,
+            back: face,
+        })
+    }
+
+    /// Whether draws in this mode write color.
+    pub fn writes_color(self) -> bool {
+        !matches!(self, StencilMode::WriteOnly { .. })
+    }
+}
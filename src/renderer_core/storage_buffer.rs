@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync::HostAccessError;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// A growable array shaders access as a `buffer` block, e.g. lights, bone matrices or
+/// particles, too large or too variable in size for a uniform buffer. Compute shaders can read
+/// and write it; vertex and fragment shaders can write it where
+/// `DeviceCapabilities::vertex_stores` and `fragment_stores` allow.
+///
+/// Descriptors bind only the uploaded elements, so shaders can loop to the block's
+/// `.length()`. An upload that outgrows the buffer moves to a new one, leaving the old one to
+/// frames still reading it, so get descriptor sets again after uploading.
+pub struct StorageBuffer<T: BufferContents + Copy> {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: MemoryTracker,
+    label: &'static str,
+    buffer: Subbuffer<[T]>,
+    usage: BufferUsage,
+    len: u32,
+}
+impl<T: BufferContents + Copy> StorageBuffer<T> {
+    /// `extra_usage` adds e.g. `VERTEX_BUFFER` for particles drawn straight from the buffer.
+    /// `label` names every buffer it moves to in `memory_tracker`.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        label: &'static str,
+        capacity: u64,
+        extra_usage: BufferUsage,
+    ) -> Self {
+        let usage = BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | extra_usage;
+        Self {
+            buffer: Self::create_buffer(&memory_allocator, memory_tracker, label, usage, capacity),
+            memory_allocator,
+            memory_tracker: memory_tracker.clone(),
+            label,
+            usage,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Replaces the elements, growing the buffer to fit. Fails if the GPU is still reading the
+    /// buffer and it didn't need to grow.
+    pub fn upload(&mut self, data: &[T]) -> Result<(), HostAccessError> {
+        if data.len() as u64 > self.capacity() {
+            let capacity = (data.len() as u64).next_power_of_two();
+            self.buffer = Self::create_buffer(
+                &self.memory_allocator,
+                &self.memory_tracker,
+                self.label,
+                self.usage,
+                capacity,
+            );
+        }
+        self.buffer.write()?[..data.len()].copy_from_slice(data);
+        self.len = data.len() as u32;
+        Ok(())
+    }
+
+    /// The whole buffer, e.g. to copy from after shaders wrote it.
+    pub fn buffer(&self) -> Subbuffer<[T]> {
+        self.buffer.clone()
+    }
+
+    /// The uploaded elements at `binding`, for `DescriptorSets::cached` or
+    /// `ComputeContext::bind`. Empty buffers bind one unused element, since bindings can't be
+    /// empty.
+    pub fn write_descriptor(&self, binding: u32) -> WriteDescriptorSet {
+        WriteDescriptorSet::buffer(
+            binding,
+            self.buffer.clone().slice(..(self.len as u64).max(1)),
+        )
+    }
+
+    fn create_buffer(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        label: &'static str,
+        usage: BufferUsage,
+        capacity: u64,
+    ) -> Subbuffer<[T]> {
+        let buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(MemoryCategory::Storage, label, buffer.buffer());
+        buffer
+    }
+}
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use lyon::math::point;
+use lyon::path::FillRule;
+use lyon::path::LineCap;
+use lyon::path::LineJoin;
+use lyon::tessellation::StrokeOptions;
+use lyon::tessellation::TessellationError;
+use usvg::tiny_skia_path::PathSegment;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+
+use super::buffer_structs::Vertex2d;
+use super::canvas::Canvas;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+#[derive(Debug)]
+pub enum SvgError {
+    Io(io::Error),
+    Svg(usvg::Error),
+    Tessellation(TessellationError),
+}
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvgError::Io(error) => write!(f, "failed to read SVG: {error}"),
+            SvgError::Svg(error) => write!(f, "invalid SVG: {error}"),
+            SvgError::Tessellation(error) => write!(f, "failed to tessellate SVG: {error}"),
+        }
+    }
+}
+impl std::error::Error for SvgError {}
+impl From<io::Error> for SvgError {
+    fn from(error: io::Error) -> Self {
+        SvgError::Io(error)
+    }
+}
+impl From<usvg::Error> for SvgError {
+    fn from(error: usvg::Error) -> Self {
+        SvgError::Svg(error)
+    }
+}
+impl From<TessellationError> for SvgError {
+    fn from(error: TessellationError) -> Self {
+        SvgError::Tessellation(error)
+    }
+}
+
+/// An SVG document tessellated once into a static vertex buffer, e.g. icons and illustrations,
+/// drawn with a `Canvas` pipeline and descriptor set. The mesh is in the document's units with
+/// its top-left corner at the origin and y down; place it with the model matrix.
+///
+/// Fills and strokes of solid colors are drawn as they are. Gradients are drawn in the average
+/// color of their stops, dashed strokes solid, and clip paths, masks and filters are ignored.
+/// Patterns, images and text are skipped.
+pub struct SvgMesh {
+    /// `None` when the document draws nothing.
+    vertex_buffer: Option<Subbuffer<[Vertex2d]>>,
+    size: [f32; 2],
+}
+impl SvgMesh {
+    /// Loads an SVG or gzipped SVGZ file, flattening curves to within `tolerance` units.
+    pub fn load(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        path: impl AsRef<Path>,
+        tolerance: f32,
+    ) -> Result<Self, SvgError> {
+        let data = fs::read(path)?;
+        Self::from_data(memory_allocator, memory_tracker, &data, tolerance)
+    }
+
+    pub fn from_data(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        data: &[u8],
+        tolerance: f32,
+    ) -> Result<Self, SvgError> {
+        let tree = usvg::Tree::from_data(data, &usvg::Options::default())?;
+        Self::from_tree(memory_allocator, memory_tracker, &tree, tolerance)
+    }
+
+    pub fn from_tree(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        tree: &usvg::Tree,
+        tolerance: f32,
+    ) -> Result<Self, SvgError> {
+        let mut canvas = Canvas::new();
+        draw_group(&mut canvas, tree.root(), 1.0, tolerance)?;
+        let vertex_buffer = (!canvas.is_empty()).then(|| {
+            let vertex_buffer = Buffer::from_iter(
+                memory_allocator,
+                BufferCreateInfo {
+                    usage: BufferUsage::VERTEX_BUFFER,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                canvas.vertices().iter().copied(),
+            )
+            .unwrap();
+            memory_tracker.track_buffer(MemoryCategory::Meshes, "svg mesh", vertex_buffer.buffer());
+            vertex_buffer
+        });
+        let size = tree.size();
+        Ok(Self {
+            vertex_buffer,
+            size: [size.width(), size.height()],
+        })
+    }
+
+    /// The document's width and height.
+    pub fn size(&self) -> [f32; 2] {
+        self.size
+    }
+
+    pub fn vertex_count(&self) -> u64 {
+        self.vertex_buffer.as_ref().map_or(0, |buffer| buffer.len())
+    }
+
+    /// Draws with a pipeline from `Canvas::get_pipeline` and a set from
+    /// `Canvas::get_descriptor_set`.
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        let Some(vertex_buffer) = &self.vertex_buffer else {
+            return;
+        };
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, vertex_buffer.clone())
+            .unwrap()
+            .draw(vertex_buffer.len() as u32, 1, 0, 0)
+            .unwrap();
+    }
+}
+
+fn draw_group(
+    canvas: &mut Canvas,
+    group: &usvg::Group,
+    opacity: f32,
+    tolerance: f32,
+) -> Result<(), TessellationError> {
+    let opacity = opacity * group.opacity().get();
+    for node in group.children() {
+        match node {
+            usvg::Node::Group(group) => draw_group(canvas, group, opacity, tolerance)?,
+            usvg::Node::Path(path) if path.is_visible() => {
+                draw_path(canvas, path, opacity, tolerance)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Tessellates `path` in its own coordinates, so strokes stay true under skews and uneven
+/// scales, then moves the new vertices into the document's.
+fn draw_path(
+    canvas: &mut Canvas,
+    path: &usvg::Path,
+    opacity: f32,
+    tolerance: f32,
+) -> Result<(), TessellationError> {
+    let transform = path.abs_transform();
+    let scale = (transform.sx * transform.sy - transform.kx * transform.ky)
+        .abs()
+        .sqrt();
+    canvas.tolerance = if scale > 0.0 {
+        tolerance / scale
+    } else {
+        tolerance
+    };
+
+    let mut builder = lyon::path::Path::builder();
+    let mut open = false;
+    for segment in path.data().segments() {
+        match segment {
+            PathSegment::MoveTo(to) => {
+                if open {
+                    builder.end(false);
+                }
+                builder.begin(point(to.x, to.y));
+                open = true;
+            }
+            PathSegment::LineTo(to) => {
+                builder.line_to(point(to.x, to.y));
+            }
+            PathSegment::QuadTo(control, to) => {
+                builder.quadratic_bezier_to(point(control.x, control.y), point(to.x, to.y));
+            }
+            PathSegment::CubicTo(first, second, to) => {
+                builder.cubic_bezier_to(
+                    point(first.x, first.y),
+                    point(second.x, second.y),
+                    point(to.x, to.y),
+                );
+            }
+            PathSegment::Close => {
+                if open {
+                    builder.end(true);
+                    open = false;
+                }
+            }
+        }
+    }
+    if open {
+        builder.end(false);
+    }
+    let lyon_path = builder.build();
+
+    let start = canvas.vertex_count();
+    let fill = |canvas: &mut Canvas| -> Result<(), TessellationError> {
+        let Some(fill) = path.fill() else {
+            return Ok(());
+        };
+        let Some(color) = paint_color(fill.paint(), opacity * fill.opacity().get()) else {
+            return Ok(());
+        };
+        let fill_rule = match fill.rule() {
+            usvg::FillRule::NonZero => FillRule::NonZero,
+            usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+        };
+        canvas.fill_path(&lyon_path, fill_rule, color)
+    };
+    let stroke = |canvas: &mut Canvas| -> Result<(), TessellationError> {
+        let Some(stroke) = path.stroke() else {
+            return Ok(());
+        };
+        let Some(color) = paint_color(stroke.paint(), opacity * stroke.opacity().get()) else {
+            return Ok(());
+        };
+        let line_cap = match stroke.linecap() {
+            usvg::LineCap::Butt => LineCap::Butt,
+            usvg::LineCap::Round => LineCap::Round,
+            usvg::LineCap::Square => LineCap::Square,
+        };
+        let line_join = match stroke.linejoin() {
+            usvg::LineJoin::Miter => LineJoin::Miter,
+            usvg::LineJoin::MiterClip => LineJoin::MiterClip,
+            usvg::LineJoin::Round => LineJoin::Round,
+            usvg::LineJoin::Bevel => LineJoin::Bevel,
+        };
+        let options = StrokeOptions::default()
+            .with_line_width(stroke.width().get())
+            .with_line_cap(line_cap)
+            .with_line_join(line_join)
+            .with_miter_limit(stroke.miterlimit().get());
+        canvas.stroke_path(&lyon_path, &options, color)
+    };
+    match path.paint_order() {
+        usvg::PaintOrder::FillAndStroke => {
+            fill(canvas)?;
+            stroke(canvas)?;
+        }
+        usvg::PaintOrder::StrokeAndFill => {
+            stroke(canvas)?;
+            fill(canvas)?;
+        }
+    }
+
+    for vertex in &mut canvas.vertices_mut()[start..] {
+        let [x, y] = vertex.position;
+        vertex.position = [
+            x * transform.sx + y * transform.kx + transform.tx,
+            x * transform.ky + y * transform.sy + transform.ty,
+        ];
+    }
+    Ok(())
+}
+
+/// `None` for paints drawn as nothing.
+fn paint_color(paint: &usvg::Paint, opacity: f32) -> Option<[f32; 4]> {
+    match paint {
+        usvg::Paint::Color(color) => {
+            let [r, g, b] = rgb(*color);
+            Some([r, g, b, opacity])
+        }
+        usvg::Paint::LinearGradient(gradient) => average_stop(gradient.stops(), opacity),
+        usvg::Paint::RadialGradient(gradient) => average_stop(gradient.stops(), opacity),
+        usvg::Paint::Pattern(_) => None,
+    }
+}
+
+fn average_stop(stops: &[usvg::Stop], opacity: f32) -> Option<[f32; 4]> {
+    if stops.is_empty() {
+        return None;
+    }
+    let mut sum = [0.0; 4];
+    for stop in stops {
+        for (total, channel) in sum.iter_mut().zip(rgb(stop.color())) {
+            *total += channel;
+        }
+        sum[3] += stop.opacity().get();
+    }
+    let [r, g, b, a] = sum.map(|total| total / stops.len() as f32);
+    Some([r, g, b, a * opacity])
+}
+
+fn rgb(color: usvg::Color) -> [f32; 3] {
+    [color.red, color.green, color.blue].map(|channel| channel as f32 / 255.0)
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::vertex_input::VertexBufferDescription;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+use vulkano::shader::EntryPoint;
+use vulkano::shader::ShaderModule;
+use vulkano::Validated;
+use vulkano::VulkanError;
+
+use super::buffer_structs::DisplacementParams;
+use super::buffer_structs::MeshVertex;
+use super::descriptor_sets::DescriptorSets;
+use super::shaders;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// Tessellation control and evaluation shaders, which subdivide each patch of
+/// `patch_control_points` vertices between the vertex and fragment shaders, e.g. to add detail
+/// near the camera.
+#[derive(Clone, Debug)]
+pub struct TessellationStages {
+    pub control: EntryPoint,
+    pub evaluation: EntryPoint,
+    pub patch_control_points: u32,
+}
+impl TessellationStages {
+    /// A pipeline for subpass 0 of `render_pass` drawing patches through these stages,
+    /// depth-tested where the subpass has depth. `None` without the `tessellation_shader`
+    /// feature, see `DeviceCapabilities::tessellation`, or with more control points than the
+    /// device takes.
+    pub fn build_pipeline(
+        self,
+        device: Arc<Device>,
+        vs_entry_point: EntryPoint,
+        fs_entry_point: EntryPoint,
+        vertex_buffer_description: VertexBufferDescription,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Option<Arc<GraphicsPipeline>> {
+        let max_patch_size = device
+            .physical_device()
+            .properties()
+            .max_tessellation_patch_size;
+        if !device.enabled_features().tessellation_shader
+            || self.patch_control_points > max_patch_size
+        {
+            return None;
+        }
+        Some(RendererCore::build_pipeline_with(
+            device,
+            vs_entry_point,
+            fs_entry_point,
+            vertex_buffer_description,
+            render_pass,
+            viewport,
+            PipelineOptions {
+                depth_test: true,
+                tessellation: Some(self),
+                ..Default::default()
+            },
+        ))
+    }
+}
+
+/// How `DisplacedSurface` tessellates and shades one draw.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplacementDraw {
+    /// World space, where detail is highest.
+    pub camera_position: Vector3<f32>,
+    pub base_color: [f32; 4],
+    /// Model-space distance along the normal a heightmap value of 1 moves a vertex.
+    pub displacement_scale: f32,
+    /// Subdivisions of edges within `lod_distance` of the camera, at most the device's
+    /// `max_tessellation_generation_level`, usually 64.
+    pub max_level: f32,
+    /// World-space distance from the camera up to which edges get `max_level`; the level
+    /// halves with each doubling of the distance beyond it.
+    pub lod_distance: f32,
+}
+impl Default for DisplacementDraw {
+    fn default() -> Self {
+        Self {
+            camera_position: Vector3::zeros(),
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            displacement_scale: 1.0,
+            max_level: 16.0,
+            lod_distance: 10.0,
+        }
+    }
+}
+
+/// Draws `MeshVertex` meshes, e.g. terrain tiles from `Resources`, tessellated finer the
+/// closer their edges are to the camera and displaced along their normals by a heightmap's
+/// first channel, so a coarse mesh gets detail only where it is seen up close. Needs
+/// `DeviceCapabilities::tessellation`.
+pub struct DisplacedSurface;
+impl DisplacedSurface {
+    /// `None` without tessellation shaders.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Option<Arc<GraphicsPipeline>> {
+        let load = |module: Result<Arc<ShaderModule>, Validated<VulkanError>>| {
+            module
+                .expect("failed to create shader module")
+                .entry_point("main")
+                .unwrap()
+        };
+        let stages = TessellationStages {
+            control: load(shaders::tcs_distance::load(device.clone())),
+            evaluation: load(shaders::tes_displaced::load(device.clone())),
+            patch_control_points: 3,
+        };
+        stages.build_pipeline(
+            device.clone(),
+            load(shaders::vs_patch::load(device.clone())),
+            load(shaders::fs_displaced::load(device)),
+            MeshVertex::per_vertex(),
+            render_pass,
+            viewport,
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and `heightmap` at binding 1.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        heightmap: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, heightmap, sampler),
+            ],
+        )
+    }
+
+    /// Sets how the following draws are tessellated and shaded. Bind `pipeline` and its
+    /// descriptor set first, then draw meshes, e.g. with `Resources::record_draw_mesh`.
+    pub fn push_params<L>(
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: &Arc<GraphicsPipeline>,
+        draw: &DisplacementDraw,
+    ) {
+        builder
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                DisplacementParams {
+                    camera_position: draw.camera_position.push(1.0).into(),
+                    base_color: draw.base_color,
+                    displacement_scale: draw.displacement_scale,
+                    max_level: draw.max_level,
+                    lod_distance: draw.lod_distance,
+                },
+            )
+            .unwrap();
+    }
+}
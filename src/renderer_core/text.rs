@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+use vulkano::sync::HostAccessError;
+
+use super::blend::BlendMode;
+use super::buffer_structs::Vertex2d;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// A monospace font as a grid of equally sized glyphs in one texture, consecutive characters
+/// from `first_char` in reading order, e.g. printable ASCII from `' '`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitmapFont {
+    pub texture_size: [u32; 2],
+    /// In texels.
+    pub glyph_size: [u32; 2],
+    pub first_char: char,
+    pub glyph_count: u32,
+}
+impl BitmapFont {
+    /// Width of one character drawn `height` units tall.
+    pub fn advance(&self, height: f32) -> f32 {
+        height * self.glyph_size[0] as f32 / self.glyph_size[1].max(1) as f32
+    }
+
+    /// Size of `text` drawn `height` units tall, lines being `height` apart.
+    pub fn measure(&self, text: &str, height: f32) -> [f32; 2] {
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let lines = text.lines().count();
+        [
+            columns.unwrap_or(0) as f32 * self.advance(height),
+            lines as f32 * height,
+        ]
+    }
+
+    /// Two triangles per character of `run` the font has. Characters it lacks still advance.
+    pub(crate) fn glyph_vertices<'a>(
+        &'a self,
+        run: &'a TextRun,
+    ) -> impl Iterator<Item = [Vertex2d; 6]> + 'a {
+        let advance = self.advance(run.height);
+        run.text.lines().enumerate().flat_map(move |(line, text)| {
+            let top = run.position[1] + line as f32 * run.height;
+            text.chars().enumerate().filter_map(move |(column, c)| {
+                let (uv_min, uv_max) = self.glyph_uv(c)?;
+                let left = run.position[0] + column as f32 * advance;
+                let corners = [
+                    ([left, top], uv_min),
+                    ([left + advance, top], [uv_max[0], uv_min[1]]),
+                    ([left, top + run.height], [uv_min[0], uv_max[1]]),
+                    ([left + advance, top + run.height], uv_max),
+                ];
+                Some([0, 1, 2, 2, 1, 3].map(|corner| {
+                    let (position, uv) = corners[corner];
+                    Vertex2d {
+                        position,
+                        uv,
+                        color: run.color,
+                    }
+                }))
+            })
+        })
+    }
+
+    /// Texture coordinates of the top-left and bottom-right corners of `c`, `None` for
+    /// characters the font lacks.
+    fn glyph_uv(&self, c: char) -> Option<([f32; 2], [f32; 2])> {
+        let index = (c as u32).checked_sub(self.first_char as u32)?;
+        if index >= self.glyph_count {
+            return None;
+        }
+        let columns = (self.texture_size[0] / self.glyph_size[0].max(1)).max(1);
+        let [width, height] = self.texture_size.map(|size| size.max(1) as f32);
+        let x = (index % columns * self.glyph_size[0]) as f32;
+        let y = (index / columns * self.glyph_size[1]) as f32;
+        Some((
+            [x / width, y / height],
+            [
+                (x + self.glyph_size[0] as f32) / width,
+                (y + self.glyph_size[1] as f32) / height,
+            ],
+        ))
+    }
+}
+
+/// Text to draw, in the same units as the projection, with y down.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRun {
+    /// Top-left corner of the first character.
+    pub position: [f32; 2],
+    pub text: String,
+    /// Of one line.
+    pub height: f32,
+    pub color: [f32; 4],
+}
+
+/// Draws up to `capacity` characters of `BitmapFont` text per frame with a single draw, e.g.
+/// labels and debug overlays.
+pub struct TextRenderer {
+    vertex_buffer: Subbuffer<[Vertex2d]>,
+    vertex_count: u32,
+}
+impl TextRenderer {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        capacity: u64,
+    ) -> Self {
+        let vertex_buffer = Buffer::new_slice(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            capacity.max(1) * 6,
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "text vertices",
+            vertex_buffer.buffer(),
+        );
+        Self {
+            vertex_buffer,
+            vertex_count: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.vertex_buffer.len() / 6
+    }
+
+    /// Replaces the text drawn. Characters past `capacity` are dropped, as are ones `font`
+    /// lacks, which still advance. Fails if the GPU is still reading the previous text.
+    pub fn upload_text(
+        &mut self,
+        font: &BitmapFont,
+        runs: &[TextRun],
+    ) -> Result<(), HostAccessError> {
+        let mut dst = self.vertex_buffer.write()?;
+        let mut count = 0;
+        let glyphs = runs.iter().flat_map(|run| font.glyph_vertices(run));
+        for (dst, glyph) in dst.chunks_exact_mut(6).zip(glyphs) {
+            dst.copy_from_slice(&glyph);
+            count += 1;
+        }
+        self.vertex_count = count * 6;
+        Ok(())
+    }
+
+    /// Alpha-blended, for fonts with antialiased or transparent glyphs.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            Vertex2d::per_vertex(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the font texture at binding 1.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        font_texture: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, font_texture, sampler),
+            ],
+        )
+    }
+
+    pub fn record_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .draw(self.vertex_count, 1, 0, 0)
+            .unwrap();
+    }
+}
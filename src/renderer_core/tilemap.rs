@@ -0,0 +1,284 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector4;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+
+use crate::tilemap::Tile;
+use crate::tilemap::TileLayer;
+use crate::tilemap::Tilemap;
+
+use super::blend::BlendMode;
+use super::buffer_structs::Vertex2d;
+use super::descriptor_sets::DescriptorSets;
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::shaders;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// The tiles of one layer from one tileset in a square of the map, drawn at once.
+struct TileChunk {
+    tileset: usize,
+    /// Pixel bounds, including tiles larger than the map's grid.
+    min: [f32; 2],
+    max: [f32; 2],
+    vertices: Subbuffer<[Vertex2d]>,
+}
+
+/// Draws a `Tilemap` from static vertex buffers, one per square chunk of `chunk_size` tiles of
+/// each layer and tileset, skipping chunks outside the view. Layers are drawn in order,
+/// alpha-blended, with the map in pixels with y down; an orthographic projection with the
+/// origin at the top left, e.g. `UiScale::projection`, draws it unscaled.
+pub struct TilemapRenderer {
+    chunks: Vec<TileChunk>,
+}
+impl TilemapRenderer {
+    /// Builds the vertex buffers of `map`'s visible layers.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        map: &Tilemap,
+        chunk_size: u32,
+    ) -> Self {
+        let chunk_size = chunk_size.max(1);
+        let chunk_counts = map.size.map(|size| size.div_ceil(chunk_size));
+        let mut chunks = Vec::new();
+        for layer in map.layers.iter().filter(|layer| layer.visible) {
+            for chunk_y in 0..chunk_counts[1] {
+                for chunk_x in 0..chunk_counts[0] {
+                    let mut per_tileset: Vec<Vec<Vertex2d>> = vec![Vec::new(); map.tilesets.len()];
+                    let xs = chunk_x * chunk_size..((chunk_x + 1) * chunk_size).min(map.size[0]);
+                    let ys = chunk_y * chunk_size..((chunk_y + 1) * chunk_size).min(map.size[1]);
+                    for y in ys {
+                        for x in xs.clone() {
+                            let tile = layer.tiles[(y * map.size[0] + x) as usize];
+                            if let Some((tileset, local_id)) = map.tileset_of(tile) {
+                                per_tileset[tileset].extend(Self::tile_vertices(
+                                    map,
+                                    layer,
+                                    tile,
+                                    tileset,
+                                    local_id,
+                                    [x, y],
+                                ));
+                            }
+                        }
+                    }
+                    for (tileset, vertices) in per_tileset.into_iter().enumerate() {
+                        if vertices.is_empty() {
+                            continue;
+                        }
+                        let mut min = [f32::INFINITY; 2];
+                        let mut max = [f32::NEG_INFINITY; 2];
+                        for vertex in &vertices {
+                            for axis in 0..2 {
+                                min[axis] = min[axis].min(vertex.position[axis]);
+                                max[axis] = max[axis].max(vertex.position[axis]);
+                            }
+                        }
+                        let vertices = Buffer::from_iter(
+                            memory_allocator.clone(),
+                            BufferCreateInfo {
+                                usage: BufferUsage::VERTEX_BUFFER,
+                                ..Default::default()
+                            },
+                            AllocationCreateInfo {
+                                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                                ..Default::default()
+                            },
+                            vertices,
+                        )
+                        .unwrap();
+                        memory_tracker.track_buffer(
+                            MemoryCategory::Meshes,
+                            "tilemap chunk",
+                            vertices.buffer(),
+                        );
+                        chunks.push(TileChunk {
+                            tileset,
+                            min,
+                            max,
+                            vertices,
+                        });
+                    }
+                }
+            }
+        }
+        Self { chunks }
+    }
+
+    /// Two triangles covering the tile at `cell`.
+    fn tile_vertices(
+        map: &Tilemap,
+        layer: &TileLayer,
+        tile: Tile,
+        tileset: usize,
+        local_id: u32,
+        cell: [u32; 2],
+    ) -> [Vertex2d; 6] {
+        let tileset = &map.tilesets[tileset];
+        let (uv_min, uv_max) = tileset.tile_uv(local_id);
+        // Top-left, top-right, bottom-left, bottom-right, flipped as Tiled does: diagonally
+        // first, then horizontally and vertically.
+        let mut uvs = [
+            uv_min,
+            [uv_max[0], uv_min[1]],
+            [uv_min[0], uv_max[1]],
+            uv_max,
+        ];
+        if tile.flipped_diagonally() {
+            uvs.swap(1, 2);
+        }
+        if tile.flipped_horizontally() {
+            uvs.swap(0, 1);
+            uvs.swap(2, 3);
+        }
+        if tile.flipped_vertically() {
+            uvs.swap(0, 2);
+            uvs.swap(1, 3);
+        }
+        // Tiles larger than the grid stick out up and to the right of their cell, keeping its
+        // bottom-left corner.
+        let left = (cell[0] * map.tile_size[0]) as f32 + layer.offset[0];
+        let bottom = ((cell[1] + 1) * map.tile_size[1]) as f32 + layer.offset[1];
+        let right = left + tileset.tile_size[0] as f32;
+        let top = bottom - tileset.tile_size[1] as f32;
+        let corners = [[left, top], [right, top], [left, bottom], [right, bottom]];
+        [0, 1, 2, 2, 1, 3].map(|corner| Vertex2d {
+            position: corners[corner],
+            uv: uvs[corner],
+            color: [1.0, 1.0, 1.0, layer.opacity],
+        })
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The map pixels `view_projection` shows, as the minimum and maximum corners, for
+    /// `record_draw`. Assumes an orthographic camera looking down the z axis at the map.
+    pub fn visible_rect(view_projection: &Matrix4<f32>) -> ([f32; 2], [f32; 2]) {
+        let Some(inverse) = view_projection.try_inverse() else {
+            return ([f32::NEG_INFINITY; 2], [f32::INFINITY; 2]);
+        };
+        let mut min = [f32::INFINITY; 2];
+        let mut max = [f32::NEG_INFINITY; 2];
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            let corner = inverse * Vector4::new(x, y, 0.0, 1.0);
+            let corner = [corner.x / corner.w, corner.y / corner.w];
+            for axis in 0..2 {
+                min[axis] = min[axis].min(corner[axis]);
+                max[axis] = max[axis].max(corner[axis]);
+            }
+        }
+        (min, max)
+    }
+
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_textured_2d::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            Vertex2d::per_vertex(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Binds `mvp_buffer` at binding 0 and the texture of one tileset at binding 1. Get one
+    /// set per tileset, in the map's order, for `record_draw`.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        tileset: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, tileset, sampler),
+            ],
+        )
+    }
+
+    /// Draws the chunks overlapping the map pixels from `visible_min` to `visible_max`, e.g.
+    /// from `visible_rect`, with `descriptor_sets[i]` binding tileset `i`. Returns how many
+    /// chunks were drawn.
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_sets: &[Arc<PersistentDescriptorSet>],
+        visible_min: [f32; 2],
+        visible_max: [f32; 2],
+    ) -> u32 {
+        builder.bind_pipeline_graphics(pipeline.clone()).unwrap();
+        let mut bound = None;
+        let mut drawn = 0;
+        for chunk in &self.chunks {
+            let visible = (0..2).all(|axis| {
+                chunk.max[axis] >= visible_min[axis] && chunk.min[axis] <= visible_max[axis]
+            });
+            if !visible {
+                continue;
+            }
+            if bound != Some(chunk.tileset) {
+                builder
+                    .bind_descriptor_sets(
+                        pipeline.bind_point(),
+                        pipeline.layout().clone(),
+                        0,
+                        descriptor_sets[chunk.tileset].clone(),
+                    )
+                    .unwrap();
+                bound = Some(chunk.tileset);
+            }
+            builder
+                .bind_vertex_buffers(0, chunk.vertices.clone())
+                .unwrap()
+                .draw(chunk.vertices.len() as u32, 1, 0, 0)
+                .unwrap();
+            drawn += 1;
+        }
+        drawn
+    }
+}
@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use vulkano::buffer::allocator::SubbufferAllocator;
+use vulkano::buffer::allocator::SubbufferAllocatorCreateInfo;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+
+/// Streams uniform data, e.g. per-frame cameras and per-object constants, into host-visible
+/// arenas instead of allocating a buffer per write. An arena is reused once nothing holds a
+/// subbuffer of it anymore, which for per-frame data is when the frames reading it have
+/// finished, so writes never wait for the GPU. Bind the subbuffers with
+/// `DescriptorSets::transient`, since every write lands somewhere new.
+///
+/// Subbuffers can be bound as vertex buffers too, for vertices rebuilt every frame, e.g. by
+/// `Canvas`.
+pub struct UniformRing {
+    allocator: SubbufferAllocator,
+    memory_tracker: MemoryTracker,
+    /// The arena of the last write, so `memory_tracker` hears about each arena once it's used.
+    last_arena: RefCell<Weak<Buffer>>,
+}
+impl UniformRing {
+    /// Arena size, enough for a few hundred objects' worth of matrices per frame. Larger
+    /// frames take more arenas.
+    pub const DEFAULT_ARENA_SIZE: u64 = 64 * 1024;
+
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+    ) -> Self {
+        Self {
+            allocator: SubbufferAllocator::new(
+                memory_allocator,
+                SubbufferAllocatorCreateInfo {
+                    arena_size: Self::DEFAULT_ARENA_SIZE,
+                    buffer_usage: BufferUsage::UNIFORM_BUFFER
+                        | BufferUsage::STORAGE_BUFFER
+                        | BufferUsage::VERTEX_BUFFER,
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+            ),
+            memory_tracker: memory_tracker.clone(),
+            last_arena: RefCell::new(Weak::new()),
+        }
+    }
+
+    /// A subbuffer holding `data`, aligned for uniform and storage binding.
+    pub fn write<T: BufferContents>(&self, data: T) -> Subbuffer<T> {
+        let subbuffer = self.allocator.allocate_sized().unwrap();
+        self.track_arena(subbuffer.buffer());
+        *subbuffer.write().unwrap() = data;
+        subbuffer
+    }
+
+    /// A subbuffer holding a copy of `data`, e.g. every object's model matrix for one draw.
+    /// `data` must not be empty.
+    pub fn write_slice<T: BufferContents + Copy>(&self, data: &[T]) -> Subbuffer<[T]> {
+        let subbuffer = self.allocator.allocate_slice(data.len() as u64).unwrap();
+        self.track_arena(subbuffer.buffer());
+        subbuffer.write().unwrap().copy_from_slice(data);
+        subbuffer
+    }
+
+    /// Makes room for `bytes` up front, e.g. before a frame known to stream a lot, so it
+    /// doesn't allocate arenas midway.
+    pub fn reserve(&self, bytes: u64) {
+        self.allocator.reserve(bytes).unwrap();
+    }
+
+    fn track_arena(&self, arena: &Arc<Buffer>) {
+        let mut last_arena = self.last_arena.borrow_mut();
+        if last_arena.as_ptr() != Arc::as_ptr(arena) {
+            self.memory_tracker
+                .track_buffer(MemoryCategory::Uniforms, "uniform ring arena", arena);
+            *last_arena = Arc::downgrade(arena);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::ops::BitOr;
+use std::ops::BitOrAssign;
+use std::sync::Arc;
+
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::vertex_input::VertexBufferDescription;
+use vulkano::pipeline::graphics::vertex_input::VertexInputRate;
+use vulkano::pipeline::graphics::vertex_input::VertexMemberInfo;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::render_pass::RenderPass;
+use vulkano::shader::EntryPoint;
+
+use crate::bounds::Aabb;
+use crate::gltf_loader::GltfPrimitive;
+
+use super::memory_stats::MemoryCategory;
+use super::memory_stats::MemoryTracker;
+use super::PipelineOptions;
+use super::RendererCore;
+
+/// Which standard attributes a mesh's vertices carry, interleaved in the order of the
+/// constants below. Shaders read them by name: `position`, `normal`, `uv`, `color` and
+/// `tangent`, declared as `vec3`, `vec3`, `vec2`, `vec4` and `vec4`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout(u32);
+impl VertexLayout {
+    /// 32-bit float xyz.
+    pub const POSITION: VertexLayout = VertexLayout(1 << 0);
+    /// 32-bit float xyz.
+    pub const NORMAL: VertexLayout = VertexLayout(1 << 1);
+    /// 32-bit float, the first UV set.
+    pub const UV: VertexLayout = VertexLayout(1 << 2);
+    /// 8-bit normalized RGBA, read as 0.0 to 1.0.
+    pub const COLOR: VertexLayout = VertexLayout(1 << 3);
+    /// 32-bit float xyzw, `w` holding the bitangent sign.
+    pub const TANGENT: VertexLayout = VertexLayout(1 << 4);
+    /// The layout of `MeshVertex`, which `Resources` meshes use.
+    pub const STANDARD: VertexLayout = VertexLayout(0b111);
+    /// Everything, e.g. for normal-mapped meshes with baked vertex colors.
+    pub const FULL: VertexLayout = VertexLayout(0b11111);
+
+    const ATTRIBUTES: [(VertexLayout, &'static str, Format); 5] = [
+        (VertexLayout::POSITION, "position", Format::R32G32B32_SFLOAT),
+        (VertexLayout::NORMAL, "normal", Format::R32G32B32_SFLOAT),
+        (VertexLayout::UV, "uv", Format::R32G32_SFLOAT),
+        (VertexLayout::COLOR, "color", Format::R8G8B8A8_UNORM),
+        (
+            VertexLayout::TANGENT,
+            "tangent",
+            Format::R32G32B32A32_SFLOAT,
+        ),
+    ];
+
+    /// The attributes `primitive` has data for.
+    pub fn of(primitive: &GltfPrimitive) -> VertexLayout {
+        let count = primitive.positions.len();
+        let mut layout = VertexLayout::POSITION | VertexLayout::NORMAL;
+        if primitive.uvs.len() == count {
+            layout.insert(VertexLayout::UV);
+        }
+        if primitive.colors.len() == count {
+            layout.insert(VertexLayout::COLOR);
+        }
+        if primitive.tangents.len() == count {
+            layout.insert(VertexLayout::TANGENT);
+        }
+        layout
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: VertexLayout) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: VertexLayout) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: VertexLayout) {
+        self.0 &= !other.0;
+    }
+
+    /// Bytes per vertex.
+    pub fn stride(self) -> u32 {
+        self.attributes()
+            .map(|(_, format)| format.block_size() as u32)
+            .sum()
+    }
+
+    /// For building a pipeline that reads meshes of this layout.
+    pub fn description(self) -> VertexBufferDescription {
+        let mut offset = 0;
+        let members = self
+            .attributes()
+            .map(|(name, format)| {
+                let member = VertexMemberInfo {
+                    offset,
+                    format,
+                    num_elements: 1,
+                };
+                offset += format.block_size() as usize;
+                (name.to_owned(), member)
+            })
+            .collect::<HashMap<_, _>>();
+        VertexBufferDescription {
+            members,
+            stride: self.stride(),
+            input_rate: VertexInputRate::Vertex,
+        }
+    }
+
+    /// `primitive`'s vertices interleaved in this layout. Attributes it lacks are filled in:
+    /// normals point along +z, UVs are zero, colors white and tangents along +x.
+    pub fn pack(self, primitive: &GltfPrimitive) -> Vec<u8> {
+        let count = primitive.positions.len();
+        let mut bytes = Vec::with_capacity(count * self.stride() as usize);
+        let floats = |bytes: &mut Vec<u8>, values: &[f32]| {
+            for value in values {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        for i in 0..count {
+            if self.contains(VertexLayout::POSITION) {
+                floats(&mut bytes, &primitive.positions[i]);
+            }
+            if self.contains(VertexLayout::NORMAL) {
+                let normal = primitive.normals.get(i).copied();
+                floats(&mut bytes, &normal.unwrap_or([0.0, 0.0, 1.0]));
+            }
+            if self.contains(VertexLayout::UV) {
+                floats(
+                    &mut bytes,
+                    &primitive.uvs.get(i).copied().unwrap_or([0.0; 2]),
+                );
+            }
+            if self.contains(VertexLayout::COLOR) {
+                let color = primitive.colors.get(i).copied().unwrap_or([1.0; 4]);
+                bytes.extend(color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+            }
+            if self.contains(VertexLayout::TANGENT) {
+                let tangent = primitive.tangents.get(i).copied();
+                floats(&mut bytes, &tangent.unwrap_or([1.0, 0.0, 0.0, 1.0]));
+            }
+        }
+        bytes
+    }
+
+    fn attributes(self) -> impl Iterator<Item = (&'static str, Format)> {
+        VertexLayout::ATTRIBUTES
+            .into_iter()
+            .filter(move |(attribute, _, _)| self.contains(*attribute))
+            .map(|(_, name, format)| (name, format))
+    }
+}
+impl BitOr for VertexLayout {
+    type Output = VertexLayout;
+
+    fn bitor(self, other: VertexLayout) -> VertexLayout {
+        VertexLayout(self.0 | other.0)
+    }
+}
+impl BitOrAssign for VertexLayout {
+    fn bitor_assign(&mut self, other: VertexLayout) {
+        self.insert(other);
+    }
+}
+
+/// A mesh in the `VertexLayout` of its choice, e.g. with vertex colors or tangents, which
+/// `Resources` meshes lack. Draw it with a pipeline from `get_pipeline` for the same layout.
+pub struct LayoutMesh {
+    layout: VertexLayout,
+    vertex_buffer: Subbuffer<[u8]>,
+    index_buffer: Subbuffer<[u32]>,
+    bounds: Aabb,
+}
+impl LayoutMesh {
+    /// `layout` must include `POSITION`.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        memory_tracker: &MemoryTracker,
+        primitive: &GltfPrimitive,
+        layout: VertexLayout,
+    ) -> Self {
+        assert!(
+            layout.contains(VertexLayout::POSITION),
+            "vertex layouts need positions"
+        );
+        let allocation_info = || AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            allocation_info(),
+            layout.pack(primitive),
+        )
+        .unwrap();
+        let index_buffer = Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            allocation_info(),
+            primitive.indices.iter().copied(),
+        )
+        .unwrap();
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "layout mesh vertices",
+            vertex_buffer.buffer(),
+        );
+        memory_tracker.track_buffer(
+            MemoryCategory::Meshes,
+            "layout mesh indices",
+            index_buffer.buffer(),
+        );
+        Self {
+            layout,
+            vertex_buffer,
+            index_buffer,
+            bounds: primitive.bounds(),
+        }
+    }
+
+    pub fn layout(&self) -> VertexLayout {
+        self.layout
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Opaque, depth-tested where `render_pass` has depth, for shaders reading `layout`'s attributes by name and an MVP
+    /// uniform buffer like `Resources` meshes get.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        vs: EntryPoint,
+        fs: EntryPoint,
+        layout: VertexLayout,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        RendererCore::build_pipeline_with(
+            device,
+            vs,
+            fs,
+            layout.description(),
+            render_pass,
+            viewport,
+            PipelineOptions {
+                depth_test: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn record_draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.index_buffer.clone())
+            .unwrap()
+            .draw_indexed(self.index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap();
+    }
+}
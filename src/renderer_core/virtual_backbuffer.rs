@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::BlitImageInfo;
+use vulkano::command_buffer::ClearColorImageInfo;
+use vulkano::command_buffer::ImageBlit;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::image::sampler::Filter;
+use vulkano::image::Image;
+
+/// A fixed-resolution offscreen target the core renders into instead of the swapchain image,
+/// blitted to the window each frame with its aspect kept. Frames then look the same whatever
+/// the window size or DPI, which suits pixel art and golden-image tests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VirtualBackbuffer {
+    pub extent: [u32; 2],
+    /// `Nearest` keeps pixel art crisp, `Linear` smooths other content.
+    pub filter: Filter,
+    /// Scale by whole multiples only, so every virtual pixel covers the same number of window
+    /// pixels. Falls back to shrinking when the window is smaller than `extent`.
+    pub integer_scaling: bool,
+    /// Fills the window around the scaled frame.
+    pub border_color: [f32; 4],
+}
+impl VirtualBackbuffer {
+    pub fn new(extent: [u32; 2]) -> Self {
+        Self {
+            extent,
+            filter: Filter::Nearest,
+            integer_scaling: false,
+            border_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// Offset and extent of the frame within a window of `window_extent`, centered with bars
+    /// along the sides that don't fit the aspect.
+    pub fn destination(&self, window_extent: [u32; 2]) -> ([u32; 2], [u32; 2]) {
+        let scale = (window_extent[0] as f32 / self.extent[0] as f32)
+            .min(window_extent[1] as f32 / self.extent[1] as f32);
+        let scale = if self.integer_scaling && scale >= 1.0 {
+            scale.floor()
+        } else {
+            scale
+        };
+        let extent = [0, 1].map(|axis| {
+            ((self.extent[axis] as f32 * scale).round() as u32).clamp(1, window_extent[axis].max(1))
+        });
+        let offset = [0, 1].map(|axis| (window_extent[axis].saturating_sub(extent[axis])) / 2);
+        (offset, extent)
+    }
+
+    /// Maps a position in window pixels, e.g. the cursor, to virtual pixels. `None` on the
+    /// borders.
+    pub fn window_to_virtual(
+        &self,
+        window_extent: [u32; 2],
+        position: [f32; 2],
+    ) -> Option<[f32; 2]> {
+        let (offset, extent) = self.destination(window_extent);
+        let virtual_position = [0, 1].map(|axis| {
+            (position[axis] - offset[axis] as f32) / extent[axis] as f32 * self.extent[axis] as f32
+        });
+        let inside =
+            (0..2).all(|axis| (0.0..self.extent[axis] as f32).contains(&virtual_position[axis]));
+        inside.then_some(virtual_position)
+    }
+
+    /// Clears `window_image` to the border color and blits `source` into the destination
+    /// rectangle. `window_image` needs `TRANSFER_DST` usage.
+    pub fn record_present(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        source: Arc<Image>,
+        window_image: Arc<Image>,
+    ) {
+        let window_extent = [window_image.extent()[0], window_image.extent()[1]];
+        let (offset, extent) = self.destination(window_extent);
+        let blit = ImageBlit {
+            src_subresource: source.subresource_layers(),
+            src_offsets: [[0, 0, 0], [self.extent[0], self.extent[1], 1]],
+            dst_subresource: window_image.subresource_layers(),
+            dst_offsets: [
+                [offset[0], offset[1], 0],
+                [offset[0] + extent[0], offset[1] + extent[1], 1],
+            ],
+            ..Default::default()
+        };
+        builder
+            .clear_color_image(ClearColorImageInfo {
+                clear_value: self.border_color.into(),
+                ..ClearColorImageInfo::image(window_image.clone())
+            })
+            .unwrap()
+            .blit_image(BlitImageInfo {
+                regions: [blit].into_iter().collect(),
+                filter: self.filter,
+                ..BlitImageInfo::images(source, window_image)
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+
+use super::buffer_structs::VolumeParams;
+use super::descriptor_sets::DescriptorSets;
+use super::shaders;
+
+/// One volume to draw: a 3D texture stretched over the unit cube, placed by `transform`.
+#[derive(Clone, Copy, Debug)]
+pub struct VolumeDraw {
+    /// Maps the unit cube, which the texture fills, into the world.
+    pub transform: Matrix4<f32>,
+    /// Color of dense regions; alpha scales the opacity of the whole volume.
+    pub color: [f32; 4],
+    /// Opacity per unit of sampled density and unit cube length.
+    pub density: f32,
+    /// Samples below it are empty, e.g. to hide noise around a scan.
+    pub threshold: f32,
+    /// Samples along the cube's diagonal; shorter rays take proportionally fewer.
+    pub steps: u32,
+}
+impl Default for VolumeDraw {
+    fn default() -> Self {
+        Self {
+            transform: Matrix4::identity(),
+            color: [1.0, 1.0, 1.0, 1.0],
+            density: 4.0,
+            threshold: 0.0,
+            steps: 128,
+        }
+    }
+}
+
+/// Raymarches 3D textures, e.g. from `Resources::create_volume_texture`, by compositing the
+/// first channel front to back as an emissive, absorbing medium. Volumes are blended over
+/// what is already drawn and tested against, but don't write, depth, so draw them after
+/// opaque geometry.
+pub struct VolumeRenderer;
+impl VolumeRenderer {
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+        depth_compare_op: CompareOp,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_volume::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_volume::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: depth_compare_op,
+                    }),
+                    ..Default::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds `mvp_buffer` at binding 0, of which only the view and projection are used, and
+    /// `volume` at binding 1. `sampler` should clamp to the edge, e.g. `SamplerDesc` with
+    /// `SamplerAddressMode::ClampToEdge`, so samples don't wrap to the opposite side.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        volume: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(1, volume, sampler),
+            ],
+        )
+    }
+
+    pub fn record_draw(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        volume: &VolumeDraw,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                VolumeParams {
+                    model: volume.transform.into(),
+                    color: volume.color,
+                    density: volume.density,
+                    threshold: volume.threshold,
+                    steps: volume.steps,
+                },
+            )
+            .unwrap()
+            .draw(36, 1, 0, 0)
+            .unwrap();
+    }
+}
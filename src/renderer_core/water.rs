@@ -0,0 +1,277 @@
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+use nalgebra::Vector3;
+use nalgebra::Vector4;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::DepthState;
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+
+use super::buffer_structs::WaterParams;
+use super::compute::ComputeContext;
+use super::descriptor_sets::DescriptorSets;
+use super::render_targets::ColorTarget;
+use super::render_targets::RenderTargets;
+use super::shaders;
+
+const DEPTH_FORMAT: Format = Format::D32_SFLOAT;
+
+/// One rectangle of water: a horizontal surface at `center.y`.
+#[derive(Clone, Copy, Debug)]
+pub struct WaterDraw {
+    pub center: Vector3<f32>,
+    /// Extent along x and z.
+    pub size: [f32; 2],
+    /// World space, for the Fresnel term: water reflects more the flatter it is viewed.
+    pub camera_position: Vector3<f32>,
+    /// Tint of the water; alpha is how much of what lies below it hides.
+    pub color: [f32; 4],
+    /// World-space size of one tile of the normal map.
+    pub ripple_scale: f32,
+    /// How far ripples shift the reflection and refraction, as a fraction of the screen.
+    pub ripple_strength: f32,
+    /// Seconds, scrolling the ripples.
+    pub time: f32,
+}
+impl Default for WaterDraw {
+    fn default() -> Self {
+        Self {
+            center: Vector3::zeros(),
+            size: [100.0, 100.0],
+            camera_position: Vector3::zeros(),
+            color: [0.0, 0.2, 0.3, 0.4],
+            ripple_scale: 4.0,
+            ripple_strength: 0.02,
+            time: 0.0,
+        }
+    }
+}
+
+/// Planar water built on `RenderTargets`: the scene is drawn once mirrored below the water
+/// level into `reflection` and once as seen into `refraction`, then the water surface mixes
+/// the two by the Fresnel term, both distorted by scrolling normal-map ripples.
+///
+/// Per frame, draw the scene into `reflection` with `reflected_view` and `clipped_projection`
+/// keeping what is above the water, and into `refraction` with the camera's view and a
+/// projection keeping what is below, then draw the water in the main pass. Mirroring flips
+/// triangle winding, so pipelines culling back faces cull front faces in the reflection.
+pub struct WaterRenderer {
+    reflection: RenderTargets,
+    refraction: RenderTargets,
+}
+impl WaterRenderer {
+    /// Both targets are `format` with depth and `extent`, which may be smaller than the screen's
+    /// to save fill rate; they are sampled in screen space, not per pixel.
+    pub fn new(context: &ComputeContext, format: Format, extent: [u32; 2]) -> Self {
+        let target = || {
+            RenderTargets::new(
+                context,
+                extent,
+                &[ColorTarget::new(format)],
+                Some(DEPTH_FORMAT),
+            )
+        };
+        Self {
+            reflection: target(),
+            refraction: target(),
+        }
+    }
+
+    pub fn reflection(&self) -> &RenderTargets {
+        &self.reflection
+    }
+
+    pub fn refraction(&self) -> &RenderTargets {
+        &self.refraction
+    }
+
+    /// `view` looking at the scene mirrored across the plane `y = height`.
+    pub fn reflected_view(view: &Matrix4<f32>, height: f32) -> Matrix4<f32> {
+        #[rustfmt::skip]
+        let mirror = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, -1.0, 0.0, 2.0 * height,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        view * mirror
+    }
+
+    /// `projection` with its near plane moved onto the plane `y = height`, so drawing with
+    /// `view` keeps only what is above it, or below it without `keep_above`. Cheaper than clip
+    /// distances and needs no shader changes, but skews depth precision and assumes depth from
+    /// 0 at the near plane to 1 at the far one, not reversed.
+    pub fn clipped_projection(
+        projection: &Matrix4<f32>,
+        view: &Matrix4<f32>,
+        height: f32,
+        keep_above: bool,
+    ) -> Matrix4<f32> {
+        let side = if keep_above { 1.0 } else { -1.0 };
+        let world_plane = Vector4::new(0.0, side, 0.0, -side * height);
+        let (Some(inverse_view), Some(inverse_projection)) =
+            (view.try_inverse(), projection.try_inverse())
+        else {
+            return *projection;
+        };
+        // Planes transform by the inverse transpose.
+        let plane = inverse_view.transpose() * world_plane;
+        let clip_plane = inverse_projection.transpose() * plane;
+        // The view frustum's corner farthest behind the plane, which the new near plane must
+        // not push beyond the far one.
+        let corner = inverse_projection
+            * Vector4::new(clip_plane.x.signum(), clip_plane.y.signum(), 1.0, 1.0);
+        let scale = projection.row(3).dot(&corner.transpose()) / plane.dot(&corner);
+        let mut clipped = *projection;
+        clipped.set_row(2, &(plane * scale).transpose());
+        clipped
+    }
+
+    /// Draws `WaterDraw` surfaces, depth-tested and written where the subpass has depth.
+    pub fn get_pipeline(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Arc<GraphicsPipeline> {
+        let vs = shaders::vs_water::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let fs = shaders::fs_water::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::new()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState {
+                    viewports: [viewport].into_iter().collect(),
+                    ..Default::default()
+                }),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: subpass
+                    .subpass_desc()
+                    .depth_stencil_attachment
+                    .is_some()
+                    .then(|| DepthStencilState {
+                        depth: Some(DepthState::simple()),
+                        ..Default::default()
+                    }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap()
+    }
+
+    /// Binds `mvp_buffer` at binding 0, of which only the view and projection are used, the
+    /// reflection and refraction at bindings 1 and 2 and `normal_map` at binding 3. `sampler`
+    /// should repeat, so the ripples tile.
+    pub fn get_descriptor_set<T: BufferContents + ?Sized>(
+        &self,
+        descriptor_sets: &mut DescriptorSets,
+        pipeline: Arc<GraphicsPipeline>,
+        mvp_buffer: Subbuffer<T>,
+        normal_map: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        descriptor_sets.cached(
+            &pipeline.layout().set_layouts()[0],
+            [
+                WriteDescriptorSet::buffer(0, mvp_buffer),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    self.reflection.color(0),
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    self.refraction.color(0),
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(3, normal_map, sampler),
+            ],
+        )
+    }
+
+    pub fn record_draw(
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<GraphicsPipeline>,
+        descriptor_set: Arc<PersistentDescriptorSet>,
+        water: &WaterDraw,
+    ) {
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                pipeline.bind_point(),
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout().clone(),
+                0,
+                WaterParams {
+                    center: water.center.push(1.0).into(),
+                    camera_position: water.camera_position.push(1.0).into(),
+                    color: water.color,
+                    size: water.size,
+                    ripple_scale: water.ripple_scale,
+                    ripple_strength: water.ripple_strength,
+                    time: water.time,
+                },
+            )
+            .unwrap()
+            .draw(6, 1, 0, 0)
+            .unwrap();
+    }
+}
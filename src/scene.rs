@@ -0,0 +1,275 @@
+use nalgebra::Matrix4;
+use slotmap::SlotMap;
+
+use crate::animation::NodeTransform;
+use crate::bounds::Aabb;
+use crate::bvh::Bvh;
+use crate::draw_list::RenderObject;
+use crate::handles::MaterialId;
+use crate::handles::MeshId;
+use crate::handles::NodeId;
+
+/// What a node draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeMesh {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    /// See `RenderObject::occlusion_query`.
+    pub occlusion_query: bool,
+    /// See `RenderObject::transparent`.
+    pub transparent: bool,
+}
+impl NodeMesh {
+    pub fn new(mesh: MeshId, material: MaterialId) -> Self {
+        Self {
+            mesh,
+            material,
+            occlusion_query: false,
+            transparent: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    local: NodeTransform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: Matrix4<f32>,
+    /// Bounds of whatever the node draws, in its own space. Nodes without bounds stay out of
+    /// the spatial index.
+    local_bounds: Option<Aabb>,
+    world_bounds: Option<Aabb>,
+    mesh: Option<NodeMesh>,
+    /// The local transform or parent changed since the last `update_transforms`.
+    dirty: bool,
+}
+
+/// What the last `Scene::update_transforms` did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransformStats {
+    /// World matrices recomputed, including the descendants of changed nodes.
+    pub recomputed_nodes: usize,
+    /// Changed subtrees that were walked.
+    pub dirty_subtrees: usize,
+    /// Objects that moved out of their spatial index leaf and were reinserted.
+    pub reinserted_bounds: usize,
+    pub total_nodes: usize,
+}
+
+/// Node hierarchy with cached world matrices. Changing a node only marks it dirty; the next
+/// `update_transforms` recomputes the changed subtrees and leaves the rest of the scene alone.
+#[derive(Default)]
+pub struct Scene {
+    nodes: SlotMap<NodeId, Node>,
+    /// Nodes marked dirty since the last update, in the order they were changed.
+    dirty_nodes: Vec<NodeId>,
+    spatial_index: Bvh,
+    stats: TransformStats,
+}
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, parent: Option<NodeId>, local: NodeTransform) -> NodeId {
+        let id = self.nodes.insert(Node {
+            local,
+            parent,
+            children: Vec::new(),
+            world: Matrix4::identity(),
+            local_bounds: None,
+            world_bounds: None,
+            mesh: None,
+            dirty: false,
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(id);
+        }
+        self.mark_dirty(id);
+        id
+    }
+
+    /// Removes `id` and all of its descendants. Their handles become stale.
+    pub fn remove_node(&mut self, id: NodeId) {
+        if let Some(parent) = self.nodes[id].parent {
+            self.nodes[parent].children.retain(|&child| child != id);
+        }
+        let mut stack = vec![id];
+        while let Some(node) = stack.pop() {
+            if let Some(removed) = self.nodes.remove(node) {
+                self.spatial_index.remove(node);
+                stack.extend(removed.children);
+            }
+        }
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id].children
+    }
+
+    pub fn local_transform(&self, id: NodeId) -> &NodeTransform {
+        &self.nodes[id].local
+    }
+
+    pub fn set_local_transform(&mut self, id: NodeId, local: NodeTransform) {
+        self.nodes[id].local = local;
+        self.mark_dirty(id);
+    }
+
+    /// Moves `id` under `parent`, or to the top level for `None`.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent;
+        while let Some(node) = ancestor {
+            assert!(node != id, "a node cannot become its own descendant");
+            ancestor = self.nodes[node].parent;
+        }
+        if let Some(old_parent) = self.nodes[id].parent {
+            self.nodes[old_parent].children.retain(|&child| child != id);
+        }
+        if let Some(new_parent) = parent {
+            self.nodes[new_parent].children.push(id);
+        }
+        self.nodes[id].parent = parent;
+        self.mark_dirty(id);
+    }
+
+    /// Gives the node a place in the spatial index from the next `update_transforms` on, or takes
+    /// it out for `None`.
+    pub fn set_local_bounds(&mut self, id: NodeId, local_bounds: Option<Aabb>) {
+        self.nodes[id].local_bounds = local_bounds;
+        self.mark_dirty(id);
+    }
+
+    /// Draws `mesh` at the node from now on, or nothing for `None`. The node also needs local
+    /// bounds to be drawn, since culling works on them.
+    pub fn set_mesh(&mut self, id: NodeId, mesh: Option<NodeMesh>) {
+        self.nodes[id].mesh = mesh;
+    }
+
+    pub fn mesh(&self, id: NodeId) -> Option<&NodeMesh> {
+        self.nodes[id].mesh.as_ref()
+    }
+
+    /// Every node with a mesh and bounds as a render object placed by its world matrix, to build
+    /// the frame's `DrawList` from after `update_transforms`. Item `object` indices of the list
+    /// then index into whatever the objects were collected into, in this order.
+    /// `DrawList::build_scene` culls through the spatial index instead of taking them all.
+    pub fn render_objects(&self) -> impl Iterator<Item = (NodeId, RenderObject)> + '_ {
+        self.nodes
+            .keys()
+            .filter_map(|id| Some((id, self.render_object(id)?)))
+    }
+
+    /// The render object of a node with a mesh and bounds, as `render_objects` gives it.
+    pub fn render_object(&self, id: NodeId) -> Option<RenderObject> {
+        let node = &self.nodes[id];
+        let mesh = node.mesh?;
+        Some(RenderObject {
+            mesh: mesh.mesh,
+            material: mesh.material,
+            world_matrix: node.world,
+            bounds: node.world_bounds?,
+            occlusion_query: mesh.occlusion_query,
+            transparent: mesh.transparent,
+        })
+    }
+
+    /// World-space bounds as of the last `update_transforms`.
+    pub fn world_bounds(&self, id: NodeId) -> Option<&Aabb> {
+        self.nodes[id].world_bounds.as_ref()
+    }
+
+    /// Hierarchy over the world bounds of all nodes that have bounds, for culling and picking.
+    /// Current as of the last `update_transforms`.
+    pub fn spatial_index(&self) -> &Bvh {
+        &self.spatial_index
+    }
+
+    /// World matrix as of the last `update_transforms`.
+    pub fn world_matrix(&self, id: NodeId) -> &Matrix4<f32> {
+        &self.nodes[id].world
+    }
+
+    pub fn is_dirty(&self, id: NodeId) -> bool {
+        self.nodes[id].dirty
+    }
+
+    fn mark_dirty(&mut self, id: NodeId) {
+        if !self.nodes[id].dirty {
+            self.nodes[id].dirty = true;
+            self.dirty_nodes.push(id);
+        }
+    }
+
+    /// Recomputes the world matrices of every changed node and its descendants. Call once per
+    /// frame, after game logic has moved things and before the draw list is built.
+    pub fn update_transforms(&mut self) -> TransformStats {
+        let mut stats = TransformStats {
+            total_nodes: self.nodes.len(),
+            ..Default::default()
+        };
+        let mut stack = Vec::new();
+        for id in std::mem::take(&mut self.dirty_nodes) {
+            // Removed since, or already handled as part of a dirty ancestor's subtree.
+            if !self.nodes.get(id).is_some_and(|node| node.dirty) || self.has_dirty_ancestor(id) {
+                continue;
+            }
+            stats.dirty_subtrees += 1;
+            stack.push(id);
+            while let Some(node_id) = stack.pop() {
+                let parent_world = match self.nodes[node_id].parent {
+                    Some(parent) => self.nodes[parent].world,
+                    None => Matrix4::identity(),
+                };
+                let node = &mut self.nodes[node_id];
+                node.world = parent_world * node.local.to_matrix();
+                node.dirty = false;
+                node.world_bounds = node
+                    .local_bounds
+                    .map(|bounds| bounds.transform(&node.world));
+                match &node.world_bounds {
+                    Some(bounds) => {
+                        if self.spatial_index.update(node_id, bounds) {
+                            stats.reinserted_bounds += 1;
+                        }
+                    }
+                    None => {
+                        self.spatial_index.remove(node_id);
+                    }
+                }
+                stack.extend_from_slice(&node.children);
+                stats.recomputed_nodes += 1;
+            }
+        }
+        self.stats = stats;
+        stats
+    }
+
+    pub fn last_update_stats(&self) -> TransformStats {
+        self.stats
+    }
+
+    fn has_dirty_ancestor(&self, id: NodeId) -> bool {
+        let mut ancestor = self.nodes[id].parent;
+        while let Some(node) = ancestor {
+            if self.nodes[node].dirty {
+                return true;
+            }
+            ancestor = self.nodes[node].parent;
+        }
+        false
+    }
+}
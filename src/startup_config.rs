@@ -0,0 +1,347 @@
+//! Startup settings read from a TOML file, e.g. a `szumi.toml` shipped with a demo, so the
+//! window and quality can be tweaked without recompiling. Every key is optional:
+//!
+//! ```toml
+//! [window]
+//! title = "Demo"
+//! size = [1280, 720]
+//! resizable = true
+//! decorations = true
+//! min_size = [640, 360]
+//! max_size = [3840, 2160]
+//! maximized = false
+//! transparent = false
+//!
+//! [swapchain]
+//! vsync = false
+//!
+//! [quality]
+//! msaa = 4
+//! internal_resolution = [640, 360]
+//!
+//! [device]
+//! gpu = "nvidia" # or an index, e.g. 1
+//! validation = true
+//! track_leaks = true
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+use toml_edit::TomlError;
+use winit::dpi::LogicalSize;
+
+use crate::renderer::RendererConfig;
+use crate::vulkan_api_connection::AdapterSelection;
+use crate::winit_app::WindowConfig;
+
+/// The file looked for in the working directory when no other is given.
+pub const CONFIG_FILE: &str = "szumi.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(TomlError),
+    /// A key whose value has the wrong type or is out of range, e.g. `quality.msaa = 3`.
+    InvalidValue {
+        key: String,
+        expected: &'static str,
+    },
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "failed to read config file: {error}"),
+            ConfigError::Parse(error) => write!(f, "invalid config file: {error}"),
+            ConfigError::InvalidValue { key, expected } => {
+                write!(f, "config key {key} must be {expected}")
+            }
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+impl From<TomlError> for ConfigError {
+    fn from(error: TomlError) -> Self {
+        ConfigError::Parse(error)
+    }
+}
+
+/// What the app starts with: the window it opens and how its renderers are set up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StartupConfig {
+    pub window: WindowConfig,
+    pub renderer: RendererConfig,
+}
+impl StartupConfig {
+    /// Reads the settings in the file at `path`, with defaults for the rest. A missing file
+    /// gives all defaults, so shipping one is optional.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// The settings in `text`, TOML laid out like the module docs show.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let document: DocumentMut = text.parse()?;
+        let mut config = Self::default();
+
+        let window = Section::new(&document, "window");
+        let window_config = &mut config.window;
+        if let Some(title) = window.string("title")? {
+            window_config.title = title;
+        }
+        if let Some(size) = window.size("size")? {
+            window_config.size = size;
+        }
+        if let Some(resizable) = window.bool("resizable")? {
+            window_config.resizable = resizable;
+        }
+        if let Some(decorations) = window.bool("decorations")? {
+            window_config.decorations = decorations;
+        }
+        window_config.min_size = window.size("min_size")?;
+        window_config.max_size = window.size("max_size")?;
+        if let Some(maximized) = window.bool("maximized")? {
+            window_config.maximized = maximized;
+        }
+        if let Some(transparent) = window.bool("transparent")? {
+            window_config.transparent = transparent;
+        }
+
+        let renderer = &mut config.renderer;
+        if let Some(vsync) = Section::new(&document, "swapchain").bool("vsync")? {
+            renderer.vsync = vsync;
+        }
+        let quality = Section::new(&document, "quality");
+        if let Some(msaa) = quality.integer("msaa")? {
+            renderer.msaa_samples = match msaa {
+                1 | 2 | 4 | 8 | 16 | 32 | 64 => msaa as u32,
+                _ => return Err(quality.invalid("msaa", "1, 2, 4, 8, 16, 32 or 64")),
+            };
+        }
+        renderer.internal_resolution = quality.extent("internal_resolution")?;
+        let device = Section::new(&document, "device");
+        renderer.adapter = match device.get("gpu") {
+            None => AdapterSelection::Auto,
+            Some(gpu) => match (gpu.as_integer(), gpu.as_str()) {
+                (Some(index), _) if index >= 0 => AdapterSelection::Index(index as usize),
+                (_, Some(name)) => AdapterSelection::Name(name.to_string()),
+                _ => return Err(device.invalid("gpu", "an adapter index or name")),
+            },
+        };
+        if let Some(validation) = device.bool("validation")? {
+            renderer.validation = validation;
+        }
+        if let Some(track_leaks) = device.bool("track_leaks")? {
+            renderer.track_leaks = track_leaks;
+        }
+        Ok(config)
+    }
+}
+
+/// One table of the file, which may be missing.
+struct Section<'a> {
+    name: &'static str,
+    item: Option<&'a Item>,
+}
+impl<'a> Section<'a> {
+    fn new(document: &'a DocumentMut, name: &'static str) -> Self {
+        Self {
+            name,
+            item: document.get(name),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Item> {
+        self.item?.get(key)
+    }
+
+    fn invalid(&self, key: &str, expected: &'static str) -> ConfigError {
+        ConfigError::InvalidValue {
+            key: format!("{}.{key}", self.name),
+            expected,
+        }
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        self.get(key)
+            .map(|item| {
+                item.as_bool()
+                    .ok_or_else(|| self.invalid(key, "true or false"))
+            })
+            .transpose()
+    }
+
+    fn integer(&self, key: &str) -> Result<Option<i64>, ConfigError> {
+        self.get(key)
+            .map(|item| {
+                item.as_integer()
+                    .ok_or_else(|| self.invalid(key, "an integer"))
+            })
+            .transpose()
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        self.get(key)
+            .map(|item| {
+                item.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| self.invalid(key, "a string"))
+            })
+            .transpose()
+    }
+
+    /// `[width, height]` in logical pixels.
+    fn size(&self, key: &str) -> Result<Option<LogicalSize<f64>>, ConfigError> {
+        let Some(item) = self.get(key) else {
+            return Ok(None);
+        };
+        let number = |value: &toml_edit::Value| {
+            value
+                .as_float()
+                .or(value.as_integer().map(|value| value as f64))
+                .filter(|value| *value > 0.0)
+        };
+        let numbers: Option<Vec<f64>> = item
+            .as_array()
+            .and_then(|array| array.iter().map(number).collect());
+        match numbers.as_deref() {
+            Some(&[width, height]) => Ok(Some(LogicalSize::new(width, height))),
+            _ => Err(self.invalid(key, "[width, height] with positive numbers")),
+        }
+    }
+
+    /// `[width, height]` in whole pixels.
+    fn extent(&self, key: &str) -> Result<Option<[u32; 2]>, ConfigError> {
+        let Some(item) = self.get(key) else {
+            return Ok(None);
+        };
+        let numbers: Option<Vec<u32>> = item.as_array().and_then(|array| {
+            array
+                .iter()
+                .map(|value| {
+                    value
+                        .as_integer()
+                        .and_then(|value| u32::try_from(value).ok())
+                })
+                .collect()
+        });
+        match numbers.as_deref() {
+            Some(&[width, height]) if width > 0 && height > 0 => Ok(Some([width, height])),
+            _ => Err(self.invalid(key, "[width, height] with positive integers")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The key and expectation `text` is rejected for.
+    fn invalid_value(text: &str) -> (String, &'static str) {
+        match StartupConfig::parse(text) {
+            Err(ConfigError::InvalidValue { key, expected }) => (key, expected),
+            other => panic!("expected an invalid value, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_every_section() {
+        let config = StartupConfig::parse(
+            r#"
+[window]
+title = "Demo"
+size = [1280, 720.5]
+resizable = false
+min_size = [640, 360]
+
+[swapchain]
+vsync = false
+
+[quality]
+msaa = 4
+internal_resolution = [640, 360]
+
+[device]
+gpu = 1
+track_leaks = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.window.title, "Demo");
+        assert_eq!(config.window.size, LogicalSize::new(1280.0, 720.5));
+        assert!(!config.window.resizable);
+        assert_eq!(config.window.min_size, Some(LogicalSize::new(640.0, 360.0)));
+        assert_eq!(config.window.max_size, None);
+        assert!(!config.renderer.vsync);
+        assert_eq!(config.renderer.msaa_samples, 4);
+        assert_eq!(config.renderer.internal_resolution, Some([640, 360]));
+        assert_eq!(config.renderer.adapter, AdapterSelection::Index(1));
+        assert!(config.renderer.track_leaks);
+
+        assert_eq!(StartupConfig::parse("").unwrap(), StartupConfig::default());
+        let config = StartupConfig::parse("[device]\ngpu = \"nvidia\"").unwrap();
+        assert_eq!(
+            config.renderer.adapter,
+            AdapterSelection::Name("nvidia".into())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(
+            invalid_value("[window]\ntitle = 5"),
+            ("window.title".into(), "a string")
+        );
+        assert_eq!(
+            invalid_value("[window]\nresizable = \"yes\""),
+            ("window.resizable".into(), "true or false")
+        );
+        for size in ["[1280]", "[1280, -720]", "\"1280x720\""] {
+            assert_eq!(
+                invalid_value(&format!("[window]\nsize = {size}")),
+                (
+                    "window.size".into(),
+                    "[width, height] with positive numbers"
+                )
+            );
+        }
+        assert_eq!(
+            invalid_value("[quality]\nmsaa = 3"),
+            ("quality.msaa".into(), "1, 2, 4, 8, 16, 32 or 64")
+        );
+        assert_eq!(
+            invalid_value("[quality]\nmsaa = 4.0"),
+            ("quality.msaa".into(), "an integer")
+        );
+        for resolution in ["[0, 360]", "[640.5, 360]", "[640, 360, 1]"] {
+            assert_eq!(
+                invalid_value(&format!("[quality]\ninternal_resolution = {resolution}")),
+                (
+                    "quality.internal_resolution".into(),
+                    "[width, height] with positive integers"
+                )
+            );
+        }
+        assert_eq!(
+            invalid_value("[device]\ngpu = -1"),
+            ("device.gpu".into(), "an adapter index or name")
+        );
+        assert!(matches!(
+            StartupConfig::parse("[window\ntitle = \"Demo\""),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,645 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Set on a gid when the tile is mirrored horizontally.
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+/// Set on a gid when the tile is mirrored vertically.
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+/// Set on a gid when the tile is mirrored across its top-left to bottom-right diagonal.
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// The bits left for the gid itself; hexagonal maps use the next flag bit for 120° rotation.
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+#[derive(Debug)]
+pub enum TilemapError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    Json(serde_json::Error),
+    /// A required attribute or key is missing or has the wrong type.
+    Invalid(String),
+    /// Infinite maps and zlib, gzip or Zstandard compressed layer data. Save with "Fixed" size
+    /// and CSV or uncompressed Base64 layer format instead.
+    Unsupported(String),
+}
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TilemapError::Io(error) => write!(f, "failed to read tilemap: {error}"),
+            TilemapError::Xml(error) => write!(f, "invalid TMX file: {error}"),
+            TilemapError::Json(error) => write!(f, "invalid Tiled JSON file: {error}"),
+            TilemapError::Invalid(what) => write!(f, "invalid tilemap: {what}"),
+            TilemapError::Unsupported(what) => write!(f, "unsupported tilemap feature: {what}"),
+        }
+    }
+}
+impl std::error::Error for TilemapError {}
+impl From<io::Error> for TilemapError {
+    fn from(error: io::Error) -> Self {
+        TilemapError::Io(error)
+    }
+}
+impl From<roxmltree::Error> for TilemapError {
+    fn from(error: roxmltree::Error) -> Self {
+        TilemapError::Xml(error)
+    }
+}
+impl From<serde_json::Error> for TilemapError {
+    fn from(error: serde_json::Error) -> Self {
+        TilemapError::Json(error)
+    }
+}
+
+/// One image of equally sized tiles in a grid, whose tiles have gids from `first_gid` on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tileset {
+    pub name: String,
+    pub first_gid: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+    /// In pixels.
+    pub tile_size: [u32; 2],
+    /// Pixels around the grid.
+    pub margin: u32,
+    /// Pixels between tiles.
+    pub spacing: u32,
+    /// Relative to the map file, also for external tilesets, which give it relative to their own
+    /// file; load it, e.g. with `Resources::create_texture`, as the texture of this tileset.
+    pub image: String,
+    /// In pixels.
+    pub image_size: [u32; 2],
+}
+impl Tileset {
+    /// Texture coordinates of the top-left and bottom-right corners of tile `local_id`.
+    pub fn tile_uv(&self, local_id: u32) -> ([f32; 2], [f32; 2]) {
+        let columns = self.columns.max(1);
+        let x = self.margin + (local_id % columns) * (self.tile_size[0] + self.spacing);
+        let y = self.margin + (local_id / columns) * (self.tile_size[1] + self.spacing);
+        let [width, height] = self.image_size.map(|size| size.max(1) as f32);
+        (
+            [x as f32 / width, y as f32 / height],
+            [
+                (x + self.tile_size[0]) as f32 / width,
+                (y + self.tile_size[1]) as f32 / height,
+            ],
+        )
+    }
+}
+
+/// A tile of a layer: a global tile id, 0 for no tile, with its flip flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tile(pub u32);
+impl Tile {
+    pub fn gid(self) -> u32 {
+        self.0 & GID_MASK
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.gid() == 0
+    }
+
+    pub fn flipped_horizontally(self) -> bool {
+        self.0 & FLIPPED_HORIZONTALLY != 0
+    }
+
+    pub fn flipped_vertically(self) -> bool {
+        self.0 & FLIPPED_VERTICALLY != 0
+    }
+
+    pub fn flipped_diagonally(self) -> bool {
+        self.0 & FLIPPED_DIAGONALLY != 0
+    }
+}
+
+/// A grid of tiles covering the map, drawn in layer order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileLayer {
+    pub name: String,
+    /// Row-major, `map.size[0]` tiles per row.
+    pub tiles: Vec<Tile>,
+    pub visible: bool,
+    pub opacity: f32,
+    /// In pixels.
+    pub offset: [f32; 2],
+}
+
+/// An orthogonal map made in the Tiled editor, for `TilemapRenderer`. Tile layers in groups
+/// are flattened into `layers`; object and image layers are skipped.
+///
+/// Positions are in pixels with y down, as in Tiled: tile `(x, y)` covers
+/// `x * tile_size[0]` to `(x + 1) * tile_size[0]` horizontally.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tilemap {
+    /// In tiles.
+    pub size: [u32; 2],
+    /// In pixels.
+    pub tile_size: [u32; 2],
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+}
+impl Tilemap {
+    /// Loads a `.tmx` file, or a `.json` or `.tmj` one, with external tilesets relative to it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TilemapError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json" | "tmj") => Self::from_json(&text, dir),
+            _ => Self::from_tmx(&text, dir),
+        }
+    }
+
+    /// Parses a TMX map, reading external `.tsx` tilesets from `dir`.
+    pub fn from_tmx(text: &str, dir: &Path) -> Result<Self, TilemapError> {
+        let document = roxmltree::Document::parse(text)?;
+        let map = document.root_element();
+        if optional_attribute(map, "infinite")?.unwrap_or(0) == 1 {
+            return Err(TilemapError::Unsupported("infinite map".into()));
+        }
+
+        let mut tilesets = Vec::new();
+        for node in map.children().filter(|node| node.has_tag_name("tileset")) {
+            let first_gid = attribute(node, "firstgid")?;
+            let tileset = match node.attribute("source") {
+                Some(source) => {
+                    let text = fs::read_to_string(dir.join(source))?;
+                    let document = roxmltree::Document::parse(&text)?;
+                    external(tmx_tileset(document.root_element(), first_gid)?, source)
+                }
+                None => tmx_tileset(node, first_gid)?,
+            };
+            tilesets.push(tileset);
+        }
+
+        let size = [attribute(map, "width")?, attribute(map, "height")?];
+        let mut layers = Vec::new();
+        for node in map.descendants().filter(|node| node.has_tag_name("layer")) {
+            let data = node
+                .children()
+                .find(|child| child.has_tag_name("data"))
+                .ok_or_else(|| TilemapError::Invalid("layer without data".into()))?;
+            if data.attribute("compression").is_some() {
+                return Err(TilemapError::Unsupported("compressed layer data".into()));
+            }
+            let text = data.text().unwrap_or("");
+            let tiles = match data.attribute("encoding") {
+                Some("csv") => parse_csv(text)?,
+                Some("base64") => parse_base64(text)?,
+                _ => data
+                    .children()
+                    .filter(|child| child.has_tag_name("tile"))
+                    .map(|child| Ok(Tile(optional_attribute(child, "gid")?.unwrap_or(0))))
+                    .collect::<Result<_, TilemapError>>()?,
+            };
+            layers.push(TileLayer {
+                name: node.attribute("name").unwrap_or("").to_string(),
+                tiles: sized(tiles, size)?,
+                visible: optional_attribute::<u32>(node, "visible")?.unwrap_or(1) != 0,
+                opacity: optional_attribute(node, "opacity")?.unwrap_or(1.0),
+                offset: [
+                    optional_attribute(node, "offsetx")?.unwrap_or(0.0),
+                    optional_attribute(node, "offsety")?.unwrap_or(0.0),
+                ],
+            });
+        }
+
+        Ok(Self {
+            size,
+            tile_size: [attribute(map, "tilewidth")?, attribute(map, "tileheight")?],
+            tilesets,
+            layers,
+        })
+    }
+
+    /// Parses a Tiled JSON map, reading external `.json` or `.tsj` tilesets from `dir`.
+    pub fn from_json(text: &str, dir: &Path) -> Result<Self, TilemapError> {
+        let map: Value = serde_json::from_str(text)?;
+        if map["infinite"].as_bool() == Some(true) {
+            return Err(TilemapError::Unsupported("infinite map".into()));
+        }
+
+        let mut tilesets = Vec::new();
+        for tileset in json_array(&map, "tilesets")? {
+            let first_gid = json_u32(tileset, "firstgid")?;
+            let tileset = match tileset["source"].as_str() {
+                Some(source) => {
+                    let text = fs::read_to_string(dir.join(source))?;
+                    external(
+                        json_tileset(&serde_json::from_str(&text)?, first_gid)?,
+                        source,
+                    )
+                }
+                None => json_tileset(tileset, first_gid)?,
+            };
+            tilesets.push(tileset);
+        }
+
+        let size = [json_u32(&map, "width")?, json_u32(&map, "height")?];
+        let mut layers = Vec::new();
+        let mut pending: Vec<&Value> = json_array(&map, "layers")?.iter().rev().collect();
+        while let Some(layer) = pending.pop() {
+            match layer["type"].as_str() {
+                Some("group") => pending.extend(json_array(layer, "layers")?.iter().rev()),
+                Some("tilelayer") => {
+                    if layer["compression"].as_str().is_some_and(|c| !c.is_empty()) {
+                        return Err(TilemapError::Unsupported("compressed layer data".into()));
+                    }
+                    let tiles = match &layer["data"] {
+                        Value::String(text) => parse_base64(text)?,
+                        Value::Array(gids) => gids
+                            .iter()
+                            .map(|gid| {
+                                gid.as_u64().map(|gid| Tile(gid as u32)).ok_or_else(|| {
+                                    TilemapError::Invalid("tile gid is not a number".into())
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                        _ => return Err(TilemapError::Invalid("layer without data".into())),
+                    };
+                    layers.push(TileLayer {
+                        name: layer["name"].as_str().unwrap_or("").to_string(),
+                        tiles: sized(tiles, size)?,
+                        visible: layer["visible"].as_bool().unwrap_or(true),
+                        opacity: layer["opacity"].as_f64().unwrap_or(1.0) as f32,
+                        offset: [
+                            layer["offsetx"].as_f64().unwrap_or(0.0) as f32,
+                            layer["offsety"].as_f64().unwrap_or(0.0) as f32,
+                        ],
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            size,
+            tile_size: [json_u32(&map, "tilewidth")?, json_u32(&map, "tileheight")?],
+            tilesets,
+            layers,
+        })
+    }
+
+    /// The index of the tileset `tile` is from, and the tile's id within it.
+    pub fn tileset_of(&self, tile: Tile) -> Option<(usize, u32)> {
+        let gid = tile.gid();
+        if gid == 0 {
+            return None;
+        }
+        // Tilesets are sorted by first gid; the tile belongs to the last one starting before it.
+        let index = self
+            .tilesets
+            .iter()
+            .rposition(|tileset| tileset.first_gid <= gid)?;
+        let local_id = gid - self.tilesets[index].first_gid;
+        (local_id < self.tilesets[index].tile_count).then_some((index, local_id))
+    }
+
+    /// Size of the whole map in pixels.
+    pub fn pixel_size(&self) -> [f32; 2] {
+        [
+            (self.size[0] * self.tile_size[0]) as f32,
+            (self.size[1] * self.tile_size[1]) as f32,
+        ]
+    }
+}
+
+fn attribute<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> Result<T, TilemapError> {
+    optional_attribute(node, name)?
+        .ok_or_else(|| TilemapError::Invalid(format!("{} without {name}", node.tag_name().name())))
+}
+
+fn optional_attribute<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    name: &str,
+) -> Result<Option<T>, TilemapError> {
+    node.attribute(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                TilemapError::Invalid(format!("{} {name} = {value:?}", node.tag_name().name()))
+            })
+        })
+        .transpose()
+}
+
+fn tmx_tileset(node: roxmltree::Node, first_gid: u32) -> Result<Tileset, TilemapError> {
+    let image = node
+        .children()
+        .find(|child| child.has_tag_name("image"))
+        .ok_or_else(|| TilemapError::Unsupported("tileset of separate images".into()))?;
+    Ok(Tileset {
+        name: node.attribute("name").unwrap_or("").to_string(),
+        first_gid,
+        tile_count: attribute(node, "tilecount")?,
+        columns: attribute(node, "columns")?,
+        tile_size: [
+            attribute(node, "tilewidth")?,
+            attribute(node, "tileheight")?,
+        ],
+        margin: optional_attribute(node, "margin")?.unwrap_or(0),
+        spacing: optional_attribute(node, "spacing")?.unwrap_or(0),
+        image: attribute(image, "source")?,
+        image_size: [attribute(image, "width")?, attribute(image, "height")?],
+    })
+}
+
+fn json_u32(value: &Value, key: &str) -> Result<u32, TilemapError> {
+    value[key]
+        .as_u64()
+        .map(|number| number as u32)
+        .ok_or_else(|| TilemapError::Invalid(format!("missing {key}")))
+}
+
+fn json_array<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, TilemapError> {
+    value[key]
+        .as_array()
+        .ok_or_else(|| TilemapError::Invalid(format!("missing {key}")))
+}
+
+fn json_tileset(tileset: &Value, first_gid: u32) -> Result<Tileset, TilemapError> {
+    let image = tileset["image"]
+        .as_str()
+        .ok_or_else(|| TilemapError::Unsupported("tileset of separate images".into()))?;
+    Ok(Tileset {
+        name: tileset["name"].as_str().unwrap_or("").to_string(),
+        first_gid,
+        tile_count: json_u32(tileset, "tilecount")?,
+        columns: json_u32(tileset, "columns")?,
+        tile_size: [
+            json_u32(tileset, "tilewidth")?,
+            json_u32(tileset, "tileheight")?,
+        ],
+        margin: json_u32(tileset, "margin").unwrap_or(0),
+        spacing: json_u32(tileset, "spacing").unwrap_or(0),
+        image: image.to_string(),
+        image_size: [
+            json_u32(tileset, "imagewidth")?,
+            json_u32(tileset, "imageheight")?,
+        ],
+    })
+}
+
+/// Makes the image path of the tileset read from `source`, which is relative to that file,
+/// relative to the map like `source` itself.
+fn external(mut tileset: Tileset, source: &str) -> Tileset {
+    if let Some(dir) = Path::new(source).parent() {
+        tileset.image = dir.join(&tileset.image).to_string_lossy().into_owned();
+    }
+    tileset
+}
+
+fn sized(tiles: Vec<Tile>, size: [u32; 2]) -> Result<Vec<Tile>, TilemapError> {
+    if tiles.len() != (size[0] * size[1]) as usize {
+        return Err(TilemapError::Invalid(format!(
+            "layer has {} tiles, not {}x{}",
+            tiles.len(),
+            size[0],
+            size[1]
+        )));
+    }
+    Ok(tiles)
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Tile>, TilemapError> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|gid| !gid.is_empty())
+        .map(|gid| {
+            gid.parse()
+                .map(Tile)
+                .map_err(|_| TilemapError::Invalid(format!("tile gid {gid:?}")))
+        })
+        .collect()
+}
+
+/// Little-endian `u32` gids, Base64 encoded.
+fn parse_base64(text: &str) -> Result<Vec<Tile>, TilemapError> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(TilemapError::Invalid("layer data is not Base64".into())),
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|gid| Tile(u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]])))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const TMX_TILESET: &str = r#"<tileset firstgid="1" name="terrain" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+  <image source="terrain.png" width="32" height="32"/>
+ </tileset>"#;
+
+    fn tmx_map(tilesets: &str, layers: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="16" tileheight="16" infinite="0">
+ {tilesets}
+ {layers}
+</map>"#
+        )
+    }
+
+    fn json_map(tilesets: &str, layers: &str) -> String {
+        format!(
+            r#"{{"width": 2, "height": 2, "tilewidth": 16, "tileheight": 16, "infinite": false,
+                "tilesets": [{tilesets}], "layers": [{layers}]}}"#
+        )
+    }
+
+    /// A fresh directory for external tileset files, with a `tilesets` subdirectory.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("szumi-tilemap-{name}-{}", std::process::id()));
+        fs::create_dir_all(dir.join("tilesets")).unwrap();
+        dir
+    }
+
+    fn gids(layer: &TileLayer) -> Vec<u32> {
+        layer.tiles.iter().map(|tile| tile.gid()).collect()
+    }
+
+    #[test]
+    fn tmx_csv_layer() {
+        let layers = r#"<layer name="ground" width="2" height="2" opacity="0.5" offsetx="4">
+  <data encoding="csv">
+1,2,
+0,2147483651
+</data>
+ </layer>"#;
+        let map = Tilemap::from_tmx(&tmx_map(TMX_TILESET, layers), Path::new("")).unwrap();
+        assert_eq!(map.size, [2, 2]);
+        assert_eq!(map.tile_size, [16, 16]);
+        assert_eq!(map.tilesets[0].image, "terrain.png");
+        assert_eq!(map.tilesets[0].tile_count, 4);
+        let layer = &map.layers[0];
+        assert_eq!(layer.name, "ground");
+        assert_eq!(gids(layer), [1, 2, 0, 3]);
+        assert!(layer.tiles[3].flipped_horizontally());
+        assert_eq!(layer.opacity, 0.5);
+        assert_eq!(layer.offset, [4.0, 0.0]);
+        assert_eq!(map.tileset_of(layer.tiles[3]), Some((0, 2)));
+    }
+
+    #[test]
+    fn tmx_base64_layer() {
+        let layers = r#"<layer name="ground" width="2" height="2">
+  <data encoding="base64">
+   AQAAAAIAAAAAAAAAAwAAAA==
+  </data>
+ </layer>"#;
+        let map = Tilemap::from_tmx(&tmx_map(TMX_TILESET, layers), Path::new("")).unwrap();
+        assert_eq!(gids(&map.layers[0]), [1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn tmx_xml_layer() {
+        let layers = r#"<layer name="ground" width="2" height="2" visible="0">
+  <data>
+   <tile gid="1"/>
+   <tile gid="2"/>
+   <tile/>
+   <tile gid="3"/>
+  </data>
+ </layer>"#;
+        let map = Tilemap::from_tmx(&tmx_map(TMX_TILESET, layers), Path::new("")).unwrap();
+        assert_eq!(gids(&map.layers[0]), [1, 2, 0, 3]);
+        assert!(!map.layers[0].visible);
+    }
+
+    #[test]
+    fn tmx_group_layers_are_flattened() {
+        let layers = r#"<layer name="ground" width="2" height="2"><data encoding="csv">1,1,1,1</data></layer>
+ <group name="details">
+  <layer name="walls" width="2" height="2"><data encoding="csv">0,2,0,2</data></layer>
+  <group name="nested">
+   <layer name="roofs" width="2" height="2"><data encoding="csv">3,0,0,0</data></layer>
+  </group>
+ </group>
+ <objectgroup name="spawns"/>"#;
+        let map = Tilemap::from_tmx(&tmx_map(TMX_TILESET, layers), Path::new("")).unwrap();
+        let names: Vec<&str> = map.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["ground", "walls", "roofs"]);
+        assert_eq!(gids(&map.layers[2]), [3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tmx_external_tileset_image_is_relative_to_the_map() {
+        let dir = scratch_dir("tmx");
+        fs::write(
+            dir.join("tilesets/terrain.tsx"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" name="terrain" tilewidth="16" tileheight="16" tilecount="4" columns="2" margin="1" spacing="2">
+ <image source="../images/terrain.png" width="32" height="32"/>
+</tileset>"#,
+        )
+        .unwrap();
+        let tilesets = r#"<tileset firstgid="5" source="tilesets/terrain.tsx"/>"#;
+        let layers = r#"<layer name="ground" width="2" height="2"><data encoding="csv">5,6,7,8</data></layer>"#;
+        let map = Tilemap::from_tmx(&tmx_map(tilesets, layers), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let tileset = &map.tilesets[0];
+        assert_eq!(tileset.first_gid, 5);
+        assert_eq!([tileset.margin, tileset.spacing], [1, 2]);
+        assert_eq!(
+            Path::new(&tileset.image),
+            Path::new("tilesets").join("../images/terrain.png")
+        );
+        assert_eq!(map.tileset_of(map.layers[0].tiles[3]), Some((0, 3)));
+    }
+
+    #[test]
+    fn json_array_and_base64_layers() {
+        let tileset = r#"{"firstgid": 1, "name": "terrain", "tilewidth": 16, "tileheight": 16,
+            "tilecount": 4, "columns": 2, "image": "terrain.png", "imagewidth": 32, "imageheight": 32}"#;
+        let layers = r#"{"type": "tilelayer", "name": "ground", "data": [1, 2, 0, 3], "opacity": 0.5},
+            {"type": "tilelayer", "name": "walls", "encoding": "base64", "compression": "",
+             "data": "AQAAAAIAAAAAAAAAAwAAAA==", "visible": false}"#;
+        let map = Tilemap::from_json(&json_map(tileset, layers), Path::new("")).unwrap();
+        assert_eq!(map.tilesets[0].image, "terrain.png");
+        assert_eq!(gids(&map.layers[0]), [1, 2, 0, 3]);
+        assert_eq!(map.layers[0].opacity, 0.5);
+        assert_eq!(gids(&map.layers[1]), [1, 2, 0, 3]);
+        assert!(!map.layers[1].visible);
+    }
+
+    #[test]
+    fn json_group_layers_are_flattened() {
+        let tileset = r#"{"firstgid": 1, "tilewidth": 16, "tileheight": 16, "tilecount": 4,
+            "columns": 2, "image": "terrain.png", "imagewidth": 32, "imageheight": 32}"#;
+        let layers = r#"{"type": "tilelayer", "name": "ground", "data": [1, 1, 1, 1]},
+            {"type": "group", "name": "details", "layers": [
+                {"type": "tilelayer", "name": "walls", "data": [0, 2, 0, 2]},
+                {"type": "group", "name": "nested", "layers": [
+                    {"type": "tilelayer", "name": "roofs", "data": [3, 0, 0, 0]}
+                ]}
+            ]},
+            {"type": "objectgroup", "name": "spawns", "objects": []}"#;
+        let map = Tilemap::from_json(&json_map(tileset, layers), Path::new("")).unwrap();
+        let names: Vec<&str> = map.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["ground", "walls", "roofs"]);
+    }
+
+    #[test]
+    fn json_external_tileset_image_is_relative_to_the_map() {
+        let dir = scratch_dir("json");
+        fs::write(
+            dir.join("tilesets/terrain.tsj"),
+            r#"{"name": "terrain", "tilewidth": 16, "tileheight": 16, "tilecount": 4, "columns": 2,
+                "image": "terrain.png", "imagewidth": 32, "imageheight": 32}"#,
+        )
+        .unwrap();
+        let tileset = r#"{"firstgid": 1, "source": "tilesets/terrain.tsj"}"#;
+        let layers = r#"{"type": "tilelayer", "name": "ground", "data": [1, 2, 3, 4]}"#;
+        let map = Tilemap::from_json(&json_map(tileset, layers), &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            Path::new(&map.tilesets[0].image),
+            Path::new("tilesets").join("terrain.png")
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_and_missized_maps() {
+        let infinite = tmx_map(TMX_TILESET, "").replace(r#"infinite="0""#, r#"infinite="1""#);
+        assert!(matches!(
+            Tilemap::from_tmx(&infinite, Path::new("")),
+            Err(TilemapError::Unsupported(_))
+        ));
+        let compressed = r#"<layer name="ground" width="2" height="2"><data encoding="base64" compression="zlib">eJw=</data></layer>"#;
+        assert!(matches!(
+            Tilemap::from_tmx(&tmx_map(TMX_TILESET, compressed), Path::new("")),
+            Err(TilemapError::Unsupported(_))
+        ));
+        let short = r#"<layer name="ground" width="2" height="2"><data encoding="csv">1,2,3</data></layer>"#;
+        assert!(matches!(
+            Tilemap::from_tmx(&tmx_map(TMX_TILESET, short), Path::new("")),
+            Err(TilemapError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use nalgebra::Isometry3;
+use nalgebra::Matrix4;
+use nalgebra::Point3;
+use nalgebra::Translation3;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+/// Position, orientation and size of something in its parent's space, kept decomposed so each
+/// part can be changed alone. Objects face -Z with Y up, as in glTF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+impl Transform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_isometry(isometry: &Isometry3<f32>) -> Self {
+        Self {
+            translation: isometry.translation.vector,
+            rotation: isometry.rotation,
+            ..Default::default()
+        }
+    }
+
+    /// At `eye`, facing `target`, rolled so `up` stays up. `up` must not be parallel to the view
+    /// direction.
+    pub fn look_at(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Self {
+        Self {
+            translation: eye.coords,
+            // `face_towards` turns +Z to the direction given; objects face -Z.
+            rotation: UnitQuaternion::face_towards(&(eye - target), &up),
+            ..Default::default()
+        }
+    }
+
+    /// Turns in place to face `target`, keeping translation and scale.
+    pub fn look_at_point(&mut self, target: Point3<f32>, up: Vector3<f32>) {
+        self.rotation = UnitQuaternion::face_towards(&(self.translation - target.coords), &up);
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        self.rotation * -Vector3::z()
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.rotation * Vector3::x()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.rotation * Vector3::y()
+    }
+
+    /// Translation and rotation without the scale.
+    pub fn isometry(&self) -> Isometry3<f32> {
+        Isometry3::from_parts(Translation3::from(self.translation), self.rotation)
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// View matrix of a camera placed by this transform. Scale is ignored.
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        self.isometry().inverse().to_homogeneous()
+    }
+
+    pub fn transform_point(&self, point: &Point3<f32>) -> Point3<f32> {
+        Point3::from(self.translation + self.rotation * point.coords.component_mul(&self.scale))
+    }
+
+    /// Blends towards `other`; `t = 0` keeps `self`, `t = 1` gives `other`.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self
+                .rotation
+                .try_slerp(&other.rotation, t, f32::EPSILON)
+                .unwrap_or(other.rotation),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+impl From<Transform> for Matrix4<f32> {
+    fn from(transform: Transform) -> Self {
+        transform.to_matrix()
+    }
+}
@@ -0,0 +1,337 @@
+use slotmap::SlotMap;
+use winit::event::ElementState;
+use winit::event::MouseButton;
+use winit::event::WindowEvent;
+
+use crate::handles::WidgetId;
+use crate::renderer_core::Panel;
+use crate::renderer_core::TextRun;
+use crate::ui_scale::UiScale;
+
+/// A rectangle in logical units, y down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UiRect {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+impl UiRect {
+    pub fn new(offset: [f32; 2], size: [f32; 2]) -> Self {
+        Self { offset, size }
+    }
+
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        (0..2).all(|axis| {
+            point[axis] >= self.offset[axis] && point[axis] < self.offset[axis] + self.size[axis]
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WidgetKind {
+    /// A nine-slice panel, e.g. a window or a group's background. Blocks clicks.
+    Panel,
+    /// A panel that darkens while pressed, brightens while hovered and reports clicks.
+    Button,
+    /// One line of text per line of `text`, each `rect.size[1]` tall.
+    Label { text: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Widget {
+    pub kind: WidgetKind,
+    /// Relative to the parent's top-left corner.
+    pub rect: UiRect,
+    pub color: [f32; 4],
+    /// Hidden widgets hide their children too.
+    pub visible: bool,
+    parent: Option<WidgetId>,
+    children: Vec<WidgetId>,
+}
+impl Widget {
+    pub fn parent(&self) -> Option<WidgetId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[WidgetId] {
+        &self.children
+    }
+}
+
+/// A minimal retained UI: a tree of panels, buttons and labels laid out in logical units, for
+/// tools and menus that don't need a full UI library.
+///
+/// Feed window events to `handle_event` for hovering and clicks, read clicks with
+/// `take_clicks`, and draw `panels` with a `NineSliceRenderer` and then `text_runs` with a
+/// `TextRenderer`, both with `UiScale::projection`. Children are drawn over their parents and
+/// later siblings over earlier ones; labels are drawn over all panels.
+pub struct Ui {
+    widgets: SlotMap<WidgetId, Widget>,
+    roots: Vec<WidgetId>,
+    cursor: Option<[f32; 2]>,
+    hovered: Option<WidgetId>,
+    pressed: Option<WidgetId>,
+    clicks: Vec<WidgetId>,
+    /// Units per border texel of the panel texture, see `Panel::border_scale`.
+    pub border_scale: f32,
+}
+impl Default for Ui {
+    fn default() -> Self {
+        Self {
+            widgets: SlotMap::with_key(),
+            roots: Vec::new(),
+            cursor: None,
+            hovered: None,
+            pressed: None,
+            clicks: Vec::new(),
+            border_scale: 1.0,
+        }
+    }
+}
+impl Ui {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_panel(
+        &mut self,
+        parent: Option<WidgetId>,
+        rect: UiRect,
+        color: [f32; 4],
+    ) -> WidgetId {
+        self.add(parent, WidgetKind::Panel, rect, color)
+    }
+
+    pub fn add_button(
+        &mut self,
+        parent: Option<WidgetId>,
+        rect: UiRect,
+        color: [f32; 4],
+    ) -> WidgetId {
+        self.add(parent, WidgetKind::Button, rect, color)
+    }
+
+    /// `rect.size[1]` is the height of one line; the width only matters for layout.
+    pub fn add_label(
+        &mut self,
+        parent: Option<WidgetId>,
+        rect: UiRect,
+        text: impl Into<String>,
+        color: [f32; 4],
+    ) -> WidgetId {
+        let kind = WidgetKind::Label { text: text.into() };
+        self.add(parent, kind, rect, color)
+    }
+
+    /// Adds a widget under `parent`, or as a root if `parent` is `None` or stale.
+    pub fn add(
+        &mut self,
+        parent: Option<WidgetId>,
+        kind: WidgetKind,
+        rect: UiRect,
+        color: [f32; 4],
+    ) -> WidgetId {
+        let parent = parent.filter(|parent| self.widgets.contains_key(*parent));
+        let id = self.widgets.insert(Widget {
+            kind,
+            rect,
+            color,
+            visible: true,
+            parent,
+            children: Vec::new(),
+        });
+        match parent {
+            Some(parent) => self.widgets[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Removes `id` and its children.
+    pub fn remove(&mut self, id: WidgetId) {
+        let Some(widget) = self.widgets.remove(id) else {
+            return;
+        };
+        match widget
+            .parent
+            .and_then(|parent| self.widgets.get_mut(parent))
+        {
+            Some(parent) => parent.children.retain(|&child| child != id),
+            None => self.roots.retain(|&root| root != id),
+        }
+        for child in widget.children {
+            self.remove(child);
+        }
+        if self.hovered == Some(id) {
+            self.hovered = None;
+        }
+        if self.pressed == Some(id) {
+            self.pressed = None;
+        }
+    }
+
+    pub fn widget(&self, id: WidgetId) -> Option<&Widget> {
+        self.widgets.get(id)
+    }
+
+    pub fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget> {
+        self.widgets.get_mut(id)
+    }
+
+    /// Replaces a label's text; does nothing for other widgets.
+    pub fn set_text(&mut self, id: WidgetId, text: impl Into<String>) {
+        if let Some(WidgetKind::Label { text: current }) =
+            self.widgets.get_mut(id).map(|widget| &mut widget.kind)
+        {
+            *current = text.into();
+        }
+    }
+
+    /// Where `id` is on screen, in logical units.
+    pub fn absolute_rect(&self, id: WidgetId) -> Option<UiRect> {
+        let mut rect = self.widgets.get(id)?.rect;
+        let mut parent = self.widgets[id].parent;
+        while let Some(id) = parent {
+            let widget = &self.widgets[id];
+            rect.offset = [0, 1].map(|axis| rect.offset[axis] + widget.rect.offset[axis]);
+            parent = widget.parent;
+        }
+        Some(rect)
+    }
+
+    /// The topmost visible panel or button at `point`, in logical units.
+    pub fn hit_test(&self, point: [f32; 2]) -> Option<WidgetId> {
+        let mut hit = None;
+        self.visit(|id, widget, rect| {
+            if widget.kind != WidgetKind::Panel && widget.kind != WidgetKind::Button {
+                return;
+            }
+            if rect.contains(point) {
+                hit = Some(id);
+            }
+        });
+        hit
+    }
+
+    /// Tracks the cursor and the left mouse button. Returns whether the event hit the UI, so
+    /// the app shouldn't also handle it, e.g. a click on a button shouldn't select what's
+    /// behind it.
+    pub fn handle_event(&mut self, event: &WindowEvent, scale: &UiScale) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = scale.to_logical([position.x as f32, position.y as f32]);
+                self.cursor = Some(cursor);
+                self.hovered = self.hit_test(cursor);
+                self.hovered.is_some()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                self.hovered = None;
+                false
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let button = self.hovered.filter(|&id| {
+                    self.widgets
+                        .get(id)
+                        .is_some_and(|widget| widget.kind == WidgetKind::Button)
+                });
+                match state {
+                    ElementState::Pressed => self.pressed = button,
+                    ElementState::Released => {
+                        if let Some(pressed) = self.pressed.take() {
+                            if button == Some(pressed) {
+                                self.clicks.push(pressed);
+                            }
+                        }
+                    }
+                }
+                self.hovered.is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Where the cursor is, in logical units, `None` while it's outside the window.
+    pub fn cursor(&self) -> Option<[f32; 2]> {
+        self.cursor
+    }
+
+    /// The panel or button under the cursor, e.g. to ignore other input while there is one.
+    pub fn hovered(&self) -> Option<WidgetId> {
+        self.hovered
+    }
+
+    /// Buttons pressed and released under the cursor since the last call, oldest first.
+    pub fn take_clicks(&mut self) -> Vec<WidgetId> {
+        std::mem::take(&mut self.clicks)
+    }
+
+    /// Panels and buttons in drawing order, for `NineSliceRenderer::upload_panels`.
+    pub fn panels(&self) -> Vec<Panel> {
+        let mut panels = Vec::new();
+        self.visit(|id, widget, rect| {
+            let tint = match widget.kind {
+                WidgetKind::Panel => 1.0,
+                WidgetKind::Button if self.pressed == Some(id) => 0.8,
+                WidgetKind::Button if self.hovered == Some(id) => 1.2,
+                WidgetKind::Button => 1.0,
+                WidgetKind::Label { .. } => return,
+            };
+            let [r, g, b, a] = widget.color;
+            panels.push(Panel {
+                offset: rect.offset,
+                size: rect.size,
+                color: [r * tint, g * tint, b * tint, a],
+                border_scale: self.border_scale,
+            });
+        });
+        panels
+    }
+
+    /// Labels in drawing order, for `TextRenderer::upload_text`.
+    pub fn text_runs(&self) -> Vec<TextRun> {
+        let mut runs = Vec::new();
+        self.visit(|_, widget, rect| {
+            if let WidgetKind::Label { text } = &widget.kind {
+                runs.push(TextRun {
+                    position: rect.offset,
+                    text: text.clone(),
+                    height: rect.size[1],
+                    color: widget.color,
+                });
+            }
+        });
+        runs
+    }
+
+    /// Calls `f` on the visible widgets with their absolute rects, parents before children.
+    fn visit<F: FnMut(WidgetId, &Widget, UiRect)>(&self, mut f: F) {
+        let mut pending: Vec<(WidgetId, [f32; 2])> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&id| (id, [0.0, 0.0]))
+            .collect();
+        while let Some((id, origin)) = pending.pop() {
+            let widget = &self.widgets[id];
+            if !widget.visible {
+                continue;
+            }
+            let rect = UiRect {
+                offset: [0, 1].map(|axis| origin[axis] + widget.rect.offset[axis]),
+                size: widget.rect.size,
+            };
+            f(id, widget, rect);
+            pending.extend(
+                widget
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, rect.offset)),
+            );
+        }
+    }
+}
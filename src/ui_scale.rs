@@ -0,0 +1,113 @@
+use nalgebra::Matrix4;
+use nalgebra::Orthographic3;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+/// Scale applied to text and 2D layouts, which are authored in logical units: one unit is one
+/// pixel on a standard-density monitor at the default settings.
+///
+/// Combines the scale factor of the monitor the window is on (its DPI relative to 96), the
+/// user's preference and a per-window zoom. Feed window events to `handle_event` so the factor
+/// follows the window across monitors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UiScale {
+    monitor_scale: f32,
+    user_scale: f32,
+    window_scale: f32,
+    /// Step the combined factor is rounded to, e.g. 0.25 so glyphs land on whole pixels more
+    /// often. 0 disables rounding.
+    pub snap: f32,
+}
+impl Default for UiScale {
+    fn default() -> Self {
+        Self {
+            monitor_scale: 1.0,
+            user_scale: 1.0,
+            window_scale: 1.0,
+            snap: 0.0,
+        }
+    }
+}
+impl UiScale {
+    /// Smallest and largest combined factor, so extreme preferences keep the UI usable.
+    pub const MIN: f32 = 0.5;
+    pub const MAX: f32 = 4.0;
+
+    /// The combined factor: physical pixels per logical unit.
+    pub fn factor(&self) -> f32 {
+        let factor = self.monitor_scale * self.user_scale * self.window_scale;
+        let factor = if self.snap > 0.0 {
+            (factor / self.snap).round() * self.snap
+        } else {
+            factor
+        };
+        factor.clamp(Self::MIN, Self::MAX)
+    }
+
+    pub fn monitor_scale(&self) -> f32 {
+        self.monitor_scale
+    }
+
+    pub fn user_scale(&self) -> f32 {
+        self.user_scale
+    }
+
+    /// E.g. from a settings menu; 1 keeps the system's size.
+    pub fn set_user_scale(&mut self, scale: f32) {
+        self.user_scale = scale.max(f32::EPSILON);
+    }
+
+    pub fn window_scale(&self) -> f32 {
+        self.window_scale
+    }
+
+    /// Zoom of this window only, e.g. a presentation view.
+    pub fn set_window_scale(&mut self, scale: f32) {
+        self.window_scale = scale.max(f32::EPSILON);
+    }
+
+    /// Reads the scale of the window's current monitor, e.g. once the window is created.
+    /// Returns whether `factor` changed.
+    pub fn refresh(&mut self, window: &Window) -> bool {
+        self.set_monitor_scale(window.scale_factor() as f32)
+    }
+
+    /// Picks up monitor changes. `ScaleFactorChanged` arrives when the window moves to a monitor
+    /// with another DPI or the system setting changes; `Moved` is checked too, as not every
+    /// platform sends the former. Returns whether `factor` changed, i.e. layouts need redoing.
+    pub fn handle_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.set_monitor_scale(*scale_factor as f32)
+            }
+            WindowEvent::Moved(_) => self.refresh(window),
+            _ => false,
+        }
+    }
+
+    fn set_monitor_scale(&mut self, scale: f32) -> bool {
+        let before = self.factor();
+        self.monitor_scale = scale;
+        self.factor() != before
+    }
+
+    pub fn to_physical(&self, logical: [f32; 2]) -> [f32; 2] {
+        logical.map(|value| value * self.factor())
+    }
+
+    pub fn to_logical(&self, physical: [f32; 2]) -> [f32; 2] {
+        physical.map(|value| value / self.factor())
+    }
+
+    /// Pixel height to rasterize text of `logical_size` at, rounded so glyphs stay sharp.
+    pub fn text_size(&self, logical_size: f32) -> f32 {
+        (logical_size * self.factor()).round().max(1.0)
+    }
+
+    /// Orthographic projection for 2D layouts in logical units over a framebuffer of
+    /// `physical_extent` pixels, origin at the top left like the core's pixel projection.
+    pub fn projection(&self, physical_extent: [u32; 2]) -> Matrix4<f32> {
+        let [width, height] = self.to_logical(physical_extent.map(|value| value as f32));
+        Orthographic3::new(0.0, width, 0.0, height, -1.0, 1.0).to_homogeneous()
+    }
+}
@@ -0,0 +1,66 @@
+/// Length unit a scene or an asset is authored in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthUnit {
+    Millimeters,
+    Centimeters,
+    Meters,
+    Inches,
+    Feet,
+    /// Arbitrary unit, given as the number of meters in one unit.
+    Custom(f32),
+}
+impl LengthUnit {
+    pub fn in_meters(self) -> f32 {
+        match self {
+            LengthUnit::Millimeters => 0.001,
+            LengthUnit::Centimeters => 0.01,
+            LengthUnit::Meters => 1.0,
+            LengthUnit::Inches => 0.0254,
+            LengthUnit::Feet => 0.3048,
+            LengthUnit::Custom(meters) => meters,
+        }
+    }
+}
+
+/// Scene-level unit configuration. Every length in the scene, e.g. positions and light ranges,
+/// is expressed in `unit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneUnits {
+    pub unit: LengthUnit,
+}
+impl Default for SceneUnits {
+    fn default() -> Self {
+        Self {
+            unit: LengthUnit::Meters,
+        }
+    }
+}
+impl SceneUnits {
+    pub fn new(unit: LengthUnit) -> Self {
+        Self { unit }
+    }
+
+    /// Converts a physical length in meters into scene units.
+    pub fn from_meters(&self, meters: f32) -> f32 {
+        meters / self.unit.in_meters()
+    }
+
+    pub fn to_meters(&self, length: f32) -> f32 {
+        length * self.unit.in_meters()
+    }
+
+    /// Factor that converts lengths authored in `asset_unit` into scene units.
+    pub fn import_scale(&self, asset_unit: LengthUnit) -> f32 {
+        asset_unit.in_meters() / self.unit.in_meters()
+    }
+
+    /// Smooth inverse-square falloff for a light whose `range` is given in scene units.
+    /// The distance is converted to meters first so the curve looks the same whatever the unit.
+    pub fn light_attenuation(&self, distance: f32, range: f32) -> f32 {
+        let distance = self.to_meters(distance);
+        let range = self.to_meters(range);
+        let ratio = (distance / range).clamp(0.0, 1.0);
+        let window = (1.0 - ratio.powi(4)).max(0.0).powi(2);
+        window / (distance * distance).max(0.01 * 0.01)
+    }
+}
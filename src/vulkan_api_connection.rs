@@ -3,29 +3,176 @@ use std::sync::Arc;
 use vulkano::{
     device::{
         physical::{PhysicalDevice, PhysicalDeviceType},
-        Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
+        Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags,
     },
-    instance::{Instance, InstanceCreateInfo},
+    instance::{Instance, InstanceCreateInfo, InstanceExtensions},
     swapchain::{Surface, SurfaceCapabilities},
-    VulkanLibrary,
+    Handle, Version, VulkanLibrary, VulkanObject,
 };
 use winit::window::Window;
 
+use crate::device_capabilities::DeviceCapabilities;
+use crate::renderer_core::MemoryBudget;
+
+/// Returns the raw handle of the physical device to use out of an instance's.
+pub type DeviceSelector<'a> = &'a dyn Fn(&Arc<Instance>) -> u64;
+
+/// Environment variable picking the GPU when the app doesn't: an index into
+/// `VulkanConnection::enumerate_adapters`, e.g. `SZUMI_GPU=1`, or part of the adapter's name.
+pub const GPU_ENV_VAR: &str = "SZUMI_GPU";
+
+/// The Khronos validation layer, enabled by `ConnectionRequirements::validation`.
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// A GPU, as listed by `VulkanConnection::enumerate_adapters`.
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    /// Position in the instance's device list, for `AdapterSelection::Index`.
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: Version,
+}
+
+/// Which GPU to render on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AdapterSelection {
+    /// `GPU_ENV_VAR` if set, otherwise the first discrete GPU, then integrated, virtual and
+    /// CPU ones.
+    #[default]
+    Auto,
+    /// The adapter with this `AdapterInfo::index`.
+    Index(usize),
+    /// The first adapter whose name contains this, ignoring case, e.g. "nvidia".
+    Name(String),
+}
+impl AdapterSelection {
+    /// Reads `GPU_ENV_VAR`: a number selects by index, anything else by name.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(GPU_ENV_VAR).ok()?;
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        Some(match value.parse() {
+            Ok(index) => AdapterSelection::Index(index),
+            Err(_) => AdapterSelection::Name(value.to_owned()),
+        })
+    }
+
+    fn matches(&self, index: usize, physical_device: &PhysicalDevice) -> bool {
+        match self {
+            AdapterSelection::Auto => true,
+            AdapterSelection::Index(wanted) => index == *wanted,
+            AdapterSelection::Name(name) => physical_device
+                .properties()
+                .device_name
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+        }
+    }
+}
+
+/// What another API sharing the instance and device needs from them, e.g. an OpenXR runtime.
+#[derive(Default)]
+pub struct ConnectionRequirements<'a> {
+    pub instance_extensions: InstanceExtensions,
+    pub device_extensions: DeviceExtensions,
+    /// Picks the physical device once the instance exists, overriding `adapter`.
+    pub physical_device: Option<DeviceSelector<'a>>,
+    /// The GPU to use when `physical_device` isn't set. Adapters that can't present to the
+    /// window or lack required extensions are skipped, falling back to `Auto`.
+    pub adapter: AdapterSelection,
+    /// Enables the validation layer, which reports API misuse on stdout. Skipped with a
+    /// message when the layer isn't installed.
+    pub validation: bool,
+}
+
+/// The device's queues by what they are used for. Roles without a queue of their own fall
+/// back to the graphics queue, which can do everything but presenting, so subsystems ask for
+/// the role they need instead of assuming one queue does it all.
+pub struct Queues {
+    graphics: Arc<Queue>,
+    present: Arc<Queue>,
+    compute: Option<Arc<Queue>>,
+    transfer: Option<Arc<Queue>>,
+}
+impl Queues {
+    /// Rendering, and anything else when there is nowhere better.
+    pub fn graphics(&self) -> &Arc<Queue> {
+        &self.graphics
+    }
+
+    /// The queue frames are presented on: `graphics` itself where its family can present,
+    /// which is nearly always; otherwise one of a family that can, sharing the swapchain
+    /// images with the graphics family.
+    pub fn present(&self) -> &Arc<Queue> {
+        &self.present
+    }
+
+    /// Whether presenting has its own queue, which then waits for frames on a semaphore.
+    pub fn separate_present(&self) -> bool {
+        self.present != self.graphics
+    }
+
+    /// Compute work: the async compute queue if there is one, the graphics queue otherwise.
+    pub fn compute(&self) -> &Arc<Queue> {
+        self.compute.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// A queue of a compute family without graphics, where the device has one. Compute work
+    /// on it overlaps with rendering; hand results over with semaphores.
+    pub fn async_compute(&self) -> Option<&Arc<Queue>> {
+        self.compute.as_ref()
+    }
+
+    /// Copies, e.g. asset uploads: the transfer queue if there is one, so they don't wait
+    /// behind frames, the graphics queue otherwise.
+    pub fn transfer(&self) -> &Arc<Queue> {
+        self.transfer.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// A queue of a transfer-only family, where the device has one. Copies on it run alongside
+    /// graphics work; resources it writes must be shared with the graphics family.
+    pub fn dedicated_transfer(&self) -> Option<&Arc<Queue>> {
+        self.transfer.as_ref()
+    }
+}
+
 /// This struct does not change during the lifetime of the application
 pub struct VulkanConnection {
     pub device: Arc<Device>,
     pub physical_device: Arc<PhysicalDevice>,
-    pub queue: Arc<Queue>,
+    pub queues: Queues,
     pub surface: Arc<Surface>,
     pub surface_caps: SurfaceCapabilities,
+    /// Limits and optional features of `device`, as created.
+    pub capabilities: DeviceCapabilities,
 }
 impl VulkanConnection {
     pub fn new(window: Arc<Window>) -> VulkanConnection {
-        let instance_extensions = Surface::required_extensions(window.clone().as_ref());
+        Self::with_requirements(window, &ConnectionRequirements::default())
+    }
+
+    /// `new`, with the extensions and device another API sharing them needs.
+    pub fn with_requirements(
+        window: Arc<Window>,
+        requirements: &ConnectionRequirements,
+    ) -> VulkanConnection {
+        let instance_extensions = Surface::required_extensions(window.clone().as_ref())
+            .union(&requirements.instance_extensions);
         let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
+        let enabled_layers = if requirements.validation {
+            VulkanConnection::validation_layers(&library)
+        } else {
+            Vec::new()
+        };
         let instance = Instance::new(
             library,
             InstanceCreateInfo {
+                enabled_layers,
                 enabled_extensions: instance_extensions,
                 ..Default::default()
             },
@@ -37,19 +184,99 @@ impl VulkanConnection {
         let device_extensions = DeviceExtensions {
             khr_swapchain: true,
             ..DeviceExtensions::empty()
+        }
+        .union(&requirements.device_extensions);
+
+        let required_device = requirements.physical_device.map(|select| select(&instance));
+        let adapter = match &requirements.adapter {
+            AdapterSelection::Auto => AdapterSelection::from_env().unwrap_or_default(),
+            adapter => adapter.clone(),
         };
+        let (physical_device, queue_family_index, present_family) =
+            VulkanConnection::select_physical_device(
+                &instance,
+                &surface,
+                &device_extensions,
+                required_device,
+                &adapter,
+            );
 
-        let (physical_device, queue_family_index) =
-            VulkanConnection::select_physical_device(&instance, &surface, &device_extensions);
+        #[cfg(feature = "mesh_shader")]
+        let device_extensions = DeviceExtensions {
+            ext_mesh_shader: VulkanConnection::mesh_shaders_supported(&physical_device),
+            ..device_extensions
+        };
 
-        let (device, mut queues) = Device::new(
+        let descriptor_indexing = VulkanConnection::descriptor_indexing_supported(&physical_device);
+        let buffer_device_address =
+            VulkanConnection::buffer_device_address_supported(&physical_device);
+        let ray_query = VulkanConnection::ray_query_supported(&physical_device);
+        let ray_tracing = VulkanConnection::ray_tracing_supported(&physical_device);
+        let device_extensions = DeviceExtensions {
+            ext_memory_budget: VulkanConnection::memory_budget_supported(&physical_device),
+            khr_acceleration_structure: ray_query,
+            khr_deferred_host_operations: ray_query,
+            khr_ray_query: ray_query,
+            khr_ray_tracing_pipeline: ray_tracing,
+            ..device_extensions
+        };
+
+        // Optional features, enabled when the device has them.
+        let supported_features = physical_device.supported_features();
+        let enabled_features = Features {
+            multi_draw_indirect: supported_features.multi_draw_indirect,
+            draw_indirect_first_instance: supported_features.draw_indirect_first_instance,
+            sampler_anisotropy: supported_features.sampler_anisotropy,
+            independent_blend: supported_features.independent_blend,
+            tessellation_shader: supported_features.tessellation_shader,
+            vertex_pipeline_stores_and_atomics: supported_features
+                .vertex_pipeline_stores_and_atomics,
+            fragment_stores_and_atomics: supported_features.fragment_stores_and_atomics,
+            texture_compression_bc: supported_features.texture_compression_bc,
+            multiview: VulkanConnection::multiview_supported(&physical_device),
+            descriptor_indexing,
+            runtime_descriptor_array: descriptor_indexing,
+            shader_sampled_image_array_non_uniform_indexing: descriptor_indexing,
+            descriptor_binding_partially_bound: descriptor_indexing,
+            descriptor_binding_variable_descriptor_count: descriptor_indexing,
+            acceleration_structure: ray_query,
+            buffer_device_address,
+            ray_query,
+            ray_tracing_pipeline: ray_tracing,
+            #[cfg(feature = "mesh_shader")]
+            task_shader: device_extensions.ext_mesh_shader,
+            #[cfg(feature = "mesh_shader")]
+            mesh_shader: device_extensions.ext_mesh_shader,
+            ..Features::empty()
+        };
+
+        let transfer_family = VulkanConnection::transfer_queue_family(&physical_device);
+        let compute_family = VulkanConnection::compute_queue_family(&physical_device);
+        // One queue per family; the present family may be one of the others.
+        let mut families: Vec<u32> = [
+            Some(queue_family_index),
+            transfer_family,
+            compute_family,
+            Some(present_family),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        families.sort_unstable();
+        families.dedup();
+        let queue_create_infos = families
+            .iter()
+            .map(|&queue_family_index| QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            })
+            .collect();
+        let (device, queues) = Device::new(
             physical_device.clone(),
             DeviceCreateInfo {
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
+                queue_create_infos,
                 enabled_extensions: device_extensions,
+                enabled_features,
                 ..Default::default()
             },
         )
@@ -59,38 +286,287 @@ impl VulkanConnection {
             .surface_capabilities(&surface, Default::default())
             .expect("failed to get surface capabilities");
 
+        let properties = physical_device.properties();
+        log::info!(
+            "rendering on {} ({:?}, Vulkan {}, driver {})",
+            properties.device_name,
+            properties.device_type,
+            physical_device.api_version(),
+            properties.driver_info.as_deref().unwrap_or("unknown"),
+        );
+        log::debug!(
+            "queue families: graphics {queue_family_index}, present {present_family}, \
+             transfer {transfer_family:?}, compute {compute_family:?}"
+        );
+
+        let queues: Vec<Arc<Queue>> = queues.collect();
+        let family_queue = |family: u32| {
+            queues
+                .iter()
+                .find(|queue| queue.queue_family_index() == family)
+                .unwrap()
+                .clone()
+        };
+
         Self {
+            capabilities: DeviceCapabilities::new(&device),
             device,
             physical_device,
-            queue: queues.next().unwrap(),
+            queues: Queues {
+                graphics: family_queue(queue_family_index),
+                present: family_queue(present_family),
+                compute: compute_family.map(family_queue),
+                transfer: transfer_family.map(family_queue),
+            },
             surface,
             surface_caps,
         }
     }
 
+    /// `VALIDATION_LAYER` if it's installed.
+    fn validation_layers(library: &VulkanLibrary) -> Vec<String> {
+        let installed = library
+            .layer_properties()
+            .unwrap()
+            .any(|layer| layer.name() == VALIDATION_LAYER);
+        if !installed {
+            log::warn!("{VALIDATION_LAYER} isn't installed, running without validation");
+            return Vec::new();
+        }
+        vec![VALIDATION_LAYER.to_string()]
+    }
+
+    /// A family with transfer but neither graphics nor compute, which discrete cards usually
+    /// back with dedicated copy engines.
+    pub fn transfer_queue_family(physical_device: &PhysicalDevice) -> Option<u32> {
+        physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|q| {
+                q.queue_flags.contains(QueueFlags::TRANSFER)
+                    && !q
+                        .queue_flags
+                        .intersects(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+            })
+            .map(|i| i as u32)
+    }
+
+    /// A family with compute but not graphics, which runs compute work asynchronously to the
+    /// graphics queue.
+    pub fn compute_queue_family(physical_device: &PhysicalDevice) -> Option<u32> {
+        physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|q| {
+                q.queue_flags.contains(QueueFlags::COMPUTE)
+                    && !q.queue_flags.intersects(QueueFlags::GRAPHICS)
+            })
+            .map(|i| i as u32)
+    }
+
+    /// Whether `VK_EXT_mesh_shader` was enabled, which takes the `mesh_shader` feature and a
+    /// device that supports it.
+    pub fn mesh_shaders_enabled(&self) -> bool {
+        self.device.enabled_extensions().ext_mesh_shader
+    }
+
+    /// `VK_EXT_mesh_shader` with task and mesh stages. Its shaders need SPIR-V 1.4, which is
+    /// core since Vulkan 1.2.
+    pub fn mesh_shaders_supported(physical_device: &PhysicalDevice) -> bool {
+        let features = physical_device.supported_features();
+        physical_device.supported_extensions().ext_mesh_shader
+            && physical_device.api_version() >= Version::V1_2
+            && features.task_shader
+            && features.mesh_shader
+    }
+
+    /// Whether `MemoryBudget::query` reports budgets and usage, not just heap sizes.
+    pub fn memory_budget_enabled(&self) -> bool {
+        self.device.enabled_extensions().ext_memory_budget
+    }
+
+    /// `VK_EXT_memory_budget`, queried through Vulkan 1.1's `vkGetPhysicalDeviceMemoryProperties2`.
+    pub fn memory_budget_supported(physical_device: &PhysicalDevice) -> bool {
+        physical_device.instance().api_version() >= Version::V1_1
+            && physical_device.api_version() >= Version::V1_1
+            && physical_device.supported_extensions().ext_memory_budget
+    }
+
+    /// How much VRAM and host memory the process uses and may use, heap by heap.
+    pub fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget::query(&self.device)
+    }
+
+    /// Whether one render pass can draw several views, as `StereoTarget` needs.
+    pub fn multiview_enabled(&self) -> bool {
+        self.device.enabled_features().multiview
+    }
+
+    /// `VK_KHR_multiview`, core since Vulkan 1.1.
+    pub fn multiview_supported(physical_device: &PhysicalDevice) -> bool {
+        physical_device.api_version() >= Version::V1_1
+            && physical_device.supported_features().multiview
+    }
+
+    /// Whether shaders can read buffers through device addresses, as `DrawDataBuffer` and
+    /// `GpuFrustumCuller::submit_cull_bda` need.
+    pub fn buffer_device_address_enabled(&self) -> bool {
+        self.device.enabled_features().buffer_device_address
+    }
+
+    /// The Vulkan 1.2 `buffer_device_address` feature.
+    pub fn buffer_device_address_supported(physical_device: &PhysicalDevice) -> bool {
+        physical_device.api_version() >= Version::V1_2
+            && physical_device.supported_features().buffer_device_address
+    }
+
+    /// Whether the descriptor indexing features bindless textures need were enabled.
+    pub fn descriptor_indexing_enabled(&self) -> bool {
+        self.device
+            .enabled_features()
+            .descriptor_binding_variable_descriptor_count
+    }
+
+    /// Vulkan 1.2 descriptor indexing with runtime-sized, partially bound, variable-count
+    /// sampler arrays indexed non-uniformly, as `BindlessTextures` uses them.
+    pub fn descriptor_indexing_supported(physical_device: &PhysicalDevice) -> bool {
+        let features = physical_device.supported_features();
+        physical_device.api_version() >= Version::V1_2
+            && features.descriptor_indexing
+            && features.runtime_descriptor_array
+            && features.shader_sampled_image_array_non_uniform_indexing
+            && features.descriptor_binding_partially_bound
+            && features.descriptor_binding_variable_descriptor_count
+    }
+
+    /// Whether acceleration structures and ray queries were enabled, which is all the
+    /// renderer's ray-traced passes need.
+    pub fn ray_query_enabled(&self) -> bool {
+        self.device.enabled_features().ray_query
+    }
+
+    /// Whether ray tracing pipelines were enabled on top of `ray_query_enabled`.
+    pub fn ray_tracing_enabled(&self) -> bool {
+        self.device.enabled_features().ray_tracing_pipeline
+    }
+
+    /// `VK_KHR_acceleration_structure` with `VK_KHR_ray_query`, on Vulkan 1.2 for buffer device
+    /// addresses and SPIR-V 1.4.
+    pub fn ray_query_supported(physical_device: &PhysicalDevice) -> bool {
+        let extensions = physical_device.supported_extensions();
+        let features = physical_device.supported_features();
+        physical_device.api_version() >= Version::V1_2
+            && extensions.khr_acceleration_structure
+            && extensions.khr_deferred_host_operations
+            && extensions.khr_ray_query
+            && features.acceleration_structure
+            && features.buffer_device_address
+            && features.ray_query
+    }
+
+    /// `ray_query_supported` plus `VK_KHR_ray_tracing_pipeline`.
+    pub fn ray_tracing_supported(physical_device: &PhysicalDevice) -> bool {
+        Self::ray_query_supported(physical_device)
+            && physical_device
+                .supported_extensions()
+                .khr_ray_tracing_pipeline
+            && physical_device.supported_features().ray_tracing_pipeline
+    }
+
+    /// Every GPU of the system, in the order `AdapterSelection::Index` counts them, whether or
+    /// not it can render to a window.
+    pub fn enumerate_adapters() -> Vec<AdapterInfo> {
+        let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
+        let instance = Instance::new(library, InstanceCreateInfo::default())
+            .expect("failed to create instance");
+        instance
+            .enumerate_physical_devices()
+            .expect("could not enumerate devices")
+            .enumerate()
+            .map(|(index, physical_device)| {
+                let properties = physical_device.properties();
+                AdapterInfo {
+                    index,
+                    name: properties.device_name.clone(),
+                    device_type: properties.device_type,
+                    vendor_id: properties.vendor_id,
+                    device_id: properties.device_id,
+                    api_version: physical_device.api_version(),
+                }
+            })
+            .collect()
+    }
+
     fn select_physical_device(
         instance: &Arc<Instance>,
         surface: &Arc<Surface>,
         device_extensions: &DeviceExtensions,
-    ) -> (Arc<PhysicalDevice>, u32) {
+        required_device: Option<u64>,
+        adapter: &AdapterSelection,
+    ) -> (Arc<PhysicalDevice>, u32, u32) {
+        let selected = VulkanConnection::best_physical_device(
+            instance,
+            surface,
+            device_extensions,
+            required_device,
+            adapter,
+        );
+        if selected.is_none() && *adapter != AdapterSelection::Auto {
+            log::warn!("GPU {adapter:?} is unavailable or unsuitable, picking one automatically");
+            return VulkanConnection::select_physical_device(
+                instance,
+                surface,
+                device_extensions,
+                required_device,
+                &AdapterSelection::Auto,
+            );
+        }
+        selected.expect("no device available")
+    }
+
+    /// A graphics family and a family that can present to `surface`: one family doing both
+    /// where there is one, else the first of each. `None` disqualifies the device.
+    fn graphics_present_families(
+        physical_device: &PhysicalDevice,
+        surface: &Surface,
+    ) -> Option<(u32, u32)> {
+        let families = physical_device.queue_family_properties();
+        let graphics = |i: usize| families[i].queue_flags.contains(QueueFlags::GRAPHICS);
+        let presents = |i: usize| {
+            physical_device
+                .surface_support(i as u32, surface)
+                .unwrap_or(false)
+        };
+        if let Some(both) = (0..families.len()).find(|&i| graphics(i) && presents(i)) {
+            return Some((both as u32, both as u32));
+        }
+        let graphics = (0..families.len()).find(|&i| graphics(i))?;
+        let present = (0..families.len()).find(|&i| presents(i))?;
+        Some((graphics as u32, present as u32))
+    }
+
+    /// The most capable device that `adapter` matches, can present to `surface` and has
+    /// `device_extensions`, with its graphics and present queue families.
+    fn best_physical_device(
+        instance: &Arc<Instance>,
+        surface: &Arc<Surface>,
+        device_extensions: &DeviceExtensions,
+        required_device: Option<u64>,
+        adapter: &AdapterSelection,
+    ) -> Option<(Arc<PhysicalDevice>, u32, u32)> {
         instance
             .enumerate_physical_devices()
             .expect("could not enumerate devices")
+            .enumerate()
+            .filter(|(index, p)| required_device.is_some() || adapter.matches(*index, p))
+            .map(|(_, p)| p)
+            .filter(|p| required_device.is_none_or(|handle| p.handle().as_raw() == handle))
             .filter(|p| p.supported_extensions().contains(&device_extensions))
             .filter_map(|p| {
-                p.queue_family_properties()
-                    .iter()
-                    .enumerate()
-                    // Find the first first queue family that is suitable.
-                    // If none is found, `None` is returned to `filter_map`,
-                    // which disqualifies this physical device.
-                    .position(|(i, q)| {
-                        q.queue_flags.contains(QueueFlags::GRAPHICS)
-                            && p.surface_support(i as u32, &surface).unwrap_or(false)
-                    })
-                    .map(|q| (p, q as u32))
+                let (graphics, present) = VulkanConnection::graphics_present_families(&p, surface)?;
+                Some((p, graphics, present))
             })
-            .min_by_key(|(p, _)| match p.properties().device_type {
+            .min_by_key(|(p, _, _)| match p.properties().device_type {
                 PhysicalDeviceType::DiscreteGpu => 0,
                 PhysicalDeviceType::IntegratedGpu => 1,
                 PhysicalDeviceType::VirtualGpu => 2,
@@ -101,6 +577,5 @@ impl VulkanConnection {
                 // match wildcard `_` to catch all unknown device types.
                 _ => 4,
             })
-            .expect("no device available")
     }
 }
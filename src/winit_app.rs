@@ -1,56 +1,343 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use winit::{
     application::ApplicationHandler,
+    dpi::LogicalSize,
     event::WindowEvent,
-    event_loop::ActiveEventLoop,
-    window::{Window, WindowId},
+    event_loop::{ActiveEventLoop, ControlFlow},
+    window::{Window, WindowAttributes, WindowId},
 };
 
-use crate::renderer::Renderer;
+use crate::frame_limiter::FrameLimiter;
+use crate::renderer::{Renderer, RendererConfig};
+use crate::ui_scale::UiScale;
+
+/// When the app renders frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedrawMode {
+    /// Every frame, as fast as presentation allows. For games.
+    #[default]
+    Continuous,
+    /// Only after input, window changes, `App::mark_dirty` or while `App::set_animating` is on,
+    /// sleeping in between. For tools that would otherwise keep the CPU and GPU busy.
+    OnDemand,
+}
+
+/// How the window `App` opens on startup looks. Sizes are logical, i.e. scaled by the
+/// monitor's scale factor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowConfig {
+    pub title: String,
+    pub size: LogicalSize<f64>,
+    pub resizable: bool,
+    /// Title bar and borders.
+    pub decorations: bool,
+    pub min_size: Option<LogicalSize<f64>>,
+    pub max_size: Option<LogicalSize<f64>>,
+    pub maximized: bool,
+    /// See-through where the frame's alpha is below one, where the platform and the
+    /// swapchain's composite alpha allow it.
+    pub transparent: bool,
+}
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "Vulkan Triangle".to_string(),
+            size: LogicalSize::new(1024.0, 1024.0),
+            resizable: true,
+            decorations: true,
+            min_size: None,
+            max_size: None,
+            maximized: false,
+            transparent: false,
+        }
+    }
+}
+impl WindowConfig {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_size(mut self, width: f64, height: f64) -> Self {
+        self.size = LogicalSize::new(width, height);
+        self
+    }
+
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn with_min_size(mut self, width: f64, height: f64) -> Self {
+        self.min_size = Some(LogicalSize::new(width, height));
+        self
+    }
+
+    pub fn with_max_size(mut self, width: f64, height: f64) -> Self {
+        self.max_size = Some(LogicalSize::new(width, height));
+        self
+    }
+
+    pub fn with_maximized(mut self, maximized: bool) -> Self {
+        self.maximized = maximized;
+        self
+    }
+
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// The winit attributes of a window like this, e.g. for `App::create_window`.
+    pub fn attributes(&self) -> WindowAttributes {
+        let mut attributes = Window::default_attributes()
+            .with_title(self.title.clone())
+            .with_inner_size(self.size)
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_maximized(self.maximized)
+            .with_transparent(self.transparent);
+        if let Some(min_size) = self.min_size {
+            attributes = attributes.with_min_inner_size(min_size);
+        }
+        if let Some(max_size) = self.max_size {
+            attributes = attributes.with_max_inner_size(max_size);
+        }
+        attributes
+    }
+}
+
+/// A window and the renderer drawing into it.
+struct AppWindow {
+    window: Arc<Window>,
+    renderer: Renderer,
+}
 
 #[derive(Default)]
 pub struct App {
-    window: Option<Arc<Window>>,
-    renderer: Option<Renderer>,
+    windows: HashMap<WindowId, AppWindow>,
+    /// The window `ui_scale` follows, the first one opened while it's open.
+    main_window: Option<WindowId>,
+    ui_scale: UiScale,
+    redraw_mode: RedrawMode,
+    /// The window opened on startup.
+    window_config: WindowConfig,
+    /// How every window's renderer is set up.
+    renderer_config: RendererConfig,
+    /// Something changed since the last frame in `RedrawMode::OnDemand`.
+    dirty: bool,
+    animating: bool,
+    /// Caps the frame rate, when set.
+    frame_limiter: Option<FrameLimiter>,
+}
+
+impl App {
+    /// An app opening a window like `window_config` on startup. `App::default` opens one
+    /// like `WindowConfig::default`.
+    pub fn new(window_config: WindowConfig) -> Self {
+        Self::with_config(window_config, RendererConfig::default())
+    }
+
+    /// `new`, with the windows' renderers set up as `renderer_config` says.
+    pub fn with_config(window_config: WindowConfig, renderer_config: RendererConfig) -> Self {
+        Self {
+            window_config,
+            renderer_config,
+            ..Default::default()
+        }
+    }
+
+    /// Opens another window with its own swapchain, rendered on the same device as the others.
+    pub fn create_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+    ) -> WindowId {
+        let window = Arc::new(event_loop.create_window(attributes).unwrap());
+        let renderer = match self.windows.values().next() {
+            Some(other) => Renderer::with_connection_config(
+                other.renderer.connection(),
+                window.clone(),
+                self.renderer_config.clone(),
+            ),
+            None => Renderer::with_config(window.clone(), self.renderer_config.clone()),
+        };
+        let id = window.id();
+        if self.main_window.is_none() {
+            self.main_window = Some(id);
+            self.ui_scale.refresh(&window);
+        }
+        self.windows.insert(id, AppWindow { window, renderer });
+        self.mark_dirty();
+        id
+    }
+
+    /// Closes a window and drops its renderer. Returns whether it was open.
+    pub fn close_window(&mut self, id: WindowId) -> bool {
+        if self.windows.remove(&id).is_none() {
+            return false;
+        }
+        if self.main_window == Some(id) {
+            self.main_window = self.windows.keys().next().copied();
+            if let Some(main_window) = self.main_window {
+                self.ui_scale.refresh(&self.windows[&main_window].window);
+            }
+        }
+        true
+    }
+
+    pub fn window_ids(&self) -> impl Iterator<Item = WindowId> + '_ {
+        self.windows.keys().copied()
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Arc<Window>> {
+        self.windows.get(&id).map(|app_window| &app_window.window)
+    }
+
+    pub fn renderer(&self, id: WindowId) -> Option<&Renderer> {
+        self.windows.get(&id).map(|app_window| &app_window.renderer)
+    }
+
+    pub fn renderer_mut(&mut self, id: WindowId) -> Option<&mut Renderer> {
+        self.windows
+            .get_mut(&id)
+            .map(|app_window| &mut app_window.renderer)
+    }
+
+    /// Scale for text and 2D layouts, kept up to date as the main window changes monitors.
+    pub fn ui_scale(&self) -> &UiScale {
+        &self.ui_scale
+    }
+
+    pub fn ui_scale_mut(&mut self) -> &mut UiScale {
+        &mut self.ui_scale
+    }
+
+    pub fn redraw_mode(&self) -> RedrawMode {
+        self.redraw_mode
+    }
+
+    pub fn set_redraw_mode(&mut self, mode: RedrawMode) {
+        self.redraw_mode = mode;
+        self.mark_dirty();
+    }
+
+    /// Asks for a frame in every window after the scene changed, e.g. from an edit that came in
+    /// without input. Frames drawn in the meantime cover it.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+        for app_window in self.windows.values() {
+            app_window.window.request_redraw();
+        }
+    }
+
+    /// Keeps rendering every frame in `RedrawMode::OnDemand` while on, e.g. during a camera
+    /// transition or playback.
+    pub fn set_animating(&mut self, animating: bool) {
+        self.animating = animating;
+        if animating {
+            self.mark_dirty();
+        }
+    }
+
+    /// Renders at most `target_fps` frames per second, on top of vsync, or as fast as the
+    /// redraw mode allows with `None`. Frames are paced by the main window's, so windows
+    /// rendering at once all keep to it.
+    pub fn set_frame_limit(&mut self, target_fps: Option<f64>) {
+        self.frame_limiter = target_fps.map(FrameLimiter::new);
+    }
+
+    pub fn frame_limit(&self) -> Option<f64> {
+        self.frame_limiter.as_ref().map(FrameLimiter::target_fps)
+    }
+
+    fn renders_continuously(&self) -> bool {
+        self.redraw_mode == RedrawMode::Continuous || self.animating
+    }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes()
-            .with_title("Vulkan Triangle")
-            .with_inner_size(winit::dpi::LogicalSize::new(1024.0, 1024.0));
-        self.window = Some(Arc::new(
-            event_loop.create_window(window_attributes).unwrap(),
-        ));
-        self.renderer = Some(Renderer::new(
-            self.window
-                .as_ref()
-                .expect("Window should be set before renderer")
-                .clone(),
-        ));
-    }
-
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
-        assert!(self.window.is_some());
-        assert!(self.renderer.is_some());
-        let window = self.window.as_ref().unwrap();
-        let renderer = self.renderer.as_mut().unwrap();
+        if !self.windows.is_empty() {
+            return;
+        }
+        let window_attributes = self.window_config.attributes();
+        self.create_window(event_loop, window_attributes);
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
+        if !self.windows.contains_key(&id) {
+            // Events can still arrive for a window that was just closed.
+            return;
+        }
+        if matches!(
+            event,
+            WindowEvent::Resized(_)
+                | WindowEvent::ScaleFactorChanged { .. }
+                | WindowEvent::Focused(_)
+                | WindowEvent::Occluded(false)
+                | WindowEvent::KeyboardInput { .. }
+                | WindowEvent::ModifiersChanged(_)
+                | WindowEvent::CursorMoved { .. }
+                | WindowEvent::CursorLeft { .. }
+                | WindowEvent::MouseWheel { .. }
+                | WindowEvent::MouseInput { .. }
+                | WindowEvent::Touch(_)
+        ) {
+            self.mark_dirty();
+        }
+        let continuous = self.renders_continuously();
+        let AppWindow { window, renderer } = self.windows.get_mut(&id).unwrap();
+        if self.main_window == Some(id) && self.ui_scale.handle_event(window, &event) {
+            log::info!("the UI scale changed to {}", self.ui_scale.factor());
+        }
         //MARK: - Event loop
         match event {
             WindowEvent::CloseRequested => {
-                println!("The close button was pressed; stopping");
-                event_loop.exit();
+                self.close_window(id);
+                if self.windows.is_empty() {
+                    log::info!("the close button was pressed; stopping");
+                    event_loop.exit();
+                }
             }
             WindowEvent::Resized(new_size) => {
-                println!("The window was resized to {:?}", new_size);
+                log::debug!("the window was resized to {new_size:?}");
                 renderer.recreate_core(window.clone());
             }
             WindowEvent::RedrawRequested => {
+                self.dirty = false;
+                if let Some(frame_limiter) = &mut self.frame_limiter {
+                    if self.main_window == Some(id) {
+                        frame_limiter.wait();
+                    }
+                }
                 renderer.on_draw(window.clone());
-                window.request_redraw();
+                if continuous {
+                    window.request_redraw();
+                }
             }
             _ => (),
         }
     }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.renders_continuously() {
+            event_loop.set_control_flow(ControlFlow::Poll);
+        } else {
+            // Sleep until the next event; redraws requested by it wake the loop again.
+            event_loop.set_control_flow(ControlFlow::Wait);
+        }
+        if self.dirty {
+            for app_window in self.windows.values() {
+                app_window.window.request_redraw();
+            }
+        }
+    }
 }
@@ -0,0 +1,66 @@
+//! VR output: headsets report a pose and an asymmetric field of view per eye, which become a
+//! `StereoCamera`, and take the eyes' images from their own swapchain. With the `openxr`
+//! feature, `OpenXrBackend` drives any OpenXR runtime.
+
+#[cfg(feature = "openxr")]
+mod openxr_backend;
+
+use nalgebra::Isometry3;
+use nalgebra::Matrix4;
+use nalgebra::Translation3;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+#[cfg(feature = "openxr")]
+pub use self::openxr_backend::OpenXrBackend;
+#[cfg(feature = "openxr")]
+pub use self::openxr_backend::XrError;
+#[cfg(feature = "openxr")]
+pub use self::openxr_backend::XrFrame;
+
+/// Angles from the view direction to the edges of an eye's view, in radians. Left and down are
+/// negative unless the view lies wholly to one side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EyeFov {
+    pub left: f32,
+    pub right: f32,
+    pub up: f32,
+    pub down: f32,
+}
+impl EyeFov {
+    /// Vulkan projection of this field of view, for a camera looking down -Z with Y up, as glTF
+    /// and OpenXR have it: Y flipped and depth from 0 at `near` to 1 at `far`.
+    pub fn projection(&self, near: f32, far: f32) -> Matrix4<f32> {
+        let [left, right, up, down] = [self.left, self.right, self.up, self.down].map(f32::tan);
+        let width = right - left;
+        let height = up - down;
+        Matrix4::new(
+            2.0 / width,
+            0.0,
+            (right + left) / width,
+            0.0,
+            //
+            0.0,
+            -2.0 / height,
+            -(up + down) / height,
+            0.0,
+            //
+            0.0,
+            0.0,
+            far / (near - far),
+            near * far / (near - far),
+            //
+            0.0,
+            0.0,
+            -1.0,
+            0.0,
+        )
+    }
+}
+
+/// View matrix of an eye at `position` turned by `orientation`, both in the tracking space.
+pub fn eye_view(position: Vector3<f32>, orientation: UnitQuaternion<f32>) -> Matrix4<f32> {
+    Isometry3::from_parts(Translation3::from(position), orientation)
+        .inverse()
+        .to_homogeneous()
+}
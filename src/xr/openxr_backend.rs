@@ -0,0 +1,650 @@
+use std::fmt;
+use std::ptr;
+use std::sync::Arc;
+
+use ash::vk;
+use nalgebra::Quaternion;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+use openxr as xr;
+use vulkano::device::Device;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::ImageAspects;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceExtensions;
+use vulkano::sync::GpuFuture;
+use vulkano::Handle;
+use vulkano::VulkanObject;
+use winit::window::Window;
+
+use super::eye_view;
+use super::EyeFov;
+use crate::renderer_core::ComputeContext;
+use crate::renderer_core::StereoCamera;
+use crate::renderer_core::StereoTarget;
+use crate::vulkan_api_connection::ConnectionRequirements;
+use crate::vulkan_api_connection::VulkanConnection;
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+/// Swapchain formats to pick when the runtime offers them, before its own first choice.
+const PREFERRED_FORMATS: [Format; 2] = [Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB];
+
+#[derive(Debug)]
+pub enum XrError {
+    /// No OpenXR loader is installed.
+    Load(xr::LoadError),
+    /// The runtime lacks `XR_KHR_vulkan_enable`.
+    VulkanUnsupported,
+    /// The device's Vulkan version is below the runtime's minimum.
+    VersionUnsupported,
+    /// The runtime offers no color format vulkano knows.
+    FormatUnsupported,
+    Runtime(xr::sys::Result),
+}
+impl fmt::Display for XrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XrError::Load(error) => write!(f, "failed to load OpenXR: {error}"),
+            XrError::VulkanUnsupported => write!(f, "the OpenXR runtime doesn't support Vulkan"),
+            XrError::VersionUnsupported => {
+                write!(
+                    f,
+                    "the device's Vulkan version is too old for the OpenXR runtime"
+                )
+            }
+            XrError::FormatUnsupported => write!(f, "no supported swapchain format"),
+            XrError::Runtime(result) => write!(f, "OpenXR call failed: {result}"),
+        }
+    }
+}
+impl std::error::Error for XrError {}
+impl From<xr::LoadError> for XrError {
+    fn from(error: xr::LoadError) -> Self {
+        XrError::Load(error)
+    }
+}
+impl From<xr::sys::Result> for XrError {
+    fn from(result: xr::sys::Result) -> Self {
+        XrError::Runtime(result)
+    }
+}
+
+/// A frame the runtime wants rendered, from `OpenXrBackend::begin_frame`.
+pub struct XrFrame {
+    /// The eyes at the time the frame will be shown; set it on the `StereoTarget`.
+    pub camera: StereoCamera,
+    views: Vec<xr::View>,
+    display_time: xr::Time,
+}
+
+/// Renders to a headset through an OpenXR runtime, in place of the window's swapchain. The
+/// runtime picks the GPU and the Vulkan extensions, so it creates the `VulkanConnection` too;
+/// the window can keep showing a mirror of one eye.
+///
+/// Each frame: `poll_events`, then `begin_frame` for the eyes' cameras, draw them into a
+/// `StereoTarget` from `create_target`, and `end_frame` to copy both layers into the runtime's
+/// swapchain and submit them to its compositor.
+pub struct OpenXrBackend {
+    // Fields drop in order: everything made from the session goes first, the queue and with it
+    // the device last.
+    copy: CopyCommands,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    space: xr::Space,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    session: xr::Session<xr::Vulkan>,
+    instance: xr::Instance,
+    queue: Arc<Queue>,
+    format: Format,
+    extent: [u32; 2],
+    blend_mode: xr::EnvironmentBlendMode,
+    event_buffer: xr::EventDataBuffer,
+    running: bool,
+    /// Clip planes of the eyes' projections.
+    pub near: f32,
+    pub far: f32,
+}
+impl OpenXrBackend {
+    /// Connects to the runtime's headset and creates the Vulkan connection on its GPU.
+    pub fn new(
+        window: Arc<Window>,
+        application_name: &str,
+    ) -> Result<(Self, VulkanConnection), XrError> {
+        let entry = unsafe { xr::Entry::load()? };
+        if !entry.enumerate_extensions()?.khr_vulkan_enable {
+            return Err(XrError::VulkanUnsupported);
+        }
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name,
+                application_version: 0,
+                engine_name: "szumi",
+                engine_version: 0,
+            },
+            &extensions,
+            &[],
+        )?;
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        // Must be queried before a session is created.
+        let requirements = instance.graphics_requirements::<xr::Vulkan>(system)?;
+        let blend_mode = instance.enumerate_environment_blend_modes(system, VIEW_TYPE)?[0];
+
+        let instance_extensions = instance.vulkan_legacy_instance_extensions(system)?;
+        let device_extensions = instance.vulkan_legacy_device_extensions(system)?;
+        let select_device = |vulkan: &Arc<Instance>| {
+            let device =
+                unsafe { instance.vulkan_graphics_device(system, vulkan.handle().as_raw() as _) };
+            device.expect("OpenXR runtime has no Vulkan device") as u64
+        };
+        let connection = VulkanConnection::with_requirements(
+            window,
+            &ConnectionRequirements {
+                instance_extensions: InstanceExtensions::from_iter(
+                    instance_extensions.split_ascii_whitespace(),
+                ),
+                device_extensions: DeviceExtensions::from_iter(
+                    device_extensions.split_ascii_whitespace(),
+                ),
+                physical_device: Some(&select_device),
+                ..Default::default()
+            },
+        );
+
+        let device = connection.device.clone();
+        let api_version = device.api_version();
+        let minimum = requirements.min_api_version_supported;
+        if (api_version.major, api_version.minor) < (minimum.major() as u32, minimum.minor() as u32)
+        {
+            return Err(XrError::VersionUnsupported);
+        }
+        let queue = connection.queues.graphics().clone();
+        let (session, frame_waiter, frame_stream) = unsafe {
+            instance.create_session::<xr::Vulkan>(
+                system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: device.instance().handle().as_raw() as _,
+                    physical_device: device.physical_device().handle().as_raw() as _,
+                    device: device.handle().as_raw() as _,
+                    queue_family_index: queue.queue_family_index(),
+                    queue_index: queue.id_within_family(),
+                },
+            )?
+        };
+
+        let view = instance.enumerate_view_configuration_views(system, VIEW_TYPE)?[0];
+        let extent = [
+            view.recommended_image_rect_width,
+            view.recommended_image_rect_height,
+        ];
+        let formats: Vec<Format> = session
+            .enumerate_swapchain_formats()?
+            .into_iter()
+            .filter_map(|raw| Format::try_from(vk::Format::from_raw(raw as i32)).ok())
+            .filter(|format| format.aspects().intersects(ImageAspects::COLOR))
+            .collect();
+        let format = PREFERRED_FORMATS
+            .into_iter()
+            .find(|format| formats.contains(format))
+            .or_else(|| formats.first().copied())
+            .ok_or(XrError::FormatUnsupported)?;
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                | xr::SwapchainUsageFlags::TRANSFER_DST,
+            format: vk::Format::from(format).as_raw() as u32,
+            sample_count: 1,
+            width: extent[0],
+            height: extent[1],
+            face_count: 1,
+            array_size: StereoTarget::VIEW_COUNT,
+            mip_count: 1,
+        })?;
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(vk::Image::from_raw)
+            .collect();
+        // Seated tracking, with the origin where the head was when the app started.
+        let space =
+            session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+
+        let backend = Self {
+            copy: CopyCommands::new(&queue),
+            swapchain,
+            images,
+            space,
+            frame_waiter,
+            frame_stream,
+            session,
+            instance,
+            queue,
+            format,
+            extent,
+            blend_mode,
+            event_buffer: xr::EventDataBuffer::new(),
+            running: false,
+            near: 0.05,
+            far: 100.0,
+        };
+        Ok((backend, connection))
+    }
+
+    /// Per eye.
+    pub fn extent(&self) -> [u32; 2] {
+        self.extent
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// A target matching the runtime's swapchain, for `end_frame`.
+    pub fn create_target(&self, context: &ComputeContext) -> StereoTarget {
+        StereoTarget::new(context, self.format, self.extent)
+    }
+
+    /// Whether the runtime shows the app, i.e. `begin_frame` can return frames.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts and stops the session as the runtime asks. Returns false once the app should
+    /// quit, e.g. after the user exits from the headset's menu.
+    pub fn poll_events(&mut self) -> Result<bool, XrError> {
+        while let Some(event) = self.instance.poll_event(&mut self.event_buffer)? {
+            match event {
+                xr::Event::SessionStateChanged(change) => match change.state() {
+                    xr::SessionState::READY => {
+                        self.session.begin(VIEW_TYPE)?;
+                        self.running = true;
+                    }
+                    xr::SessionState::STOPPING => {
+                        self.session.end()?;
+                        self.running = false;
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => return Ok(false),
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Asks the runtime to stop the session; `poll_events` returns false when it has.
+    pub fn request_exit(&self) -> Result<(), XrError> {
+        self.session.request_exit()?;
+        Ok(())
+    }
+
+    /// Waits until the runtime wants the next frame and returns where the eyes will be when
+    /// it's shown. `None` when the session isn't running or the runtime skips this frame, which
+    /// then needs no `end_frame`.
+    pub fn begin_frame(&mut self) -> Result<Option<XrFrame>, XrError> {
+        if !self.running {
+            return Ok(None);
+        }
+        let state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+        if !state.should_render {
+            self.queue.with(|_queue| {
+                self.frame_stream
+                    .end(state.predicted_display_time, self.blend_mode, &[])
+            })?;
+            return Ok(None);
+        }
+
+        let (_, views) =
+            self.session
+                .locate_views(VIEW_TYPE, state.predicted_display_time, &self.space)?;
+        let eye = |view: &xr::View| {
+            let xr::Posef {
+                orientation,
+                position,
+            } = view.pose;
+            let orientation = UnitQuaternion::from_quaternion(Quaternion::new(
+                orientation.w,
+                orientation.x,
+                orientation.y,
+                orientation.z,
+            ));
+            let fov = EyeFov {
+                left: view.fov.angle_left,
+                right: view.fov.angle_right,
+                up: view.fov.angle_up,
+                down: view.fov.angle_down,
+            };
+            (
+                eye_view(
+                    Vector3::new(position.x, position.y, position.z),
+                    orientation,
+                ),
+                fov.projection(self.near, self.far),
+            )
+        };
+        let (left_view, left_projection) = eye(&views[0]);
+        let (right_view, right_projection) = eye(&views[1]);
+        Ok(Some(XrFrame {
+            camera: StereoCamera {
+                views: [left_view, right_view],
+                projections: [left_projection, right_projection],
+            },
+            views,
+            display_time: state.predicted_display_time,
+        }))
+    }
+
+    /// Copies both eyes of `target` into the runtime's swapchain once `rendered`, the drawing of
+    /// `frame` into `target`, is done, and submits them to its compositor. The copy waits for
+    /// the drawing on the GPU; the CPU only waits for the copy, before handing the image back.
+    /// `target` comes from `create_target`.
+    pub fn end_frame(
+        &mut self,
+        frame: XrFrame,
+        target: &StereoTarget,
+        rendered: Box<dyn GpuFuture>,
+    ) -> Result<(), XrError> {
+        // Kept until the copy is done, which is after the drawing, so dropping it never blocks.
+        let rendered = rendered.then_signal_fence_and_flush().unwrap();
+        let rendered_queue = rendered.queue().unwrap_or_else(|| self.queue.clone());
+        self.copy.signal_rendered(&rendered_queue);
+
+        let index = self.swapchain.acquire_image()?;
+        self.swapchain.wait_image(xr::Duration::INFINITE)?;
+        self.copy.submit(
+            &self.queue,
+            target.color().image().handle(),
+            self.images[index as usize],
+            self.extent,
+        );
+        // The compositor reads the image as soon as it's released.
+        self.copy.wait();
+        // The runtime may use the queue while releasing and ending, which vulkano must not.
+        self.queue.with(|_queue| self.swapchain.release_image())?;
+
+        let rect = xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: self.extent[0] as i32,
+                height: self.extent[1] as i32,
+            },
+        };
+        let views: Vec<_> = frame
+            .views
+            .iter()
+            .enumerate()
+            .map(|(eye, view)| {
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&self.swapchain)
+                            .image_array_index(eye as u32)
+                            .image_rect(rect),
+                    )
+            })
+            .collect();
+        let layer = xr::CompositionLayerProjection::new()
+            .space(&self.space)
+            .views(&views);
+        self.queue.with(|_queue| {
+            self.frame_stream
+                .end(frame.display_time, self.blend_mode, &[&layer])
+        })?;
+        Ok(())
+    }
+}
+
+/// Copies the stereo target into swapchain images with raw commands, since vulkano can't wrap
+/// images whose memory it didn't bind.
+struct CopyCommands {
+    device: Arc<Device>,
+    pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    /// Signaled once the target is drawn, waited on by the copy.
+    rendered: vk::Semaphore,
+    fence: vk::Fence,
+}
+impl CopyCommands {
+    fn new(queue: &Queue) -> Self {
+        let device = queue.device().clone();
+        let fns = &device.fns().v1_0;
+        let mut pool = vk::CommandPool::null();
+        let mut command_buffer = vk::CommandBuffer::null();
+        let mut rendered = vk::Semaphore::null();
+        let mut fence = vk::Fence::null();
+        unsafe {
+            (fns.create_command_pool)(
+                device.handle(),
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                    queue_family_index: queue.queue_family_index(),
+                    ..Default::default()
+                },
+                ptr::null(),
+                &mut pool,
+            )
+            .result()
+            .unwrap();
+            (fns.allocate_command_buffers)(
+                device.handle(),
+                &vk::CommandBufferAllocateInfo {
+                    command_pool: pool,
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                },
+                &mut command_buffer,
+            )
+            .result()
+            .unwrap();
+            (fns.create_semaphore)(
+                device.handle(),
+                &vk::SemaphoreCreateInfo::default(),
+                ptr::null(),
+                &mut rendered,
+            )
+            .result()
+            .unwrap();
+            (fns.create_fence)(
+                device.handle(),
+                &vk::FenceCreateInfo::default(),
+                ptr::null(),
+                &mut fence,
+            )
+            .result()
+            .unwrap();
+        }
+        Self {
+            device,
+            pool,
+            command_buffer,
+            rendered,
+            fence,
+        }
+    }
+
+    /// Signals the semaphore the next copy waits on once everything submitted to `queue` so far,
+    /// i.e. the drawing of the target, is done.
+    fn signal_rendered(&self, queue: &Arc<Queue>) {
+        let fns = &self.device.fns().v1_0;
+        let submit_info = vk::SubmitInfo {
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &self.rendered,
+            ..Default::default()
+        };
+        unsafe {
+            queue
+                .with(|_queue| {
+                    (fns.queue_submit)(queue.handle(), 1, &submit_info, vk::Fence::null())
+                })
+                .result()
+                .unwrap();
+        }
+    }
+
+    /// Copies both layers of `source` into `image` once the target is drawn, and leaves `image`
+    /// as the compositor expects it. Returns right after submitting; `wait` for the copy.
+    fn submit(&self, queue: &Arc<Queue>, source: vk::Image, image: vk::Image, extent: [u32; 2]) {
+        let fns = &self.device.fns().v1_0;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: StereoTarget::VIEW_COUNT,
+        };
+        let barrier = |image, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range,
+                ..Default::default()
+            }
+        };
+        // vulkano returns the target to its default layout after each command buffer, which is
+        // general for its mix of usages.
+        let before = [
+            barrier(
+                source,
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            barrier(
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        ];
+        let after = [barrier(
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::empty(),
+        )];
+        let layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: StereoTarget::VIEW_COUNT,
+        };
+        let region = vk::ImageCopy {
+            src_subresource: layers,
+            src_offset: vk::Offset3D::default(),
+            dst_subresource: layers,
+            dst_offset: vk::Offset3D::default(),
+            extent: vk::Extent3D {
+                width: extent[0],
+                height: extent[1],
+                depth: 1,
+            },
+        };
+
+        unsafe {
+            (fns.reset_command_buffer)(self.command_buffer, vk::CommandBufferResetFlags::empty())
+                .result()
+                .unwrap();
+            (fns.begin_command_buffer)(
+                self.command_buffer,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                },
+            )
+            .result()
+            .unwrap();
+            (fns.cmd_pipeline_barrier)(
+                self.command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                0,
+                ptr::null(),
+                0,
+                ptr::null(),
+                before.len() as u32,
+                before.as_ptr(),
+            );
+            (fns.cmd_copy_image)(
+                self.command_buffer,
+                source,
+                vk::ImageLayout::GENERAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                1,
+                &region,
+            );
+            (fns.cmd_pipeline_barrier)(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                0,
+                ptr::null(),
+                0,
+                ptr::null(),
+                after.len() as u32,
+                after.as_ptr(),
+            );
+            (fns.end_command_buffer)(self.command_buffer)
+                .result()
+                .unwrap();
+
+            let wait_stage = vk::PipelineStageFlags::TRANSFER;
+            let submit_info = vk::SubmitInfo {
+                wait_semaphore_count: 1,
+                p_wait_semaphores: &self.rendered,
+                p_wait_dst_stage_mask: &wait_stage,
+                command_buffer_count: 1,
+                p_command_buffers: &self.command_buffer,
+                ..Default::default()
+            };
+            queue
+                .with(|_queue| (fns.queue_submit)(queue.handle(), 1, &submit_info, self.fence))
+                .result()
+                .unwrap();
+        }
+    }
+
+    /// Blocks until the last `submit` is done, after which the command buffer can be reused.
+    fn wait(&self) {
+        let device = self.device.handle();
+        let fns = &self.device.fns().v1_0;
+        unsafe {
+            (fns.wait_for_fences)(device, 1, &self.fence, vk::TRUE, u64::MAX)
+                .result()
+                .unwrap();
+            (fns.reset_fences)(device, 1, &self.fence).result().unwrap();
+        }
+    }
+}
+impl Drop for CopyCommands {
+    fn drop(&mut self) {
+        let device = self.device.handle();
+        let fns = &self.device.fns().v1_0;
+        unsafe {
+            (fns.destroy_fence)(device, self.fence, ptr::null());
+            (fns.destroy_semaphore)(device, self.rendered, ptr::null());
+            // Frees the command buffer along with the pool.
+            (fns.destroy_command_pool)(device, self.pool, ptr::null());
+        }
+    }
+}